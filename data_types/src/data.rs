@@ -212,6 +212,62 @@ pub fn split_lines_into_write_entry_partitions(
     data.split_off(idx)
 }
 
+/// Creates a `WriteBufferBatch` with a delete entry for each of
+/// `partition_keys`, recording the deletion of the rows of
+/// `table_name` (or all tables, if `None`) that match `predicate` and are within
+/// `start_time` (inclusive) and `stop_time` (exclusive)
+pub fn delete_to_write_entry_partitions(
+    partition_keys: &[&str],
+    table_name: Option<&str>,
+    predicate: &str,
+    start_time: i64,
+    stop_time: i64,
+) -> Vec<u8> {
+    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+
+    let entries = partition_keys
+        .iter()
+        .map(|key| {
+            let partition_key = fbb.create_string(key);
+            let table_name = table_name.map(|name| fbb.create_string(name));
+            let predicate = fbb.create_string(predicate);
+
+            let delete = wb::WriteBufferDelete::create(
+                &mut fbb,
+                &wb::WriteBufferDeleteArgs {
+                    table_name,
+                    predicate: Some(predicate),
+                    start_time,
+                    stop_time,
+                },
+            );
+
+            wb::WriteBufferEntry::create(
+                &mut fbb,
+                &wb::WriteBufferEntryArgs {
+                    partition_key: Some(partition_key),
+                    delete: Some(delete),
+                    ..Default::default()
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let entries_vec = fbb.create_vector(&entries);
+
+    let batch = wb::WriteBufferBatch::create(
+        &mut fbb,
+        &wb::WriteBufferBatchArgs {
+            entries: Some(entries_vec),
+        },
+    );
+
+    fbb.finish(batch, None);
+
+    let (mut data, idx) = fbb.collapse();
+    data.split_off(idx)
+}
+
 fn add_write_entry<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     partition_key: Option<&str>,
@@ -14,6 +14,13 @@ include!(concat!(
 ));
 include!(concat!(env!("OUT_DIR"), "/wal_generated.rs"));
 
+/// Prometheus remote write / remote read protocol. These live in their own
+/// module as several of the message names (e.g. `ReadResponse`) collide with
+/// the storage gRPC types.
+pub mod prometheus {
+    include!(concat!(env!("OUT_DIR"), "/prometheus.rs"));
+}
+
 // Can't implement `Default` because `prost::Message` implements `Default`
 impl TimestampRange {
     pub fn max() -> Self {
@@ -471,36 +471,86 @@ impl PartialEq<String> for EscapedStr<'_> {
 }
 
 pub fn parse_lines(input: &str) -> impl Iterator<Item = Result<ParsedLine<'_>>> {
-    split_lines(input).filter_map(|line| {
-        let i = trim_leading(line);
+    split_lines(input).filter_map(parse_line_text)
+}
+
+/// A line of the input of `parse_numbered_lines`
+#[derive(Debug)]
+pub struct NumberedLine<'a> {
+    /// The 1-based number of the line of the input on which the line
+    /// starts: a quoted string field value may span several lines
+    pub number: usize,
+    /// The text of the line
+    pub text: &'a str,
+    pub parsed: Result<ParsedLine<'a>>,
+}
+
+/// Like `parse_lines`, but also returns the number and text of each
+/// line, so that errors can be traced back to where they are
+pub fn parse_numbered_lines(input: &str) -> impl Iterator<Item = NumberedLine<'_>> {
+    let mut next_number = 1;
+    split_lines(input).filter_map(move |text| {
+        let number = next_number;
+        next_number += 1 + text.matches('\n').count();
+
+        parse_line_text(text).map(|parsed| NumberedLine {
+            number,
+            text,
+            parsed,
+        })
+    })
+}
 
-        if i.is_empty() {
-            return None;
+/// Splits `input` after its first `max_lines` complete lines, those
+/// followed by a newline, returning them and the rest of the input.
+///
+/// This allows input arriving in pieces to be parsed as it arrives: the
+/// rest may be the start of a line whose end hasn't arrived yet, such
+/// as a quoted string field value spanning several lines.
+pub fn split_complete_lines(input: &str, max_lines: usize) -> (&str, &str) {
+    // every line but the last one was followed by a newline
+    let mut lines = split_lines(input).peekable();
+    let mut end = 0;
+    for _ in 0..max_lines {
+        match (lines.next(), lines.peek()) {
+            (Some(line), Some(_)) => end += line.len() + 1,
+            _ => break,
         }
+    }
+    input.split_at(end)
+}
 
-        let res = match parse_line(i) {
-            Ok((remaining, line)) => {
-                // should have parsed the whole input line, if any
-                // data remains it is a parse error for this line
-                // corresponding Go logic:
-                // https://github.com/influxdata/influxdb/blob/217eddc87e14a79b01d0c22994fc139f530094a2/models/points_parser.go#L259-L266
-                if !remaining.is_empty() {
-                    Some(Err(Error::CannotParseEntireLine {
-                        trailing_content: String::from(remaining),
-                    }))
-                } else {
-                    Some(Ok(line))
-                }
+/// Parses one line of the input of `parse_lines`, returning `None` if
+/// there is nothing to parse
+fn parse_line_text(line: &str) -> Option<Result<ParsedLine<'_>>> {
+    let i = trim_leading(line);
+
+    if i.is_empty() {
+        return None;
+    }
+
+    let res = match parse_line(i) {
+        Ok((remaining, line)) => {
+            // should have parsed the whole input line, if any
+            // data remains it is a parse error for this line
+            // corresponding Go logic:
+            // https://github.com/influxdata/influxdb/blob/217eddc87e14a79b01d0c22994fc139f530094a2/models/points_parser.go#L259-L266
+            if !remaining.is_empty() {
+                Some(Err(Error::CannotParseEntireLine {
+                    trailing_content: String::from(remaining),
+                }))
+            } else {
+                Some(Ok(line))
             }
-            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Some(Err(e)),
-            Err(nom::Err::Incomplete(_)) => unreachable!("Cannot have incomplete data"), // Only streaming parsers have this
-        };
-
-        if let Some(Err(r)) = &res {
-            debug!("Error parsing line: '{}'. Error was {:?}", line, r);
         }
-        res
-    })
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => Some(Err(e)),
+        Err(nom::Err::Incomplete(_)) => unreachable!("Cannot have incomplete data"), // Only streaming parsers have this
+    };
+
+    if let Some(Err(r)) = &res {
+        debug!("Error parsing line: '{}'. Error was {:?}", line, r);
+    }
+    res
 }
 
 /// Split `input` into invidividual lines to be parsed, based on the
@@ -1869,6 +1919,60 @@ her"#,
         Ok(())
     }
 
+    #[test]
+    fn parse_numbered_lines() -> Result {
+        let input = "# a comment\n\
+                     foo asdf=1\n\
+                     \n\
+                     foo asdf=\"multi\nline\"\n\
+                     foo asdf=23.1.22\n";
+
+        let lines: Vec<_> = super::parse_numbered_lines(input).collect();
+
+        let numbers: Vec<_> = lines.iter().map(|line| line.number).collect();
+        assert_eq!(numbers, vec![2, 4, 6]);
+
+        assert_eq!(lines[0].text, "foo asdf=1");
+        let parsed_line = lines[0].parsed.as_ref().expect("first line succeeded");
+        assert_eq!(parsed_line.field_set[0].0, "asdf");
+        assert_eq!(lines[1].text, "foo asdf=\"multi\nline\"");
+        assert!(lines[1].parsed.is_ok());
+        assert_eq!(lines[2].text, "foo asdf=23.1.22");
+        assert!(lines[2].parsed.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn split_complete_lines() {
+        let input = "foo asdf=1\n\
+                     foo asdf=\"multi\nline\"\n\
+                     foo asdf=\"incomp";
+
+        assert_eq!(
+            super::split_complete_lines(input, 1),
+            (
+                "foo asdf=1\n",
+                "foo asdf=\"multi\nline\"\nfoo asdf=\"incomp"
+            )
+        );
+        assert_eq!(
+            super::split_complete_lines(input, 10),
+            (
+                "foo asdf=1\nfoo asdf=\"multi\nline\"\n",
+                "foo asdf=\"incomp"
+            )
+        );
+        assert_eq!(
+            super::split_complete_lines("foo asdf=\"multi\nli", 10),
+            ("", "foo asdf=\"multi\nli")
+        );
+        assert_eq!(
+            super::split_complete_lines("foo asdf=1\n", 10),
+            ("foo asdf=1\n", "")
+        );
+    }
+
     #[test]
     fn parse_advance_after_error() -> Result {
         // Note that the first line has an error (23.1.22 is not a number)
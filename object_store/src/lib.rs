@@ -17,10 +17,15 @@
 use bytes::Bytes;
 use futures::{stream, Stream, StreamExt, TryStreamExt};
 use rusoto_core::ByteStream;
-use rusoto_credential::ChainProvider;
+use rusoto_credential::{ChainProvider, StaticProvider};
 use rusoto_s3::S3;
 use snafu::{ensure, futures::TryStreamExt as _, OptionExt, ResultExt, Snafu};
-use std::{collections::BTreeMap, fmt, io, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 use tokio::{fs, sync::RwLock};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
@@ -228,6 +233,85 @@ pub struct AmazonS3 {
     bucket_name: String,
 }
 
+/// The configuration of a connection to Amazon S3 or an S3-compatible service,
+/// built with [`AmazonS3Config::new`] and its setters.
+#[derive(Clone, Default)]
+pub struct AmazonS3Config {
+    bucket_name: String,
+    region: Option<String>,
+    endpoint: Option<String>,
+    credentials: Option<(String, String)>,
+}
+
+impl fmt::Debug for AmazonS3Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AmazonS3Config")
+            .field("bucket_name", &self.bucket_name)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field(
+                "credentials",
+                &self
+                    .credentials
+                    .as_ref()
+                    .map(|(access_key_id, _)| access_key_id),
+            )
+            .finish()
+    }
+}
+
+impl AmazonS3Config {
+    /// The name of the region used for custom endpoints without a configured region.
+    pub const DEFAULT_CUSTOM_REGION: &'static str = "us-east-1";
+
+    /// Configure a connection to the specified bucket, in the region named by
+    /// `AWS_DEFAULT_REGION` or `AWS_REGION`, using the credentials found by
+    /// [`AmazonS3::new`].
+    pub fn new(bucket_name: impl Into<String>) -> Self {
+        Self {
+            bucket_name: bucket_name.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Use the Amazon region with the specified name, such as `us-east-2`.
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Connect to the specified endpoint, such as `http://localhost:9000` for a local
+    /// MinIO server, rather than to Amazon.
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Authenticate with the specified access key rather than searching for
+    /// credentials.
+    pub fn credentials(
+        mut self,
+        access_key_id: impl Into<String>,
+        secret_access_key: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((access_key_id.into(), secret_access_key.into()));
+        self
+    }
+
+    fn rusoto_region(&self) -> InternalResult<rusoto_core::Region> {
+        match (&self.endpoint, &self.region) {
+            (Some(endpoint), region) => Ok(rusoto_core::Region::Custom {
+                name: region
+                    .clone()
+                    .unwrap_or_else(|| Self::DEFAULT_CUSTOM_REGION.to_string()),
+                endpoint: endpoint.clone(),
+            }),
+            (None, Some(region)) => region.parse().context(InvalidS3Region { region }),
+            (None, None) => Ok(rusoto_core::Region::default()),
+        }
+    }
+}
+
 impl fmt::Debug for AmazonS3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AmazonS3")
@@ -258,6 +342,30 @@ impl AmazonS3 {
         }
     }
 
+    /// Configure a connection to Amazon S3, or to an S3-compatible service such as
+    /// [MinIO](https://min.io/), as described by `config`.
+    pub fn from_config(config: &AmazonS3Config) -> Result<Self> {
+        let region = config.rusoto_region()?;
+        let http_client = rusoto_core::request::HttpClient::new()
+            .expect("Current implementation of rusoto_core has no way for this to fail");
+
+        // rusoto always uses path-style addressing, so custom endpoints don't need
+        // a DNS entry for each bucket.
+        let client = match &config.credentials {
+            Some((access_key_id, secret_access_key)) => {
+                let credentials_provider =
+                    StaticProvider::new_minimal(access_key_id.clone(), secret_access_key.clone());
+                rusoto_s3::S3Client::new_with(http_client, credentials_provider, region)
+            }
+            None => rusoto_s3::S3Client::new_with(http_client, ChainProvider::new(), region),
+        };
+
+        Ok(Self {
+            client,
+            bucket_name: config.bucket_name.clone(),
+        })
+    }
+
     /// Save the provided bytes to the specified location.
     async fn put<S>(&self, location: &str, bytes: S, length: usize) -> InternalResult<()>
     where
@@ -456,6 +564,10 @@ impl InMemory {
 }
 
 /// Local filesystem storage suitable for testing or for opting out of using a cloud storage provider.
+///
+/// Locations map to paths below the root directory, with each `/`-separated
+/// component of a location becoming a directory. Locations that would escape
+/// the root directory, being absolute or containing `..`, are rejected.
 #[derive(Debug)]
 pub struct File {
     root: PathBuf,
@@ -467,8 +579,17 @@ impl File {
         Self { root: root.into() }
     }
 
-    fn path(&self, location: &str) -> PathBuf {
-        self.root.join(location)
+    fn path(&self, location: &str) -> InternalResult<PathBuf> {
+        let relative = Path::new(location);
+        ensure!(
+            !location.is_empty()
+                && relative
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_))),
+            InvalidLocation { location }
+        );
+
+        Ok(self.root.join(relative))
     }
 
     /// Save the provided bytes to the specified location.
@@ -490,15 +611,53 @@ impl File {
             }
         );
 
-        let path = self.path(location);
-        let mut file = fs::File::create(&path)
-            .await
-            .context(UnableToCreateFile { path })?;
-        tokio::io::copy(&mut &content[..], &mut file)
+        let path = self.path(location)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context(UnableToCreateDir { path: parent })?;
+        }
+
+        // Write to a temporary file first and move it into place once it is
+        // complete, so that readers never see a partially written object.
+        let temp_path = Self::temp_path(&path);
+        let mut file = fs::File::create(&temp_path)
             .await
-            .context(UnableToCopyDataToFile)?;
+            .context(UnableToCreateFile { path: &temp_path })?;
 
-        Ok(())
+        let result: InternalResult<()> = async {
+            tokio::io::copy(&mut &content[..], &mut file)
+                .await
+                .context(UnableToCopyDataToFile)?;
+            file.sync_all().await.context(UnableToCopyDataToFile)?;
+            drop(file);
+
+            fs::rename(&temp_path, &path)
+                .await
+                .context(UnableToRenameFile { path })
+        }
+        .await;
+
+        if result.is_err() {
+            // the error that failed the put is more useful than any
+            // error removing the temporary file
+            let _ = fs::remove_file(&temp_path).await;
+        }
+        result
+    }
+
+    /// The path of a hidden file next to `path` to write its contents to
+    /// before renaming it. `list` skips hidden files, so objects that are
+    /// still being written are never listed.
+    fn temp_path(path: &Path) -> PathBuf {
+        static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let id = NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed);
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        path.with_file_name(format!(".{}.{}.tmp", file_name, id))
     }
 
     /// Return the bytes that are stored at the specified location.
@@ -506,7 +665,7 @@ impl File {
         &self,
         location: &str,
     ) -> InternalResult<impl Stream<Item = InternalResult<Bytes>>> {
-        let path = self.path(location);
+        let path = self.path(location)?;
 
         let file = fs::File::open(&path)
             .await
@@ -520,7 +679,7 @@ impl File {
 
     /// Delete the object at the specified location.
     async fn delete(&self, location: &str) -> InternalResult<()> {
-        let path = self.path(location);
+        let path = self.path(location)?;
         fs::remove_file(&path)
             .await
             .context(UnableToDeleteFile { path })?;
@@ -532,26 +691,37 @@ impl File {
         &'a self,
         prefix: Option<&'a str>,
     ) -> InternalResult<impl Stream<Item = InternalResult<Vec<String>>> + 'a> {
-        let dirs = fs::read_dir(&self.root)
-            .await
-            .context(UnableToListDirectory { path: &self.root })?;
+        // Each directory below the root holds the objects whose locations
+        // start with its path, so walk them all.
+        let mut names = vec![];
+        let mut dirs = vec![(self.root.clone(), String::new())];
+        while let Some((dir, dir_location)) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir)
+                .await
+                .context(UnableToListDirectory { path: &dir })?;
 
-        let s = dirs
-            .context(UnableToProcessEntry)
-            .and_then(|entry| {
+            while let Some(entry) = entries.next_entry().await.context(UnableToProcessEntry)? {
                 let name = entry
                     .file_name()
                     .into_string()
                     .ok()
-                    .context(UnableToGetFileName);
-                async move { name }
-            })
-            .try_filter(move |name| {
-                let matches = prefix.map_or(true, |p| name.starts_with(p));
-                async move { matches }
-            })
-            .map_ok(|name| vec![name]);
-        Ok(s)
+                    .context(UnableToGetFileName)?;
+                if name.starts_with('.') {
+                    continue;
+                }
+
+                let location = format!("{}{}", dir_location, name);
+                let file_type = entry.file_type().await.context(UnableToProcessEntry)?;
+                if file_type.is_dir() {
+                    dirs.push((entry.path(), format!("{}/", location)));
+                } else if prefix.map_or(true, |p| location.starts_with(p)) {
+                    names.push(location);
+                }
+            }
+        }
+        names.sort();
+
+        Ok(futures::stream::once(async move { Ok(names) }))
     }
 }
 
@@ -630,6 +800,11 @@ enum InternalError {
         source: rusoto_core::RusotoError<rusoto_s3::DeleteObjectError>,
     },
     NoDataFromS3,
+    #[snafu(display("Invalid Amazon region {:?}: {}", region, source))]
+    InvalidS3Region {
+        source: rusoto_core::region::ParseRegionError,
+        region: String,
+    },
     UnableToReadBytesFromS3 {
         source: std::io::Error,
     },
@@ -652,6 +827,16 @@ enum InternalError {
         source: io::Error,
         path: PathBuf,
     },
+    #[snafu(display("Unable to create directory {}: {}", path.display(), source))]
+    UnableToCreateDir {
+        source: io::Error,
+        path: PathBuf,
+    },
+    #[snafu(display("Unable to rename file to {}: {}", path.display(), source))]
+    UnableToRenameFile {
+        source: io::Error,
+        path: PathBuf,
+    },
     #[snafu(display("Unable to open file {}: {}", path.display(), source))]
     UnableToOpenFile {
         source: io::Error,
@@ -682,6 +867,13 @@ enum InternalError {
     },
     #[snafu(display("Unable to retrieve filename"))]
     UnableToGetFileName,
+    #[snafu(display(
+        "Invalid location {:?}: must be a relative path without '..'",
+        location
+    ))]
+    InvalidLocation {
+        location: String,
+    },
 }
 
 #[cfg(test)]
@@ -804,6 +996,36 @@ mod tests {
             Ok(())
         }
 
+        /// Runs against an S3-compatible server such as MinIO if
+        /// `AWS_S3_ENDPOINT` is set, using `AWS_ACCESS_KEY_ID` and
+        /// `AWS_SECRET_ACCESS_KEY` as its credentials.
+        #[tokio::test]
+        async fn s3_custom_endpoint_test() -> Result<()> {
+            dotenv::dotenv().ok();
+            let endpoint = match env::var("AWS_S3_ENDPOINT") {
+                Ok(endpoint) => endpoint,
+                Err(_) => {
+                    eprintln!("skipping: AWS_S3_ENDPOINT is not set");
+                    return Ok(());
+                }
+            };
+            let bucket_name = env::var("AWS_S3_BUCKET_NAME")
+                .map_err(|_| "The environment variable AWS_S3_BUCKET_NAME must be set")?;
+
+            let mut config = AmazonS3Config::new(bucket_name).endpoint(endpoint);
+            if let (Ok(access_key_id), Ok(secret_access_key)) = (
+                env::var("AWS_ACCESS_KEY_ID"),
+                env::var("AWS_SECRET_ACCESS_KEY"),
+            ) {
+                config = config.credentials(access_key_id, secret_access_key);
+            }
+
+            let integration = ObjectStore::new_amazon_s3(AmazonS3::from_config(&config)?);
+            check_credentials(put_get_delete_list(&integration).await)?;
+
+            Ok(())
+        }
+
         fn region_and_bucket_name() -> Result<(rusoto_core::Region, String)> {
             dotenv::dotenv().ok();
 
@@ -829,6 +1051,55 @@ mod tests {
         }
     }
 
+    mod amazon_s3_config {
+        use super::*;
+
+        #[test]
+        fn region() -> Result<()> {
+            let config = AmazonS3Config::new("bucket").region("us-east-2");
+            assert_eq!(config.rusoto_region()?, rusoto_core::Region::UsEast2);
+
+            let config = AmazonS3Config::new("bucket").region("mars-north-1");
+            assert_eq!(
+                config.rusoto_region().unwrap_err().to_string(),
+                r#"Invalid Amazon region "mars-north-1": Not a valid AWS region: mars-north-1"#
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn custom_endpoint() -> Result<()> {
+            let config = AmazonS3Config::new("bucket").endpoint("http://localhost:9000");
+            assert_eq!(
+                config.rusoto_region()?,
+                rusoto_core::Region::Custom {
+                    name: "us-east-1".to_string(),
+                    endpoint: "http://localhost:9000".to_string(),
+                }
+            );
+
+            let config = config.region("eu-west-1");
+            assert_eq!(
+                config.rusoto_region()?,
+                rusoto_core::Region::Custom {
+                    name: "eu-west-1".to_string(),
+                    endpoint: "http://localhost:9000".to_string(),
+                }
+            );
+
+            Ok(())
+        }
+
+        #[test]
+        fn debug_hides_secret() {
+            let config = AmazonS3Config::new("bucket").credentials("minio", "minio123");
+            let debug = format!("{:?}", config);
+            assert!(debug.contains("minio"), "was: {}", debug);
+            assert!(!debug.contains("minio123"), "was: {}", debug);
+        }
+    }
+
     mod in_memory {
         use super::*;
 
@@ -891,5 +1162,122 @@ mod tests {
 
             Ok(())
         }
+
+        #[tokio::test]
+        async fn nested_locations() -> Result<()> {
+            let root = TempDir::new()?;
+            let integration = ObjectStore::new_file(File::new(root.path()));
+
+            for location in &["db/data/table.parquet", "db/meta.json", "other"] {
+                let data = Bytes::from(location.to_string());
+                let len = data.len();
+                integration
+                    .put(location, stream::once(async { Ok(data) }), len)
+                    .await?;
+            }
+
+            // locations map to directories below the root
+            let stored = std::fs::read_to_string(root.path().join("db/data/table.parquet"))?;
+            assert_eq!(stored, "db/data/table.parquet");
+
+            let content_list = flatten_list_stream(&integration, None).await?;
+            assert_eq!(
+                content_list,
+                &["db/data/table.parquet", "db/meta.json", "other"]
+            );
+
+            let content_list = flatten_list_stream(&integration, Some("db/d")).await?;
+            assert_eq!(content_list, &["db/data/table.parquet"]);
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn put_overwrites() -> Result<()> {
+            let root = TempDir::new()?;
+            let integration = ObjectStore::new_file(File::new(root.path()));
+
+            for data in &["first", "second"] {
+                let data = Bytes::from(*data);
+                let len = data.len();
+                integration
+                    .put("snapshot", stream::once(async { Ok(data) }), len)
+                    .await?;
+            }
+
+            let read_data = integration
+                .get("snapshot")
+                .await?
+                .map_ok(|b| bytes::BytesMut::from(&b[..]))
+                .try_concat()
+                .await?;
+            assert_eq!(&*read_data, b"second");
+
+            // no temporary files are left behind
+            let files = std::fs::read_dir(root.path())?.count();
+            assert_eq!(files, 1);
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn locations_outside_root_are_rejected() -> Result<()> {
+            let dir = TempDir::new()?;
+            let root = dir.path().join("root");
+            let integration = ObjectStore::new_file(File::new(&root));
+
+            let outside = dir.path().join("outside");
+            for location in &[
+                "../outside",
+                "db/../../outside",
+                outside.to_str().expect("temp dir is valid UTF-8"),
+                "",
+            ] {
+                let data = Bytes::from("data");
+                let len = data.len();
+                let res = integration
+                    .put(location, stream::once(async { Ok(data) }), len)
+                    .await;
+                assert_error!(res, InternalError::InvalidLocation { .. });
+
+                assert_error!(
+                    integration.get(location).await.map(|_| ()),
+                    InternalError::InvalidLocation { .. },
+                );
+                assert_error!(
+                    integration.delete(location).await,
+                    InternalError::InvalidLocation { .. },
+                );
+            }
+            assert!(!outside.exists());
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn failed_put_removes_temporary_file() -> Result<()> {
+            let root = TempDir::new()?;
+            let integration = ObjectStore::new_file(File::new(root.path()));
+
+            let data = Bytes::from("data");
+            let len = data.len();
+            integration
+                .put("db/meta.json", stream::once(async { Ok(data) }), len)
+                .await?;
+
+            // a directory is in the way of the rename
+            let data = Bytes::from("data");
+            let res = integration
+                .put("db", stream::once(async { Ok(data) }), len)
+                .await;
+            assert_error!(res, InternalError::UnableToRenameFile { .. });
+
+            let names: Vec<_> = std::fs::read_dir(root.path())?
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<std::result::Result<_, _>>()?;
+            assert_eq!(names, vec!["db"]);
+
+            Ok(())
+        }
     }
 }
@@ -3,9 +3,15 @@ use tracing::{debug, info};
 use std::fs;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use std::{env::VarError, path::PathBuf};
 
-use crate::server::http_routes;
+use crate::server::cors::CorsConfig;
+use crate::server::http_routes::{self, AppServer};
+use crate::server::opentsdb::WriteTarget;
+use crate::server::query_cache::QueryCache;
+use crate::server::rate_limit::RateLimiter;
+use crate::server::replication::{ReplicationConfig, ReplicationSink};
 use crate::server::rpc::storage;
 
 use ::storage::exec::Executor as StorageExecutor;
@@ -56,6 +62,10 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How long query responses are cached for unless
+/// `INFLUXDB_IOX_QUERY_CACHE_TTL_SECONDS` is set
+const DEFAULT_QUERY_CACHE_TTL_SECONDS: u64 = 10;
+
 pub async fn main() -> Result<()> {
     dotenv::dotenv().ok();
 
@@ -119,12 +129,106 @@ pub async fn main() -> Result<()> {
         }
     };
 
+    let mut app_server = AppServer::new(storage);
+
+    // Optionally replicate all writes to a secondary server
+    if let Ok(replication_url) = std::env::var("INFLUXDB_IOX_REPLICATION_URL") {
+        let auth_token = std::env::var("INFLUXDB_IOX_REPLICATION_TOKEN").unwrap_or_default();
+        info!("Replicating writes to {}", replication_url);
+        app_server.replication = Some(ReplicationSink::new(ReplicationConfig::new(
+            replication_url,
+            auth_token,
+        )));
+    }
+
+    // Optionally write OpenTSDB put requests without org and bucket
+    // to a default bucket
+    if let (Ok(org), Ok(bucket)) = (
+        std::env::var("INFLUXDB_IOX_OPENTSDB_ORG"),
+        std::env::var("INFLUXDB_IOX_OPENTSDB_BUCKET"),
+    ) {
+        info!(
+            "Writing OpenTSDB data points to org {} bucket {}",
+            org, bucket
+        );
+        app_server.opentsdb_target = Some(WriteTarget { org, bucket });
+    }
+
+    // Optionally cache the responses of read requests
+    if let Ok(capacity) = std::env::var("INFLUXDB_IOX_QUERY_CACHE_SIZE") {
+        let capacity = capacity
+            .parse()
+            .expect("INFLUXDB_IOX_QUERY_CACHE_SIZE environment variable not a valid number");
+        let ttl = match std::env::var("INFLUXDB_IOX_QUERY_CACHE_TTL_SECONDS") {
+            Ok(ttl) => ttl.parse().expect(
+                "INFLUXDB_IOX_QUERY_CACHE_TTL_SECONDS environment variable not a valid number",
+            ),
+            Err(_) => DEFAULT_QUERY_CACHE_TTL_SECONDS,
+        };
+        info!(
+            "Caching up to {} query responses for {} seconds",
+            capacity, ttl
+        );
+        app_server.query_cache = Some(QueryCache::new(capacity, Duration::from_secs(ttl)));
+    }
+
+    if let Ok(max_request_size) = std::env::var("INFLUXDB_IOX_MAX_REQUEST_SIZE") {
+        app_server.max_request_size = max_request_size
+            .parse()
+            .expect("INFLUXDB_IOX_MAX_REQUEST_SIZE environment variable not a valid number");
+    }
+
+    if let Ok(max_lines_per_write) = std::env::var("INFLUXDB_IOX_MAX_LINES_PER_WRITE") {
+        app_server.max_lines_per_write =
+            Some(max_lines_per_write.parse().expect(
+                "INFLUXDB_IOX_MAX_LINES_PER_WRITE environment variable not a valid number",
+            ));
+    }
+
+    if let Ok(query_timeout) = std::env::var("INFLUXDB_IOX_QUERY_TIMEOUT_SECONDS") {
+        let query_timeout = query_timeout
+            .parse()
+            .expect("INFLUXDB_IOX_QUERY_TIMEOUT_SECONDS environment variable not a valid number");
+        app_server.query_timeout = Duration::from_secs(query_timeout);
+    }
+
+    // Optionally limit the rate at which each org may write lines
+    if let Ok(lines_per_second) = std::env::var("INFLUXDB_IOX_WRITE_RATE_LIMIT_LINES_PER_SECOND") {
+        let lines_per_second = lines_per_second.parse().expect(
+            "INFLUXDB_IOX_WRITE_RATE_LIMIT_LINES_PER_SECOND environment variable not a valid number",
+        );
+        let burst = match std::env::var("INFLUXDB_IOX_WRITE_RATE_LIMIT_BURST") {
+            Ok(burst) => burst.parse().expect(
+                "INFLUXDB_IOX_WRITE_RATE_LIMIT_BURST environment variable not a valid number",
+            ),
+            Err(_) => lines_per_second,
+        };
+        info!(
+            "Limiting writes to {} lines per second per org, with bursts of {} lines",
+            lines_per_second, burst
+        );
+        app_server.rate_limiter = Some(RateLimiter::new(lines_per_second, burst));
+    }
+
+    // comma separated origins, or * for any
+    if let Ok(allowed_origins) = std::env::var("INFLUXDB_IOX_CORS_ALLOWED_ORIGINS") {
+        let allowed_origins = allowed_origins
+            .split(',')
+            .map(|origin| origin.trim().to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect::<Vec<_>>();
+        info!("Allowing CORS requests from {:?}", allowed_origins);
+        app_server.cors = Some(CorsConfig::new(allowed_origins));
+    }
+
+    let app_server = Arc::new(app_server);
+
     let make_svc = make_service_fn(move |_conn| {
-        let storage = storage.clone();
+        let app_server = app_server.clone();
         async move {
             Ok::<_, http::Error>(service_fn(move |req| {
-                let state = storage.clone();
-                http_routes::service(req, state)
+                let app_server = app_server.clone();
+                http_routes::service(req, app_server)
             }))
         }
     });
@@ -1,2 +1,20 @@
+pub mod cors;
+pub mod csv_import;
+pub mod export;
 pub mod http_routes;
+pub mod jsonl_import;
+pub mod metrics;
+pub mod opentsdb;
+pub mod parquet_import;
+pub mod prometheus;
+pub mod query_cache;
+pub mod rate_limit;
+pub mod read_format;
+pub mod replication;
 pub mod rpc;
+pub mod schema_rules;
+#[cfg(any(test, feature = "test_utils"))]
+// outside of this crate's tests nothing uses the harness
+#[cfg_attr(not(test), allow(dead_code))]
+pub mod test_utils;
+pub mod trace_context;
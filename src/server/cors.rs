@@ -0,0 +1,138 @@
+//! This module contains the Cross-Origin Resource Sharing (CORS)
+//! configuration of the HTTP API, which lets browser applications
+//! served from other origins call it.
+//!
+//! If configured, `OPTIONS` preflight requests from allowed origins
+//! are answered with the allowed methods and headers, and responses to
+//! requests from allowed origins carry `Access-Control-Allow-Origin`.
+//! Without it the server sends no CORS headers, so browsers only allow
+//! same origin requests.
+use http::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, VARY,
+};
+use hyper::{Body, Response, StatusCode};
+
+/// The methods allowed by default
+pub const DEFAULT_ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+
+/// The request headers allowed by default
+pub const DEFAULT_ALLOWED_HEADERS: &str =
+    "Accept, Accept-Encoding, Authorization, Content-Encoding, Content-Type";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsConfig {
+    /// The origins allowed to make requests, such as
+    /// `https://dashboard.example.com`. `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// The value of `Access-Control-Allow-Methods`
+    pub allowed_methods: HeaderValue,
+
+    /// The value of `Access-Control-Allow-Headers`
+    pub allowed_headers: HeaderValue,
+}
+
+impl CorsConfig {
+    /// Allows requests from `allowed_origins` with the default methods
+    /// and headers
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self {
+            allowed_origins,
+            allowed_methods: HeaderValue::from_static(DEFAULT_ALLOWED_METHODS),
+            allowed_headers: HeaderValue::from_static(DEFAULT_ALLOWED_HEADERS),
+        }
+    }
+
+    /// Returns the value of `Access-Control-Allow-Origin` for a request
+    /// from `origin`, or `None` if the origin is not allowed
+    pub fn allow_origin<'a>(&'a self, origin: &'a str) -> Option<&'a str> {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            Some("*")
+        } else if self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    /// Adds `Access-Control-Allow-Origin` to `response` if `origin`,
+    /// the `Origin` of the request, is allowed
+    pub fn add_allow_origin(&self, origin: Option<&str>, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        // the header depends on the origin of the request
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+
+        let allow_origin = origin
+            .and_then(|origin| self.allow_origin(origin))
+            .and_then(|allow_origin| HeaderValue::from_str(allow_origin).ok());
+        if let Some(allow_origin) = allow_origin {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+    }
+
+    /// Makes the response to a preflight request from `origin`, which
+    /// only allows the request if the origin is allowed
+    pub fn preflight_response(&self, origin: Option<&str>) -> Response<Body> {
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("Should have been able to construct a response");
+
+        if origin
+            .and_then(|origin| self.allow_origin(origin))
+            .is_some()
+        {
+            let headers = response.headers_mut();
+            headers.insert(ACCESS_CONTROL_ALLOW_METHODS, self.allowed_methods.clone());
+            headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, self.allowed_headers.clone());
+        }
+        self.add_allow_origin(origin, &mut response);
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_origin() {
+        let cors = CorsConfig::new(vec!["https://a.example.com".to_string()]);
+        assert_eq!(
+            cors.allow_origin("https://a.example.com"),
+            Some("https://a.example.com")
+        );
+        assert_eq!(cors.allow_origin("https://b.example.com"), None);
+
+        let cors = CorsConfig::new(vec!["*".to_string()]);
+        assert_eq!(cors.allow_origin("https://b.example.com"), Some("*"));
+    }
+
+    #[test]
+    fn test_preflight_response() {
+        let cors = CorsConfig::new(vec!["https://a.example.com".to_string()]);
+
+        let response = cors.preflight_response(Some("https://a.example.com"));
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://a.example.com"
+        );
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_METHODS],
+            DEFAULT_ALLOWED_METHODS
+        );
+        assert_eq!(
+            headers[ACCESS_CONTROL_ALLOW_HEADERS],
+            DEFAULT_ALLOWED_HEADERS
+        );
+
+        let response = cors.preflight_response(Some("https://b.example.com"));
+        let headers = response.headers();
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).is_none());
+    }
+}
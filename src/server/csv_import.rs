@@ -0,0 +1,338 @@
+//! This module contains code to convert CSV data into InfluxDB line
+//! protocol for bulk imports.
+//!
+//! Each CSV row becomes one point in the configured measurement:
+//!
+//! * The configured tag columns become tags
+//! * The configured time column becomes the timestamp
+//! * All other columns become fields. Values that parse as numbers
+//!   become float fields (so a column of mixed integer and decimal
+//!   values has a single type), `true` / `false` become boolean
+//!   fields and everything else becomes a string field
+//!
+//! Empty values are treated as missing. Problems with individual rows
+//! (e.g. bad timestamps) are collected per row so the remaining rows
+//! can still be imported.
+use chrono::DateTime;
+use influxdb2_client::{data_point::DataPointError, DataPoint, WriteDataPoint};
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading CSV header: {}", source))]
+    ReadingHeader { source: csv::Error },
+
+    #[snafu(display("Column '{}' not found in CSV header", column_name))]
+    ColumnNotFound { column_name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors converting a single CSV row
+#[derive(Debug, Snafu)]
+pub enum RowError {
+    #[snafu(display("Error reading row: {}", source))]
+    ReadingRow { source: csv::Error },
+
+    #[snafu(display(
+        "Row has {} values, but the header has {} columns",
+        num_values,
+        num_columns
+    ))]
+    TooManyValues {
+        num_values: usize,
+        num_columns: usize,
+    },
+
+    #[snafu(display("Missing value for time column '{}'", column_name))]
+    MissingTime { column_name: String },
+
+    #[snafu(display("Invalid RFC3339 timestamp '{}': {}", value, source))]
+    InvalidRfc3339 {
+        value: String,
+        source: chrono::ParseError,
+    },
+
+    #[snafu(display(
+        "RFC3339 timestamp '{}' is out of the range of nanosecond timestamps",
+        value
+    ))]
+    Rfc3339OutOfRange { value: String },
+
+    #[snafu(display("Invalid nanosecond timestamp '{}': {}", value, source))]
+    InvalidUnixNs {
+        value: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Row has no field values"))]
+    NoFields {},
+
+    #[snafu(display("Error converting row: {}", source))]
+    ConvertingRow { source: DataPointError },
+
+    #[snafu(display("Error writing line protocol: {}", source))]
+    WritingLineProtocol { source: std::io::Error },
+}
+
+/// How the values of the time column are formatted
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// e.g. `2020-11-01T00:00:00Z`
+    Rfc3339,
+    /// Nanoseconds since the epoch
+    UnixNs,
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        Self::Rfc3339
+    }
+}
+
+/// Describes how CSV columns map onto the InfluxDB data model
+#[derive(Debug, Clone)]
+pub struct CsvMapping {
+    pub measurement: String,
+    pub tag_columns: Vec<String>,
+    pub time_column: String,
+    pub time_format: TimeFormat,
+}
+
+/// The result of converting CSV data into line protocol
+#[derive(Debug, Default)]
+pub struct ConvertedCsv {
+    /// One line of line protocol for each successfully converted row
+    pub lines: Vec<String>,
+
+    /// The (1-based) line number in the CSV data and error of each row
+    /// that could not be converted
+    pub errors: Vec<(u64, RowError)>,
+}
+
+/// Converts `data`, CSV with a header row, into line protocol
+/// according to `mapping`.
+///
+/// Fails if the header can't be read or lacks any of the mapped
+/// columns; errors in individual rows are returned in
+/// `ConvertedCsv::errors`.
+pub fn csv_to_lp(mapping: &CsvMapping, data: &[u8]) -> Result<ConvertedCsv> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+
+    let headers = reader.headers().context(ReadingHeader)?.clone();
+
+    let column_index = |column_name: &str| {
+        headers
+            .iter()
+            .position(|header| header == column_name)
+            .context(ColumnNotFound { column_name })
+    };
+
+    let time_index = column_index(&mapping.time_column)?;
+    let tag_indices = mapping
+        .tag_columns
+        .iter()
+        .map(|column_name| column_index(column_name))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut converted = ConvertedCsv::default();
+
+    for record in reader.records() {
+        let (line, result) = match record {
+            Ok(record) => (
+                record.position().map(|position| position.line()),
+                row_to_lp(mapping, &headers, time_index, &tag_indices, &record),
+            ),
+            Err(source) => (
+                source.position().map(|position| position.line()),
+                Err(RowError::ReadingRow { source }),
+            ),
+        };
+
+        match result {
+            Ok(line) => converted.lines.push(line),
+            Err(e) => converted.errors.push((line.unwrap_or_default(), e)),
+        }
+    }
+
+    Ok(converted)
+}
+
+fn row_to_lp(
+    mapping: &CsvMapping,
+    headers: &csv::StringRecord,
+    time_index: usize,
+    tag_indices: &[usize],
+    record: &csv::StringRecord,
+) -> Result<String, RowError> {
+    if record.len() > headers.len() {
+        return TooManyValues {
+            num_values: record.len(),
+            num_columns: headers.len(),
+        }
+        .fail();
+    }
+
+    let time_value = record
+        .get(time_index)
+        .filter(|value| !value.is_empty())
+        .context(MissingTime {
+            column_name: &mapping.time_column,
+        })?;
+
+    let timestamp = match mapping.time_format {
+        TimeFormat::Rfc3339 => {
+            let time = DateTime::parse_from_rfc3339(time_value)
+                .context(InvalidRfc3339 { value: time_value })?;
+            time.timestamp()
+                .checked_mul(1_000_000_000)
+                .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos().into()))
+                .context(Rfc3339OutOfRange { value: time_value })?
+        }
+        TimeFormat::UnixNs => time_value
+            .parse()
+            .context(InvalidUnixNs { value: time_value })?,
+    };
+
+    let mut builder = DataPoint::builder(&mapping.measurement).timestamp(timestamp);
+    let mut has_fields = false;
+
+    for (index, (column_name, value)) in headers.iter().zip(record.iter()).enumerate() {
+        if index == time_index || value.is_empty() {
+            continue;
+        }
+
+        if tag_indices.contains(&index) {
+            builder = builder.tag(column_name, value);
+            continue;
+        }
+
+        // line protocol can't represent non finite floats, so
+        // e.g. "NaN" stays a string
+        builder = if let Some(value) = value.parse::<f64>().ok().filter(|v| v.is_finite()) {
+            builder.field(column_name, value)
+        } else if let Ok(value) = value.parse::<bool>() {
+            builder.field(column_name, value)
+        } else {
+            builder.field(column_name, value)
+        };
+        has_fields = true;
+    }
+    ensure!(has_fields, NoFields);
+
+    let point = builder.build().context(ConvertingRow)?;
+
+    let mut lp_data = Vec::new();
+    point
+        .write_data_point_to(&mut lp_data)
+        .context(WritingLineProtocol)?;
+    lp_data.pop(); // trailing newline
+
+    Ok(String::from_utf8(lp_data).expect("line protocol is valid utf8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(time_format: TimeFormat) -> CsvMapping {
+        CsvMapping {
+            measurement: "weather".into(),
+            tag_columns: vec!["location".into()],
+            time_column: "ts".into(),
+            time_format,
+        }
+    }
+
+    #[test]
+    fn test_csv_to_lp() {
+        let data = "ts,location,temp,raining,note\n\
+                    2020-11-01T00:00:00Z,\"Boston, MA\",70.5,false,sunny\n\
+                    2020-11-01T00:00:01Z,\"Boston, MA\",71,,\n";
+
+        let converted = csv_to_lp(&mapping(TimeFormat::Rfc3339), data.as_bytes()).unwrap();
+
+        assert!(converted.errors.is_empty(), "{:?}", converted.errors);
+        assert_eq!(
+            converted.lines,
+            vec![
+                "weather,location=Boston\\,\\ MA note=\"sunny\",raining=false,temp=70.5 \
+                 1604188800000000000",
+                "weather,location=Boston\\,\\ MA temp=71 1604188801000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_to_lp_row_errors() {
+        let data = "ts,location,temp\n\
+                    100,a,1\n\
+                    not_a_time,a,2\n\
+                    ,a,3\n\
+                    200,a\n\
+                    300,a,4,5\n\
+                    400,a,5\n";
+
+        let converted = csv_to_lp(&mapping(TimeFormat::UnixNs), data.as_bytes()).unwrap();
+
+        assert_eq!(
+            converted.lines,
+            vec![
+                "weather,location=a temp=1 100",
+                "weather,location=a temp=5 400"
+            ]
+        );
+
+        let errors = converted
+            .errors
+            .iter()
+            .map(|(line, e)| format!("{}: {}", line, e))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "3: Invalid nanosecond timestamp 'not_a_time': invalid digit found in string",
+                "4: Missing value for time column 'ts'",
+                "5: Row has no field values",
+                "6: Row has 4 values, but the header has 3 columns",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_to_lp_rfc3339_out_of_range() {
+        let data = "ts,location,temp\n\
+                    2020-11-01T00:00:00Z,a,1\n\
+                    9999-01-01T00:00:00Z,a,2\n";
+
+        let converted = csv_to_lp(&mapping(TimeFormat::Rfc3339), data.as_bytes()).unwrap();
+
+        assert_eq!(
+            converted.lines,
+            vec!["weather,location=a temp=1 1604188800000000000"]
+        );
+        let errors = converted
+            .errors
+            .iter()
+            .map(|(line, e)| format!("{}: {}", line, e))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "3: RFC3339 timestamp '9999-01-01T00:00:00Z' is out of the range of nanosecond \
+                 timestamps"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_to_lp_missing_column() {
+        let data = "time,location,temp\n100,a,1\n";
+
+        let err = csv_to_lp(&mapping(TimeFormat::UnixNs), data.as_bytes()).unwrap_err();
+
+        assert!(matches!(err, Error::ColumnNotFound { column_name } if column_name == "ts"));
+    }
+}
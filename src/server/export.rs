@@ -0,0 +1,106 @@
+//! This module contains code to export the data of a database as
+//! InfluxDB line protocol.
+//!
+//! Each row of each `SeriesSet` becomes one line, so the output is
+//! ordered by measurement, then series key (tag values) and then time,
+//! which keeps repeated exports of the same data diffable.
+use std::io::Write;
+
+use bytes::Bytes;
+use hyper::body::Sender;
+use snafu::{ResultExt, Snafu};
+use storage::exec::{
+    seriesset::Error as SeriesSetError,
+    seriesset_lp::{self, series_set_to_line_protocol},
+    Executor, SeriesSetPlans,
+};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error running series set plans: {}", source))]
+    RunningPlans { source: storage::exec::Error },
+
+    #[snafu(display("Error computing series set: {}", source))]
+    ComputingSeriesSet { source: SeriesSetError },
+
+    #[snafu(display("Error converting series set of table '{}': {}", table_name, source))]
+    ConvertingSeriesSet {
+        table_name: String,
+        source: seriesset_lp::Error,
+    },
+
+    #[snafu(display("Error gzip compressing export chunk: {}", source))]
+    CompressingChunk { source: std::io::Error },
+
+    #[snafu(display("Error sending export chunk: {}", source))]
+    SendingChunk { source: hyper::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Line protocol is buffered up to (roughly) this many bytes before
+/// being sent to the client as one chunk
+pub const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Runs `series_plans` and sends the results to `sender` as line
+/// protocol, chunk by chunk.
+///
+/// If `gzip` is set, each chunk is compressed as a separate gzip
+/// member; the concatenation of such members is itself a valid gzip
+/// stream. On error the body is aborted so the client can not mistake
+/// a truncated export for a complete one.
+pub async fn export_series(
+    executor: &Executor,
+    series_plans: SeriesSetPlans,
+    mut sender: Sender,
+    gzip: bool,
+) -> Result<()> {
+    // run the plans while sending the results, as the channel can't
+    // hold all of them
+    let (tx, mut rx) = mpsc::channel(4);
+    let run_plans = executor.to_series_set(series_plans, tx);
+    let send_chunks = async {
+        let mut lp_data = Vec::new();
+        while let Some(series_set) = rx.recv().await {
+            let series_set = series_set.context(ComputingSeriesSet)?;
+            let lines = series_set_to_line_protocol(&series_set).context(ConvertingSeriesSet {
+                table_name: series_set.table_name.as_str(),
+            })?;
+            lp_data.extend_from_slice(lines.as_bytes());
+
+            if lp_data.len() >= EXPORT_CHUNK_SIZE {
+                send_chunk(&mut sender, &mut lp_data, gzip).await?;
+            }
+        }
+
+        if !lp_data.is_empty() {
+            send_chunk(&mut sender, &mut lp_data, gzip).await?;
+        }
+        Ok::<_, Error>(())
+    };
+    let (run_result, send_result) = futures::join!(run_plans, send_chunks);
+
+    let result = send_result.and_then(|_| run_result.context(RunningPlans));
+    if result.is_err() {
+        sender.abort();
+    }
+    result
+}
+
+/// Sends (and clears) the line protocol buffered in `lp_data`
+async fn send_chunk(sender: &mut Sender, lp_data: &mut Vec<u8>, gzip: bool) -> Result<()> {
+    let chunk = if gzip {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).context(CompressingChunk)?;
+        encoder.write_all(lp_data).context(CompressingChunk)?;
+        lp_data.clear();
+        encoder.finish().into_result().context(CompressingChunk)?
+    } else {
+        std::mem::take(lp_data)
+    };
+
+    sender
+        .send_data(Bytes::from(chunk))
+        .await
+        .context(SendingChunk)
+}
@@ -12,45 +12,66 @@
 //! Long term, we expect to create IOx specific api in terms of
 //! database names and may remove this quasi /v2 API from the Deloren.
 
-use http::header::CONTENT_ENCODING;
-use tracing::{debug, error, info};
+use http::header::{
+    HeaderValue, ACCEPT, ACCEPT_ENCODING, AGE, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE,
+    ORIGIN, RETRY_AFTER,
+};
+use tracing::{debug, error, info, info_span};
+use tracing_futures::Instrument;
 
-use arrow_deps::arrow;
-use influxdb_line_protocol::parse_lines;
-use storage::{org_and_bucket_to_database, Database, DatabaseStore};
+use super::{
+    cors::CorsConfig,
+    csv_import::{self, CsvMapping, TimeFormat},
+    export,
+    jsonl_import::{self, JsonlMapping},
+    metrics::{self, Metrics},
+    opentsdb::{self, WriteTarget},
+    parquet_import::{self, ParquetMapping},
+    prometheus,
+    query_cache::{QueryCache, QueryCacheKey},
+    rate_limit::RateLimiter,
+    read_format::{self, ReadFormat},
+    replication::ReplicationSink,
+    schema_rules::{self, SchemaRules, ViolationPolicy},
+    trace_context::TraceContext,
+};
+use data_types::error::ErrorLogger;
+use generated_types::prometheus::{QueryResult, ReadResponse};
+use influxdb_line_protocol::{parse_lines, parse_numbered_lines, split_complete_lines, ParsedLine};
+use storage::{
+    delete_predicate::DeletePredicate, exec::Executor, org_and_bucket_to_database,
+    predicate::PredicateBuilder, predicate::TimestampRange, Database, DatabaseError, DatabaseStore,
+};
 
 use bytes::{Bytes, BytesMut};
+use chrono::DateTime;
 use futures::{self, StreamExt};
 use hyper::{Body, Method, StatusCode};
 use serde::Deserialize;
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::str;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::sync::mpsc;
 
 #[derive(Debug, Snafu)]
 pub enum ApplicationError {
     // Internal (unexpected) errors
-    #[snafu(display(
-        "Internal error accessing org {}, bucket {}:  {}",
-        org,
-        bucket_name,
-        source
-    ))]
+    #[snafu(display("Internal error accessing database {}:  {}", database, source))]
     BucketByName {
-        org: String,
-        bucket_name: String,
+        database: String,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
     #[snafu(display(
-        "Internal error writing points into org {}, bucket {}:  {}",
-        org,
-        bucket_name,
+        "Internal error writing points into database {}:  {}",
+        database,
         source
     ))]
     WritingPoints {
-        org: String,
-        bucket_name: String,
+        database: String,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
@@ -71,6 +92,9 @@ pub enum ApplicationError {
     #[snafu(display("Body exceeds limit of {} bytes", max_body_size))]
     RequestSizeExceeded { max_body_size: usize },
 
+    #[snafu(display("Decompressed body exceeds limit of {} bytes", max_decompressed_size))]
+    DecompressedSizeExceeded { max_decompressed_size: usize },
+
     #[snafu(display("Expected query string in request, but none was provided"))]
     ExpectedQueryString {},
 
@@ -85,13 +109,20 @@ pub enum ApplicationError {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
+    #[snafu(display("Error formatting query results: {}", source))]
+    FormattingResults { source: read_format::Error },
+
     #[snafu(display("Invalid request body '{}': {}", request_body, source))]
     InvalidRequestBody {
         request_body: String,
         source: serde_json::error::Error,
     },
 
-    #[snafu(display("Invalid content encoding: {}", content_encoding))]
+    #[snafu(display(
+        "Invalid content encoding: {}, expected one of {}",
+        content_encoding,
+        SUPPORTED_CONTENT_ENCODINGS
+    ))]
     InvalidContentEncoding { content_encoding: String },
 
     #[snafu(display("Error reading request header '{}' as Utf8: {}", header_name, source))]
@@ -106,19 +137,163 @@ pub enum ApplicationError {
     #[snafu(display("Error reading request body as utf8: {}", source))]
     ReadingBodyAsUtf8 { source: std::str::Utf8Error },
 
+    #[snafu(display("Invalid precision '{}', expected one of s, ms, us or ns", precision))]
+    InvalidPrecision { precision: String },
+
+    #[snafu(display(
+        "Timestamp {} is out of range for precision '{}'",
+        timestamp,
+        precision
+    ))]
+    TimestampOutOfRange { timestamp: i64, precision: String },
+
     #[snafu(display("Error parsing line protocol: {}", source))]
     ParsingLineProtocol {
         source: influxdb_line_protocol::Error,
     },
 
+    #[snafu(display("Error parsing line protocol at line {}: {}", line_number, source))]
+    ParsingLineProtocolAtLine {
+        line_number: usize,
+        /// The (possibly truncated) text of the line
+        line: String,
+        source: influxdb_line_protocol::Error,
+    },
+
     #[snafu(display("Error decompressing body as gzip: {}", source))]
     ReadingBodyAsGzip { source: std::io::Error },
 
+    #[snafu(display("Error decompressing body as deflate: {}", source))]
+    ReadingBodyAsDeflate { source: std::io::Error },
+
     #[snafu(display("No handler for {:?} {}", method, path))]
     RouteNotFound { method: Method, path: String },
 
     #[snafu(display("Internal error creating gzip decoder: {:?}", source))]
     CreatingGzipDecoder { source: std::io::Error },
+
+    #[snafu(display("Error decoding Prometheus remote write request: {}", source))]
+    DecodingPrometheusWrite {
+        source: crate::server::prometheus::Error,
+    },
+
+    #[snafu(display("Error importing CSV: {}", source))]
+    ImportingCsv {
+        source: crate::server::csv_import::Error,
+    },
+
+    #[snafu(display("Error importing Parquet: {}", source))]
+    ImportingParquet {
+        source: crate::server::parquet_import::Error,
+    },
+
+    #[snafu(display(
+        "Parquet data conflicts with the existing data of table {} in columns: {}",
+        table,
+        columns
+    ))]
+    ParquetSchemaConflict { table: String, columns: String },
+
+    #[snafu(display("Error decoding OpenTSDB put request: {}", source))]
+    DecodingOpenTsdbPut {
+        source: crate::server::opentsdb::Error,
+    },
+
+    #[snafu(display(
+        "No org and bucket given for OpenTSDB put request and no default is configured"
+    ))]
+    MissingOpenTsdbTarget {},
+
+    #[snafu(display("Error parsing delete predicate: {}", source))]
+    ParsingDeletePredicate {
+        source: storage::delete_predicate::Error,
+    },
+
+    #[snafu(display("Invalid {} time '{}': {}", name, value, reason))]
+    InvalidDeleteTime {
+        name: String,
+        value: String,
+        reason: String,
+    },
+
+    #[snafu(display("Delete start time {} is after stop time {}", start, stop))]
+    InvalidDeleteRange { start: String, stop: String },
+
+    #[snafu(display("Invalid delete from database {}: {}", database, source))]
+    InvalidDelete {
+        database: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Error deleting points from database {}:  {}", database, source))]
+    DeletingPoints {
+        database: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("No schema rules for bucket {} in org {}", bucket, org))]
+    SchemaRulesNotFound { org: String, bucket: String },
+
+    #[snafu(display("Error decoding Prometheus remote read request: {}", source))]
+    DecodingPrometheusRead {
+        source: crate::server::prometheus::Error,
+    },
+
+    #[snafu(display("Internal error encoding Prometheus remote read response: {}", source))]
+    EncodingPrometheusRead {
+        source: crate::server::prometheus::Error,
+    },
+
+    #[snafu(display(
+        "Write rate limit of {} exceeded, retry after {:?}",
+        limited,
+        retry_after
+    ))]
+    RateLimited {
+        limited: String,
+        retry_after: Duration,
+    },
+
+    #[snafu(display("Write has {} lines, exceeding the limit of {} lines", actual, limit))]
+    TooManyLines {
+        limit: usize,
+        /// The number of lines read when the limit was exceeded, which
+        /// for streamed writes may be fewer than the body has
+        actual: usize,
+    },
+
+    #[snafu(display("{} ({} lines were written before the error)", source, accepted))]
+    PartiallyWritten {
+        accepted: usize,
+        source: Box<ApplicationError>,
+    },
+
+    #[snafu(display("Query did not finish within {:?}", timeout))]
+    QueryTimeout { timeout: Duration },
+
+    #[snafu(display("Database {} not found", database))]
+    DatabaseNotFound { database: String },
+
+    #[snafu(display("Invalid {} name {:?}: {}", kind, name, reason))]
+    InvalidDatabaseName {
+        kind: &'static str,
+        name: String,
+        reason: &'static str,
+    },
+
+    #[snafu(display("Expected either the org and bucket parameters or the db parameter"))]
+    InvalidDatabaseParameters,
+
+    #[snafu(display(
+        "Writes to databases named by the db parameter can't be replicated, use org and bucket"
+    ))]
+    ReplicatingDbParameter,
+
+    #[snafu(display("Table {} not found in partition {}", table, partition))]
+    TableNotFound { table: String, partition: String },
+
+    #[snafu(display("Internal error gzip compressing response: {}", source))]
+    CompressingResponse { source: std::io::Error },
 }
 
 impl ApplicationError {
@@ -128,333 +303,5744 @@ impl ApplicationError {
             Self::WritingPoints { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::QueryError { .. } => StatusCode::BAD_REQUEST,
+            Self::FormattingResults { source } => match source {
+                read_format::Error::UnknownFormat { .. } => StatusCode::BAD_REQUEST,
+                read_format::Error::PrettyPrinting { .. }
+                | read_format::Error::WritingCsv { .. }
+                | read_format::Error::WritingArrowStream { .. }
+                | read_format::Error::UnsupportedDataType { .. } => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            },
             Self::BucketNotFound { .. } => StatusCode::NOT_FOUND,
-            Self::RequestSizeExceeded { .. } => StatusCode::BAD_REQUEST,
+            Self::RequestSizeExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::DecompressedSizeExceeded { .. } => StatusCode::PAYLOAD_TOO_LARGE,
             Self::ExpectedQueryString { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidQueryString { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidRequestBody { .. } => StatusCode::BAD_REQUEST,
-            Self::InvalidContentEncoding { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidContentEncoding { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             Self::ReadingHeaderAsUtf8 { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBody { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBodyAsUtf8 { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidPrecision { .. } => StatusCode::BAD_REQUEST,
+            Self::TimestampOutOfRange { .. } => StatusCode::BAD_REQUEST,
             Self::ParsingLineProtocol { .. } => StatusCode::BAD_REQUEST,
+            Self::ParsingLineProtocolAtLine { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBodyAsGzip { .. } => StatusCode::BAD_REQUEST,
+            Self::ReadingBodyAsDeflate { .. } => StatusCode::BAD_REQUEST,
             Self::RouteNotFound { .. } => StatusCode::NOT_FOUND,
             Self::CreatingGzipDecoder { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::DecodingPrometheusWrite { .. } => StatusCode::BAD_REQUEST,
+            Self::ImportingCsv { .. } => StatusCode::BAD_REQUEST,
+            Self::ImportingParquet { .. } => StatusCode::BAD_REQUEST,
+            Self::ParquetSchemaConflict { .. } => StatusCode::BAD_REQUEST,
+            Self::DecodingOpenTsdbPut { .. } => StatusCode::BAD_REQUEST,
+            Self::MissingOpenTsdbTarget { .. } => StatusCode::BAD_REQUEST,
+            Self::ParsingDeletePredicate { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidDeleteTime { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidDeleteRange { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidDelete { .. } => StatusCode::BAD_REQUEST,
+            Self::DeletingPoints { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::SchemaRulesNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::DecodingPrometheusRead { .. } => StatusCode::BAD_REQUEST,
+            Self::EncodingPrometheusRead { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Self::TooManyLines { .. } => StatusCode::BAD_REQUEST,
+            Self::PartiallyWritten { source, .. } => source.status_code(),
+            Self::QueryTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::DatabaseNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::InvalidDatabaseName { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidDatabaseParameters => StatusCode::BAD_REQUEST,
+            Self::ReplicatingDbParameter => StatusCode::BAD_REQUEST,
+            Self::TableNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::CompressingResponse { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The code of the error in the vocabulary of the InfluxDB v2 API,
+    /// which its client libraries use to tell errors apart
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::BucketByName { .. } => "internal error",
+            Self::WritingPoints { .. } => "internal error",
+            Self::Query { .. } => "internal error",
+            Self::QueryError { .. } => "invalid",
+            Self::FormattingResults { source } => match source {
+                read_format::Error::UnknownFormat { .. } => "invalid",
+                read_format::Error::PrettyPrinting { .. }
+                | read_format::Error::WritingCsv { .. }
+                | read_format::Error::WritingArrowStream { .. }
+                | read_format::Error::UnsupportedDataType { .. } => "internal error",
+            },
+            Self::BucketNotFound { .. } => "not found",
+            Self::RequestSizeExceeded { .. } => "request too large",
+            Self::DecompressedSizeExceeded { .. } => "request too large",
+            Self::ExpectedQueryString { .. } => "invalid",
+            Self::InvalidQueryString { .. } => "invalid",
+            Self::InvalidRequestBody { .. } => "invalid",
+            Self::InvalidContentEncoding { .. } => "unsupported media type",
+            Self::ReadingHeaderAsUtf8 { .. } => "invalid",
+            Self::ReadingBody { .. } => "invalid",
+            Self::ReadingBodyAsUtf8 { .. } => "invalid",
+            Self::InvalidPrecision { .. } => "invalid",
+            Self::TimestampOutOfRange { .. } => "invalid",
+            Self::ParsingLineProtocol { .. } => "invalid",
+            Self::ParsingLineProtocolAtLine { .. } => "invalid",
+            Self::ReadingBodyAsGzip { .. } => "invalid",
+            Self::ReadingBodyAsDeflate { .. } => "invalid",
+            Self::RouteNotFound { .. } => "not found",
+            Self::CreatingGzipDecoder { .. } => "internal error",
+            Self::DecodingPrometheusWrite { .. } => "invalid",
+            Self::ImportingCsv { .. } => "invalid",
+            Self::ImportingParquet { .. } => "invalid",
+            Self::ParquetSchemaConflict { .. } => "conflict",
+            Self::DecodingOpenTsdbPut { .. } => "invalid",
+            Self::MissingOpenTsdbTarget { .. } => "invalid",
+            Self::ParsingDeletePredicate { .. } => "invalid",
+            Self::InvalidDeleteTime { .. } => "invalid",
+            Self::InvalidDeleteRange { .. } => "invalid",
+            Self::InvalidDelete { .. } => "invalid",
+            Self::DeletingPoints { .. } => "internal error",
+            Self::SchemaRulesNotFound { .. } => "not found",
+            Self::DecodingPrometheusRead { .. } => "invalid",
+            Self::EncodingPrometheusRead { .. } => "internal error",
+            Self::RateLimited { .. } => "too many requests",
+            Self::TooManyLines { .. } => "invalid",
+            Self::PartiallyWritten { source, .. } => source.error_code(),
+            Self::QueryTimeout { .. } => "unavailable",
+            Self::DatabaseNotFound { .. } => "not found",
+            Self::InvalidDatabaseName { .. } => "invalid",
+            Self::InvalidDatabaseParameters => "invalid",
+            Self::ReplicatingDbParameter => "invalid",
+            Self::TableNotFound { .. } => "not found",
+            Self::CompressingResponse { .. } => "internal error",
+        }
+    }
+
+    /// The JSON body of the response to the error, in the shape of the
+    /// InfluxDB v2 API: its code and message and, for line protocol
+    /// errors, the number and text of the line
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::ParsingLineProtocolAtLine {
+                line_number, line, ..
+            } => serde_json::json!({
+                "code": self.error_code(),
+                "message": self.to_string(),
+                "line_number": line_number,
+                "line": line,
+            }),
+            Self::PartiallyWritten { accepted, source } => {
+                let mut json = source.to_json();
+                json["message"] = self.to_string().into();
+                json["accepted"] = (*accepted).into();
+                json
+            }
+            _ => serde_json::json!({
+                "code": self.error_code(),
+                "message": self.to_string(),
+            }),
+        }
+    }
+}
+
+/// The default maximum size of request bodies: 10MB
+pub const DEFAULT_MAX_REQUEST_SIZE: usize = 10_485_760;
+
+/// The default maximum time read queries may run
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The state shared by all HTTP request handlers
+#[derive(Debug)]
+pub struct AppServer<T> {
+    pub write_buffer: Arc<T>,
+
+    /// If set, accepted writes are also forwarded to a replica
+    pub replication: Option<ReplicationSink>,
+
+    /// Where OpenTSDB put requests without org and bucket are written
+    pub opentsdb_target: Option<WriteTarget>,
+
+    /// If set, responses to read requests are cached
+    pub query_cache: Option<QueryCache>,
+
+    /// The maximum size of request bodies in bytes
+    pub max_request_size: usize,
+
+    /// If set, the maximum number of lines of a write or import
+    pub max_lines_per_write: Option<usize>,
+
+    /// The maximum time read queries may run. Requests may ask for a
+    /// shorter timeout with `timeout_seconds`.
+    pub query_timeout: Duration,
+
+    /// The metrics rendered by /metrics
+    pub metrics: Metrics,
+
+    /// If set, browser applications from the allowed origins may call
+    /// the API
+    pub cors: Option<CorsConfig>,
+
+    /// If set, limits the rate at which each org may write and import
+    /// lines
+    pub rate_limiter: Option<RateLimiter>,
+
+    /// The schema rules of each database with rules, enforced on
+    /// writes and imports
+    schema_rules: RwLock<HashMap<String, SchemaRules>>,
+}
+
+impl<T: DatabaseStore> AppServer<T> {
+    pub fn new(write_buffer: Arc<T>) -> Self {
+        Self {
+            write_buffer,
+            replication: None,
+            opentsdb_target: None,
+            query_cache: None,
+            max_request_size: DEFAULT_MAX_REQUEST_SIZE,
+            max_lines_per_write: None,
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+            metrics: Metrics::default(),
+            cors: None,
+            rate_limiter: None,
+            schema_rules: Default::default(),
+        }
+    }
+
+    /// Returns the schema rules of `db_name`, if it has any
+    fn schema_rules(&self, db_name: &str) -> Option<SchemaRules> {
+        let schema_rules = self
+            .schema_rules
+            .read()
+            .expect("schema rules lock poisoned");
+        schema_rules.get(db_name).cloned()
+    }
+
+    /// Whether the schema rules of `db_name` reject writes with lines
+    /// violating them
+    fn rejects_writes(&self, db_name: &str) -> bool {
+        self.schema_rules(db_name)
+            .map_or(false, |rules| rules.policy == ViolationPolicy::Reject)
+    }
+
+    /// Removes the cached query responses of `db_name`, which has
+    /// been changed. Must be called before the change is acknowledged.
+    fn invalidate_query_cache(&self, db_name: &str) {
+        if let Some(query_cache) = &self.query_cache {
+            query_cache.invalidate(db_name);
         }
     }
 }
 
-const MAX_SIZE: usize = 10_485_760; // max write request size of 10MB
+/// Rejects `name`, the name of an org, bucket or database, if it is
+/// empty or has characters that don't belong in the file and object
+/// store paths derived from database names
+fn validate_name(kind: &'static str, name: &str) -> Result<(), ApplicationError> {
+    let reason = if name.is_empty() {
+        "must not be empty"
+    } else if name.contains(|c| c == '/' || c == '\\') {
+        "must not contain path separators"
+    } else if name.contains("..") {
+        "must not contain '..'"
+    } else if name.chars().any(char::is_control) {
+        "must not contain control characters"
+    } else {
+        return Ok(());
+    };
+
+    InvalidDatabaseName { kind, name, reason }.fail()
+}
+
+/// Rejects invalid org names. As `_` separates the org from the bucket
+/// in database names, orgs can't contain it: otherwise org `a_b` with
+/// bucket `c` and org `a` with bucket `b_c` would share a database.
+fn validate_org(org: &str) -> Result<(), ApplicationError> {
+    validate_name("org", org)?;
+    ensure!(
+        !org.contains('_'),
+        InvalidDatabaseName {
+            kind: "org",
+            name: org,
+            reason: "must not contain '_'",
+        }
+    );
+    Ok(())
+}
+
+/// Returns the name of the database of `org` and `bucket`, if both
+/// names are valid
+fn database_name(org: &str, bucket: &str) -> Result<String, ApplicationError> {
+    validate_org(org)?;
+    validate_name("bucket", bucket)?;
+    Ok(org_and_bucket_to_database(org, bucket))
+}
+
+/// The database of a request, named either by the `org` and `bucket`
+/// parameters or, verbatim, by the `db` parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RequestDatabase<'a> {
+    OrgAndBucket { org: &'a str, bucket: &'a str },
+    Db(&'a str),
+}
+
+impl<'a> RequestDatabase<'a> {
+    fn from_params(
+        org: &'a Option<String>,
+        bucket: &'a Option<String>,
+        db: &'a Option<String>,
+    ) -> Result<Self, ApplicationError> {
+        match (org.as_deref(), bucket.as_deref(), db.as_deref()) {
+            (Some(org), Some(bucket), None) => Self::org_and_bucket(org, bucket),
+            (None, None, Some(db)) => {
+                validate_name("database", db)?;
+                Ok(Self::Db(db))
+            }
+            _ => InvalidDatabaseParameters.fail(),
+        }
+    }
+
+    /// The database of `org` and `bucket`, if both names are valid
+    fn org_and_bucket(org: &'a str, bucket: &'a str) -> Result<Self, ApplicationError> {
+        validate_org(org)?;
+        validate_name("bucket", bucket)?;
+        Ok(Self::OrgAndBucket { org, bucket })
+    }
+
+    /// The name of the database
+    fn name(self) -> String {
+        match self {
+            Self::OrgAndBucket { org, bucket } => org_and_bucket_to_database(org, bucket),
+            Self::Db(db) => db.to_string(),
+        }
+    }
+
+    /// What the write rate limit applies to: the org, or the database
+    /// if there is none
+    fn rate_limit_key(self) -> &'a str {
+        match self {
+            Self::OrgAndBucket { org, .. } => org,
+            Self::Db(db) => db,
+        }
+    }
+
+    /// Describes `rate_limit_key` for errors
+    fn rate_limited(self) -> String {
+        match self {
+            Self::OrgAndBucket { org, .. } => format!("org {}", org),
+            Self::Db(db) => format!("database {}", db),
+        }
+    }
+
+    /// The error for a database that doesn't exist
+    fn not_found(self) -> ApplicationError {
+        match self {
+            Self::OrgAndBucket { org, bucket } => ApplicationError::BucketNotFound {
+                org: org.to_string(),
+                bucket: bucket.to_string(),
+            },
+            Self::Db(db) => ApplicationError::DatabaseNotFound {
+                database: db.to_string(),
+            },
+        }
+    }
+}
 
 #[derive(Debug, Deserialize)]
 /// Body of the request to the /write endpoint
 struct WriteInfo {
-    org: String,
-    bucket: String,
+    org: Option<String>,
+    bucket: Option<String>,
+    /// The name of the database, instead of `org` and `bucket`
+    db: Option<String>,
+    /// The precision of the timestamps of a /api/v2/write request: s,
+    /// ms, us or ns (the default)
+    precision: Option<String>,
+    /// If set, the lines that parse are written even if others don't,
+    /// and the response lists the ones that don't
+    #[serde(default)]
+    partial: bool,
+}
+
+impl WriteInfo {
+    fn database(&self) -> Result<RequestDatabase<'_>, ApplicationError> {
+        RequestDatabase::from_params(&self.org, &self.bucket, &self.db)
+    }
+}
+
+/// Returns the number of nanoseconds in one unit of `precision`
+fn precision_to_nanos(precision: &str) -> Option<i64> {
+    match precision {
+        "s" => Some(1_000_000_000),
+        "ms" => Some(1_000_000),
+        "us" => Some(1_000),
+        "ns" => Some(1),
+        _ => None,
+    }
+}
+
+/// The content encodings of request bodies the server can decode
+const SUPPORTED_CONTENT_ENCODINGS: &str = "gzip, deflate";
+
+/// The compression of a request body
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ContentEncoding {
+    Identity,
+    Gzip,
+    Deflate,
 }
 
-/// Parse the request's body into raw bytes, applying size limits and
-/// content encoding as needed.
-async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError> {
+/// Returns how the request's body is compressed, according to its
+/// `Content-Encoding` header
+fn content_encoding(req: &hyper::Request<Body>) -> Result<ContentEncoding, ApplicationError> {
     // clippy says the const needs to be assigned to a local variable:
     // error: a `const` item with interior mutability should not be borrowed
     let header_name = CONTENT_ENCODING;
-    let ungzip = match req.headers().get(&header_name) {
-        None => false,
+    match req.headers().get(&header_name) {
+        None => Ok(ContentEncoding::Identity),
         Some(content_encoding) => {
             let content_encoding = content_encoding.to_str().context(ReadingHeaderAsUtf8 {
                 header_name: header_name.as_str(),
             })?;
             match content_encoding {
-                "gzip" => true,
-                _ => InvalidContentEncoding { content_encoding }.fail()?,
+                "gzip" => Ok(ContentEncoding::Gzip),
+                "deflate" => Ok(ContentEncoding::Deflate),
+                _ => InvalidContentEncoding { content_encoding }.fail(),
             }
         }
+    }
+}
+
+/// Parse the request's body into raw bytes, applying the size limit of
+/// `max_size` bytes and content encoding as needed.
+async fn parse_body(req: hyper::Request<Body>, max_size: usize) -> Result<Bytes, ApplicationError> {
+    let encoding = content_encoding(&req)?;
+
+    let body = read_body(req, max_size).await?;
+
+    // apply any content encoding needed
+    let decoded_data = match encoding {
+        ContentEncoding::Identity => return Ok(body),
+        ContentEncoding::Gzip => {
+            let decoder = libflate::gzip::Decoder::new(&body[..]).context(CreatingGzipDecoder)?;
+            read_decoded(decoder, max_size).context(ReadingBodyAsGzip)?
+        }
+        // HTTP's deflate is zlib wrapped, but some clients send raw
+        // deflate data instead
+        ContentEncoding::Deflate if has_zlib_header(&body) => {
+            let decoder = libflate::zlib::Decoder::new(&body[..]).context(ReadingBodyAsDeflate)?;
+            read_decoded(decoder, max_size).context(ReadingBodyAsDeflate)?
+        }
+        ContentEncoding::Deflate => {
+            let decoder = libflate::deflate::Decoder::new(&body[..]);
+            read_decoded(decoder, max_size).context(ReadingBodyAsDeflate)?
+        }
     };
+    ensure!(
+        decoded_data.len() <= max_size,
+        DecompressedSizeExceeded {
+            max_decompressed_size: max_size
+        }
+    );
+    Ok(decoded_data.into())
+}
 
-    let mut payload = req.into_body();
+/// Reads the output of `decoder`, stopping at most one byte after
+/// `max_size` bytes to detect bodies exceeding the limit without
+/// decompressing all of them
+fn read_decoded(decoder: impl std::io::Read, max_size: usize) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut decoded_data = Vec::new();
+    decoder
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decoded_data)?;
+    Ok(decoded_data)
+}
+
+/// Returns true if `data` starts with a zlib header (RFC 1950): a
+/// deflate compression method and a valid header checksum
+fn has_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        _ => false,
+    }
+}
 
+/// Read the request's body into raw bytes, applying the size limit of
+/// `max_size` bytes but no content encoding.
+async fn read_body(req: hyper::Request<Body>, max_size: usize) -> Result<Bytes, ApplicationError> {
+    check_content_length(&req, max_size)?;
+
+    let mut payload = req.into_body();
     let mut body = BytesMut::new();
     while let Some(chunk) = payload.next().await {
-        let chunk = chunk.expect("Should have been able to read the next chunk");
+        let chunk = chunk.context(ReadingBody)?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_SIZE {
+        if (body.len() + chunk.len()) > max_size {
             return Err(ApplicationError::RequestSizeExceeded {
-                max_body_size: MAX_SIZE,
+                max_body_size: max_size,
             });
         }
         body.extend_from_slice(&chunk);
     }
-    let body = body.freeze();
+    Ok(body.freeze())
+}
 
-    // apply any content encoding needed
-    if ungzip {
-        use libflate::gzip::Decoder;
-        use std::io::Read;
-        let mut decoder = Decoder::new(&body[..]).context(CreatingGzipDecoder)?;
-        // TODO cap the size of the decoded data (right
-        // now this could decompress some crazy large
-        // request)
-        let mut decoded_data = Vec::new();
-        decoder
-            .read_to_end(&mut decoded_data)
-            .context(ReadingBodyAsGzip)?;
-        Ok(decoded_data.into())
-    } else {
-        Ok(body)
+/// Rejects requests whose `Content-Length` header says their body
+/// exceeds the size limit of `max_size` bytes, before reading any of it
+fn check_content_length(
+    req: &hyper::Request<Body>,
+    max_size: usize,
+) -> Result<(), ApplicationError> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<u64>().ok());
+
+    match content_length {
+        Some(content_length) if content_length > max_size as u64 => RequestSizeExceeded {
+            max_body_size: max_size,
+        }
+        .fail(),
+        _ => Ok(()),
     }
 }
 
+/// The maximum number of lines of a streamed partial write that are
+/// written to the database at once
+const WRITE_BATCH_SIZE: usize = 1000;
+
+/// The response header with the number of lines a write wrote
+const LINES_WRITTEN_HEADER: &str = "x-influxdb-lines-written";
+
+/// The response header with the number of bytes of line protocol a
+/// write read, after decompression
+const BYTES_READ_HEADER: &str = "x-influxdb-bytes-read";
+
+/// Writes the line protocol of the body. If the database has schema
+/// rules, lines violating them are rejected according to their policy,
+/// with a partial write response describing the violations.
+///
+/// By default a write is all or nothing: the whole body is read,
+/// parsed and checked before any of it is written. With
+/// `partial=true`, lines that don't parse are dropped instead, and
+/// uncompressed bodies are written in batches of `WRITE_BATCH_SIZE`
+/// lines as they arrive, so only the current batch is kept in memory
+/// (unless the schema rules reject whole writes, which are then also
+/// read whole). If such a body turns out to exceed the size or line
+/// limit after some batches were written, the error says how many
+/// lines were written before it.
 #[tracing::instrument(level = "debug")]
 async fn write<T: DatabaseStore>(
     req: hyper::Request<Body>,
-    storage: Arc<T>,
-) -> Result<Option<Body>, ApplicationError> {
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
     let query = req.uri().query().context(ExpectedQueryString)?;
 
     let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
         query_string: String::from(query),
     })?;
 
-    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket);
-
-    let db = storage
-        .db_or_create(&db_name)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(BucketByName {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    let database = write_info.database()?;
+    if let (RequestDatabase::Db(_), Some(_)) = (database, &server.replication) {
+        // replicas are written by org and bucket
+        return ReplicatingDbParameter.fail();
+    }
 
-    let body = parse_body(req).await?;
+    let mut write = DbWrite::start(&server, database)?;
 
-    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+    let precision = write_info.precision.as_deref().unwrap_or("ns");
+    let nanos_per_unit = precision_to_nanos(precision).context(InvalidPrecision { precision })?;
 
-    let lines = parse_lines(body)
-        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
-        .context(ParsingLineProtocol)?;
+    write.open().await?;
+    let streamed = write_info.partial
+        && !server.rejects_writes(&write.db_name)
+        && content_encoding(&req)? == ContentEncoding::Identity;
 
-    debug!(
-        "Inserting {} lines into database {} (org {} bucket {})",
-        lines.len(),
-        db_name,
-        write_info.org,
-        write_info.bucket
-    );
+    let mut writer = LineWriter {
+        write,
+        partial: write_info.partial,
+        precision,
+        nanos_per_unit,
+        next_line_number: 1,
+        bytes_read: 0,
+        parse_errors: 0,
+    };
 
-    db.write_lines(&lines)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(WritingPoints {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    if streamed {
+        check_content_length(&req, server.max_request_size)?;
+        let result = writer
+            .write_stream(req.into_body(), server.max_request_size)
+            .await;
+        match result {
+            Err(e) if writer.write.accepted > 0 => {
+                return PartiallyWritten {
+                    accepted: writer.write.accepted,
+                    source: Box::new(e),
+                }
+                .fail()
+            }
+            result => result?,
+        }
+    } else {
+        let body = parse_body(req, server.max_request_size).await?;
+        writer.bytes_read = body.len();
+        let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+        writer.write_all(body).await?;
+    }
 
-    Ok(None)
+    Ok(writer.response())
 }
 
-#[derive(Deserialize, Debug)]
-/// Body of the request to the /read endpoint
-struct ReadInfo {
-    org: String,
-    bucket: String,
-    // TODL This is currently a "SQL" request -- should be updated to conform
-    // to the V2 API for reading (using timestamps, etc).
-    sql_query: String,
+/// The lines one request writes to a database. All writes and imports
+/// write their lines through it, so that they are all subject to the
+/// rate limit, the line limit and the schema rules, and are all
+/// counted, cached and replicated alike.
+struct DbWrite<'a, T: DatabaseStore> {
+    server: &'a AppServer<T>,
+    database: RequestDatabase<'a>,
+    db_name: String,
+    /// Set by `open`
+    db: Option<Arc<T::Database>>,
+    total_lines: usize,
+    accepted: usize,
+    violations: usize,
+    /// The lines that were rejected, by the request or for violating
+    /// the schema rules
+    rejected: Vec<serde_json::Value>,
+    /// Set if the schema rules rejected the rest of the write
+    write_rejected: bool,
 }
 
-// TODO: figure out how to stream read results out rather than rendering the whole thing in mem
-#[tracing::instrument(level = "debug")]
-async fn read<T: DatabaseStore>(
-    req: hyper::Request<Body>,
-    storage: Arc<T>,
-) -> Result<Option<Body>, ApplicationError> {
-    let query = req.uri().query().context(ExpectedQueryString {})?;
+impl<'a, T: DatabaseStore> DbWrite<'a, T> {
+    /// Starts a write to `database`, unless its rate limit is exceeded
+    fn start(
+        server: &'a AppServer<T>,
+        database: RequestDatabase<'a>,
+    ) -> Result<Self, ApplicationError> {
+        if let Some(rate_limiter) = &server.rate_limiter {
+            if let Err(retry_after) = rate_limiter.check(database.rate_limit_key()) {
+                return RateLimited {
+                    limited: database.rate_limited(),
+                    retry_after,
+                }
+                .fail();
+            }
+        }
 
-    let read_info: ReadInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: query,
-    })?;
+        Ok(Self {
+            server,
+            database,
+            db_name: database.name(),
+            db: None,
+            total_lines: 0,
+            accepted: 0,
+            violations: 0,
+            rejected: vec![],
+            write_rejected: false,
+        })
+    }
 
-    let db_name = org_and_bucket_to_database(&read_info.org, &read_info.bucket);
+    /// Returns the database, creating it if it doesn't exist
+    async fn open(&mut self) -> Result<Arc<T::Database>, ApplicationError> {
+        if let Some(db) = &self.db {
+            return Ok(Arc::clone(db));
+        }
 
-    let db = storage.db(&db_name).await.context(BucketNotFound {
-        org: read_info.org.clone(),
-        bucket: read_info.bucket.clone(),
-    })?;
+        let db = self
+            .server
+            .write_buffer
+            .db_or_create(&self.db_name)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(BucketByName {
+                database: &self.db_name,
+            })?;
+        self.db = Some(Arc::clone(&db));
+        Ok(db)
+    }
 
-    let results = db
-        .query(&read_info.sql_query)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(QueryError {})?;
-    let results = arrow::util::pretty::pretty_format_batches(&results).unwrap();
+    /// Counts `lines` lines of the write towards the line limit
+    fn count_lines(&mut self, lines: usize) -> Result<(), ApplicationError> {
+        self.total_lines += lines;
+        if let Some(max_lines) = self.server.max_lines_per_write {
+            ensure!(
+                self.total_lines <= max_lines,
+                TooManyLines {
+                    limit: max_lines,
+                    actual: self.total_lines,
+                }
+            );
+        }
+        Ok(())
+    }
 
-    Ok(Some(results.into_bytes().into()))
-}
+    /// Writes the `lines` that conform to the schema rules, or none of
+    /// them if the rules reject the write. `lp_data` is the line
+    /// protocol of the `lines`, which is replicated.
+    async fn write_lines(
+        &mut self,
+        lines: Vec<ParsedLine<'_>>,
+        mut lp_data: Cow<'_, str>,
+    ) -> Result<(), ApplicationError> {
+        self.count_lines(lines.len())?;
+        if self.write_rejected {
+            return Ok(());
+        }
 
-// Route to test that the server is alive
-#[tracing::instrument(level = "debug")]
-async fn ping(req: hyper::Request<Body>) -> Result<Option<Body>, ApplicationError> {
-    let response_body = "PONG";
-    Ok(Some(response_body.into()))
-}
+        let lines = match self.server.schema_rules(&self.db_name) {
+            Some(rules) => {
+                let checked = rules.check_lines(lines);
+                if !checked.violations.is_empty() {
+                    self.violations += checked.violations.len();
+                    self.rejected.extend(
+                        checked
+                            .violations
+                            .iter()
+                            .map(|(line, violation)| rejected_line_json(line, violation)),
+                    );
+                    if rules.policy == ViolationPolicy::Reject {
+                        self.write_rejected = true;
+                        return Ok(());
+                    }
+                    // only the conforming lines are replicated
+                    lp_data = Cow::Owned(checked.conforming_lp());
+                }
+                checked.conforming
+            }
+            None => lines,
+        };
 
-fn no_op(name: &str) -> Result<Option<Body>, ApplicationError> {
-    info!("NOOP: {}", name);
-    Ok(None)
-}
+        if lines.is_empty() {
+            return Ok(());
+        }
 
-pub async fn service<T: DatabaseStore>(
-    req: hyper::Request<Body>,
-    storage: Arc<T>,
-) -> http::Result<hyper::Response<Body>> {
-    let method = req.method().clone();
-    let uri = req.uri().clone();
+        debug!(
+            "Inserting {} lines into database {}",
+            lines.len(),
+            self.db_name
+        );
 
-    let response = match (req.method(), req.uri().path()) {
-        (&Method::POST, "/api/v2/write") => write(req, storage).await,
-        (&Method::POST, "/api/v2/buckets") => no_op("create bucket"),
-        (&Method::GET, "/ping") => ping(req).await,
-        (&Method::GET, "/api/v2/read") => read(req, storage).await,
-        _ => Err(ApplicationError::RouteNotFound {
-            method: method.clone(),
-            path: uri.to_string(),
-        }),
-    };
+        let db = self.open().await?;
+        db.write_lines(&lines)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(WritingPoints {
+                database: &self.db_name,
+            })?;
+        self.accepted += lines.len();
+        if let Some(rate_limiter) = &self.server.rate_limiter {
+            rate_limiter.consume(self.database.rate_limit_key(), lines.len());
+        }
+        self.server.invalidate_query_cache(&self.db_name);
+        self.server.metrics.record_write(lines.len(), lp_data.len());
 
-    let result = match response {
-        Ok(Some(body)) => hyper::Response::builder()
-            .body(body)
-            .expect("Should have been able to construct a response"),
-        Ok(None) => hyper::Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .body(Body::empty())
-            .expect("Should have been able to construct a response"),
-        Err(e) => {
-            error!(error = ?e, method = ?method, uri = ?uri, "Error while handing request");
-            let json = serde_json::json!({"error": e.to_string()}).to_string();
-            hyper::Response::builder()
-                .status(e.status_code())
-                .body(json.into())
-                .expect("Should have been able to construct a response")
+        if let (Some(replication), RequestDatabase::OrgAndBucket { org, bucket }) =
+            (&self.server.replication, self.database)
+        {
+            replication.replicate(org, bucket, lp_data);
+        }
+
+        Ok(())
+    }
+
+    /// Makes the response to a write of which the schema rules rejected
+    /// or dropped lines, or returns `None` if they didn't
+    fn violations_response(self) -> Option<hyper::Response<Body>> {
+        let message = if self.write_rejected {
+            format!(
+                "write rejected: {} of {} lines violate the schema rules",
+                self.violations, self.total_lines
+            )
+        } else if self.violations > 0 {
+            format!(
+                "partial write: dropped {} of {} lines violating the schema rules",
+                self.violations, self.total_lines
+            )
+        } else {
+            return None;
+        };
+        Some(partial_write_response(
+            message,
+            self.accepted,
+            self.rejected,
+        ))
+    }
+}
+
+/// Parses the line protocol of one write, in batches if it is
+/// streamed, and writes it
+struct LineWriter<'a, T: DatabaseStore> {
+    write: DbWrite<'a, T>,
+    partial: bool,
+    precision: &'a str,
+    nanos_per_unit: i64,
+    /// The number of the first line of the next batch in the body
+    next_line_number: usize,
+    bytes_read: usize,
+    parse_errors: usize,
+}
+
+impl<'a, T: DatabaseStore> LineWriter<'a, T> {
+    /// Writes the body as it arrives, applying the size limit of
+    /// `max_size` bytes to the whole body. Only the lines of the current
+    /// batch and an incomplete last line are kept in memory.
+    async fn write_stream(
+        &mut self,
+        mut payload: Body,
+        max_size: usize,
+    ) -> Result<(), ApplicationError> {
+        let mut pending = BytesMut::new();
+        let mut pending_newlines = 0;
+
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.context(ReadingBody)?;
+            self.bytes_read += chunk.len();
+            ensure!(
+                self.bytes_read <= max_size,
+                RequestSizeExceeded {
+                    max_body_size: max_size
+                }
+            );
+            pending_newlines += chunk.iter().filter(|&&b| b == b'\n').count();
+            pending.extend_from_slice(&chunk);
+
+            while pending_newlines >= WRITE_BATCH_SIZE && !self.write.write_rejected {
+                let (batch_len, batch_newlines) = {
+                    let text = utf8_prefix(&pending)?;
+                    let (batch, _) = split_complete_lines(text, WRITE_BATCH_SIZE);
+                    if batch.is_empty() {
+                        // newlines within a quoted field value that
+                        // hasn't ended yet
+                        break;
+                    }
+                    self.write_batch(batch).await?;
+                    (batch.len(), batch.matches('\n').count())
+                };
+                let _ = pending.split_to(batch_len);
+                pending_newlines -= batch_newlines;
+            }
+
+            if self.write.write_rejected {
+                return Ok(());
+            }
+        }
+
+        let rest = str::from_utf8(&pending).context(ReadingBodyAsUtf8)?;
+        self.write_all(rest).await
+    }
+
+    /// Writes all lines of `text` at once, so that none of them are
+    /// written if any of them fail to parse or are rejected
+    async fn write_all(&mut self, text: &str) -> Result<(), ApplicationError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        self.write_batch(text).await
+    }
+
+    /// Parses and checks all lines of `batch`, then writes them
+    async fn write_batch(&mut self, batch: &str) -> Result<(), ApplicationError> {
+        let mut lines = vec![];
+        let mut parse_errors = 0;
+        for line in parse_numbered_lines(batch) {
+            let parsed = line.parsed.with_context(|| ParsingLineProtocolAtLine {
+                line_number: self.next_line_number + line.number - 1,
+                line: truncate_line(line.text),
+            });
+            match parsed {
+                Ok(parsed) => lines.push(parsed),
+                Err(e) if self.partial => {
+                    self.write.rejected.push(e.to_json());
+                    parse_errors += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.next_line_number += batch.matches('\n').count();
+        self.parse_errors += parse_errors;
+        self.write.count_lines(parse_errors)?;
+
+        if self.nanos_per_unit != 1 {
+            for line in &mut lines {
+                if let Some(timestamp) = line.timestamp {
+                    let nanos = timestamp.checked_mul(self.nanos_per_unit).context(
+                        TimestampOutOfRange {
+                            timestamp,
+                            precision: self.precision,
+                        },
+                    )?;
+                    line.timestamp = Some(nanos);
+                }
+            }
+        }
+
+        let mut lp_data = Cow::Borrowed(batch);
+        if self.nanos_per_unit != 1 || parse_errors > 0 {
+            // replicas receive nanosecond timestamps, and only the lines
+            // that parsed
+            lp_data = Cow::Owned(
+                lines
+                    .iter()
+                    .map(schema_rules::line_to_lp)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+        }
+
+        self.write.write_lines(lines, lp_data).await
+    }
+
+    /// Makes the response to the write: a 204, or a partial write
+    /// response if any lines were rejected, with headers saying how
+    /// many lines were written and bytes read
+    fn response(self) -> hyper::Response<Body> {
+        let lines_written = self.write.accepted;
+        let bytes_read = self.bytes_read;
+
+        let mut response = self.status_response();
+        let headers = response.headers_mut();
+        headers.insert(LINES_WRITTEN_HEADER, HeaderValue::from(lines_written));
+        headers.insert(BYTES_READ_HEADER, HeaderValue::from(bytes_read));
+        response
+    }
+
+    /// Makes the response to the write, without the headers
+    fn status_response(self) -> hyper::Response<Body> {
+        let write = self.write;
+        if self.parse_errors == 0 || write.write_rejected {
+            return write
+                .violations_response()
+                .unwrap_or_else(|| body_response(None));
+        }
+
+        let message = format!(
+            "partial write: rejected {} of {} lines",
+            write.rejected.len(),
+            write.total_lines
+        );
+        partial_write_response(message, write.accepted, write.rejected)
+    }
+}
+
+/// Returns the longest prefix of `bytes` that is valid UTF-8, which may
+/// end in the middle of a character whose other bytes haven't arrived
+fn utf8_prefix(bytes: &[u8]) -> Result<&str, ApplicationError> {
+    match str::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(e) if e.error_len().is_none() => {
+            Ok(str::from_utf8(&bytes[..e.valid_up_to()]).expect("prefix is valid UTF-8"))
         }
+        Err(e) => Err(e).context(ReadingBodyAsUtf8),
+    }
+}
+
+/// The maximum length, in characters, of lines quoted in errors
+const MAX_ERROR_LINE_LENGTH: usize = 100;
+
+/// Returns `line`, shortened to `MAX_ERROR_LINE_LENGTH` characters if
+/// it is longer
+fn truncate_line(line: &str) -> String {
+    let line = line.trim();
+    match line.char_indices().nth(MAX_ERROR_LINE_LENGTH) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// Describes a `line` of a write that was rejected because of `error`,
+/// in the same shape as the errors of lines that didn't parse
+fn rejected_line_json(line: &str, error: &impl std::fmt::Display) -> serde_json::Value {
+    serde_json::json!({"code": "invalid", "message": error.to_string(), "line": line})
+}
+
+/// Makes the response to a write of which only `accepted` lines were
+/// written: a 400 with a JSON body holding the `invalid` error code,
+/// `message`, `accepted` and the `rejected` lines, each with its error
+/// and, for lines that didn't parse, its line number
+fn partial_write_response(
+    message: String,
+    accepted: usize,
+    rejected: Vec<serde_json::Value>,
+) -> hyper::Response<Body> {
+    let json = serde_json::json!({
+        "code": "invalid",
+        "message": message,
+        "accepted": accepted,
+        "rejected": rejected,
+    })
+    .to_string();
+
+    hyper::Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json.into())
+        .expect("Should have been able to construct a response")
+}
+
+#[derive(Debug, Deserialize)]
+/// Parameters of the requests to the /schema_rules endpoint
+struct SchemaRulesInfo {
+    org: String,
+    bucket: String,
+}
+
+impl SchemaRulesInfo {
+    fn from_request(req: &hyper::Request<Body>) -> Result<Self, ApplicationError> {
+        let query = req.uri().query().context(ExpectedQueryString)?;
+
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })
+    }
+}
+
+/// Sets the schema rules of a database, which need not exist yet, to
+/// the JSON body, replacing any previous rules
+#[tracing::instrument(level = "debug")]
+async fn put_schema_rules<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let rules_info = SchemaRulesInfo::from_request(&req)?;
+
+    let body = read_body(req, server.max_request_size).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let rules: SchemaRules =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+    let db_name = database_name(&rules_info.org, &rules_info.bucket)?;
+    info!("Setting the schema rules of database {}", db_name);
+
+    server
+        .schema_rules
+        .write()
+        .expect("schema rules lock poisoned")
+        .insert(db_name, rules);
+
+    Ok(None)
+}
+
+/// Returns the schema rules of a database as JSON
+#[tracing::instrument(level = "debug")]
+async fn get_schema_rules<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let rules_info = SchemaRulesInfo::from_request(&req)?;
+
+    let db_name = database_name(&rules_info.org, &rules_info.bucket)?;
+    let rules = server.schema_rules(&db_name).context(SchemaRulesNotFound {
+        org: &rules_info.org,
+        bucket: &rules_info.bucket,
+    })?;
+
+    let json = serde_json::to_string(&rules).expect("schema rules serialize to JSON");
+
+    Ok(Some(json.into()))
+}
+
+/// Removes the schema rules of a database, ending their enforcement
+#[tracing::instrument(level = "debug")]
+async fn delete_schema_rules<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let rules_info = SchemaRulesInfo::from_request(&req)?;
+
+    let db_name = database_name(&rules_info.org, &rules_info.bucket)?;
+    info!("Removing the schema rules of database {}", db_name);
+
+    server
+        .schema_rules
+        .write()
+        .expect("schema rules lock poisoned")
+        .remove(&db_name);
+
+    Ok(None)
+}
+
+/// The response header holding the number of points removed by a
+/// delete request
+const DELETED_POINTS_HEADER: &str = "X-Influxdb-Deleted-Points";
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to the /delete endpoint
+struct DeleteInfo {
+    /// RFC3339 time of the first point to delete
+    start: String,
+    /// RFC3339 time of the last point to delete (inclusive)
+    stop: String,
+    /// Only delete points matching this predicate, e.g.
+    /// `_measurement="cpu" AND host="a"`
+    #[serde(default)]
+    predicate: String,
+}
+
+/// Deletes the points between `start` and `stop` that match the
+/// predicate of the JSON body. The response is empty (204) with the
+/// number of deleted points in the `X-Influxdb-Deleted-Points`
+/// header. Deletes are not replicated.
+#[tracing::instrument(level = "debug")]
+async fn delete<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let delete_target: WriteInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let database = delete_target.database()?;
+    let db_name = database.name();
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .ok_or_else(|| database.not_found())?;
+
+    let body = parse_body(req, server.max_request_size).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let delete_info: DeleteInfo =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+    let parse_time = |name: &str, value: &str| {
+        let time = DateTime::parse_from_rfc3339(value).map_err(|e| {
+            ApplicationError::InvalidDeleteTime {
+                name: name.to_string(),
+                value: value.to_string(),
+                reason: e.to_string(),
+            }
+        })?;
+        // times outside of 1677-2262 don't fit in nanoseconds
+        time.timestamp()
+            .checked_mul(1_000_000_000)
+            .and_then(|nanos| nanos.checked_add(time.timestamp_subsec_nanos().into()))
+            .context(InvalidDeleteTime {
+                name,
+                value,
+                reason: "out of range",
+            })
     };
-    info!(method = ?method, uri = ?uri, status = ?result.status(), "Handled request");
-    Ok(result)
+    let start = parse_time("start", &delete_info.start)?;
+    let stop = parse_time("stop", &delete_info.stop)?;
+    if start > stop {
+        return InvalidDeleteRange {
+            start: delete_info.start,
+            stop: delete_info.stop,
+        }
+        .fail();
+    }
+
+    let predicate = DeletePredicate::parse(&delete_info.predicate)
+        .context(ParsingDeletePredicate)?
+        .to_predicate(TimestampRange::new(start, stop.saturating_add(1)));
+
+    let deleted = db.delete(predicate).await.map_err(|e| {
+        let database = db_name.clone();
+        // predicates that can't be applied to the data are the client's
+        // fault, failures to store the delete are the server's
+        if e.is_invalid_request() {
+            ApplicationError::InvalidDelete {
+                database,
+                source: Box::new(e),
+            }
+        } else {
+            ApplicationError::DeletingPoints {
+                database,
+                source: Box::new(e),
+            }
+        }
+    })?;
+    server.invalidate_query_cache(&db_name);
+
+    debug!(
+        "Deleted {} points matching '{}' from database {}",
+        deleted, delete_info.predicate, db_name
+    );
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .header(DELETED_POINTS_HEADER, deleted)
+        .body(Body::empty())
+        .expect("Should have been able to construct a response"))
+}
+
+#[derive(Debug, Deserialize)]
+/// Parameters of the request to the /import/csv endpoint
+struct CsvImportInfo {
+    org: String,
+    bucket: String,
+    measurement: String,
+    /// Comma separated names of the columns to store as tags
+    #[serde(default)]
+    tag_columns: String,
+    time_column: String,
+    #[serde(default)]
+    time_format: TimeFormat,
+}
+
+/// The number of lines written to the database at a time by streamed
+/// JSON lines imports
+const IMPORT_BATCH_SIZE: usize = 1000;
+
+/// Imports CSV data with a header row. Rows that can't be converted
+/// are skipped and reported, with their line number, in the JSON
+/// response alongside the number of rows written.
+#[tracing::instrument(level = "debug")]
+async fn import_csv<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let import_info: CsvImportInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let database = RequestDatabase::org_and_bucket(&import_info.org, &import_info.bucket)?;
+    let mut write = DbWrite::start(&server, database)?;
+
+    let body = parse_body(req, server.max_request_size).await?;
+
+    let mapping = CsvMapping {
+        measurement: import_info.measurement,
+        tag_columns: import_info
+            .tag_columns
+            .split(',')
+            .filter(|column_name| !column_name.is_empty())
+            .map(|column_name| column_name.to_string())
+            .collect(),
+        time_column: import_info.time_column,
+        time_format: import_info.time_format,
+    };
+
+    let converted = csv_import::csv_to_lp(&mapping, &body).context(ImportingCsv)?;
+
+    // only now that the data was converted is the database created
+    write.open().await?;
+    let lp_data = converted.lines.join("\n");
+    let lines = parse_lines(&lp_data)
+        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+        .context(ParsingLineProtocol)?;
+    write.write_lines(lines, lp_data.as_str().into()).await?;
+
+    debug!(
+        "Imported {} CSV rows into database {} ({} rows failed)",
+        write.accepted,
+        write.db_name,
+        converted.errors.len()
+    );
+
+    let errors = converted
+        .errors
+        .iter()
+        .map(|(line, e)| serde_json::json!({"line": line, "error": e.to_string()}))
+        .collect::<Vec<_>>();
+    Ok(import_response(write, errors))
+}
+
+/// Makes the response to a CSV or Parquet import: a JSON body with
+/// the number of rows written, the rows that couldn't be converted
+/// and, if any, the rows that violated the schema rules, or a partial
+/// write response if the schema rules rejected the import
+fn import_response<T: DatabaseStore>(
+    write: DbWrite<'_, T>,
+    errors: Vec<serde_json::Value>,
+) -> hyper::Response<Body> {
+    if write.write_rejected {
+        return write
+            .violations_response()
+            .expect("rejected writes have violations");
+    }
+
+    let mut json = serde_json::json!({
+        "rows_written": write.accepted,
+        "errors": errors,
+    });
+    if !write.rejected.is_empty() {
+        json["violations"] = write.rejected.into();
+    }
+
+    body_response(Some(json.to_string().into()))
+}
+
+#[derive(Debug, Deserialize)]
+/// Parameters of the request to the /import/jsonl endpoint
+struct JsonlImportInfo {
+    org: String,
+    bucket: String,
+    measurement: String,
+    /// Comma separated names of the keys to store as tags
+    #[serde(default)]
+    tag_keys: String,
+}
+
+/// Imports newline delimited JSON events, see `jsonl_import`. Lines
+/// that can't be converted are skipped and reported, with their line
+/// number, in a partial write response.
+///
+/// Like writes, uncompressed bodies are imported in batches of
+/// `IMPORT_BATCH_SIZE` lines as they arrive, and compressed bodies are
+/// read in full first.
+#[tracing::instrument(level = "debug")]
+async fn import_jsonl<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let import_info: JsonlImportInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let database = RequestDatabase::org_and_bucket(&import_info.org, &import_info.bucket)?;
+    let mut write = DbWrite::start(&server, database)?;
+    write.open().await?;
+
+    // lines violating schema rules that reject writes must be found
+    // before any batch is written
+    let batch_size = if server.rejects_writes(&write.db_name) {
+        usize::MAX
+    } else {
+        IMPORT_BATCH_SIZE
+    };
+
+    let mut importer = JsonlImporter {
+        write,
+        mapping: JsonlMapping {
+            measurement: import_info.measurement.clone(),
+            tag_keys: import_info
+                .tag_keys
+                .split(',')
+                .filter(|key| !key.is_empty())
+                .map(|key| key.to_string())
+                .collect(),
+        },
+        batch_size,
+        next_line_number: 1,
+        lines: vec![],
+    };
+
+    if content_encoding(&req)? != ContentEncoding::Identity {
+        let body = parse_body(req, server.max_request_size).await?;
+        let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+        importer.import_lines(body).await?;
+    } else {
+        check_content_length(&req, server.max_request_size)?;
+        let max_size = server.max_request_size;
+        let mut payload = req.into_body();
+        let mut pending = BytesMut::new();
+        let mut bytes_read = 0;
+
+        while let Some(chunk) = payload.next().await {
+            let chunk = chunk.context(ReadingBody)?;
+            bytes_read += chunk.len();
+            ensure!(
+                bytes_read <= max_size,
+                RequestSizeExceeded {
+                    max_body_size: max_size
+                }
+            );
+            pending.extend_from_slice(&chunk);
+
+            // JSON strings can't contain newlines, so every newline
+            // ends a line
+            if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                let complete = pending.split_to(end + 1);
+                let complete = str::from_utf8(&complete).context(ReadingBodyAsUtf8)?;
+                importer.import_lines(complete).await?;
+            }
+        }
+
+        let rest = str::from_utf8(&pending).context(ReadingBodyAsUtf8)?;
+        importer.import_lines(rest).await?;
+    }
+    importer.flush().await?;
+
+    debug!(
+        "Imported {} JSON lines into database {} ({} lines failed)",
+        importer.write.accepted,
+        importer.write.db_name,
+        importer.write.rejected.len()
+    );
+
+    Ok(importer.response())
+}
+
+/// Converts JSON lines and writes them in batches, keeping track of
+/// which lines were written and which were rejected
+struct JsonlImporter<'a, T: DatabaseStore> {
+    write: DbWrite<'a, T>,
+    mapping: JsonlMapping,
+    /// The number of lines written at a time
+    batch_size: usize,
+    /// The number of the next line in the body
+    next_line_number: usize,
+    /// The converted lines of the current batch
+    lines: Vec<String>,
+}
+
+impl<'a, T: DatabaseStore> JsonlImporter<'a, T> {
+    /// Converts the lines of `text`, writing each full batch
+    async fn import_lines(&mut self, text: &str) -> Result<(), ApplicationError> {
+        for line in text.lines() {
+            let line_number = self.next_line_number;
+            self.next_line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match jsonl_import::json_line_to_lp(&self.mapping, line) {
+                Ok(lp) => {
+                    self.lines.push(lp);
+                    if self.lines.len() >= self.batch_size {
+                        self.flush().await?;
+                    }
+                }
+                Err(e) => {
+                    self.write.count_lines(1)?;
+                    self.write.rejected.push(serde_json::json!({
+                        "code": "invalid",
+                        "message": e.to_string(),
+                        "line_number": line_number,
+                        "line": truncate_line(line),
+                    }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the converted lines of the current batch
+    async fn flush(&mut self) -> Result<(), ApplicationError> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+
+        let lp_data = self.lines.join("\n");
+        let lines = parse_lines(&lp_data)
+            .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+            .context(ParsingLineProtocol)?;
+        self.write
+            .write_lines(lines, lp_data.as_str().into())
+            .await?;
+
+        self.lines.clear();
+        Ok(())
+    }
+
+    /// Makes the response to the import: a 204, or a partial write
+    /// response if any lines were rejected
+    fn response(self) -> hyper::Response<Body> {
+        let write = self.write;
+        if write.write_rejected {
+            return write
+                .violations_response()
+                .expect("rejected writes have violations");
+        }
+        if write.rejected.is_empty() {
+            return body_response(None);
+        }
+
+        let message = format!(
+            "partial write: rejected {} of {} lines",
+            write.rejected.len(),
+            write.total_lines
+        );
+        partial_write_response(message, write.accepted, write.rejected)
+    }
+}
+
+fn default_time_column() -> String {
+    "time".into()
+}
+
+#[derive(Debug, Deserialize)]
+/// Parameters of the request to the /import/parquet endpoint
+struct ParquetImportInfo {
+    org: String,
+    bucket: String,
+    table: String,
+    /// Comma separated names of the columns to store as tags
+    #[serde(default)]
+    tag_columns: String,
+    #[serde(default = "default_time_column")]
+    time_column: String,
+}
+
+/// Imports a Parquet file, e.g. one exported from another system, into
+/// a table. Before anything is written, the file's columns are checked
+/// against the existing data of the table. Rows that can't be converted
+/// are skipped and reported, with their index, in the JSON response
+/// alongside the number of rows written.
+#[tracing::instrument(level = "debug")]
+async fn import_parquet<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let import_info: ParquetImportInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let database = RequestDatabase::org_and_bucket(&import_info.org, &import_info.bucket)?;
+    let mut write = DbWrite::start(&server, database)?;
+    let db_name = write.db_name.clone();
+
+    let body = parse_body(req, server.max_request_size).await?;
+
+    let mapping = ParquetMapping {
+        measurement: import_info.table.clone(),
+        tag_columns: import_info
+            .tag_columns
+            .split(',')
+            .filter(|column_name| !column_name.is_empty())
+            .map(|column_name| column_name.to_string())
+            .collect(),
+        time_column: import_info.time_column,
+    };
+
+    let converted =
+        parquet_import::parquet_to_lp(&mapping, body.to_vec()).context(ImportingParquet)?;
+
+    // only now that the file was converted is the database created
+    let db = write.open().await?;
+
+    // check the schema up front so a conflict doesn't leave a partial import
+    let predicate = PredicateBuilder::default()
+        .table(&import_info.table)
+        .build();
+    let executor = Executor::default();
+
+    let tag_plan = db
+        .tag_column_names(predicate.clone())
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+    let existing_tags = executor
+        .to_string_set(tag_plan)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+
+    let field_plan = db
+        .field_columns(predicate)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+    let existing_fields = executor
+        .to_fieldlist(field_plan)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+
+    let conflicts = converted.conflicting_columns(&existing_tags, &existing_fields);
+    if !conflicts.is_empty() {
+        return ParquetSchemaConflict {
+            table: import_info.table,
+            columns: conflicts.join(", "),
+        }
+        .fail();
+    }
+
+    let lp_data = converted.lines.join("\n");
+    let lines = parse_lines(&lp_data)
+        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+        .context(ParsingLineProtocol)?;
+    write.write_lines(lines, lp_data.as_str().into()).await?;
+
+    debug!(
+        "Imported {} Parquet rows into table {} of database {} ({} rows failed)",
+        write.accepted,
+        import_info.table,
+        db_name,
+        converted.errors.len()
+    );
+
+    let errors = converted
+        .errors
+        .iter()
+        .map(|(row, e)| serde_json::json!({"row": row, "error": e.to_string()}))
+        .collect::<Vec<_>>();
+    Ok(import_response(write, errors))
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+#[derive(Debug, Deserialize)]
+/// Parameters of the request to the OpenTSDB /api/put endpoint
+struct OpenTsdbPutInfo {
+    /// Defaults to the org of the server's configured OpenTSDB target
+    org: Option<String>,
+    /// Defaults to the bucket of the server's configured OpenTSDB target
+    bucket: Option<String>,
+    /// If present, the response describes each data point that failed
+    details: Option<String>,
+}
+
+/// Accepts OpenTSDB put requests: a JSON data point or array of data
+/// points, optionally gzip compressed. Like OpenTSDB, the response
+/// summarizes how many data points were written and failed, and is a
+/// 400 if any failed.
+#[tracing::instrument(level = "debug")]
+async fn opentsdb_put<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().unwrap_or_default();
+
+    let put_info: OpenTsdbPutInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: String::from(query),
+        })?;
+
+    let default_target = server.opentsdb_target.as_ref();
+    let (org, bucket) = match (put_info.org, put_info.bucket) {
+        (Some(org), Some(bucket)) => (org, bucket),
+        (org, bucket) => {
+            let default_target = default_target.context(MissingOpenTsdbTarget)?;
+            (
+                org.unwrap_or_else(|| default_target.org.clone()),
+                bucket.unwrap_or_else(|| default_target.bucket.clone()),
+            )
+        }
+    };
+
+    let database = RequestDatabase::org_and_bucket(&org, &bucket)?;
+    // the database is only created once a data point is written
+    let mut write = DbWrite::start(&server, database)?;
+
+    let body = parse_body(req, server.max_request_size).await?;
+
+    let converted = opentsdb::put_to_lp(&body).context(DecodingOpenTsdbPut)?;
+
+    let lp_data = converted.lines.join("\n");
+    let lines = parse_lines(&lp_data)
+        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+        .context(ParsingLineProtocol)?;
+    write.write_lines(lines, lp_data.as_str().into()).await?;
+
+    debug!(
+        "Inserted {} OpenTSDB data points into database {} ({} failed)",
+        write.accepted,
+        write.db_name,
+        converted.errors.len()
+    );
+
+    // data points violating the schema rules failed too
+    let failed = converted.errors.len() + converted.lines.len() - write.accepted;
+    let mut summary = serde_json::json!({
+        "success": write.accepted,
+        "failed": failed,
+    });
+    if put_info.details.is_some() {
+        let conversion_errors = converted
+            .errors
+            .iter()
+            .map(|(datum, e)| serde_json::json!({"datapoint": datum, "error": e.to_string()}));
+        let violations = write
+            .rejected
+            .iter()
+            .map(|violation| serde_json::json!({"line": violation["line"], "error": violation["message"]}));
+        summary["errors"] = conversion_errors.chain(violations).collect();
+    }
+
+    let status = if failed == 0 {
+        StatusCode::OK
+    } else {
+        StatusCode::BAD_REQUEST
+    };
+
+    Ok(hyper::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(summary.to_string().into())
+        .expect("Should have been able to construct a response"))
+}
+
+/// Accepts Prometheus remote write requests: snappy compressed,
+/// protobuf encoded `WriteRequest`s (Prometheus sets
+/// `Content-Encoding: snappy`, which is always assumed here)
+#[tracing::instrument(level = "debug")]
+async fn prom_write<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: String::from(query),
+    })?;
+
+    let database = write_info.database()?;
+    if let (RequestDatabase::Db(_), Some(_)) = (database, &server.replication) {
+        return ReplicatingDbParameter.fail();
+    }
+    let mut write = DbWrite::start(&server, database)?;
+
+    let body = read_body(req, server.max_request_size).await?;
+    check_snappy_len(&body, server.max_request_size)?;
+
+    let write_request = prometheus::decode_write_request(&body).context(DecodingPrometheusWrite)?;
+    let lp_data =
+        prometheus::write_request_to_lp(&write_request).context(DecodingPrometheusWrite)?;
+
+    let lines = parse_lines(&lp_data)
+        .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+        .context(ParsingLineProtocol)?;
+    // only valid requests create the database
+    write.open().await?;
+
+    debug!(
+        "Inserting {} lines from Prometheus remote write into database {}",
+        lines.len(),
+        write.db_name
+    );
+    write.write_lines(lines, lp_data.as_str().into()).await?;
+
+    Ok(write
+        .violations_response()
+        .unwrap_or_else(|| body_response(None)))
+}
+
+/// Fails if the snappy compressed `body` of a Prometheus request would
+/// decompress to more than `max_size` bytes, according to its header.
+/// Bodies with an invalid header are left for the decoder to report.
+fn check_snappy_len(body: &[u8], max_size: usize) -> Result<(), ApplicationError> {
+    match snap::raw::decompress_len(body) {
+        Ok(len) if len > max_size => RequestSizeExceeded {
+            max_body_size: max_size,
+        }
+        .fail(),
+        _ => Ok(()),
+    }
+}
+
+/// Answers Prometheus remote read requests (snappy compressed,
+/// protobuf encoded `ReadRequest`s) from the data in the bucket
+/// named by the `org` and `bucket` query parameters
+#[tracing::instrument(level = "debug")]
+async fn prom_read<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let read_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: String::from(query),
+    })?;
+
+    let database = read_info.database()?;
+    let db_name = database.name();
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .ok_or_else(|| database.not_found())?;
+
+    let body = read_body(req, server.max_request_size).await?;
+    check_snappy_len(&body, server.max_request_size)?;
+
+    let read_request = prometheus::decode_read_request(&body).context(DecodingPrometheusRead)?;
+
+    let executor = Executor::default();
+    let mut results = Vec::with_capacity(read_request.queries.len());
+
+    for prom_query in &read_request.queries {
+        let predicate =
+            prometheus::query_to_predicate(prom_query).context(DecodingPrometheusRead)?;
+
+        let series_plans = db
+            .query_series(predicate)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { database: &db_name })?;
+
+        // run the plans while collecting their results, as the
+        // channel can't hold all of them
+        let (tx, mut rx) = mpsc::channel(4);
+        let run_plans = executor.to_series_set(series_plans, tx);
+        let collect_series = async {
+            let mut timeseries = Vec::new();
+            while let Some(series_set) = rx.recv().await {
+                match series_set {
+                    Ok(series_set) => {
+                        timeseries.extend(prometheus::series_set_to_time_series(&series_set))
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(timeseries)
+        };
+        let (run_result, timeseries) = futures::join!(run_plans, collect_series);
+
+        run_result
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { database: &db_name })?;
+        let timeseries = timeseries
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { database: &db_name })?;
+
+        results.push(QueryResult { timeseries });
+    }
+
+    debug!(
+        "Answered {} Prometheus remote read queries from database {}",
+        results.len(),
+        db_name
+    );
+
+    let response = prometheus::encode_read_response(&ReadResponse { results })
+        .context(EncodingPrometheusRead)?;
+
+    Ok(Some(response.into()))
+}
+
+#[derive(Deserialize, Debug)]
+/// Body of the request to the /read endpoint
+struct ReadInfo {
+    org: Option<String>,
+    bucket: Option<String>,
+    /// The name of the database, instead of `org` and `bucket`
+    db: Option<String>,
+    // TODL This is currently a "SQL" request -- should be updated to conform
+    // to the V2 API for reading (using timestamps, etc).
+    sql_query: String,
+    /// The format of the results, see `read_format`. Takes precedence
+    /// over the `Accept` header.
+    format: Option<String>,
+    /// The timeout of the query, at most the server's query timeout
+    timeout_seconds: Option<u64>,
+}
+
+impl ReadInfo {
+    fn database(&self) -> Result<RequestDatabase<'_>, ApplicationError> {
+        RequestDatabase::from_params(&self.org, &self.bucket, &self.db)
+    }
+
+    /// Returns the timeout of the query, bounded by `max_timeout`
+    fn timeout(&self, max_timeout: Duration) -> Duration {
+        self.timeout_seconds.map_or(max_timeout, |timeout_seconds| {
+            Duration::from_secs(timeout_seconds).min(max_timeout)
+        })
+    }
+}
+
+/// The response header telling whether a response was served from the
+/// query cache ("hit") or not ("miss")
+const CACHE_HEADER: &str = "X-Cache";
+
+// TODO: figure out how to stream read results out rather than rendering the whole thing in mem
+/// Runs the SQL query of the request, if the query cache is enabled
+/// answering from the cache where possible. The response is gzip
+/// compressed if the client accepts it.
+#[tracing::instrument(level = "debug")]
+async fn read<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let read_info: ReadInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let format = match &read_info.format {
+        Some(format) => ReadFormat::from_name(format).context(FormattingResults)?,
+        None => accepted_read_format(&req)?,
+    };
+
+    let gzip = accepts_gzip(&req)?;
+    let timeout = read_info.timeout(server.query_timeout);
+
+    let database = read_info.database()?;
+    let db_name = database.name();
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .ok_or_else(|| database.not_found())?;
+
+    let mut response = hyper::Response::builder().header(CONTENT_TYPE, format.content_type());
+    if gzip {
+        response = response.header(CONTENT_ENCODING, "gzip");
+    }
+
+    let query_cache = match &server.query_cache {
+        Some(query_cache) => query_cache,
+        None => {
+            let results =
+                run_read_query(&*db, &read_info.sql_query, format, timeout, &server.metrics)
+                    .await?;
+            return Ok(response
+                .body(encode_body(results, gzip)?)
+                .expect("Should have been able to construct a response"));
+        }
+    };
+
+    let key = QueryCacheKey::new(&db_name, &read_info.sql_query, format.name());
+    if let Some(cached) = query_cache.get(&key) {
+        debug!("Answered query from the cache of database {}", db_name);
+        return Ok(response
+            .header(CACHE_HEADER, "hit")
+            .header(AGE, cached.age.as_secs())
+            .body(encode_body(cached.body, gzip)?)
+            .expect("Should have been able to construct a response"));
+    }
+
+    // writes after this point invalidate the results
+    let generation = query_cache.generation(&db_name);
+    let results =
+        run_read_query(&*db, &read_info.sql_query, format, timeout, &server.metrics).await?;
+    query_cache.insert(key, generation, results.clone());
+
+    Ok(response
+        .header(CACHE_HEADER, "miss")
+        .body(encode_body(results, gzip)?)
+        .expect("Should have been able to construct a response"))
+}
+
+/// Runs `sql_query` against `db`, returning the results in `format`
+/// and recording its duration in `metrics`
+async fn run_read_query<D: Database>(
+    db: &D,
+    sql_query: &str,
+    format: ReadFormat,
+    timeout: Duration,
+    metrics: &Metrics,
+) -> Result<Bytes, ApplicationError> {
+    let start = std::time::Instant::now();
+    let results = tokio::time::timeout(timeout, db.query(sql_query))
+        .await
+        .ok()
+        .context(QueryTimeout { timeout })?
+        .map_err(|e| Box::new(e) as _)
+        .context(QueryError {})?;
+    metrics.record_query(start.elapsed());
+
+    format.format(&results).context(FormattingResults)
+}
+
+/// Returns the format of read results the `Accept` header of `req`
+/// asks for, or the default format if it asks for none
+fn accepted_read_format(req: &hyper::Request<Body>) -> Result<ReadFormat, ApplicationError> {
+    // clippy says the const needs to be assigned to a local variable:
+    // error: a `const` item with interior mutability should not be borrowed
+    let header_name = ACCEPT;
+    match req.headers().get(&header_name) {
+        None => Ok(ReadFormat::default()),
+        Some(accept) => {
+            let accept = accept.to_str().context(ReadingHeaderAsUtf8 {
+                header_name: header_name.as_str(),
+            })?;
+            Ok(ReadFormat::from_accept(accept).unwrap_or_default())
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// Parameters of the request to the /export endpoint
+struct ExportInfo {
+    org: String,
+    bucket: String,
+    /// Only export this measurement, if specified
+    measurement: Option<String>,
+    /// Only export points at or after this time (in nanoseconds), if specified
+    start: Option<i64>,
+    /// Only export points before this time (in nanoseconds), if specified
+    stop: Option<i64>,
+}
+
+/// Streams the data of a bucket as line protocol, ordered by
+/// measurement, series key and then time. The response is gzip
+/// compressed if the client accepts it.
+#[tracing::instrument(level = "debug")]
+async fn export<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let export_info: ExportInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db_name = database_name(&export_info.org, &export_info.bucket)?;
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .context(BucketNotFound {
+            org: export_info.org.clone(),
+            bucket: export_info.bucket.clone(),
+        })?;
+
+    let gzip = accepts_gzip(&req)?;
+
+    let range = match (export_info.start, export_info.stop) {
+        (None, None) => None,
+        (start, stop) => Some(TimestampRange::new(
+            start.unwrap_or(i64::MIN),
+            stop.unwrap_or(i64::MAX),
+        )),
+    };
+
+    let predicate = PredicateBuilder::default()
+        .table_option(export_info.measurement)
+        .timestamp_range_option(range)
+        .build();
+
+    let series_plans = db
+        .query_series(predicate)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+
+    debug!("Exporting database {} as line protocol", db_name);
+
+    // the export task sends the results chunk by chunk
+    let (sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let executor = Executor::default();
+        export::export_series(&executor, series_plans, sender, gzip)
+            .await
+            .log_if_error("Exporting line protocol")
+    });
+
+    let mut response = hyper::Response::builder().header(CONTENT_TYPE, "text/plain; charset=utf-8");
+    if gzip {
+        response = response.header(CONTENT_ENCODING, "gzip");
+    }
+
+    Ok(response
+        .body(body)
+        .expect("Should have been able to construct a response"))
+}
+
+#[derive(Deserialize, Debug)]
+/// Parameters of the request to list buckets
+struct BucketsInfo {
+    org: String,
+}
+
+/// Returns the names of the buckets of an org as a sorted JSON array.
+/// Databases are named `<org>_<bucket>` and org names can't contain
+/// `_`, so the buckets of other orgs are never listed. Databases named
+/// verbatim by the `db` parameter can't be told apart from those,
+/// though: a database `acme_x` created by `db=acme_x` is listed as the
+/// bucket `x` of the org `acme`, which is also how writes to that org
+/// and bucket address it.
+#[tracing::instrument(level = "debug")]
+async fn list_buckets<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let buckets_info: BucketsInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    validate_org(&buckets_info.org)?;
+    let prefix = org_and_bucket_to_database(&buckets_info.org, "");
+    let buckets = server
+        .write_buffer
+        .db_names()
+        .await
+        .into_iter()
+        .filter_map(|db_name| db_name.strip_prefix(&prefix).map(String::from))
+        .collect::<Vec<_>>();
+
+    let json = serde_json::to_string(&buckets).expect("strings serialize to JSON");
+
+    Ok(Some(json.into()))
+}
+
+#[derive(Deserialize, Debug)]
+/// Parameters of the request to the /partitions endpoint
+struct PartitionsInfo {
+    org: String,
+    bucket: String,
+    /// Only list the partition keys, as the endpoint used to
+    #[serde(default)]
+    names_only: bool,
+}
+
+/// Returns the partitions of a bucket as a JSON array of objects with
+/// the key, approximate size in bytes, table count and row count of
+/// each partition, or of only the keys with `names_only=true`. The
+/// response is gzip compressed if the client accepts it.
+#[tracing::instrument(level = "debug")]
+async fn list_partitions<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let partitions_info: PartitionsInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db_name = database_name(&partitions_info.org, &partitions_info.bucket)?;
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .context(BucketNotFound {
+            org: partitions_info.org.clone(),
+            bucket: partitions_info.bucket.clone(),
+        })?;
+
+    let json = if partitions_info.names_only {
+        let partition_keys = db
+            .partition_keys()
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { database: &db_name })?;
+
+        serde_json::to_string(&partition_keys).expect("strings serialize to JSON")
+    } else {
+        let summaries = db
+            .partition_summaries()
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(Query { database: &db_name })?;
+
+        let summaries: Vec<_> = summaries
+            .into_iter()
+            .map(|summary| {
+                serde_json::json!({
+                    "key": summary.key,
+                    "size_bytes": summary.size_bytes,
+                    "table_count": summary.table_count,
+                    "row_count": summary.row_count,
+                })
+            })
+            .collect();
+        serde_json::to_string(&summaries).expect("summaries serialize to JSON")
+    };
+
+    let gzip = accepts_gzip(&req)?;
+    let mut response = hyper::Response::builder();
+    if gzip {
+        response = response.header(CONTENT_ENCODING, "gzip");
+    }
+
+    Ok(response
+        .body(encode_body(json.into(), gzip)?)
+        .expect("Should have been able to construct a response"))
+}
+
+#[derive(Deserialize, Debug)]
+/// Parameters of the request to the /tables endpoint
+struct TablesInfo {
+    org: String,
+    bucket: String,
+    partition: String,
+}
+
+/// Returns the names of the tables of a partition as a sorted JSON
+/// array, which is empty if there is no such partition
+#[tracing::instrument(level = "debug")]
+async fn list_tables<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let tables_info: TablesInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db_name = database_name(&tables_info.org, &tables_info.bucket)?;
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .context(BucketNotFound {
+            org: tables_info.org.clone(),
+            bucket: tables_info.bucket.clone(),
+        })?;
+
+    let table_names = db
+        .table_names_for_partition(&tables_info.partition)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+
+    let json = serde_json::to_string(&table_names).expect("strings serialize to JSON");
+
+    Ok(Some(json.into()))
+}
+
+#[derive(Deserialize, Debug)]
+/// Parameters of the request to the /schema endpoint
+struct SchemaInfo {
+    org: String,
+    bucket: String,
+    partition: String,
+    /// Only return the schema of this table, if specified
+    table: Option<String>,
+}
+
+/// Returns the columns of the tables of a partition as a JSON object
+/// mapping table names to arrays of columns, each with its name, Arrow
+/// data type and category (tag, field or time)
+#[tracing::instrument(level = "debug")]
+async fn table_schemas<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> Result<Option<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString {})?;
+
+    let schema_info: SchemaInfo =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db_name = database_name(&schema_info.org, &schema_info.bucket)?;
+
+    let db = server
+        .write_buffer
+        .db(&db_name)
+        .await
+        .context(BucketNotFound {
+            org: schema_info.org.clone(),
+            bucket: schema_info.bucket.clone(),
+        })?;
+
+    let mut table_schemas = db
+        .partition_table_schemas(&schema_info.partition)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database: &db_name })?;
+
+    if let Some(table) = &schema_info.table {
+        let columns = table_schemas.remove(table).context(TableNotFound {
+            table,
+            partition: &schema_info.partition,
+        })?;
+        table_schemas = std::iter::once((table.clone(), columns)).collect();
+    }
+
+    let json = table_schemas
+        .into_iter()
+        .map(|(table_name, columns)| {
+            let columns = columns
+                .iter()
+                .map(|column| {
+                    serde_json::json!({
+                        "name": column.name,
+                        "data_type": format!("{:?}", column.data_type),
+                        "category": column.category.name(),
+                    })
+                })
+                .collect();
+            (table_name, serde_json::Value::Array(columns))
+        })
+        .collect::<serde_json::Map<_, _>>();
+
+    Ok(Some(serde_json::Value::Object(json).to_string().into()))
+}
+
+/// Returns true if the `Accept-Encoding` header of `req` accepts gzip:
+/// if gzip, or failing that `*`, is listed with a non zero quality value
+fn accepts_gzip(req: &hyper::Request<Body>) -> Result<bool, ApplicationError> {
+    // clippy says the const needs to be assigned to a local variable:
+    // error: a `const` item with interior mutability should not be borrowed
+    let header_name = ACCEPT_ENCODING;
+    match req.headers().get(&header_name) {
+        None => Ok(false),
+        Some(accept_encoding) => {
+            let accept_encoding = accept_encoding.to_str().context(ReadingHeaderAsUtf8 {
+                header_name: header_name.as_str(),
+            })?;
+
+            let mut gzip = None;
+            let mut any = None;
+            for entry in accept_encoding.split(',') {
+                let mut parts = entry.split(';');
+                let coding = parts.next().unwrap_or_default().trim();
+                if coding.eq_ignore_ascii_case("gzip") {
+                    gzip = Some(quality_value(parts));
+                } else if coding == "*" {
+                    any = Some(quality_value(parts));
+                }
+            }
+            Ok(gzip.or(any).map_or(false, |q| q > 0.0))
+        }
+    }
+}
+
+/// Returns the quality value (`q=`) among the `params` of an
+/// `Accept-Encoding` entry, which is 1 if it has none. Malformed
+/// values count as 0, so the encoding isn't used.
+fn quality_value<'a>(params: impl Iterator<Item = &'a str>) -> f32 {
+    params
+        .filter_map(|param| {
+            let mut key_value = param.splitn(2, '=');
+            let key = key_value.next()?.trim();
+            let value = key_value.next()?.trim();
+            if key.eq_ignore_ascii_case("q") {
+                Some(value.parse().unwrap_or(0.0))
+            } else {
+                None
+            }
+        })
+        .next()
+        .unwrap_or(1.0)
+}
+
+/// Makes a response body of `body`, gzip compressed if `gzip` is set
+fn encode_body(body: Bytes, gzip: bool) -> Result<Body, ApplicationError> {
+    use libflate::gzip::Encoder;
+    use std::io::Write;
+
+    if !gzip {
+        return Ok(body.into());
+    }
+
+    let mut encoder = Encoder::new(Vec::new()).context(CompressingResponse)?;
+    encoder.write_all(&body).context(CompressingResponse)?;
+    let compressed = encoder
+        .finish()
+        .into_result()
+        .context(CompressingResponse)?;
+
+    Ok(compressed.into())
+}
+
+/// Renders the metrics of the server in the Prometheus text exposition
+/// format
+#[tracing::instrument(level = "debug")]
+async fn render_metrics<T: DatabaseStore>(server: Arc<AppServer<T>>) -> hyper::Response<Body> {
+    hyper::Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(server.metrics.render().into())
+        .expect("Should have been able to construct a response")
+}
+
+/// Reports whether the server is ready to handle requests: a 200 if
+/// all of its checks pass, a 503 if any fails
+#[tracing::instrument(level = "debug")]
+async fn health<T: DatabaseStore>(server: Arc<AppServer<T>>) -> hyper::Response<Body> {
+    let write_buffer = match server.write_buffer.health().await {
+        Ok(()) => serde_json::json!({"status": "pass"}),
+        Err(e) => {
+            error!(error = ?e, "Write buffer health check failed");
+            serde_json::json!({"status": "fail", "error": e.to_string()})
+        }
+    };
+
+    let healthy = write_buffer["status"] == "pass";
+    let checks = serde_json::json!({ "write_buffer": write_buffer });
+
+    let (status, status_code) = if healthy {
+        ("pass", StatusCode::OK)
+    } else {
+        ("fail", StatusCode::SERVICE_UNAVAILABLE)
+    };
+    let json = serde_json::json!({"status": status, "checks": checks}).to_string();
+
+    hyper::Response::builder()
+        .status(status_code)
+        .header(CONTENT_TYPE, "application/json")
+        .body(json.into())
+        .expect("Should have been able to construct a response")
+}
+
+/// The response header with the version of the server, which clients
+/// read to detect the server flavor
+const VERSION_HEADER: &str = "x-influxdb-version";
+
+/// The response header with the build of the server
+const BUILD_HEADER: &str = "x-influxdb-build";
+
+/// The build reported in `BUILD_HEADER`
+const BUILD: &str = "IOx";
+
+// Route to test that the server is alive. HEAD requests get an empty
+// (204) response, GET requests a PONG.
+#[tracing::instrument(level = "debug")]
+async fn ping(req: hyper::Request<Body>) -> Result<hyper::Response<Body>, ApplicationError> {
+    let response = if req.method() == Method::HEAD {
+        hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+    } else {
+        hyper::Response::builder().body("PONG".into())
+    };
+
+    let mut response = response.expect("Should have been able to construct a response");
+    let headers = response.headers_mut();
+    headers.insert(
+        VERSION_HEADER,
+        HeaderValue::from_static(env!("CARGO_PKG_VERSION")),
+    );
+    headers.insert(BUILD_HEADER, HeaderValue::from_static(BUILD));
+    Ok(response)
+}
+
+fn no_op(name: &str) -> Result<Option<Body>, ApplicationError> {
+    info!("NOOP: {}", name);
+    Ok(None)
+}
+
+/// Makes a response with `body`, or an empty (204) response if
+/// there is none
+fn body_response(body: Option<Body>) -> hyper::Response<Body> {
+    match body {
+        Some(body) => hyper::Response::builder()
+            .body(body)
+            .expect("Should have been able to construct a response"),
+        None => hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("Should have been able to construct a response"),
+    }
+}
+
+/// The response header echoing the trace id of requests that carry
+/// trace context
+const TRACE_ID_HEADER: &str = "x-trace-id";
+
+/// The request and response header with the id of a request, which is
+/// generated if the request has none
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The maximum length of request ids accepted from clients
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+pub async fn service<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+) -> http::Result<hyper::Response<Body>> {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|request_id| request_id.to_str().ok())
+        .map(str::trim)
+        .filter(|request_id| !request_id.is_empty() && request_id.len() <= MAX_REQUEST_ID_LENGTH)
+        .map_or_else(generate_request_id, String::from);
+    let trace_context = TraceContext::from_headers(req.headers());
+
+    // Handle the request in a span carrying its id and the caller's
+    // trace context, if any, so the logs and spans of this request
+    // can be attributed to it
+    let span = match &trace_context {
+        Some(trace_context) => info_span!(
+            "http_request",
+            request_id = %request_id,
+            trace_id = %trace_context.trace_id,
+            parent_span_id = %trace_context.parent_span_id,
+            sampled = trace_context.sampled,
+            trace_state = ?trace_context.trace_state
+        ),
+        None => info_span!("http_request", request_id = %request_id),
+    };
+    let mut response = handle(req, server, &request_id).instrument(span).await?;
+
+    let headers = response.headers_mut();
+    headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&request_id).expect("request id is a valid header value"),
+    );
+    if let Some(trace_context) = trace_context {
+        let trace_id = HeaderValue::from_str(&trace_context.trace_id)
+            .expect("trace id is a valid header value");
+        headers.insert(TRACE_ID_HEADER, trace_id);
+    }
+
+    Ok(response)
+}
+
+/// Returns a random (version 4) UUID
+fn generate_request_id() -> String {
+    let mut uuid: u128 = rand::random();
+    // the version and variant bits
+    uuid = (uuid & !(0xf << 76)) | (0x4 << 76);
+    uuid = (uuid & !(0x3 << 62)) | (0x2 << 62);
+
+    let hex = format!("{:032x}", uuid);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+async fn handle<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    server: Arc<AppServer<T>>,
+    request_id: &str,
+) -> http::Result<hyper::Response<Body>> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let app_server = Arc::clone(&server);
+    let origin = req
+        .headers()
+        .get(ORIGIN)
+        .and_then(|origin| origin.to_str().ok())
+        .map(String::from);
+
+    // each arm yields the route that labels the metrics of its request.
+    // CORS preflight requests are answered for any path, so they are
+    // counted as unmatched
+    let (route, response) = match (&method, uri.path()) {
+        (&Method::OPTIONS, _) if server.cors.is_some() => {
+            let cors = server.cors.as_ref().expect("CORS is configured");
+            (
+                metrics::UNMATCHED_ROUTE,
+                Ok(cors.preflight_response(origin.as_deref())),
+            )
+        }
+        (&Method::POST, route @ "/api/v2/write") => (route, write(req, server).await),
+        (&Method::POST, route @ "/api/v2/delete") => (route, delete(req, server).await),
+        (&Method::POST, route @ "/api/v1/import/csv") => (route, import_csv(req, server).await),
+        (&Method::POST, route @ "/api/v1/import/jsonl") => (route, import_jsonl(req, server).await),
+        (&Method::POST, route @ "/api/v1/import/parquet") => {
+            (route, import_parquet(req, server).await)
+        }
+        (&Method::POST, route @ "/api/put") => (route, opentsdb_put(req, server).await),
+        (&Method::POST, route @ "/api/v1/prom/write") => (route, prom_write(req, server).await),
+        (&Method::POST, route @ "/api/v1/prom/read") => {
+            (route, prom_read(req, server).await.map(body_response))
+        }
+        (&Method::POST, route @ "/api/v2/buckets") => {
+            (route, no_op("create bucket").map(body_response))
+        }
+        (&Method::GET, route @ "/api/v2/buckets") => {
+            (route, list_buckets(req, server).await.map(body_response))
+        }
+        (&Method::GET, route @ "/ping") | (&Method::HEAD, route @ "/ping") => {
+            (route, ping(req).await)
+        }
+        (&Method::GET, route @ "/health") => (route, Ok(health(server).await)),
+        (&Method::GET, route @ "/metrics") => (route, Ok(render_metrics(server).await)),
+        (&Method::GET, route @ "/api/v2/read") => (route, read(req, server).await),
+        (&Method::GET, route @ "/api/v1/export") => (route, export(req, server).await),
+        (&Method::GET, route @ "/api/v1/partitions") => (route, list_partitions(req, server).await),
+        (&Method::GET, route @ "/api/v1/tables") => {
+            (route, list_tables(req, server).await.map(body_response))
+        }
+        (&Method::GET, route @ "/api/v1/schema") => {
+            (route, table_schemas(req, server).await.map(body_response))
+        }
+        (&Method::PUT, route @ "/api/v1/schema_rules") => (
+            route,
+            put_schema_rules(req, server).await.map(body_response),
+        ),
+        (&Method::GET, route @ "/api/v1/schema_rules") => (
+            route,
+            get_schema_rules(req, server).await.map(body_response),
+        ),
+        (&Method::DELETE, route @ "/api/v1/schema_rules") => (
+            route,
+            delete_schema_rules(req, server).await.map(body_response),
+        ),
+        _ => (
+            metrics::UNMATCHED_ROUTE,
+            Err(ApplicationError::RouteNotFound {
+                method: method.clone(),
+                path: uri.to_string(),
+            }),
+        ),
+    };
+
+    let mut result = match response {
+        Ok(response) => response,
+        Err(e) => {
+            error!(error = ?e, method = ?method, uri = ?uri, "Error while handing request");
+            let mut json = e.to_json();
+            json["request_id"] = request_id.into();
+            let json = json.to_string();
+            let mut response = hyper::Response::builder()
+                .status(e.status_code())
+                .header(CONTENT_TYPE, "application/json");
+            if let ApplicationError::RateLimited { retry_after, .. } = &e {
+                // in whole seconds, rounded up
+                let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                response = response.header(RETRY_AFTER, retry_after);
+            }
+            response
+                .body(json.into())
+                .expect("Should have been able to construct a response")
+        }
+    };
+    if let Some(cors) = &app_server.cors {
+        if method != Method::OPTIONS {
+            cors.add_allow_origin(origin.as_deref(), &mut result);
+        }
+    }
+    app_server.metrics.record_request(route, result.status());
+    info!(method = ?method, uri = ?uri, status = ?result.status(), "Handled request");
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use arrow_deps::arrow;
+    use http::header;
+    use reqwest::{Client, Response};
+
+    use crate::server::test_utils::TestServer;
+    use storage::{test::TestDatabaseStore, DatabaseStore};
+
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T, E = Error> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn test_ping() -> Result<()> {
+        let server = TestServer::new();
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/ping", server.url())).send().await;
+
+        // Print the response so if the test fails, we have a log of what went wrong
+        check_response("ping", response, StatusCode::OK, "PONG").await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ping_head() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+
+        let response = client
+            .head(&format!("{}/ping", server.url()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers()[VERSION_HEADER],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(response.headers()[BUILD_HEADER], "IOx");
+        assert!(response.bytes().await?.is_empty());
+
+        // GET responses carry the same headers
+        let response = client.get(&format!("{}/ping", server.url())).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[VERSION_HEADER],
+            env!("CARGO_PKG_VERSION")
+        );
+        assert_eq!(response.headers()[BUILD_HEADER], "IOx");
+        assert_eq!(response.text().await?, "PONG");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_trace_context() -> Result<()> {
+        use test_helpers::tracing::TracingCapture;
+
+        let tracing_capture = TracingCapture::new();
+
+        let server = TestServer::new();
+        let server_url = server.url();
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/ping", server_url))
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["x-trace-id"],
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+
+        let logs = tracing_capture.to_string();
+        assert!(
+            logs.contains("trace_id = 4bf92f3577b34da6a3ce929d0e0e4736; ")
+                && logs.contains("parent_span_id = 00f067aa0ba902b7; "),
+            "trace context not found in logs: {}",
+            logs
+        );
+
+        // without trace context nothing is echoed
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("x-trace-id").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_health() -> Result<()> {
+        let server = TestServer::new();
+        let health_url = format!("{}/health", server.url());
+        let client = Client::new();
+
+        let response = client.get(&health_url).send().await;
+        check_response(
+            "health",
+            response,
+            StatusCode::OK,
+            r#"{"checks":{"write_buffer":{"status":"pass"}},"status":"pass"}"#,
+        )
+        .await;
+
+        server.store().set_health_error("disk unavailable").await;
+        let response = client.get(&health_url).send().await;
+        check_response(
+            "health_failing",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"checks":{"write_buffer":{"error":"Test database error:  disk unavailable","status":"fail"}},"status":"fail"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+
+        server
+            .write_lp("MyOrg", "MyBucket", "cpu,host=a usage=1.5 100")
+            .await;
+        server
+            .write_lp(
+                "MyOrg",
+                "MyBucket",
+                "cpu,host=b usage=2.5 100\ncpu,host=c usage=3.5 100",
+            )
+            .await;
+
+        // imports count as writes too
+        let response = client
+            .post(&format!("{}/api/put?org=MyOrg&bucket=MyBucket", server.url()))
+            .body(r#"{"metric": "sys.cpu", "timestamp": 1568756160, "value": 42, "tags": {"host": "a"}}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        test_db.set_query_values(vec![]).await;
+        let read_url = format!(
+            "{}/api/v2/read?org=MyOrg&bucket=MyBucket&sql_query=select%20*%20from%20cpu",
+            server.url()
+        );
+        let response = client.get(&read_url).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the query values were consumed, so this one fails
+        let response = client.get(&read_url).send().await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .get(&format!("{}/not/a/route", server.url()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = client
+            .get(&format!("{}/metrics", server.url()))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let metrics = response.text().await?;
+
+        for expected in &[
+            r#"iox_http_requests_total{route="/api/v2/write",status="2xx"} 2"#,
+            r#"iox_http_requests_total{route="/api/v2/read",status="2xx"} 1"#,
+            r#"iox_http_requests_total{route="/api/v2/read",status="4xx"} 1"#,
+            r#"iox_http_requests_total{route="/api/put",status="2xx"} 1"#,
+            r#"iox_http_requests_total{route="unmatched",status="4xx"} 1"#,
+            "iox_http_write_lines_total 4",
+            "iox_http_write_bytes_total 116",
+            "iox_http_query_duration_seconds_count 1",
+        ] {
+            assert!(
+                metrics.lines().any(|line| line == *expected),
+                "expected {} in:\n{}",
+                expected,
+                metrics
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write() -> Result<()> {
+        let server = TestServer::new();
+
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        // Check that the data got into the right bucket
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        // Ensure the same line protocol data gets through
+        assert_eq!(test_db.get_lines().await, vec![lp_data]);
+        Ok(())
+    }
+
+    /// Posts `body` to `url` in chunks of `chunk_size` bytes, with chunked
+    /// transfer encoding
+    async fn post_chunked(
+        url: &str,
+        body: &str,
+        chunk_size: usize,
+    ) -> Result<hyper::Response<Body>> {
+        let chunks: Vec<_> = body
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(Bytes::copy_from_slice)
+            .collect();
+        let (mut sender, body) = Body::channel();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                // the server may respond before reading the whole body
+                if sender.send_data(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let request = hyper::Request::post(url).body(body)?;
+        Ok(hyper::Client::new().request(request).await?)
+    }
+
+    #[tokio::test]
+    async fn test_write_streamed() -> Result<()> {
+        let server = TestServer::new();
+        let write_url = format!(
+            "{}/api/v2/write?org=MyOrg&bucket=MyBucket&partial=true",
+            server.url()
+        );
+
+        // several batches of lines, split across chunks in the middle of
+        // lines and of multi-byte characters
+        let lines: Vec<_> = (0..2500)
+            .map(|i| format!("cpu,host=hö{} count={}i {}", i, i, i))
+            .collect();
+        let lp_data = lines.join("\n");
+        let response = post_chunked(&write_url, &lp_data, 777).await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[LINES_WRITTEN_HEADER], "2500");
+        assert_eq!(
+            response.headers()[BYTES_READ_HEADER],
+            lp_data.len().to_string().as_str()
+        );
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.get_lines().await, lines);
+
+        // line numbers count from the start of the body, not the batch
+        let mut lp_data = "cpu,host=a count=1i 100\n".repeat(2000);
+        lp_data.push_str("cpu,host=b count=1.2.3 100\ncpu,host=c count=3i 100");
+        let response = post_chunked(&write_url, &lp_data, 1000).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        // only the accepted lines count as written
+        assert_eq!(response.headers()[LINES_WRITTEN_HEADER], "2001");
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["message"], "partial write: rejected 1 of 2002 lines");
+        assert_eq!(json["accepted"], 2001);
+        assert_eq!(json["rejected"][0]["line_number"], 2001);
+        assert_eq!(test_db.get_lines().await.len(), 2500 + 2001);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_is_atomic() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.max_lines_per_write = Some(1500);
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let write_url = format!("{}/api/v2/write?org=MyOrg&bucket=MyBucket", server.url());
+
+        // more good lines than fit in a batch, then a bad one
+        let mut lp_data = "cpu,host=a count=1i 100\n".repeat(1200);
+        lp_data.push_str("cpu,host=b count=1.2.3 100\n");
+        let response = post_chunked(&write_url, &lp_data, 1000).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["line_number"], 1201);
+
+        // nothing was written
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert!(test_db.get_lines().await.is_empty());
+
+        // nor when the body turns out to have too many lines
+        let lp_data = "cpu,host=a count=1i 100\n".repeat(2000);
+        let response = post_chunked(&write_url, &lp_data, 1000).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(test_db.get_lines().await.is_empty());
+
+        // partial writes are streamed, so the error says how many lines
+        // were written before it
+        let response = post_chunked(&format!("{}&partial=true", write_url), &lp_data, 1000).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(json["code"], "invalid");
+        assert_eq!(json["accepted"], 1000);
+        assert_eq!(
+            json["message"],
+            "Write has 2000 lines, exceeding the limit of 1500 lines \
+             (1000 lines were written before the error)"
+        );
+        assert_eq!(test_db.get_lines().await.len(), 1000);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_error_codes() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?org=MyOrg&bucket=MyBucket",
+                server.url()
+            ))
+            .body("cpu,host=a usage=")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(json["code"], "invalid");
+        assert!(json["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error parsing line protocol at line 1"));
+
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=NotMyBucket&sql_query=select%20*%20from%20cpu",
+                server.url()
+            ))
+            .header(REQUEST_ID_HEADER, "my-request")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "code": "not found",
+                "message": "Bucket NotMyBucket not found in org MyOrg",
+                "request_id": "my-request",
+            })
+        );
+
+        // failures of the database are server errors (the test database
+        // fails deletes without a saved delete count)
+        server
+            .store()
+            .db_or_create("MyOrg_MyBucket")
+            .await
+            .expect("created database");
+        let response = client
+            .post(&format!(
+                "{}/api/v2/delete?org=MyOrg&bucket=MyBucket",
+                server.url()
+            ))
+            .body(r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z"}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(json["code"], "internal error");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        let accepts = |accept_encoding: Option<&str>| {
+            let mut req = hyper::Request::builder();
+            if let Some(accept_encoding) = accept_encoding {
+                req = req.header(header::ACCEPT_ENCODING, accept_encoding);
+            }
+            accepts_gzip(&req.body(Body::empty()).unwrap()).unwrap()
+        };
+
+        assert!(!accepts(None));
+        assert!(accepts(Some("gzip")));
+        assert!(accepts(Some("deflate, GZIP;q=0.5")));
+        assert!(accepts(Some("*")));
+        assert!(accepts(Some("br;q=1.0, *;q=0.1")));
+        assert!(!accepts(Some("deflate, br")));
+        assert!(!accepts(Some("gzip;q=0")));
+        assert!(!accepts(Some("gzip; q=0.000")));
+        assert!(!accepts(Some("*;q=0")));
+        // an explicit gzip entry takes precedence over *
+        assert!(!accepts(Some("gzip;q=0, *")));
+        assert!(accepts(Some("*;q=0, gzip")));
+        assert!(!accepts(Some("gzip;q=high")));
+    }
+
+    #[test]
+    fn test_formatting_results_error_codes() {
+        // only an unknown format is the fault of the client
+        let e = ApplicationError::FormattingResults {
+            source: read_format::Error::UnknownFormat {
+                format: "xml".into(),
+            },
+        };
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(e.error_code(), "invalid");
+
+        let e = ApplicationError::FormattingResults {
+            source: read_format::Error::UnsupportedDataType {
+                column_name: "x".into(),
+                data_type: arrow::datatypes::DataType::Binary,
+            },
+        };
+        assert_eq!(e.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(e.error_code(), "internal error");
+    }
+
+    #[tokio::test]
+    async fn test_request_id() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+
+        // echoed on successful, failed and unrouted requests
+        for path in &["/ping", "/api/v2/read", "/not/a/route"] {
+            let response = client
+                .get(&format!("{}{}", server.url(), path))
+                .header(REQUEST_ID_HEADER, "my-request")
+                .send()
+                .await?;
+            assert_eq!(response.headers()[REQUEST_ID_HEADER], "my-request");
+        }
+
+        // and generated if the request has none
+        let mut request_ids = std::collections::BTreeSet::new();
+        for path in &["/ping", "/api/v2/read", "/not/a/route"] {
+            let response = client
+                .get(&format!("{}{}", server.url(), path))
+                .send()
+                .await?;
+            let request_id = response.headers()[REQUEST_ID_HEADER].to_str()?.to_string();
+            assert_eq!(request_id.len(), 36, "{}", request_id);
+
+            if !response.status().is_success() {
+                let json: serde_json::Value = response.json().await?;
+                assert_eq!(json["request_id"], request_id.as_str());
+            }
+            request_ids.insert(request_id);
+        }
+        assert_eq!(request_ids.len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_parse_error_line_number() -> Result<()> {
+        let server = TestServer::new();
+        let write_url = format!("{}/api/v2/write?org=MyOrg&bucket=MyBucket", server.url());
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a usage=1.5 100\n\
+                       \n\
+                       cpu,host=b usage=1.2.3 100\n\
+                       cpu,host=c usage=2.5 100";
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response(
+            "write_parse_error",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","line":"cpu,host=b usage=1.2.3 100","line_number":3,"message":"Error parsing line protocol at line 3: Could not parse entire line. Found trailing content: '.3 100'"}"#,
+        )
+        .await;
+
+        // long lines are truncated
+        let measurement = "m".repeat(150);
+        let response = client
+            .post(&write_url)
+            .body(format!("{} usage=1.2.3", measurement))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = response.json().await?;
+        assert_eq!(json["line_number"], 1);
+        assert_eq!(json["line"], format!("{}...", &measurement[..100]));
+
+        // nothing was written
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert!(test_db.get_lines().await.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_write() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a usage=1.5 100\n\
+                       cpu,host=b usage=1.2.3 100\n\
+                       cpu,host=c usage=2.5 100\n\
+                       cpu,host=d usage=4.5.6 100\n\
+                       cpu,host=e usage=3.5 100";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?org=MyOrg&bucket=MyBucket&partial=true",
+                server.url()
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+        check_response(
+            "partial_write",
+            response,
+            StatusCode::BAD_REQUEST,
+            concat!(
+                r#"{"accepted":3,"code":"invalid","message":"partial write: rejected 2 of 5 lines","rejected":["#,
+                r#"{"code":"invalid","line":"cpu,host=b usage=1.2.3 100","line_number":2,"message":"Error parsing line protocol at line 2: Could not parse entire line. Found trailing content: '.3 100'"},"#,
+                r#"{"code":"invalid","line":"cpu,host=d usage=4.5.6 100","line_number":4,"message":"Error parsing line protocol at line 4: Could not parse entire line. Found trailing content: '.6 100'"}"#,
+                r#"]}"#
+            ),
+        )
+        .await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "cpu,host=a usage=1.5 100",
+                "cpu,host=c usage=2.5 100",
+                "cpu,host=e usage=3.5 100",
+            ]
+        );
+
+        Ok(())
+    }
+
+    fn gzip_str(s: &str) -> Vec<u8> {
+        use libflate::gzip::Encoder;
+        use std::io::Write;
+
+        let mut encoder = Encoder::new(Vec::new()).expect("creating gzip encoder");
+        write!(encoder, "{}", s).expect("writing into encoder");
+        encoder
+            .finish()
+            .into_result()
+            .expect("successfully encoding gzip data")
+    }
+
+    fn zlib_str(s: &str) -> Vec<u8> {
+        use libflate::zlib::Encoder;
+        use std::io::Write;
+
+        let mut encoder = Encoder::new(Vec::new()).expect("creating zlib encoder");
+        write!(encoder, "{}", s).expect("writing into encoder");
+        encoder
+            .finish()
+            .into_result()
+            .expect("successfully encoding zlib data")
+    }
+
+    fn deflate_str(s: &str) -> Vec<u8> {
+        use libflate::deflate::Encoder;
+        use std::io::Write;
+
+        let mut encoder = Encoder::new(Vec::new());
+        write!(encoder, "{}", s).expect("writing into encoder");
+        encoder
+            .finish()
+            .into_result()
+            .expect("successfully encoding deflate data")
+    }
+
+    fn gunzip_str(data: &[u8]) -> String {
+        use libflate::gzip::Decoder;
+        use std::io::Read;
+
+        let mut decoder = Decoder::new(data).expect("creating gzip decoder");
+        let mut s = String::new();
+        decoder
+            .read_to_string(&mut s)
+            .expect("successfully decoding gzip data");
+        s
+    }
+
+    #[tokio::test]
+    async fn test_gzip_write() -> Result<()> {
+        let server = TestServer::new();
+        let server_url = server.url();
+        let test_storage = server.store();
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data encoded with gzip
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(gzip_str(lp_data))
+            .send()
+            .await;
+
+        check_write_response("write", response, 1, lp_data).await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        // Ensure the same line protocol data gets through
+        assert_eq!(test_db.get_lines().await, vec![lp_data]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_deflate_write() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // both zlib wrapped and raw deflate data are accepted
+        for body in vec![zlib_str(lp_data), deflate_str(lp_data)] {
+            let response = client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    server.url()
+                ))
+                .header(header::CONTENT_ENCODING, "deflate")
+                .body(body)
+                .send()
+                .await;
+
+            check_write_response("write", response, 1, lp_data).await;
+        }
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.get_lines().await, vec![lp_data, lp_data]);
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server.url()
+            ))
+            .header(header::CONTENT_ENCODING, "br")
+            .body(lp_data)
+            .send()
+            .await;
+        check_response(
+            "write_unsupported_encoding",
+            response,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            r#"{"code":"unsupported media type","message":"Invalid content encoding: br, expected one of gzip, deflate"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cors() -> Result<()> {
+        let origin = "https://dashboard.example.com";
+        let read_path = "/api/v2/read?org=MyOrg&bucket=MyBucket&sql_query=select%20*%20from%20cpu";
+        let client = Client::new();
+
+        // disabled by default
+        let server = TestServer::new();
+        let response = client
+            .request(Method::OPTIONS, &format!("{}{}", server.url(), read_path))
+            .header(header::ORIGIN, origin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.cors = Some(CorsConfig::new(vec![origin.to_string()]));
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let read_url = format!("{}{}", server.url(), read_path);
+
+        let response = client
+            .request(Method::OPTIONS, &read_url)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let headers = response.headers();
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST, PUT, DELETE, OPTIONS"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "Accept, Accept-Encoding, Authorization, Content-Encoding, Content-Type"
+        );
+
+        // other origins are not allowed
+        let response = client
+            .request(Method::OPTIONS, &read_url)
+            .header(header::ORIGIN, "https://evil.example.com")
+            .send()
+            .await?;
+        assert!(response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+
+        let test_db = server.store().db_or_create("MyOrg_MyBucket").await?;
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .get(&read_url)
+            .header(header::ORIGIN, origin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            origin
+        );
+
+        // preflight requests are counted as unmatched, whatever their
+        // path
+        let response = client
+            .request(Method::OPTIONS, &format!("{}/not/a/route", server.url()))
+            .header(header::ORIGIN, origin)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let metrics = client
+            .get(&format!("{}/metrics", server.url()))
+            .send()
+            .await?
+            .text()
+            .await?;
+        for expected in &[
+            r#"iox_http_requests_total{route="/api/v2/read",status="2xx"} 1"#,
+            r#"iox_http_requests_total{route="unmatched",status="2xx"} 3"#,
+        ] {
+            assert!(
+                metrics.lines().any(|line| line == *expected),
+                "expected {} in:\n{}",
+                expected,
+                metrics
+            );
+        }
+        assert!(!metrics.contains("/not/a/route"), "{}", metrics);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_timeout() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.query_timeout = Duration::from_millis(100);
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let client = Client::new();
+
+        let test_db = server.store().db_or_create("MyOrg_MyBucket").await?;
+        test_db.set_query_delay(Duration::from_secs(1)).await;
+
+        // requests can't ask for more than the server's query timeout
+        for params in &["", "&timeout_seconds=60"] {
+            test_db.set_query_values(vec![]).await;
+            let response = client
+                .get(&format!(
+                    "{}/api/v2/read?org=MyOrg&bucket=MyBucket&sql_query=select%20*%20from%20cpu{}",
+                    server.url(),
+                    params
+                ))
+                .send()
+                .await;
+            check_response(
+                "read_timeout",
+                response,
+                StatusCode::GATEWAY_TIMEOUT,
+                r#"{"code":"unavailable","message":"Query did not finish within 100ms"}"#,
+            )
+            .await;
+        }
+
+        test_db.set_query_delay(Duration::from_millis(0)).await;
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?org=MyOrg&bucket=MyBucket&sql_query=select%20*%20from%20cpu",
+                server.url()
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_db_parameter() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let client = Client::new();
+
+        // the database name is used verbatim
+        let lp_data = "cpu,host=a usage=1.5 100";
+        let response = client
+            .post(&format!("{}/api/v2/write?db=MyOrg_MyBucket", server.url()))
+            .body(lp_data)
+            .send()
+            .await;
+        check_write_response("write_db", response, 1, lp_data).await;
+
+        let sql_query = "sql_query=select%20host,%20usage%20from%20cpu&format=csv";
+        for params in &["db=MyOrg_MyBucket", "org=MyOrg&bucket=MyBucket"] {
+            let response = client
+                .get(&format!(
+                    "{}/api/v2/read?{}&{}",
+                    server.url(),
+                    params,
+                    sql_query
+                ))
+                .send()
+                .await;
+            check_response("read_db", response, StatusCode::OK, "host,usage\na,1.5\n").await;
+        }
+
+        let response = client
+            .get(&format!(
+                "{}/api/v2/read?db=NotMyDb&{}",
+                server.url(),
+                sql_query
+            ))
+            .send()
+            .await;
+        check_response(
+            "read_missing_db",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"Database NotMyDb not found"}"#,
+        )
+        .await;
+
+        // exactly one way of naming the database
+        for params in &["org=MyOrg&bucket=MyBucket&db=MyOrg_MyBucket", "org=MyOrg"] {
+            let response = client
+                .post(&format!("{}/api/v2/write?{}", server.url(), params))
+                .body(lp_data)
+                .send()
+                .await;
+            check_response(
+                "write_invalid_db_params",
+                response,
+                StatusCode::BAD_REQUEST,
+                r#"{"code":"invalid","message":"Expected either the org and bucket parameters or the db parameter"}"#,
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_database_names() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+        let lp_data = "cpu,host=a usage=1 100";
+
+        // a bucket may contain `_`, so an org can't
+        let response = client
+            .post(&format!("{}/api/v2/write?org=a&bucket=b_c", server.url()))
+            .body(lp_data)
+            .send()
+            .await;
+        check_write_response("write_bucket_underscore", response, 1, lp_data).await;
+
+        let cases = &[
+            (
+                "org=a_b&bucket=c",
+                r#"Invalid org name \"a_b\": must not contain '_'"#,
+            ),
+            (
+                "org=..%2Fetc&bucket=c",
+                r#"Invalid org name \"../etc\": must not contain path separators"#,
+            ),
+            (
+                "org=a&bucket=",
+                r#"Invalid bucket name \"\": must not be empty"#,
+            ),
+            (
+                "org=a&bucket=b%0A",
+                r#"Invalid bucket name \"b\\n\": must not contain control characters"#,
+            ),
+            (
+                "db=..",
+                r#"Invalid database name \"..\": must not contain '..'"#,
+            ),
+        ];
+        for (params, message) in cases {
+            let response = client
+                .post(&format!("{}/api/v2/write?{}", server.url(), params))
+                .body(lp_data)
+                .send()
+                .await;
+            check_response(
+                "write_invalid_name",
+                response,
+                StatusCode::BAD_REQUEST,
+                &format!(r#"{{"code":"invalid","message":"{}"}}"#, message),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_rate_limit() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.rate_limiter = Some(RateLimiter::new(1, 1));
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a usage=1 100";
+        let write_url =
+            |org: &str| format!("{}/api/v2/write?bucket=MyBucket&org={}", server.url(), org);
+
+        let response = client.post(&write_url("MyOrg")).body(lp_data).send().await;
+        check_write_response("first_write", response, 1, lp_data).await;
+
+        let response = client
+            .post(&write_url("MyOrg"))
+            .body(lp_data)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+        let body = response.text().await?;
+        assert!(
+            body.starts_with(
+                r#"{"code":"too many requests","message":"Write rate limit of org MyOrg exceeded"#
+            ),
+            "{}",
+            body
+        );
+
+        // nor may it import
+        let response = client
+            .post(&format!(
+                "{}/api/put?bucket=MyBucket&org=MyOrg",
+                server.url()
+            ))
+            .body(r#"{"metric": "sys.cpu", "timestamp": 1, "value": 1, "tags": {"host": "a"}}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // other orgs are not limited by the writes of MyOrg
+        let response = client
+            .post(&write_url("OtherOrg"))
+            .body(lp_data)
+            .send()
+            .await;
+        check_write_response("other_org_write", response, 1, lp_data).await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.get_lines().await, vec![lp_data]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_request_size() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.max_request_size = 50;
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server.url());
+
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a usage=1 100";
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_write_response("write_under_limit", response, 1, lp_data).await;
+
+        let response = client
+            .post(&write_url)
+            .body(format!("{}\n{}\n{}", lp_data, lp_data, lp_data))
+            .send()
+            .await;
+        check_response(
+            "write_over_limit",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"code":"request too large","message":"Body exceeds limit of 50 bytes"}"#,
+        )
+        .await;
+
+        // without a Content-Length, the limit applies to the chunks read
+        let response = post_chunked(
+            &write_url,
+            &format!("{}\n{}\n{}", lp_data, lp_data, lp_data),
+            10,
+        )
+        .await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        assert_eq!(
+            without_request_id(str::from_utf8(&body)?),
+            r#"{"code":"request too large","message":"Body exceeds limit of 50 bytes"}"#
+        );
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.get_lines().await, vec![lp_data]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_content_length_over_limit() {
+        // the body never arrives, so reading it would wait forever
+        let (_sender, body) = Body::channel();
+        let req = hyper::Request::post("/api/v2/write")
+            .header(header::CONTENT_LENGTH, "51")
+            .body(body)
+            .unwrap();
+
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), read_body(req, 50))
+            .await
+            .expect("Should have rejected the request without reading its body");
+        assert!(
+            matches!(
+                result,
+                Err(ApplicationError::RequestSizeExceeded { max_body_size: 50 })
+            ),
+            "{:?}",
+            result
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_lines_per_write() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.max_lines_per_write = Some(2);
+        let server = TestServer::with_app_server(Arc::new(app_server));
+        let client = Client::new();
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server.url());
+
+        let lp_data = "cpu,host=a usage=1 100\ncpu,host=b usage=2 100";
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_write_response("write_within_limit", response, 2, lp_data).await;
+
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=a usage=1 200\ncpu,host=b usage=2 200\ncpu,host=c usage=3 200")
+            .send()
+            .await;
+        check_response(
+            "write_over_limit",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Write has 3 lines, exceeding the limit of 2 lines"}"#,
+        )
+        .await;
+
+        // the limit applies to imports too
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/csv?bucket=MyBucket&org=MyOrg&measurement=cpu\
+                 &time_column=ts&time_format=rfc3339",
+                server.url()
+            ))
+            .body(
+                "ts,usage\n\
+                 2020-11-01T00:00:00Z,1\n\
+                 2020-11-01T00:00:01Z,2\n\
+                 2020-11-01T00:00:02Z,3\n",
+            )
+            .send()
+            .await;
+        check_response(
+            "import_over_limit",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Write has 3 lines, exceeding the limit of 2 lines"}"#,
+        )
+        .await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(test_db.get_lines().await.len(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_body_stream_error() -> Result<()> {
+        let server = Arc::new(AppServer::new(Arc::new(TestDatabaseStore::new())));
+
+        // partial uncompressed bodies are streamed, others read whole
+        for (query, content_encoding) in &[("&partial=true", None), ("", None), ("", Some("gzip"))]
+        {
+            // like a client resetting the connection after the first chunk
+            let chunks: Vec<std::result::Result<_, std::io::Error>> = vec![
+                Ok(Bytes::from("cpu,host=a usage=1 100\n")),
+                Err(std::io::ErrorKind::ConnectionReset.into()),
+            ];
+            let mut req =
+                hyper::Request::post(format!("/api/v2/write?org=MyOrg&bucket=MyBucket{}", query));
+            if let Some(content_encoding) = content_encoding {
+                req = req.header(header::CONTENT_ENCODING, *content_encoding);
+            }
+            let req = req.body(Body::wrap_stream(futures::stream::iter(chunks)))?;
+
+            let response = service(req, Arc::clone(&server)).await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            let body: serde_json::Value = serde_json::from_slice(&body)?;
+            assert_eq!(body["code"], "invalid");
+            assert!(
+                body["message"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("Error reading request body: "),
+                "{}",
+                body
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gzip_write_decompressed_size_limit() -> Result<()> {
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.max_request_size = 1000;
+        let server = TestServer::with_app_server(Arc::new(app_server));
+
+        // compresses to far less than the limit, but decompresses to
+        // far more
+        let lp_data = "cpu,host=a usage=1 100\n".repeat(10_000);
+        let body = gzip_str(&lp_data);
+        assert!(body.len() < 1000);
+
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server.url()
+            ))
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(body)
+            .send()
+            .await;
+        check_response(
+            "gzip_write_over_limit",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"code":"request too large","message":"Decompressed body exceeds limit of 1000 bytes"}"#,
+        )
+        .await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert!(test_db.get_lines().await.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_precision() -> Result<()> {
+        let server = TestServer::new();
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server.url());
+
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a usage=1 1568756160\ncpu,host=b usage=2";
+        let response = client
+            .post(&format!("{}&precision=s", write_url))
+            .body(lp_data)
+            .send()
+            .await;
+        check_write_response("write_seconds", response, 2, lp_data).await;
+
+        let lp_data = "cpu,host=a usage=3 1568756160123";
+        let response = client
+            .post(&format!("{}&precision=ms", write_url))
+            .body(lp_data)
+            .send()
+            .await;
+        check_write_response("write_milliseconds", response, 1, lp_data).await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        // lines without a timestamp are unchanged
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "cpu,host=a usage=1 1568756160000000000",
+                "cpu,host=b usage=2",
+                "cpu,host=a usage=3 1568756160123000000",
+            ]
+        );
+
+        let response = client
+            .post(&format!("{}&precision=m", write_url))
+            .body("cpu,host=a usage=1 1568756160")
+            .send()
+            .await;
+        check_response(
+            "write_invalid_precision",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Invalid precision 'm', expected one of s, ms, us or ns"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}&precision=s", write_url))
+            .body("cpu,host=a usage=1 1568756160000000000")
+            .send()
+            .await;
+        check_response(
+            "write_timestamp_out_of_range",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Timestamp 1568756160000000000 is out of range for precision 's'"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_formats() -> Result<()> {
+        use arrow::{
+            array::{ArrayRef, Float64Array, StringArray},
+            datatypes::{DataType, Field, Schema},
+            record_batch::RecordBatch,
+        };
+
+        let server = TestServer::new();
+        let read_url = format!(
+            "{}/api/v2/read?org=MyOrg&bucket=MyBucket&sql_query=select%20*%20from%20cpu",
+            server.url()
+        );
+        let test_db = server.store().db_or_create("MyOrg_MyBucket").await?;
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("usage", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+                Arc::new(Float64Array::from(vec![1.5, 2.5])) as ArrayRef,
+            ],
+        )?;
+        let expected_json = serde_json::json!([
+            {"host": "a", "usage": 1.5},
+            {"host": "b", "usage": 2.5},
+        ]);
+
+        let client = Client::new();
+
+        // pretty printing is the default
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let response = client.get(&read_url).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response.text().await?.trim(),
+            "+------+-------+\n\
+             | host | usage |\n\
+             +------+-------+\n\
+             | a    | 1.5   |\n\
+             | b    | 2.5   |\n\
+             +------+-------+"
+        );
+
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let response = client
+            .get(&format!("{}&format=json", read_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response.json::<serde_json::Value>().await?, expected_json);
+
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let response = client
+            .get(&read_url)
+            .header(header::ACCEPT, "application/json")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<serde_json::Value>().await?, expected_json);
+
+        // CSV has a single header row for all batches
+        test_db
+            .set_query_values(vec![batch.clone(), batch.clone()])
+            .await;
+        let response = client
+            .get(&read_url)
+            .header(header::ACCEPT, "text/csv")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "text/csv");
+        let body = response.text().await?;
+        let mut reader = csv::Reader::from_reader(body.as_bytes());
+        let headers = reader
+            .headers()?
+            .iter()
+            .map(String::from)
+            .collect::<Vec<_>>();
+        assert_eq!(headers, vec!["host", "usage"]);
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let rows = records
+            .iter()
+            .map(|record| record.iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                vec!["a", "1.5"],
+                vec!["b", "2.5"],
+                vec!["a", "1.5"],
+                vec!["b", "2.5"],
+            ]
+        );
+
+        let batches = vec![batch.clone(), batch.clone()];
+        test_db.set_query_values(batches.clone()).await;
+        let response = client
+            .get(&read_url)
+            .header(header::ACCEPT, "application/vnd.apache.arrow.stream")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[CONTENT_TYPE],
+            "application/vnd.apache.arrow.stream"
+        );
+        let body = response.bytes().await?;
+        let reader = arrow::ipc::reader::StreamReader::try_new(body.as_ref())?;
+        assert_eq!(reader.schema(), batch.schema());
+        let read_batches = reader.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(read_batches.len(), batches.len());
+        for (read_batch, batch) in read_batches.iter().zip(&batches) {
+            assert_eq!(read_batch.columns(), batch.columns());
+        }
+
+        // gzip compressed when the client accepts it
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let response = client
+            .get(&format!("{}&format=json", read_url))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let json = gunzip_str(&response.bytes().await?);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json)?,
+            expected_json
+        );
+
+        let response = client.get(&format!("{}&format=xml", read_url)).send().await;
+        check_response(
+            "read_unknown_format",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Error formatting query results: Unknown format 'xml', expected one of pretty, json, csv or arrow"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_query_cache() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let mut app_server = AppServer::new(storage);
+        app_server.query_cache = Some(QueryCache::new(10, std::time::Duration::from_secs(60)));
+        let server = TestServer::with_app_server(Arc::new(app_server));
+
+        let client = Client::new();
+
+        server
+            .write_lp("MyOrg", "MyBucket", "h2o,state=MA temp=70.4 100")
+            .await;
+
+        /// Returns the `X-Cache` header, whether there is an `Age`
+        /// header and the body of the response to the query
+        async fn read(client: &Client, server_url: &str) -> (String, bool, String) {
+            let response = client
+                .get(&format!("{}/api/v2/read", server_url))
+                .query(&[
+                    ("org", "MyOrg"),
+                    ("bucket", "MyBucket"),
+                    ("sql_query", r#"select state, temp, "time" from h2o"#),
+                ])
+                .send()
+                .await
+                .expect("sending read request");
+            assert_eq!(response.status(), StatusCode::OK);
+            let cache = response.headers()[CACHE_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let has_age = response.headers().contains_key(header::AGE);
+            let body = response.text().await.expect("reading read response");
+            (cache, has_age, body.trim_end().to_string())
+        }
+
+        let one_row = "+-------+------+------+\n\
+                       | state | temp | time |\n\
+                       +-------+------+------+\n\
+                       | MA    | 70.4 | 100  |\n\
+                       +-------+------+------+";
+
+        assert_eq!(
+            read(&client, server.url()).await,
+            ("miss".to_string(), false, one_row.to_string())
+        );
+        assert_eq!(
+            read(&client, server.url()).await,
+            ("hit".to_string(), true, one_row.to_string())
+        );
+
+        // the write invalidates the cached response before it is acknowledged
+        server
+            .write_lp("MyOrg", "MyBucket", "h2o,state=NY temp=68.2 200")
+            .await;
+
+        let two_rows = "+-------+------+------+\n\
+                        | state | temp | time |\n\
+                        +-------+------+------+\n\
+                        | MA    | 70.4 | 100  |\n\
+                        | NY    | 68.2 | 200  |\n\
+                        +-------+------+------+";
+        assert_eq!(
+            read(&client, server.url()).await,
+            ("miss".to_string(), false, two_rows.to_string())
+        );
+        assert_eq!(
+            read(&client, server.url()).await,
+            ("hit".to_string(), true, two_rows.to_string())
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_schema_rules_api() -> Result<()> {
+        let server = TestServer::new();
+        let rules_url = format!(
+            "{}/api/v1/schema_rules?org=MyOrg&bucket=MyBucket",
+            server.url()
+        );
+
+        let client = Client::new();
+
+        let response = client.get(&rules_url).send().await;
+        check_response(
+            "get_missing_schema_rules",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"No schema rules for bucket MyBucket in org MyOrg"}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&rules_url)
+            .body(r#"{"measurements": {"cpu": {"fields": {"usage": "double"}}}}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .put(&rules_url)
+            .body(r#"{"measurements": {"cpu": {"tags": ["host"], "fields": {"usage": "float"}}}}"#)
+            .send()
+            .await;
+        check_response("put_schema_rules", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client.get(&rules_url).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let rules: serde_json::Value = response.json().await?;
+        assert_eq!(
+            rules,
+            serde_json::json!({
+                "policy": "reject",
+                "measurements": {"cpu": {"tags": ["host"], "fields": {"usage": "float"}}}
+            })
+        );
+
+        let response = client.delete(&rules_url).send().await;
+        check_response("delete_schema_rules", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client.get(&rules_url).send().await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    /// Lines for `SCHEMA_RULES`: the first conforms, the others don't
+    const SCHEMA_RULES_LINES: &str = "cpu,host=b usage=2.5 200\n\
+                                      cpuu,host=b usage=2.5 200\n\
+                                      cpu,host=b,hots=c usage=2.5 200\n\
+                                      cpu,host=b usage=3i 300";
+
+    /// Sets the schema rules of MyOrg's MyBucket with `policy` and
+    /// returns a server backed by the write buffer
+    async fn schema_rules_server(
+        policy: &str,
+    ) -> Result<(
+        TestServer<write_buffer::WriteBufferDatabases>,
+        tempfile::TempDir,
+    )> {
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(write_buffer::WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+
+        let rules = serde_json::json!({
+            "policy": policy,
+            "measurements": {"cpu": {"tags": ["host"], "fields": {"usage": "float"}}}
+        });
+        let response = Client::new()
+            .put(&format!(
+                "{}/api/v1/schema_rules?org=MyOrg&bucket=MyBucket",
+                server.url()
+            ))
+            .body(rules.to_string())
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        Ok((server, dir))
+    }
+
+    /// Writes `SCHEMA_RULES_LINES` and asserts the partial write
+    /// response reports the violations and `accepted` written lines
+    async fn write_schema_rules_lines(
+        server: &TestServer<write_buffer::WriteBufferDatabases>,
+        message: &str,
+        accepted: usize,
+    ) -> Result<()> {
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?org=MyOrg&bucket=MyBucket",
+                server.url()
+            ))
+            .body(SCHEMA_RULES_LINES)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "code": "invalid",
+                "message": message,
+                "accepted": accepted,
+                "rejected": [
+                    {
+                        "code": "invalid",
+                        "line": "cpuu,host=b usage=2.5 200",
+                        "message": "Measurement 'cpuu' is not allowed"
+                    },
+                    {
+                        "code": "invalid",
+                        "line": "cpu,host=b,hots=c usage=2.5 200",
+                        "message": "Tag key 'hots' is not allowed in measurement 'cpu'"
+                    },
+                    {
+                        "code": "invalid",
+                        "line": "cpu,host=b usage=3i 300",
+                        "message": "Field 'usage' of measurement 'cpu' must be float, not integer"
+                    },
+                ]
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_schema_rules_reject() -> Result<()> {
+        let (server, _dir) = schema_rules_server("reject").await?;
+
+        // conforming writes are unaffected
+        server
+            .write_lp("MyOrg", "MyBucket", "cpu,host=a usage=1.5 100")
+            .await;
+
+        write_schema_rules_lines(
+            &server,
+            "write rejected: 3 of 4 lines violate the schema rules",
+            0,
+        )
+        .await?;
+
+        // imports are checked against the rules too
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v1/import/csv?org=MyOrg&bucket=MyBucket&measurement=mem\
+                 &time_column=ts&time_format=rfc3339",
+                server.url()
+            ))
+            .body("ts,usage\n2020-11-01T00:00:00Z,2.5\n")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = response.json().await?;
+        assert_eq!(
+            body["message"],
+            "write rejected: 1 of 1 lines violate the schema rules"
+        );
+        assert_eq!(
+            body["rejected"][0]["message"],
+            "Measurement 'mem' is not allowed"
+        );
+
+        // none of the lines were written
+        server
+            .assert_query(
+                "MyOrg",
+                "MyBucket",
+                r#"select host, usage, "time" from cpu"#,
+                &[
+                    "+------+-------+------+",
+                    "| host | usage | time |",
+                    "+------+-------+------+",
+                    "| a    | 1.5   | 100  |",
+                    "+------+-------+------+",
+                ],
+            )
+            .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_schema_rules_drop() -> Result<()> {
+        let (server, _dir) = schema_rules_server("drop").await?;
+
+        server
+            .write_lp("MyOrg", "MyBucket", "cpu,host=a usage=1.5 100")
+            .await;
+
+        write_schema_rules_lines(
+            &server,
+            "partial write: dropped 3 of 4 lines violating the schema rules",
+            1,
+        )
+        .await?;
+
+        // only the conforming line was written
+        server
+            .assert_query(
+                "MyOrg",
+                "MyBucket",
+                r#"select host, usage, "time" from cpu"#,
+                &[
+                    "+------+-------+------+",
+                    "| host | usage | time |",
+                    "+------+-------+------+",
+                    "| a    | 1.5   | 100  |",
+                    "| b    | 2.5   | 200  |",
+                    "+------+-------+------+",
+                ],
+            )
+            .await;
+
+        let db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        let plan = db.table_names(PredicateBuilder::default().build()).await?;
+        let table_names = Executor::default().to_string_set(plan).await?;
+        assert_eq!(
+            table_names.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["cpu"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_delete() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
+
+        let client = Client::new();
+
+        let lp_data = "cpu,host=a,region=west usage=1.5 100\n\
+                       cpu,host=b,region=west usage=2.5 200\n\
+                       cpu,host=b,region=east,core=0 usage=3.5 300\n\
+                       cpu,host=a,region=west usage=4.5 400\n\
+                       mem,host=b used=10i 250";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        let delete_url = format!("{}/api/v2/delete?bucket=MyBucket&org=MyOrg", server_url);
+        let response = client
+            .post(&delete_url)
+            .body(
+                r#"{"start": "1970-01-01T00:00:00.000000150Z",
+                    "stop": "1970-01-01T00:00:00.000000300Z",
+                    "predicate": "_measurement=\"cpu\" AND host=\"b\""}"#,
+            )
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(response.headers()[DELETED_POINTS_HEADER], "2");
+
+        server
+            .assert_query(
+                "MyOrg",
+                "MyBucket",
+                r#"select host, usage, "time" from cpu"#,
+                &[
+                    "+------+-------+------+",
+                    "| host | usage | time |",
+                    "+------+-------+------+",
+                    "| a    | 1.5   | 100  |",
+                    "| a    | 4.5   | 400  |",
+                    "+------+-------+------+",
+                ],
+            )
+            .await;
+        server
+            .assert_query(
+                "MyOrg",
+                "MyBucket",
+                r#"select host, used, "time" from mem"#,
+                &[
+                    "+------+------+------+",
+                    "| host | used | time |",
+                    "+------+------+------+",
+                    "| b    | 10   | 250  |",
+                    "+------+------+------+",
+                ],
+            )
+            .await;
+
+        // the tags of the deleted points are gone as well
+        let db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        let executor = Executor::default();
+        let cpu_predicate = || PredicateBuilder::default().table("cpu").build();
+
+        let plan = db.tag_column_names(cpu_predicate()).await?;
+        let tag_keys = executor.to_string_set(plan).await?;
+        assert_eq!(
+            tag_keys.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["host", "region"]
+        );
+
+        let plan = db.column_values("host", cpu_predicate()).await?;
+        let host_values = executor.to_string_set(plan).await?;
+        assert_eq!(
+            host_values.iter().map(String::as_str).collect::<Vec<_>>(),
+            vec!["a"]
+        );
+
+        let response = client
+            .post(&delete_url)
+            .body(
+                r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z",
+                    "predicate": "host=\"a\" OR host=\"b\""}"#,
+            )
+            .send()
+            .await;
+        check_response(
+            "delete_invalid_predicate",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Error parsing delete predicate: Syntax error at position 9: expected AND"}"#,
+        )
+        .await;
+
+        // only tag and string columns can be compared
+        let response = client
+            .post(&delete_url)
+            .body(
+                r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z",
+                    "predicate": "usage=\"1.5\""}"#,
+            )
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.text().await?;
+        assert!(
+            body.contains(r#""code":"invalid""#)
+                && body.contains("Can not delete by column 'usage' of type f64"),
+            "unexpected body: {}",
+            body
+        );
+
+        let response = client
+            .post(&delete_url)
+            .body(r#"{"start": "yesterday", "stop": "1970-01-01T00:00:01Z"}"#)
+            .send()
+            .await;
+        check_response(
+            "delete_invalid_time",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Invalid start time 'yesterday': input contains invalid characters"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&delete_url)
+            .body(r#"{"start": "1970-01-01T00:00:00Z", "stop": "9999-12-31T00:00:00Z"}"#)
+            .send()
+            .await;
+        check_response(
+            "delete_time_out_of_range",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"invalid","message":"Invalid stop time '9999-12-31T00:00:00Z': out of range"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v2/delete?bucket=NotMyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(r#"{"start": "1970-01-01T00:00:00Z", "stop": "1970-01-01T00:00:01Z"}"#)
+            .send()
+            .await;
+        check_response(
+            "delete_missing_bucket",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"Bucket NotMyBucket not found in org MyOrg"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_jsonl() -> Result<()> {
+        let server = TestServer::new();
+        let client = Client::new();
+        let import_url = format!(
+            "{}/api/v1/import/jsonl?bucket=MyBucket&org=MyOrg&measurement=logs&tag_keys=host",
+            server.url()
+        );
+
+        let jsonl_data = r#"{"host":"a","msg":"started","took":1.5,"time":"2020-11-01T00:00:00Z"}
+
+{"host":"b","took":2,"time":1604188801000000000}
+{"host":"a","request":{"path":"/"},"time":1604188802000000000}
+not json
+{"host":"c","ok":true,"time":1604188803000000000}"#;
+
+        // streamed in chunks that split lines
+        let response = post_chunked(&import_url, jsonl_data, 7).await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let json: serde_json::Value = serde_json::from_slice(&body)?;
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "code": "invalid",
+                "message": "partial write: rejected 2 of 5 lines",
+                "accepted": 3,
+                "rejected": [
+                    {
+                        "code": "invalid",
+                        "message": "Nested value for key 'request' is not supported",
+                        "line_number": 4,
+                        "line": r#"{"host":"a","request":{"path":"/"},"time":1604188802000000000}"#,
+                    },
+                    {
+                        "code": "invalid",
+                        "message": "Error parsing JSON: expected ident at line 1 column 2",
+                        "line_number": 5,
+                        "line": "not json",
+                    },
+                ],
+            })
+        );
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "logs,host=a msg=started,took=1.5 1604188800000000000",
+                "logs,host=b took=2 1604188801000000000",
+                "logs,host=c ok=true 1604188803000000000",
+            ]
+        );
+
+        // without errors, as gzip compressed data
+        let response = client
+            .post(&import_url)
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(gzip_str(
+                r#"{"host":"d","took":3,"time":1604188804000000000}"#,
+            ))
+            .send()
+            .await;
+        check_response("import_jsonl_gzip", response, StatusCode::NO_CONTENT, "").await;
+        assert_eq!(test_db.get_lines().await.len(), 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_csv() -> Result<()> {
+        let server = TestServer::new();
+        let server_url = server.url();
+        let test_storage = server.store();
+
+        let client = Client::new();
+
+        let csv_data = "ts,location,station,temp,note\n\
+                        2020-11-01T00:00:00Z,\"Boston, MA\",a,70.5,sunny\n\
+                        2020-11-01T00:00:01Z,\"Boston, MA\",a,71,\n\
+                        yesterday,\"Boston, MA\",a,72,cloudy\n\
+                        2020-11-01T00:00:02Z,New York,b,65,\"rain, later\"\n";
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/csv?bucket=MyBucket&org=MyOrg&measurement=weather\
+                 &tag_columns=location,station&time_column=ts&time_format=rfc3339",
+                server_url
+            ))
+            .body(csv_data)
+            .send()
+            .await;
+
+        check_response(
+            "import_csv",
+            response,
+            StatusCode::OK,
+            r#"{"errors":[{"error":"Invalid RFC3339 timestamp 'yesterday': input contains invalid characters","line":4}],"rows_written":3}"#,
+        )
+        .await;
+
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                r#"weather,location=Boston\,\ MA,station=a note=sunny,temp=70.5 1604188800000000000"#,
+                r#"weather,location=Boston\,\ MA,station=a temp=71 1604188801000000000"#,
+                r#"weather,location=New\ York,station=b note=rain, later,temp=65 1604188802000000000"#,
+            ]
+        );
+
+        // a mapped column that is not in the header fails the import
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/csv?bucket=MyBucket&org=MyOrg&measurement=weather\
+                 &time_column=time",
+                server_url
+            ))
+            .body(csv_data)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // failed imports don't create the database
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/csv?bucket=OtherBucket&org=MyOrg&measurement=weather\
+                 &time_column=time",
+                server_url
+            ))
+            .body(csv_data)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(test_storage.db("MyOrg_OtherBucket").await.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_import_parquet() -> Result<()> {
+        use arrow_deps::parquet::arrow::arrow_writer::ArrowWriter;
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
+
+        let client = Client::new();
+
+        let lp_data = "h2o,state=MA,city=Boston temp=70.4,reading=3i 100\n\
+                       h2o,state=NY,city=New\\ York temp=68.2 200\n\
+                       h2o,state=MA,city=Boston temp=72.4 250";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        // write the table to a Parquet file, like a snapshot would
+        let batches = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists")
+            .table_to_arrow("h2o", &[])
+            .await?;
+        let path = dir.path().join("h2o.parquet");
+        let mut writer =
+            ArrowWriter::try_new(std::fs::File::create(&path)?, batches[0].schema(), None)?;
+        for batch in &batches {
+            writer.write(batch)?;
+        }
+        writer.close()?;
+        let parquet_data = std::fs::read(&path)?;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/parquet?bucket=Imported&org=MyOrg&table=h2o\
+                 &tag_columns=city,state",
+                server_url
+            ))
+            .body(parquet_data.clone())
+            .send()
+            .await;
+        check_response(
+            "import_parquet",
+            response,
+            StatusCode::OK,
+            r#"{"errors":[],"rows_written":3}"#,
+        )
+        .await;
+
+        let sql_query =
+            "select city, state, reading, temp, \"time\" from h2o order by \"time\", city";
+        let original = server.query("MyOrg", "MyBucket", sql_query).await;
+        let imported = server.query("MyOrg", "Imported", sql_query).await;
+        assert_eq!(
+            arrow::util::pretty::pretty_format_batches(&original)?,
+            arrow::util::pretty::pretty_format_batches(&imported)?,
+        );
+
+        // columns conflicting with existing data fail the import
+        server
+            .write_lp("MyOrg", "Conflict", "h2o,state=MA city=1i,temp=\"warm\" 50")
+            .await;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/parquet?bucket=Conflict&org=MyOrg&table=h2o\
+                 &tag_columns=city,state",
+                server_url
+            ))
+            .body(parquet_data.clone())
+            .send()
+            .await;
+        check_response(
+            "import_parquet",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"code":"conflict","message":"Parquet data conflicts with the existing data of table h2o in columns: city, temp"}"#,
+        )
+        .await;
+
+        // a tag column that is not a string fails the import
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/parquet?bucket=Imported&org=MyOrg&table=h2o\
+                 &tag_columns=reading",
+                server_url
+            ))
+            .body(parquet_data)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // failed imports don't create the database
+        let response = client
+            .post(&format!(
+                "{}/api/v1/import/parquet?bucket=NotParquet&org=MyOrg&table=h2o",
+                server_url
+            ))
+            .body("not a parquet file")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(server.store().db("MyOrg_NotParquet").await.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_opentsdb_put() -> Result<()> {
+        let server = TestServer::new();
+        let server_url = server.url();
+
+        let client = Client::new();
+
+        // an array of data points, one of which is invalid
+        let body = r#"[
+            {"metric": "sys.cpu", "timestamp": 1568756160, "value": 42, "tags": {"host": "a"}},
+            {"metric": "sys.cpu", "timestamp": 1568756160500, "value": 42.5, "tags": {"host": "b"}},
+            {"metric": "sys.cpu", "timestamp": 1568756160, "value": "lots", "tags": {"host": "c"}}
+        ]"#;
+
+        let response = client
+            .post(&format!(
+                "{}/api/put?bucket=MyBucket&org=MyOrg&details",
+                server_url
+            ))
+            .body(body)
+            .send()
+            .await;
+        check_response(
+            "opentsdb_put",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"errors":[{"datapoint":{"metric":"sys.cpu","tags":{"host":"c"},"timestamp":1568756160,"value":"lots"},"error":"Invalid value '\"lots\"', expected a finite number"}],"failed":1,"success":2}"#,
+        )
+        .await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "sys.cpu,host=a value=42 1568756160000000000",
+                "sys.cpu,host=b value=42.5 1568756160500000000",
+            ]
+        );
+
+        // a single, gzip compressed data point
+        let body =
+            r#"{"metric": "sys.mem", "timestamp": 1568756161, "value": 7, "tags": {"host": "a"}}"#;
+        let response = client
+            .post(&format!("{}/api/put?bucket=MyBucket&org=MyOrg", server_url))
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(gzip_str(body))
+            .send()
+            .await;
+        check_response(
+            "opentsdb_put",
+            response,
+            StatusCode::OK,
+            r#"{"failed":0,"success":1}"#,
+        )
+        .await;
+        assert_eq!(
+            test_db.get_lines().await[2],
+            "sys.mem,host=a value=7 1568756161000000000"
+        );
+
+        // puts that write nothing don't create the database
+        for body in &[
+            "not json",
+            r#"{"metric": "sys.cpu", "timestamp": 1568756160, "value": "lots"}"#,
+        ] {
+            let response = client
+                .post(&format!(
+                    "{}/api/put?bucket=NotCreated&org=MyOrg",
+                    server_url
+                ))
+                .body(*body)
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        }
+        assert!(server.store().db("MyOrg_NotCreated").await.is_none());
+
+        // without org and bucket and no default target
+        let response = client
+            .post(&format!("{}/api/put", server_url))
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_opentsdb_put_default_target() -> Result<()> {
+        use crate::server::opentsdb::WriteTarget;
+
+        let mut app_server = AppServer::new(Arc::new(TestDatabaseStore::new()));
+        app_server.opentsdb_target = Some(WriteTarget {
+            org: "MyOrg".into(),
+            bucket: "Legacy".into(),
+        });
+        let server = TestServer::with_app_server(Arc::new(app_server));
+
+        let body =
+            r#"{"metric": "sys.cpu", "timestamp": 1568756160, "value": 1, "tags": {"host": "a"}}"#;
+        let response = Client::new()
+            .post(&format!("{}/api/put", server.url()))
+            .body(body)
+            .send()
+            .await;
+        check_response(
+            "opentsdb_put",
+            response,
+            StatusCode::OK,
+            r#"{"failed":0,"success":1}"#,
+        )
+        .await;
+
+        let test_db = server
+            .store()
+            .db("MyOrg_Legacy")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_lines().await,
+            vec!["sys.cpu,host=a value=1 1568756160000000000"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prom_write() -> Result<()> {
+        use generated_types::prometheus::{Label, Sample, TimeSeries, WriteRequest};
+        use prost::Message;
+
+        let server = TestServer::new();
+        let server_url = server.url();
+        let test_storage = server.store();
+
+        let client = Client::new();
+
+        let label = |name: &str, value: &str| Label {
+            name: name.into(),
+            value: value.into(),
+        };
+
+        let write_request = WriteRequest {
+            timeseries: vec![
+                TimeSeries {
+                    labels: vec![
+                        label("__name__", "node_cpu_seconds_total"),
+                        label("mode", "idle"),
+                        label("cpu", "0"),
+                    ],
+                    samples: vec![
+                        Sample {
+                            value: 10.5,
+                            timestamp: 1_600_000_000_000,
+                        },
+                        Sample {
+                            value: 11.25,
+                            timestamp: 1_600_000_015_000,
+                        },
+                    ],
+                },
+                TimeSeries {
+                    labels: vec![label("__name__", "up"), label("job", "node")],
+                    samples: vec![Sample {
+                        value: 1.0,
+                        timestamp: 1_600_000_000_000,
+                    }],
+                },
+            ],
+        };
+
+        let mut encoded = Vec::new();
+        write_request.encode(&mut encoded)?;
+        let body = snap::raw::Encoder::new().compress_vec(&encoded)?;
+
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .header(header::CONTENT_ENCODING, "snappy")
+            .body(body)
+            .send()
+            .await;
 
-    use http::header;
-    use reqwest::{Client, Response};
+        check_response("prom_write", response, StatusCode::NO_CONTENT, "").await;
+
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
 
-    use hyper::service::{make_service_fn, service_fn};
-    use hyper::Server;
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "node_cpu_seconds_total,cpu=0,mode=idle value=10.5 1600000000000000000",
+                "node_cpu_seconds_total,cpu=0,mode=idle value=11.25 1600000015000000000",
+                "up,job=node value=1 1600000000000000000",
+            ]
+        );
 
-    use storage::{test::TestDatabaseStore, DatabaseStore};
+        // A body that is not snappy compressed protobuf is rejected,
+        // without creating the database
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/write?bucket=NotCreated&org={}",
+                server_url, org_name
+            ))
+            .body(&b"\xff\xff\xff\xff\xff\xff"[..])
+            .send()
+            .await
+            .expect("sending request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(test_storage.db("MyOrg_NotCreated").await.is_none());
 
-    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T, E = Error> = std::result::Result<T, E>;
+        // as is a body claiming to decompress past the size limit,
+        // before it is decompressed
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(&b"\x80\x80\x80\x40"[..])
+            .send()
+            .await;
+        check_response(
+            "prom_write_too_large",
+            response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            r#"{"code":"request too large","message":"Body exceeds limit of 10485760 bytes"}"#,
+        )
+        .await;
+
+        // so are samples whose timestamps don't fit in nanoseconds
+        let write_request = WriteRequest {
+            timeseries: vec![TimeSeries {
+                labels: vec![label("__name__", "up")],
+                samples: vec![Sample {
+                    value: 1.0,
+                    timestamp: i64::MAX,
+                }],
+            }],
+        };
+        let mut encoded = Vec::new();
+        write_request.encode(&mut encoded)?;
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .header(header::CONTENT_ENCODING, "snappy")
+            .body(snap::raw::Encoder::new().compress_vec(&encoded)?)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(test_db.get_lines().await.len(), 3);
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn test_ping() -> Result<()> {
-        let test_storage = Arc::new(TestDatabaseStore::new());
-        let server_url = test_server(test_storage.clone());
+    async fn test_prom_read() -> Result<()> {
+        use generated_types::prometheus::{
+            label_matcher, Label, LabelMatcher, Query, ReadRequest, Sample, TimeSeries,
+            WriteRequest,
+        };
+        use prost::Message;
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
 
         let client = Client::new();
-        let response = client.get(&format!("{}/ping", server_url)).send().await;
 
-        // Print the response so if the test fails, we have a log of what went wrong
-        check_response("ping", response, StatusCode::OK, "PONG").await;
+        let label = |name: &str, value: &str| Label {
+            name: name.into(),
+            value: value.into(),
+        };
+        let sample = |value: f64, timestamp: i64| Sample { value, timestamp };
+        let matcher = |matcher_type: label_matcher::Type, name: &str, value: &str| LabelMatcher {
+            r#type: matcher_type as i32,
+            name: name.into(),
+            value: value.into(),
+        };
+
+        let idle = TimeSeries {
+            labels: vec![
+                label("__name__", "node_cpu_seconds_total"),
+                label("cpu", "0"),
+                label("mode", "idle"),
+            ],
+            samples: vec![
+                sample(10.5, 1_600_000_000_000),
+                sample(11.25, 1_600_000_015_000),
+            ],
+        };
+        let user = TimeSeries {
+            labels: vec![
+                label("__name__", "node_cpu_seconds_total"),
+                label("cpu", "0"),
+                label("mode", "user"),
+            ],
+            samples: vec![sample(3.0, 1_600_000_000_000)],
+        };
+        let system = TimeSeries {
+            labels: vec![
+                label("__name__", "node_cpu_seconds_total"),
+                label("cpu", "0"),
+                label("mode", "system"),
+            ],
+            samples: vec![
+                sample(1.5, 1_600_000_000_000),
+                sample(2.5, 1_600_000_030_000),
+            ],
+        };
+        let up = TimeSeries {
+            labels: vec![label("__name__", "up"), label("job", "node")],
+            samples: vec![sample(1.0, 1_600_000_000_000)],
+        };
+
+        let write_request = WriteRequest {
+            timeseries: vec![idle.clone(), user.clone(), system.clone(), up.clone()],
+        };
+        let mut encoded = Vec::new();
+        write_request.encode(&mut encoded)?;
+        let body = snap::raw::Encoder::new().compress_vec(&encoded)?;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(body)
+            .send()
+            .await;
+        check_response("prom_write", response, StatusCode::NO_CONTENT, "").await;
+
+        let read_request = ReadRequest {
+            queries: vec![
+                Query {
+                    start_timestamp_ms: 1_600_000_000_000,
+                    end_timestamp_ms: 1_600_000_015_000,
+                    matchers: vec![
+                        matcher(
+                            label_matcher::Type::Eq,
+                            "__name__",
+                            "node_cpu_seconds_total",
+                        ),
+                        matcher(label_matcher::Type::Re, "mode", "idle|system"),
+                    ],
+                },
+                Query {
+                    start_timestamp_ms: 0,
+                    end_timestamp_ms: 2_000_000_000_000,
+                    matchers: vec![matcher(label_matcher::Type::Neq, "job", "other")],
+                },
+            ],
+        };
+        let mut encoded = Vec::new();
+        read_request.encode(&mut encoded)?;
+        let body = snap::raw::Encoder::new().compress_vec(&encoded)?;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/read?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.bytes().await?;
+        let decompressed = snap::raw::Decoder::new().decompress_vec(&body)?;
+        let read_response = ReadResponse::decode(&decompressed[..])?;
+
+        // the query time range is inclusive of its end, so the
+        // system sample at 1_600_000_030_000 is excluded
+        let mut system_in_range = system;
+        system_in_range.samples.truncate(1);
+
+        assert_eq!(read_response.results.len(), 2);
+        assert_eq!(
+            read_response.results[0].timeseries,
+            vec![idle, system_in_range]
+        );
+        assert_eq!(read_response.results[1].timeseries, vec![up]);
+
+        // Regular expressions other than simple alternations are rejected
+        let read_request = ReadRequest {
+            queries: vec![Query {
+                start_timestamp_ms: 0,
+                end_timestamp_ms: 2_000_000_000_000,
+                matchers: vec![matcher(label_matcher::Type::Re, "mode", "id.*")],
+            }],
+        };
+        let mut encoded = Vec::new();
+        read_request.encode(&mut encoded)?;
+        let body = snap::raw::Encoder::new().compress_vec(&encoded)?;
+
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/read?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(body)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // Bodies claiming to decompress past the size limit are
+        // rejected before they are decompressed
+        let response = client
+            .post(&format!(
+                "{}/api/v1/prom/read?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(&b"\x80\x80\x80\x40"[..])
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_write() -> Result<()> {
-        let test_storage = Arc::new(TestDatabaseStore::new());
-        let server_url = test_server(test_storage.clone());
+    async fn test_list_buckets() -> Result<()> {
+        let server = TestServer::new();
+        for db_name in &["MyOrg_b", "OtherOrg_c", "MyOrg_a", "MyOrgs_d", "rawdb"] {
+            server.store().db_or_create(db_name).await?;
+        }
 
         let client = Client::new();
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=MyOrg", server.url()))
+            .send()
+            .await;
+        check_response("list_buckets", response, StatusCode::OK, r#"["a","b"]"#).await;
 
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=NoOrg", server.url()))
+            .send()
+            .await;
+        check_response("list_buckets_no_org", response, StatusCode::OK, "[]").await;
 
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
+        // a database named by the db parameter in the form of an org
+        // and bucket is listed as that bucket
+        let response = client
+            .post(&format!("{}/api/v2/write?db=MyOrg_c", server.url()))
+            .body("cpu,host=a usage=1 1")
+            .send()
+            .await?;
+        assert!(response.status().is_success());
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=MyOrg", server.url()))
+            .send()
+            .await;
+        check_response(
+            "list_buckets_db_parameter",
+            response,
+            StatusCode::OK,
+            r#"["a","b","c"]"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_partitions() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
+
+        let lp_data = "cpu,host=a usage=1.5 1600107710000000000\n\
+                       disk,host=a bytes=10i 1600136510000000000\n\
+                       cpu,host=b usage=2.5 1600107720000000000";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        let client = Client::new();
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/partitions?org=MyOrg&bucket=MyBucket",
+                server_url
+            ))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let summaries: serde_json::Value = serde_json::from_slice(&response.bytes().await?)?;
+        assert_eq!(summaries[0]["key"], "2020-09-14T18");
+        assert_eq!(summaries[0]["table_count"], 1);
+        assert_eq!(summaries[0]["row_count"], 2);
+        assert_eq!(summaries[1]["key"], "2020-09-15T02");
+        assert_eq!(summaries[1]["table_count"], 1);
+        assert_eq!(summaries[1]["row_count"], 1);
+        assert!(
+            summaries[0]["size_bytes"].as_u64().unwrap()
+                > summaries[1]["size_bytes"].as_u64().unwrap()
+        );
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/partitions?org=MyOrg&bucket=MyBucket&names_only=true",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "list_partitions_names_only",
+            response,
+            StatusCode::OK,
+            r#"["2020-09-14T18","2020-09-15T02"]"#,
+        )
+        .await;
+
+        // gzip compressed when the client accepts it
+        let response = client
+            .get(&format!(
+                "{}/api/v1/partitions?org=MyOrg&bucket=MyBucket&names_only=true",
+                server_url
+            ))
+            .header(header::ACCEPT_ENCODING, "deflate, gzip;q=0.8")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(
+            gunzip_str(&response.bytes().await?),
+            r#"["2020-09-14T18","2020-09-15T02"]"#
+        );
+
+        // but empty write responses are not
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/api/v2/write?org=MyOrg&bucket=MyBucket",
+                server_url
+            ))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body("cpu,host=c usage=3.5 1600107730000000000")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/partitions?org=MyOrg&bucket=NotMyBucket",
+                server_url
             ))
-            .body(lp_data)
             .send()
             .await;
+        check_response(
+            "list_partitions_missing_bucket",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"Bucket NotMyBucket not found in org MyOrg"}"#,
+        )
+        .await;
 
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        Ok(())
+    }
 
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db("MyOrg_MyBucket")
-            .await
-            .expect("Database exists");
+    #[tokio::test]
+    async fn test_list_tables() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
+
+        let lp_data = "cpu,host=a usage=1.5 1600107710000000000\n\
+                       mem,host=a free=10i 1600107710000000000\n\
+                       disk,host=a bytes=10i 1600136510000000000";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        let client = Client::new();
+        let list_tables = |partition: &str| {
+            client
+                .get(&format!(
+                    "{}/api/v1/tables?org=MyOrg&bucket=MyBucket&partition={}",
+                    server_url, partition
+                ))
+                .send()
+        };
+
+        let response = list_tables("2020-09-14T18").await;
+        check_response("list_tables", response, StatusCode::OK, r#"["cpu","mem"]"#).await;
+
+        let response = list_tables("2020-09-15T02").await;
+        check_response("list_tables", response, StatusCode::OK, r#"["disk"]"#).await;
+
+        let response = list_tables("2020-09-16T00").await;
+        check_response(
+            "list_tables_unknown_partition",
+            response,
+            StatusCode::OK,
+            "[]",
+        )
+        .await;
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/tables?org=MyOrg&bucket=NotMyBucket&partition=2020-09-14T18",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "list_tables_missing_bucket",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"Bucket NotMyBucket not found in org MyOrg"}"#,
+        )
+        .await;
 
-        // Ensure the same line protocol data gets through
-        assert_eq!(test_db.get_lines().await, vec![lp_data]);
         Ok(())
     }
 
-    fn gzip_str(s: &str) -> Vec<u8> {
-        use libflate::gzip::Encoder;
-        use std::io::Write;
+    #[tokio::test]
+    async fn test_table_schemas() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
 
-        let mut encoder = Encoder::new(Vec::new()).expect("creating gzip encoder");
-        write!(encoder, "{}", s).expect("writing into encoder");
-        encoder
-            .finish()
-            .into_result()
-            .expect("successfully encoding gzip data")
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let schema_url = format!(
+            "{}/api/v1/schema?org=MyOrg&bucket=MyBucket&partition=2020-09-14T18",
+            server.url()
+        );
+
+        let lp_data = "cpu,host=a usage=1.5,count=2i 1600107710000000000\n\
+                       mem,host=a free=10i 1600107710000000000";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        let client = Client::new();
+
+        let response = client
+            .get(&format!("{}&table=cpu", schema_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.json::<serde_json::Value>().await?,
+            serde_json::json!({
+                "cpu": [
+                    {"name": "count", "data_type": "Int64", "category": "field"},
+                    {"name": "host", "data_type": "Utf8", "category": "tag"},
+                    {"name": "time", "data_type": "Int64", "category": "time"},
+                    {"name": "usage", "data_type": "Float64", "category": "field"},
+                ]
+            })
+        );
+
+        // all tables without a table
+        let response = client.get(&schema_url).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let schemas = response.json::<serde_json::Value>().await?;
+        assert_eq!(
+            schemas["mem"],
+            serde_json::json!([
+                {"name": "free", "data_type": "Int64", "category": "field"},
+                {"name": "host", "data_type": "Utf8", "category": "tag"},
+                {"name": "time", "data_type": "Int64", "category": "time"},
+            ])
+        );
+        assert_eq!(
+            schemas.as_object().unwrap().keys().collect::<Vec<_>>(),
+            vec!["cpu", "mem"]
+        );
+
+        let response = client
+            .get(&format!("{}&table=disk", schema_url))
+            .send()
+            .await;
+        check_response(
+            "table_schemas_missing_table",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"code":"not found","message":"Table disk not found in partition 2020-09-14T18"}"#,
+        )
+        .await;
+
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_gzip_write() -> Result<()> {
-        let test_storage = Arc::new(TestDatabaseStore::new());
-        let server_url = test_server(test_storage.clone());
+    async fn test_export() -> Result<()> {
+        use write_buffer::WriteBufferDatabases;
+
+        let dir = test_helpers::tmp_dir()?;
+        let storage = Arc::new(WriteBufferDatabases::new(dir.path()));
+        let server = TestServer::with_store(storage);
+        let server_url = server.url();
 
         let client = Client::new();
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
 
-        // send write data encoded with gzip
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
+        let lp_data = "h2o,state=NY,city=New\\ York temp=68.2 200\n\
+                       h2o,state=MA,city=Boston temp=70.4,reading=3i,sky=\"partly cloudy, warm\",valid=true 100\n\
+                       cpu,host=a usage=0.5 100\n\
+                       h2o,state=MA,city=Boston temp=72.4 250";
+        server.write_lp("MyOrg", "MyBucket", lp_data).await;
+
+        // ordered by measurement, series key and then time
+        let expected_export = "cpu,host=a usage=0.5 100\n\
+                               h2o,city=Boston,state=MA reading=3i,sky=\"partly cloudy, warm\",temp=70.4,valid=true 100\n\
+                               h2o,city=Boston,state=MA temp=72.4 250\n\
+                               h2o,city=New\\ York,state=NY temp=68.2 200\n";
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/export?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response("export", response, StatusCode::OK, expected_export).await;
+
+        // restricted to a measurement and time range
+        let response = client
+            .get(&format!(
+                "{}/api/v1/export?bucket=MyBucket&org=MyOrg&measurement=h2o&start=150&stop=250",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "export",
+            response,
+            StatusCode::OK,
+            "h2o,city=New\\ York,state=NY temp=68.2 200\n",
+        )
+        .await;
+
+        // gzip compressed when the client accepts it
+        let response = client
+            .get(&format!(
+                "{}/api/v1/export?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        let body = response.bytes().await?;
+        let mut exported = String::new();
+        {
+            use libflate::gzip::MultiDecoder;
+            use std::io::Read;
+            MultiDecoder::new(&body[..])?.read_to_string(&mut exported)?;
+        }
+        assert_eq!(exported, expected_export);
+
+        // re-importing the export into a fresh bucket yields the same data
+        server.write_lp("MyOrg", "Restored", &exported).await;
+
+        server
+            .assert_query(
+                "MyOrg",
+                "Restored",
+                "select city, state, temp, \"time\" from h2o order by \"time\", city",
+                &[
+                    "+----------+-------+------+------+",
+                    "| city     | state | temp | time |",
+                    "+----------+-------+------+------+",
+                    "| Boston   | MA    | 70.4 | 100  |",
+                    "| New York | NY    | 68.2 | 200  |",
+                    "| Boston   | MA    | 72.4 | 250  |",
+                    "+----------+-------+------+------+",
+                ],
+            )
+            .await;
+
+        for sql_query in &[
+            "select city, state, reading, sky, temp, valid, \"time\" from h2o order by \"time\", city",
+            "select host, usage, \"time\" from cpu",
+        ] {
+            let original = server.query("MyOrg", "MyBucket", sql_query).await;
+            let restored = server.query("MyOrg", "Restored", sql_query).await;
+            assert_eq!(
+                arrow::util::pretty::pretty_format_batches(&original)?,
+                arrow::util::pretty::pretty_format_batches(&restored)?,
+                "results of {}",
+                sql_query
+            );
+        }
+
+        let response = client
+            .get(&format!(
+                "{}/api/v1/export?bucket=Restored&org=MyOrg",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response("export", response, StatusCode::OK, expected_export).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_replication() -> Result<()> {
+        use crate::server::replication::{ReplicationConfig, ReplicationSink};
+
+        let replica = TestServer::new();
+        let replica_storage = replica.store();
+
+        let primary_storage = Arc::new(TestDatabaseStore::new());
+        let mut primary = AppServer::new(primary_storage.clone());
+        primary.replication = Some(ReplicationSink::new(ReplicationConfig::new(
+            replica.url(),
+            "token",
+        )));
+        let primary = TestServer::with_app_server(Arc::new(primary));
+        let primary_url = primary.url();
+
+        let client = Client::new();
+        let lp_data = "h2o_temperature,location=santa_monica surface_degrees=65.2 1568756160";
+
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                primary_url
             ))
-            .header(header::CONTENT_ENCODING, "gzip")
-            .body(gzip_str(lp_data))
+            .body(lp_data)
             .send()
             .await;
+        check_write_response("write", response, 1, lp_data).await;
 
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        let primary_db = primary_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(primary_db.get_lines().await, vec![lp_data]);
 
-        // Check that the data got into the right bucket
-        let test_db = test_storage
+        // replication happens in the background
+        let mut replica_lines = vec![];
+        for _ in 0..100 {
+            if let Some(replica_db) = replica_storage.db("MyOrg_MyBucket").await {
+                replica_lines = replica_db.get_lines().await;
+                if !replica_lines.is_empty() {
+                    break;
+                }
+            }
+            tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+        }
+        assert_eq!(replica_lines, vec![lp_data]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_replication_overflow() -> Result<()> {
+        use crate::server::replication::{ReplicationConfig, ReplicationSink};
+        use std::sync::atomic::Ordering;
+
+        // nothing listens on port 1, and the long backoff keeps the
+        // forwarder busy with the first write
+        let mut config = ReplicationConfig::new("http://127.0.0.1:1", "token");
+        config.buffer_size = 1;
+        config.initial_backoff = std::time::Duration::from_secs(60);
+
+        let primary_storage = Arc::new(TestDatabaseStore::new());
+        let mut primary = AppServer::new(primary_storage.clone());
+        primary.replication = Some(ReplicationSink::new(config));
+        let primary = TestServer::with_app_server(Arc::new(primary));
+        let primary_url = primary.url();
+
+        let client = Client::new();
+
+        // replication must not fail the client's writes
+        for i in 0..5 {
+            let lp_data = format!("cpu usage={} {}", i, i);
+            let response = client
+                .post(&format!(
+                    "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                    primary_url
+                ))
+                .body(lp_data.clone())
+                .send()
+                .await;
+            check_write_response("write", response, 1, &lp_data).await;
+        }
+
+        let primary_db = primary_storage
             .db("MyOrg_MyBucket")
             .await
             .expect("Database exists");
+        assert_eq!(primary_db.get_lines().await.len(), 5);
+
+        // at most one write is being retried and one is queued
+        let counters = primary
+            .app_server()
+            .replication
+            .as_ref()
+            .unwrap()
+            .counters();
+        assert!(counters.overflowed.load(Ordering::Relaxed) >= 3);
+        assert_eq!(counters.forwarded.load(Ordering::Relaxed), 0);
 
-        // Ensure the same line protocol data gets through
-        assert_eq!(test_db.get_lines().await, vec![lp_data]);
         Ok(())
     }
 
+    /// checks the response to a write that wrote all of its
+    /// `lines_written` lines of `lp_data`
+    async fn check_write_response(
+        description: &str,
+        response: Result<Response, reqwest::Error>,
+        lines_written: usize,
+        lp_data: &str,
+    ) {
+        println!("{} response: {:?}", description, response);
+
+        let response = response.expect("Unexpected error response");
+        let headers = response.headers();
+        assert_eq!(
+            headers[LINES_WRITTEN_HEADER],
+            lines_written.to_string().as_str()
+        );
+        assert_eq!(
+            headers[BYTES_READ_HEADER],
+            lp_data.len().to_string().as_str()
+        );
+
+        check_response(description, Ok(response), StatusCode::NO_CONTENT, "").await;
+    }
+
     /// checks a http response against expected results
+    /// Removes the request id from `body` if it is a JSON error, as
+    /// the ids generated for requests differ between runs
+    fn without_request_id(body: &str) -> String {
+        match serde_json::from_str::<serde_json::Value>(body) {
+            Ok(serde_json::Value::Object(mut json)) if json.contains_key("request_id") => {
+                let request_id = json.remove("request_id").unwrap();
+                assert!(!request_id.as_str().unwrap().is_empty());
+                serde_json::Value::Object(json).to_string()
+            }
+            _ => body.to_string(),
+        }
+    }
+
     async fn check_response(
         description: &str,
         response: Result<Response, reqwest::Error>,
@@ -473,31 +6059,9 @@ mod tests {
                 .expect("Converting request body to string");
 
             assert_eq!(status, expected_status);
-            assert_eq!(body, expected_body);
+            assert_eq!(without_request_id(&body), expected_body);
         } else {
             panic!("Unexpected error response: {:?}", response);
         }
     }
-
-    /// creates an instance of the http service backed by a in-memory
-    /// testable database.  Returns the url of the server
-    fn test_server(storage: Arc<TestDatabaseStore>) -> String {
-        let make_svc = make_service_fn(move |_conn| {
-            let storage = storage.clone();
-            async move {
-                Ok::<_, http::Error>(service_fn(move |req| {
-                    let state = storage.clone();
-                    super::service(req, state)
-                }))
-            }
-        });
-
-        // NB: specify port 0 to let the OS pick the port.
-        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
-        let server = Server::bind(&bind_addr).serve(make_svc);
-        let server_url = format!("http://{}", server.local_addr());
-        tokio::task::spawn(server);
-        println!("Started server at {}", server_url);
-        server_url
-    }
 }
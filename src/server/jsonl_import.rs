@@ -0,0 +1,199 @@
+//! This module contains code to convert newline delimited JSON events
+//! into InfluxDB line protocol for imports of log-style data.
+//!
+//! Each line is one JSON object, which becomes one point in the
+//! configured measurement:
+//!
+//! * The configured tag keys become tags
+//! * The `time` key becomes the timestamp, either as an RFC3339 string
+//!   or as nanoseconds since the epoch. Without it the point is stored
+//!   with the server's time.
+//! * All other keys become fields. Numbers become float fields (so a
+//!   key of mixed integer and decimal values has a single type), and
+//!   booleans and strings become boolean and string fields
+//!
+//! `null` values are treated as missing. Nested objects and arrays are
+//! not flattened: lines with them are rejected.
+use chrono::DateTime;
+use influxdb2_client::{data_point::DataPointError, DataPoint, WriteDataPoint};
+use serde_json::Value;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+/// The key of the timestamp of each event
+pub const TIME_KEY: &str = "time";
+
+/// Errors converting a single line
+#[derive(Debug, Snafu)]
+pub enum LineError {
+    #[snafu(display("Error parsing JSON: {}", source))]
+    ParsingJson { source: serde_json::Error },
+
+    #[snafu(display("Expected a JSON object"))]
+    NotAnObject {},
+
+    #[snafu(display("Nested value for key '{}' is not supported", key))]
+    NestedValue { key: String },
+
+    #[snafu(display("Invalid RFC3339 timestamp '{}': {}", value, source))]
+    InvalidRfc3339 {
+        value: String,
+        source: chrono::ParseError,
+    },
+
+    #[snafu(display(
+        "RFC3339 timestamp '{}' is out of the range of nanosecond timestamps",
+        value
+    ))]
+    Rfc3339OutOfRange { value: String },
+
+    #[snafu(display(
+        "Invalid timestamp {}, expected an RFC3339 string or nanoseconds",
+        value
+    ))]
+    InvalidTime { value: Value },
+
+    #[snafu(display("Line has no field values"))]
+    NoFields {},
+
+    #[snafu(display("Error converting line: {}", source))]
+    ConvertingLine { source: DataPointError },
+
+    #[snafu(display("Error writing line protocol: {}", source))]
+    WritingLineProtocol { source: std::io::Error },
+}
+
+/// Describes how the keys of JSON events map onto the InfluxDB data
+/// model
+#[derive(Debug, Clone)]
+pub struct JsonlMapping {
+    pub measurement: String,
+    pub tag_keys: Vec<String>,
+}
+
+/// Converts `line`, a JSON object, into a line of line protocol
+/// according to `mapping`
+pub fn json_line_to_lp(mapping: &JsonlMapping, line: &str) -> Result<String, LineError> {
+    let value: Value = serde_json::from_str(line).context(ParsingJson)?;
+    let object = match value {
+        Value::Object(object) => object,
+        _ => return NotAnObject.fail(),
+    };
+
+    let mut builder = DataPoint::builder(&mapping.measurement);
+    let mut has_fields = false;
+
+    for (key, value) in object {
+        if key == TIME_KEY {
+            let timestamp = match &value {
+                Value::String(time) => {
+                    let parsed = DateTime::parse_from_rfc3339(time)
+                        .context(InvalidRfc3339 { value: time })?;
+                    parsed
+                        .timestamp()
+                        .checked_mul(1_000_000_000)
+                        .and_then(|nanos| nanos.checked_add(parsed.timestamp_subsec_nanos().into()))
+                        .context(Rfc3339OutOfRange { value: time })?
+                }
+                Value::Number(time) => time.as_i64().context(InvalidTime {
+                    value: value.clone(),
+                })?,
+                _ => return InvalidTime { value }.fail(),
+            };
+            builder = builder.timestamp(timestamp);
+            continue;
+        }
+
+        let is_tag = mapping.tag_keys.contains(&key);
+        builder = match value {
+            Value::Null => continue,
+            Value::Object(_) | Value::Array(_) => return NestedValue { key }.fail(),
+            Value::String(value) if is_tag => builder.tag(key, value),
+            value if is_tag => builder.tag(key, value.to_string()),
+            Value::Number(value) => {
+                let value = value
+                    .as_f64()
+                    .expect("JSON numbers are representable as f64");
+                builder.field(key, value)
+            }
+            Value::Bool(value) => builder.field(key, value),
+            Value::String(value) => builder.field(key, value),
+        };
+        has_fields |= !is_tag;
+    }
+    ensure!(has_fields, NoFields);
+
+    let point = builder.build().context(ConvertingLine)?;
+
+    let mut lp_data = Vec::new();
+    point
+        .write_data_point_to(&mut lp_data)
+        .context(WritingLineProtocol)?;
+    lp_data.pop(); // trailing newline
+
+    Ok(String::from_utf8(lp_data).expect("line protocol is valid utf8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> JsonlMapping {
+        JsonlMapping {
+            measurement: "logs".into(),
+            tag_keys: vec!["host".into(), "status".into()],
+        }
+    }
+
+    #[test]
+    fn test_json_line_to_lp() {
+        let lines = [
+            r#"{"host":"a","status":500,"msg":"failed","ok":false,"took":1.5,"time":"2020-11-01T00:00:00Z"}"#,
+            r#"{"host":"b","took":2,"user":null,"time":1604188801000000000}"#,
+        ];
+
+        let converted = lines
+            .iter()
+            .map(|line| json_line_to_lp(&mapping(), line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            converted,
+            vec![
+                "logs,host=a,status=500 msg=\"failed\",ok=false,took=1.5 1604188800000000000",
+                "logs,host=b took=2 1604188801000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_json_line_to_lp_errors() {
+        let lines = [
+            r#"{"host":"a","took":"#,
+            r#"[1, 2]"#,
+            r#"{"host":"a","request":{"path":"/"}}"#,
+            r#"{"host":"a","took":1,"time":"yesterday"}"#,
+            r#"{"host":"a","took":1,"time":"9999-01-01T00:00:00Z"}"#,
+            r#"{"host":"a","took":1,"time":true}"#,
+            r#"{"host":"a"}"#,
+        ];
+
+        let errors = lines
+            .iter()
+            .map(|line| json_line_to_lp(&mapping(), line).unwrap_err().to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            errors,
+            vec![
+                "Error parsing JSON: EOF while parsing a value at line 1 column 19",
+                "Expected a JSON object",
+                "Nested value for key 'request' is not supported",
+                "Invalid RFC3339 timestamp 'yesterday': input contains invalid characters",
+                "RFC3339 timestamp '9999-01-01T00:00:00Z' is out of the range of nanosecond \
+                 timestamps",
+                "Invalid timestamp true, expected an RFC3339 string or nanoseconds",
+                "Line has no field values",
+            ]
+        );
+    }
+}
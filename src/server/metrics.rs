@@ -0,0 +1,196 @@
+//! This module contains the metrics of the HTTP API, which
+//! `/metrics` renders in the Prometheus text exposition format:
+//!
+//! * `iox_http_requests_total`: requests by route and status class
+//! * `iox_http_write_lines_total`: lines written by writes and imports
+//! * `iox_http_write_bytes_total`: bytes of line protocol written by
+//!   writes and imports, after decompression and conversion
+//! * `iox_http_query_duration_seconds`: a summary of the time taken by
+//!   /api/v2/read queries
+//!
+//! Requests are counted under the path of their route. Requests to
+//! paths without a route, including CORS preflight requests, are
+//! counted under the route `unmatched`, so that clients can't create
+//! arbitrarily many series.
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use hyper::StatusCode;
+
+/// The route label of requests without a route
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// The number of requests by route and status class
+    requests: Mutex<BTreeMap<(String, &'static str), u64>>,
+    write_lines: AtomicU64,
+    write_bytes: AtomicU64,
+    query_durations: Mutex<Summary>,
+}
+
+#[derive(Debug, Default)]
+struct Summary {
+    sum: Duration,
+    count: u64,
+}
+
+impl Metrics {
+    /// Counts a request to `route` answered with `status`
+    pub fn record_request(&self, route: &str, status: StatusCode) {
+        let status_class = match status.as_u16() / 100 {
+            1 => "1xx",
+            2 => "2xx",
+            3 => "3xx",
+            4 => "4xx",
+            _ => "5xx",
+        };
+
+        let mut requests = self.requests.lock().expect("metrics mutex poisoned");
+        *requests
+            .entry((route.to_string(), status_class))
+            .or_default() += 1;
+    }
+
+    /// Counts a write of `lines` lines of line protocol, `bytes` long
+    pub fn record_write(&self, lines: usize, bytes: usize) {
+        self.write_lines.fetch_add(lines as u64, Ordering::Relaxed);
+        self.write_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a query that took `duration` to run
+    pub fn record_query(&self, duration: Duration) {
+        let mut query_durations = self.query_durations.lock().expect("metrics mutex poisoned");
+        query_durations.sum += duration;
+        query_durations.count += 1;
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "iox_http_requests_total",
+            "counter",
+            "HTTP requests by route and status class",
+        );
+        let requests = self.requests.lock().expect("metrics mutex poisoned");
+        for ((route, status_class), count) in requests.iter() {
+            writeln!(
+                out,
+                "iox_http_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                escape_label_value(route),
+                status_class,
+                count
+            )
+            .expect("writing to a String never fails");
+        }
+        drop(requests);
+
+        write_header(
+            &mut out,
+            "iox_http_write_lines_total",
+            "counter",
+            "Lines of line protocol written",
+        );
+        writeln!(
+            out,
+            "iox_http_write_lines_total {}",
+            self.write_lines.load(Ordering::Relaxed)
+        )
+        .expect("writing to a String never fails");
+
+        write_header(
+            &mut out,
+            "iox_http_write_bytes_total",
+            "counter",
+            "Bytes of line protocol written",
+        );
+        writeln!(
+            out,
+            "iox_http_write_bytes_total {}",
+            self.write_bytes.load(Ordering::Relaxed)
+        )
+        .expect("writing to a String never fails");
+
+        write_header(
+            &mut out,
+            "iox_http_query_duration_seconds",
+            "summary",
+            "Time taken to run read queries",
+        );
+        let query_durations = self.query_durations.lock().expect("metrics mutex poisoned");
+        writeln!(
+            out,
+            "iox_http_query_duration_seconds_sum {}\n\
+             iox_http_query_duration_seconds_count {}",
+            query_durations.sum.as_secs_f64(),
+            query_durations.count
+        )
+        .expect("writing to a String never fails");
+
+        out
+    }
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} {}",
+        name, help, name, metric_type
+    )
+    .expect("writing to a String never fails");
+}
+
+/// Escapes the backslashes, double quotes and newlines of a label value
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_request("/api/v2/write", StatusCode::NO_CONTENT);
+        metrics.record_request("/api/v2/write", StatusCode::NO_CONTENT);
+        metrics.record_request("/api/v2/write", StatusCode::BAD_REQUEST);
+        metrics.record_request("/a\"b", StatusCode::OK);
+        metrics.record_write(2, 50);
+        metrics.record_write(1, 25);
+        metrics.record_query(Duration::from_millis(250));
+        metrics.record_query(Duration::from_millis(500));
+
+        assert_eq!(
+            metrics.render(),
+            "# HELP iox_http_requests_total HTTP requests by route and status class\n\
+             # TYPE iox_http_requests_total counter\n\
+             iox_http_requests_total{route=\"/a\\\"b\",status=\"2xx\"} 1\n\
+             iox_http_requests_total{route=\"/api/v2/write\",status=\"2xx\"} 2\n\
+             iox_http_requests_total{route=\"/api/v2/write\",status=\"4xx\"} 1\n\
+             # HELP iox_http_write_lines_total Lines of line protocol written\n\
+             # TYPE iox_http_write_lines_total counter\n\
+             iox_http_write_lines_total 3\n\
+             # HELP iox_http_write_bytes_total Bytes of line protocol written\n\
+             # TYPE iox_http_write_bytes_total counter\n\
+             iox_http_write_bytes_total 75\n\
+             # HELP iox_http_query_duration_seconds Time taken to run read queries\n\
+             # TYPE iox_http_query_duration_seconds summary\n\
+             iox_http_query_duration_seconds_sum 0.75\n\
+             iox_http_query_duration_seconds_count 2\n"
+        );
+    }
+}
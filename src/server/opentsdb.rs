@@ -0,0 +1,244 @@
+//! This module contains code to convert the JSON bodies of OpenTSDB
+//! `/api/put` requests into InfluxDB line protocol, for legacy
+//! collectors that can't be changed to write line protocol.
+//!
+//! A body holds either a single data point or an array of them:
+//!
+//! ```text
+//! [{"metric": "sys.cpu", "timestamp": 1568756160, "value": 42, "tags": {"host": "a"}}]
+//! ```
+//!
+//! Each data point becomes one point in the `metric` measurement with
+//! the data point's tags and its value as the float field `value`.
+//! Following OpenTSDB, timestamps that fit into 32 bits are seconds and
+//! larger ones milliseconds. Data points that can't be converted are
+//! collected so the remaining ones can still be written.
+use std::collections::BTreeMap;
+
+use influxdb2_client::{data_point::DataPointError, DataPoint, WriteDataPoint};
+use serde::Deserialize;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+/// The name of the field holding the value of a data point
+pub const VALUE_FIELD_NAME: &str = "value";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error parsing OpenTSDB put request as JSON: {}", source))]
+    ParsingJson { source: serde_json::Error },
+
+    #[snafu(display("Expected a data point or an array of data points"))]
+    UnexpectedJson {},
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors converting a single data point
+#[derive(Debug, Snafu)]
+pub enum DatumError {
+    #[snafu(display("Invalid data point: {}", source))]
+    InvalidDatum { source: serde_json::Error },
+
+    #[snafu(display("Metric name is empty"))]
+    EmptyMetric {},
+
+    #[snafu(display("Data point has no tags"))]
+    NoTags {},
+
+    #[snafu(display("Invalid value '{}', expected a finite number", value))]
+    InvalidValue { value: String },
+
+    #[snafu(display("Invalid timestamp {}", timestamp))]
+    InvalidTimestamp { timestamp: i64 },
+
+    #[snafu(display("Error converting data point: {}", source))]
+    ConvertingDatum { source: DataPointError },
+
+    #[snafu(display("Error writing line protocol: {}", source))]
+    WritingLineProtocol { source: std::io::Error },
+}
+
+/// The org and bucket OpenTSDB data points are written to when a put
+/// request doesn't specify them
+#[derive(Debug, Clone)]
+pub struct WriteTarget {
+    pub org: String,
+    pub bucket: String,
+}
+
+/// One OpenTSDB data point
+#[derive(Debug, Deserialize)]
+struct Datum {
+    metric: String,
+    timestamp: i64,
+    /// A number, or a string holding one
+    value: serde_json::Value,
+    tags: BTreeMap<String, String>,
+}
+
+/// The result of converting an OpenTSDB put request into line protocol
+#[derive(Debug, Default)]
+pub struct ConvertedPut {
+    /// One line of line protocol for each successfully converted data
+    /// point
+    pub lines: Vec<String>,
+
+    /// Each data point that could not be converted, with its error
+    pub errors: Vec<(serde_json::Value, DatumError)>,
+}
+
+/// Converts `body`, the JSON body of a put request, into line
+/// protocol.
+///
+/// Fails if `body` isn't a JSON object or array of objects; errors in
+/// individual data points are returned in `ConvertedPut::errors`.
+pub fn put_to_lp(body: &[u8]) -> Result<ConvertedPut> {
+    let data = match serde_json::from_slice(body).context(ParsingJson)? {
+        serde_json::Value::Array(data) => data,
+        datum @ serde_json::Value::Object(_) => vec![datum],
+        _ => return UnexpectedJson.fail(),
+    };
+
+    let mut converted = ConvertedPut::default();
+
+    for datum in data {
+        match datum_to_lp(&datum) {
+            Ok(line) => converted.lines.push(line),
+            Err(e) => converted.errors.push((datum, e)),
+        }
+    }
+
+    Ok(converted)
+}
+
+fn datum_to_lp(datum: &serde_json::Value) -> Result<String, DatumError> {
+    let datum = Datum::deserialize(datum).context(InvalidDatum)?;
+
+    ensure!(!datum.metric.is_empty(), EmptyMetric);
+    ensure!(!datum.tags.is_empty(), NoTags);
+
+    let value = match &datum.value {
+        serde_json::Value::Number(value) => value.as_f64(),
+        serde_json::Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+    .filter(|value: &f64| value.is_finite())
+    .context(InvalidValue {
+        value: datum.value.to_string(),
+    })?;
+
+    let timestamp = timestamp_to_nanos(datum.timestamp).context(InvalidTimestamp {
+        timestamp: datum.timestamp,
+    })?;
+
+    let mut builder = DataPoint::builder(&datum.metric)
+        .field(VALUE_FIELD_NAME, value)
+        .timestamp(timestamp);
+    for (key, value) in &datum.tags {
+        builder = builder.tag(key, value);
+    }
+
+    let point = builder.build().context(ConvertingDatum)?;
+
+    let mut lp_data = Vec::new();
+    point
+        .write_data_point_to(&mut lp_data)
+        .context(WritingLineProtocol)?;
+    lp_data.pop(); // trailing newline
+
+    Ok(String::from_utf8(lp_data).expect("line protocol is valid utf8"))
+}
+
+/// Converts an OpenTSDB timestamp, in seconds if it fits into 32 bits
+/// and milliseconds otherwise, into nanoseconds
+fn timestamp_to_nanos(timestamp: i64) -> Option<i64> {
+    match timestamp {
+        t if t < 0 => None,
+        t if t <= i64::from(u32::MAX) => Some(t * 1_000_000_000),
+        t => t.checked_mul(1_000_000),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_put_to_lp() {
+        let body = r#"[
+            {"metric": "sys.cpu", "timestamp": 1568756160, "value": 42, "tags": {"host": "a", "dc": "lga"}},
+            {"metric": "sys.cpu", "timestamp": 1568756160123, "value": "42.5", "tags": {"host": "b"}}
+        ]"#;
+
+        let converted = put_to_lp(body.as_bytes()).unwrap();
+
+        assert!(converted.errors.is_empty(), "{:?}", converted.errors);
+        assert_eq!(
+            converted.lines,
+            vec![
+                "sys.cpu,dc=lga,host=a value=42 1568756160000000000",
+                "sys.cpu,host=b value=42.5 1568756160123000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_put_to_lp_single_datum() {
+        let body =
+            r#"{"metric": "sys.cpu", "timestamp": 100, "value": 1.5, "tags": {"host": "a"}}"#;
+
+        let converted = put_to_lp(body.as_bytes()).unwrap();
+
+        assert!(converted.errors.is_empty(), "{:?}", converted.errors);
+        assert_eq!(
+            converted.lines,
+            vec!["sys.cpu,host=a value=1.5 100000000000"]
+        );
+    }
+
+    #[test]
+    fn test_put_to_lp_datum_errors() {
+        let body = r#"[
+            {"metric": "sys.cpu", "timestamp": 100, "value": 1, "tags": {"host": "a"}},
+            {"metric": "sys.cpu", "value": 2, "tags": {"host": "a"}},
+            {"metric": "", "timestamp": 100, "value": 3, "tags": {"host": "a"}},
+            {"metric": "sys.cpu", "timestamp": 100, "value": 4, "tags": {}},
+            {"metric": "sys.cpu", "timestamp": 100, "value": "four", "tags": {"host": "a"}},
+            {"metric": "sys.cpu", "timestamp": -1, "value": 5, "tags": {"host": "a"}},
+            {"metric": "sys.cpu", "timestamp": 99999999999999999, "value": 6, "tags": {"host": "a"}}
+        ]"#;
+
+        let converted = put_to_lp(body.as_bytes()).unwrap();
+
+        assert_eq!(converted.lines, vec!["sys.cpu,host=a value=1 100000000000"]);
+
+        let errors = converted
+            .errors
+            .iter()
+            .map(|(_, e)| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "Invalid data point: missing field `timestamp`",
+                "Metric name is empty",
+                "Data point has no tags",
+                "Invalid value '\"four\"', expected a finite number",
+                "Invalid timestamp -1",
+                "Invalid timestamp 99999999999999999",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_put_to_lp_invalid_body() {
+        assert!(matches!(
+            put_to_lp(b"not json").unwrap_err(),
+            Error::ParsingJson { .. }
+        ));
+        assert!(matches!(
+            put_to_lp(b"42").unwrap_err(),
+            Error::UnexpectedJson {}
+        ));
+    }
+}
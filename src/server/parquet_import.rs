@@ -0,0 +1,589 @@
+//! This module contains code to convert Parquet files into InfluxDB
+//! line protocol for bulk imports.
+//!
+//! Each row becomes one point in the configured measurement:
+//!
+//! * The configured tag columns, which must be strings, become tags
+//! * The configured time column becomes the timestamp. It must either
+//!   be an integer column of nanoseconds since the epoch or a timestamp
+//!   column
+//! * All other columns become fields of the corresponding type.
+//!   Unsigned integers become integer fields
+//!
+//! Null values are treated as missing. Problems with individual rows
+//! (e.g. a null timestamp) are collected per row so the remaining rows
+//! can still be imported.
+use std::{collections::BTreeSet, convert::TryFrom, rc::Rc};
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array,
+        },
+        compute::kernels::cast::cast,
+        datatypes::{DataType as ArrowDataType, Schema, TimeUnit},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    parquet::{
+        arrow::arrow_reader::{ArrowReader, ParquetFileArrowReader},
+        errors::ParquetError,
+        file::{reader::SerializedFileReader, serialized_reader::SliceableCursor},
+    },
+};
+use influxdb2_client::{data_point::DataPointError, DataPoint, WriteDataPoint};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use storage::exec::fieldlist::FieldList;
+
+/// The number of rows decoded from the Parquet file at a time
+const DECODE_BATCH_SIZE: usize = 1000;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading Parquet file: {}", source))]
+    ReadingParquet { source: ParquetError },
+
+    #[snafu(display("Error decoding Parquet data: {}", source))]
+    DecodingParquet { source: ArrowError },
+
+    #[snafu(display("Column '{}' not found in Parquet file", column_name))]
+    ColumnNotFound { column_name: String },
+
+    #[snafu(display(
+        "Time column '{}' has type {:?}, expected an integer or timestamp",
+        column_name,
+        data_type
+    ))]
+    InvalidTimeColumn {
+        column_name: String,
+        data_type: ArrowDataType,
+    },
+
+    #[snafu(display(
+        "Tag column '{}' has type {:?}, expected a string",
+        column_name,
+        data_type
+    ))]
+    InvalidTagColumn {
+        column_name: String,
+        data_type: ArrowDataType,
+    },
+
+    #[snafu(display("Field column '{}' has unsupported type {:?}", column_name, data_type))]
+    UnsupportedFieldColumn {
+        column_name: String,
+        data_type: ArrowDataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors converting a single row
+#[derive(Debug, Snafu)]
+pub enum RowError {
+    #[snafu(display("Missing value for time column '{}'", column_name))]
+    MissingTime { column_name: String },
+
+    #[snafu(display("Timestamp {} is out of range", value))]
+    TimestampOutOfRange { value: i64 },
+
+    #[snafu(display(
+        "Value {} of column '{}' is too large for an integer field",
+        value,
+        column_name
+    ))]
+    UnsignedOutOfRange { column_name: String, value: u64 },
+
+    #[snafu(display("Row has no field values"))]
+    NoFields {},
+
+    #[snafu(display("Error converting row: {}", source))]
+    ConvertingRow { source: DataPointError },
+
+    #[snafu(display("Error writing line protocol: {}", source))]
+    WritingLineProtocol { source: std::io::Error },
+}
+
+/// Describes how Parquet columns map onto the InfluxDB data model
+#[derive(Debug, Clone)]
+pub struct ParquetMapping {
+    pub measurement: String,
+    pub tag_columns: Vec<String>,
+    pub time_column: String,
+}
+
+/// The result of converting a Parquet file into line protocol
+#[derive(Debug, Default)]
+pub struct ConvertedParquet {
+    /// One line of line protocol for each successfully converted row
+    pub lines: Vec<String>,
+
+    /// The (0-based) index in the file and error of each row that
+    /// could not be converted
+    pub errors: Vec<(usize, RowError)>,
+
+    /// The names of the columns imported as tags
+    pub tags: Vec<String>,
+
+    /// The name and type of each column imported as a field, using the
+    /// types of the write buffer (e.g. `Int64` for unsigned integers)
+    pub fields: Vec<(String, ArrowDataType)>,
+}
+
+impl ConvertedParquet {
+    /// Returns the names of the imported columns which conflict with
+    /// the existing data of the measurement, described by
+    /// `existing_tags` and `existing_fields`: tags that are fields,
+    /// and fields that are tags or fields of a different type.
+    pub fn conflicting_columns(
+        &self,
+        existing_tags: &BTreeSet<String>,
+        existing_fields: &FieldList,
+    ) -> Vec<String> {
+        let existing_field_type = |name: &str| {
+            existing_fields
+                .fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|field| &field.data_type)
+        };
+
+        let tag_conflicts = self
+            .tags
+            .iter()
+            .filter(|name| existing_field_type(name.as_str()).is_some());
+
+        let field_conflicts = self
+            .fields
+            .iter()
+            .filter(|(name, data_type)| {
+                existing_tags.contains(name)
+                    || existing_field_type(name.as_str())
+                        .map_or(false, |existing| existing != data_type)
+            })
+            .map(|(name, _)| name);
+
+        tag_conflicts.chain(field_conflicts).cloned().collect()
+    }
+}
+
+/// Converts `data`, the contents of a Parquet file, into line protocol
+/// according to `mapping`.
+///
+/// Fails if the file can't be decoded, lacks any of the mapped columns
+/// or any column has an unsupported type; errors in individual rows
+/// are returned in `ConvertedParquet::errors`.
+pub fn parquet_to_lp(mapping: &ParquetMapping, data: Vec<u8>) -> Result<ConvertedParquet> {
+    let file_reader =
+        SerializedFileReader::new(SliceableCursor::new(data)).context(ReadingParquet)?;
+    let mut reader = ParquetFileArrowReader::new(Rc::new(file_reader));
+
+    let schema = reader.get_schema().context(ReadingParquet)?;
+    let time_unit_nanos = validate_schema(mapping, &schema)?;
+
+    let mut converted = ConvertedParquet::default();
+    for field in schema.fields() {
+        let name = field.name();
+        if name == &mapping.time_column {
+            continue;
+        }
+
+        if mapping.tag_columns.contains(name) {
+            converted.tags.push(name.to_string());
+        } else {
+            let data_type = match field.data_type() {
+                ArrowDataType::UInt64 => ArrowDataType::Int64,
+                data_type => data_type.clone(),
+            };
+            converted.fields.push((name.to_string(), data_type));
+        }
+    }
+
+    let mut row_offset = 0;
+    for batch in reader
+        .get_record_reader(DECODE_BATCH_SIZE)
+        .context(ReadingParquet)?
+    {
+        let batch = batch.context(DecodingParquet)?;
+        let time_index = batch
+            .schema()
+            .index_of(&mapping.time_column)
+            .expect("time column validated");
+        let timestamps =
+            cast(batch.column(time_index), &ArrowDataType::Int64).context(DecodingParquet)?;
+        let timestamps = timestamps
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .expect("cast to Int64");
+
+        for row in 0..batch.num_rows() {
+            let result = if timestamps.is_null(row) {
+                MissingTime {
+                    column_name: &mapping.time_column,
+                }
+                .fail()
+            } else {
+                let value = timestamps.value(row);
+                value
+                    .checked_mul(time_unit_nanos)
+                    .context(TimestampOutOfRange { value })
+                    .and_then(|timestamp| row_to_lp(mapping, &batch, time_index, timestamp, row))
+            };
+
+            match result {
+                Ok(line) => converted.lines.push(line),
+                Err(e) => converted.errors.push((row_offset + row, e)),
+            }
+        }
+
+        row_offset += batch.num_rows();
+    }
+
+    Ok(converted)
+}
+
+/// Checks that all mapped columns exist and all columns have supported
+/// types, returning the number of nanoseconds per unit of the time
+/// column
+fn validate_schema(mapping: &ParquetMapping, schema: &Schema) -> Result<i64> {
+    let time_field = schema
+        .field_with_name(&mapping.time_column)
+        .ok()
+        .context(ColumnNotFound {
+            column_name: &mapping.time_column,
+        })?;
+
+    let time_unit_nanos = match time_field.data_type() {
+        ArrowDataType::Int64 | ArrowDataType::Timestamp(TimeUnit::Nanosecond, _) => 1,
+        ArrowDataType::Timestamp(TimeUnit::Microsecond, _) => 1_000,
+        ArrowDataType::Timestamp(TimeUnit::Millisecond, _) => 1_000_000,
+        ArrowDataType::Timestamp(TimeUnit::Second, _) => 1_000_000_000,
+        data_type => {
+            return InvalidTimeColumn {
+                column_name: &mapping.time_column,
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    };
+
+    for column_name in &mapping.tag_columns {
+        let field = schema
+            .field_with_name(column_name)
+            .ok()
+            .context(ColumnNotFound { column_name })?;
+
+        ensure!(
+            field.data_type() == &ArrowDataType::Utf8,
+            InvalidTagColumn {
+                column_name,
+                data_type: field.data_type().clone(),
+            }
+        );
+    }
+
+    for field in schema.fields() {
+        let column_name = field.name();
+        if column_name == &mapping.time_column || mapping.tag_columns.contains(column_name) {
+            continue;
+        }
+
+        match field.data_type() {
+            ArrowDataType::Float64
+            | ArrowDataType::Int64
+            | ArrowDataType::UInt64
+            | ArrowDataType::Utf8
+            | ArrowDataType::Boolean => {}
+            data_type => {
+                return UnsupportedFieldColumn {
+                    column_name,
+                    data_type: data_type.clone(),
+                }
+                .fail()
+            }
+        }
+    }
+
+    Ok(time_unit_nanos)
+}
+
+fn row_to_lp(
+    mapping: &ParquetMapping,
+    batch: &RecordBatch,
+    time_index: usize,
+    timestamp: i64,
+    row: usize,
+) -> Result<String, RowError> {
+    let mut builder = DataPoint::builder(&mapping.measurement).timestamp(timestamp);
+    let mut has_fields = false;
+
+    let schema = batch.schema();
+    for (index, (field, column)) in schema.fields().iter().zip(batch.columns()).enumerate() {
+        let column_name = field.name();
+        if index == time_index || column.is_null(row) {
+            continue;
+        }
+
+        if mapping.tag_columns.contains(column_name) {
+            let value = as_array::<StringArray>(column).value(row);
+            // line protocol can't represent empty tag values
+            if !value.is_empty() {
+                builder = builder.tag(column_name, value);
+            }
+            continue;
+        }
+
+        builder = match column.data_type() {
+            ArrowDataType::Float64 => {
+                builder.field(column_name, as_array::<Float64Array>(column).value(row))
+            }
+            ArrowDataType::Int64 => {
+                builder.field(column_name, as_array::<Int64Array>(column).value(row))
+            }
+            ArrowDataType::UInt64 => {
+                let value = as_array::<UInt64Array>(column).value(row);
+                let value = i64::try_from(value)
+                    .ok()
+                    .context(UnsignedOutOfRange { column_name, value })?;
+                builder.field(column_name, value)
+            }
+            ArrowDataType::Utf8 => {
+                builder.field(column_name, as_array::<StringArray>(column).value(row))
+            }
+            ArrowDataType::Boolean => {
+                builder.field(column_name, as_array::<BooleanArray>(column).value(row))
+            }
+            data_type => unreachable!("field column type validated: {:?}", data_type),
+        };
+        has_fields = true;
+    }
+    ensure!(has_fields, NoFields);
+
+    let point = builder.build().context(ConvertingRow)?;
+
+    let mut lp_data = Vec::new();
+    point
+        .write_data_point_to(&mut lp_data)
+        .context(WritingLineProtocol)?;
+    lp_data.pop(); // trailing newline
+
+    Ok(String::from_utf8(lp_data).expect("line protocol is valid utf8"))
+}
+
+fn as_array<T: 'static>(column: &ArrayRef) -> &T {
+    column
+        .as_any()
+        .downcast_ref::<T>()
+        .expect("column type validated")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_deps::{
+        arrow::datatypes::Field as ArrowField, parquet::arrow::arrow_writer::ArrowWriter,
+    };
+    use storage::exec::fieldlist::Field;
+
+    use super::*;
+
+    fn mapping() -> ParquetMapping {
+        ParquetMapping {
+            measurement: "h2o".into(),
+            tag_columns: vec!["state".into()],
+            time_column: "time".into(),
+        }
+    }
+
+    /// Encodes `columns` as a Parquet file
+    fn to_parquet(columns: Vec<(&str, ArrayRef)>) -> Vec<u8> {
+        let fields = columns
+            .iter()
+            .map(|(name, array)| ArrowField::new(name, array.data_type().clone(), true))
+            .collect();
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(
+            Arc::clone(&schema),
+            columns.into_iter().map(|(_, array)| array).collect(),
+        )
+        .expect("created record batch");
+
+        let dir = test_helpers::tmp_dir().expect("created temp dir");
+        let path = dir.path().join("data.parquet");
+        let file = std::fs::File::create(&path).expect("created parquet file");
+
+        let mut writer = ArrowWriter::try_new(file, schema, None).expect("created writer");
+        writer.write(&batch).expect("wrote batch");
+        writer.close().expect("closed writer");
+
+        std::fs::read(&path).expect("read parquet file")
+    }
+
+    #[test]
+    fn test_parquet_to_lp() {
+        let data = to_parquet(vec![
+            (
+                "state",
+                Arc::new(StringArray::from(vec![Some("MA"), None, Some("CA")])) as ArrayRef,
+            ),
+            (
+                "temp",
+                Arc::new(Float64Array::from(vec![Some(70.5), Some(71.0), None])) as ArrayRef,
+            ),
+            (
+                "reading",
+                Arc::new(UInt64Array::from(vec![Some(3), None, Some(u64::MAX)])) as ArrayRef,
+            ),
+            (
+                "valid",
+                Arc::new(BooleanArray::from(vec![Some(true), Some(false), None])) as ArrayRef,
+            ),
+            (
+                "time",
+                Arc::new(Int64Array::from(vec![Some(100), Some(200), Some(300)])) as ArrayRef,
+            ),
+        ]);
+
+        let converted = parquet_to_lp(&mapping(), data).unwrap();
+
+        assert_eq!(
+            converted.lines,
+            vec![
+                "h2o,state=MA reading=3i,temp=70.5,valid=true 100",
+                "h2o temp=71,valid=false 200",
+            ]
+        );
+
+        let errors = converted
+            .errors
+            .iter()
+            .map(|(row, e)| format!("{}: {}", row, e))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec!["2: Value 18446744073709551615 of column 'reading' is too large for an integer field"]
+        );
+
+        assert_eq!(converted.tags, vec!["state"]);
+        assert_eq!(
+            converted.fields,
+            vec![
+                ("temp".to_string(), ArrowDataType::Float64),
+                ("reading".to_string(), ArrowDataType::Int64),
+                ("valid".to_string(), ArrowDataType::Boolean),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parquet_to_lp_row_errors() {
+        let data = to_parquet(vec![
+            (
+                "state",
+                Arc::new(StringArray::from(vec!["MA", "MA"])) as ArrayRef,
+            ),
+            (
+                "temp",
+                Arc::new(Float64Array::from(vec![Some(70.5), None])) as ArrayRef,
+            ),
+            (
+                "time",
+                Arc::new(Int64Array::from(vec![None, Some(200)])) as ArrayRef,
+            ),
+        ]);
+
+        let converted = parquet_to_lp(&mapping(), data).unwrap();
+
+        assert!(converted.lines.is_empty(), "{:?}", converted.lines);
+        let errors = converted
+            .errors
+            .iter()
+            .map(|(row, e)| format!("{}: {}", row, e))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            vec![
+                "0: Missing value for time column 'time'",
+                "1: Row has no field values",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parquet_to_lp_invalid_schema() {
+        let columns = || {
+            vec![
+                ("state", Arc::new(Int64Array::from(vec![1])) as ArrayRef),
+                ("temp", Arc::new(Float64Array::from(vec![70.5])) as ArrayRef),
+                ("time", Arc::new(StringArray::from(vec!["100"])) as ArrayRef),
+            ]
+        };
+
+        let make_mapping = |tag_columns: &[&str], time_column: &str| ParquetMapping {
+            measurement: "h2o".into(),
+            tag_columns: tag_columns.iter().map(|name| name.to_string()).collect(),
+            time_column: time_column.into(),
+        };
+
+        let cases = vec![
+            (
+                make_mapping(&[], "timestamp"),
+                "Column 'timestamp' not found in Parquet file",
+            ),
+            (
+                make_mapping(&[], "time"),
+                "Time column 'time' has type Utf8, expected an integer or timestamp",
+            ),
+            (
+                make_mapping(&["state"], "temp"),
+                "Tag column 'state' has type Int64, expected a string",
+            ),
+        ];
+
+        for (mapping, expected) in cases {
+            let e = parquet_to_lp(&mapping, to_parquet(columns())).unwrap_err();
+            assert_eq!(e.to_string(), expected);
+        }
+
+        assert!(matches!(
+            parquet_to_lp(&make_mapping(&[], "time"), b"not parquet".to_vec()).unwrap_err(),
+            Error::ReadingParquet { .. }
+        ));
+    }
+
+    #[test]
+    fn test_conflicting_columns() {
+        let converted = ConvertedParquet {
+            tags: vec!["state".into(), "city".into()],
+            fields: vec![
+                ("temp".into(), ArrowDataType::Float64),
+                ("reading".into(), ArrowDataType::Int64),
+                ("region".into(), ArrowDataType::Utf8),
+                ("valid".into(), ArrowDataType::Boolean),
+            ],
+            ..Default::default()
+        };
+
+        let existing_tags = vec!["state".to_string(), "region".to_string()]
+            .into_iter()
+            .collect::<BTreeSet<_>>();
+        let field = |name: &str, data_type| Field {
+            name: name.into(),
+            data_type,
+            last_timestamp: 0,
+        };
+        let existing_fields = FieldList {
+            fields: vec![
+                field("city", ArrowDataType::Utf8),
+                field("temp", ArrowDataType::Float64),
+                field("reading", ArrowDataType::Float64),
+            ],
+        };
+
+        assert_eq!(
+            converted.conflicting_columns(&existing_tags, &existing_fields),
+            vec!["city", "reading", "region"]
+        );
+    }
+}
@@ -0,0 +1,609 @@
+//! This module contains conversions between the Prometheus remote
+//! write / remote read protocols and the InfluxDB IOx data model.
+//!
+//! Each Prometheus time series is mapped onto the InfluxDB data model
+//! as follows:
+//!
+//! * The metric name (the `__name__` label) becomes the measurement
+//! * All other labels become tags
+//! * Each sample becomes a point with a single `value` field
+//! * Sample timestamps (milliseconds) are scaled to nanoseconds
+//!
+//! Remote read maps the other way, turning each `Query` into a
+//! `Predicate` and each resulting `SeriesSet` back into a time series.
+use arrow_deps::{
+    arrow::{
+        array::{Array, Float64Array, Int64Array},
+        datatypes::DataType,
+    },
+    datafusion::{
+        logical_plan::{Expr, Operator},
+        scalar::ScalarValue,
+    },
+};
+use generated_types::prometheus::{
+    label_matcher, Label, LabelMatcher, Query, ReadRequest, ReadResponse, Sample, TimeSeries,
+    WriteRequest,
+};
+use influxdb2_client::{data_point::DataPointError, DataPoint, WriteDataPoint};
+use storage::{
+    exec::seriesset::SeriesSet,
+    predicate::{Predicate, PredicateBuilder},
+};
+
+use prost::Message;
+use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::debug;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error decompressing snappy encoded body: {}", source))]
+    DecompressingSnappy { source: snap::Error },
+
+    #[snafu(display("Error decoding remote write request: {}", source))]
+    DecodingWriteRequest { source: prost::DecodeError },
+
+    #[snafu(display("Time series has no metric name ('{}' label)", METRIC_NAME_LABEL))]
+    MissingMetricName {},
+
+    #[snafu(display("Error converting sample of metric '{}': {}", metric_name, source))]
+    ConvertingSample {
+        metric_name: String,
+        source: DataPointError,
+    },
+
+    #[snafu(display(
+        "Timestamp {}ms of sample of metric '{}' is out of range",
+        timestamp,
+        metric_name
+    ))]
+    TimestampOutOfRange { metric_name: String, timestamp: i64 },
+
+    #[snafu(display("Error writing line protocol: {}", source))]
+    WritingLineProtocol { source: std::io::Error },
+
+    #[snafu(display("Error decoding remote read request: {}", source))]
+    DecodingReadRequest { source: prost::DecodeError },
+
+    #[snafu(display("Error encoding remote read response: {}", source))]
+    EncodingReadResponse { source: prost::EncodeError },
+
+    #[snafu(display("Error compressing remote read response: {}", source))]
+    CompressingSnappy { source: snap::Error },
+
+    #[snafu(display("Unknown label matcher type {} for label '{}'", matcher_type, name))]
+    UnknownMatcherType { name: String, matcher_type: i32 },
+
+    #[snafu(display(
+        "Unsupported label matcher '{}' ({:?} '{}'): only non empty equality, inequality and \
+         simple alternation (e.g. 'a|b') regular expressions are supported",
+        name,
+        matcher_type,
+        value
+    ))]
+    UnsupportedMatcher {
+        name: String,
+        matcher_type: label_matcher::Type,
+        value: String,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The label Prometheus uses to store the metric name
+pub const METRIC_NAME_LABEL: &str = "__name__";
+
+/// The name of the field that sample values are stored in
+pub const VALUE_FIELD_NAME: &str = "value";
+
+const NANOS_PER_MILLI: i64 = 1_000_000;
+
+/// Decodes a snappy compressed, protobuf encoded `WriteRequest`, the
+/// body format used by Prometheus remote write
+pub fn decode_write_request(body: &[u8]) -> Result<WriteRequest> {
+    let decompressed = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .context(DecompressingSnappy)?;
+
+    WriteRequest::decode(&decompressed[..]).context(DecodingWriteRequest)
+}
+
+/// Decodes a snappy compressed, protobuf encoded `ReadRequest`, the
+/// body format used by Prometheus remote read
+pub fn decode_read_request(body: &[u8]) -> Result<ReadRequest> {
+    let decompressed = snap::raw::Decoder::new()
+        .decompress_vec(body)
+        .context(DecompressingSnappy)?;
+
+    ReadRequest::decode(&decompressed[..]).context(DecodingReadRequest)
+}
+
+/// Encodes `read_response` as snappy compressed protobuf, the body
+/// format expected by Prometheus remote read
+pub fn encode_read_response(read_response: &ReadResponse) -> Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    read_response
+        .encode(&mut encoded)
+        .context(EncodingReadResponse)?;
+
+    snap::raw::Encoder::new()
+        .compress_vec(&encoded)
+        .context(CompressingSnappy)
+}
+
+/// Converts all samples in `write_request` into line protocol, one
+/// line per sample.
+///
+/// Line protocol can not represent non finite floating point values
+/// so samples with `NaN` or infinite values (e.g. Prometheus' stale
+/// markers) are skipped.
+pub fn write_request_to_lp(write_request: &WriteRequest) -> Result<String> {
+    let mut lp_data = Vec::new();
+
+    for series in &write_request.timeseries {
+        let metric_name = series
+            .labels
+            .iter()
+            .find(|label| label.name == METRIC_NAME_LABEL)
+            .map(|label| label.value.as_str())
+            .context(MissingMetricName)?;
+
+        for sample in &series.samples {
+            if !sample.value.is_finite() {
+                debug!(
+                    "Skipping non finite sample {} for metric {}",
+                    sample.value, metric_name
+                );
+                continue;
+            }
+
+            // Labels with empty values are the same as missing labels
+            // in Prometheus, and are not valid line protocol tags
+            let builder = series
+                .labels
+                .iter()
+                .filter(|label| label.name != METRIC_NAME_LABEL && !label.value.is_empty())
+                .fold(DataPoint::builder(metric_name), |builder, label| {
+                    builder.tag(&label.name, &label.value)
+                });
+
+            let timestamp =
+                sample
+                    .timestamp
+                    .checked_mul(NANOS_PER_MILLI)
+                    .context(TimestampOutOfRange {
+                        metric_name,
+                        timestamp: sample.timestamp,
+                    })?;
+            let point = builder
+                .field(VALUE_FIELD_NAME, sample.value)
+                .timestamp(timestamp)
+                .build()
+                .context(ConvertingSample { metric_name })?;
+
+            point
+                .write_data_point_to(&mut lp_data)
+                .context(WritingLineProtocol)?;
+        }
+    }
+
+    Ok(String::from_utf8(lp_data).expect("line protocol is valid utf8"))
+}
+
+/// Translates the time range and label matchers of a remote read
+/// `Query` into a `Predicate` selecting the `value` field.
+///
+/// Matchers on the metric name restrict the tables, all other
+/// matchers become tag expressions. Regular expression matchers are
+/// only supported if they are a simple alternation of literal values
+/// (e.g. `a|b`), which Prometheus treats as fully anchored.
+pub fn query_to_predicate(query: &Query) -> Result<Predicate> {
+    // Prometheus time ranges are inclusive of the end time. Times
+    // beyond the range of nanosecond timestamps are clamped to it.
+    let mut builder = PredicateBuilder::default()
+        .timestamp_range(
+            query.start_timestamp_ms.saturating_mul(NANOS_PER_MILLI),
+            query
+                .end_timestamp_ms
+                .saturating_add(1)
+                .saturating_mul(NANOS_PER_MILLI),
+        )
+        .field_columns(vec![VALUE_FIELD_NAME.to_string()]);
+
+    let mut table_names = None;
+
+    for matcher in &query.matchers {
+        let matcher_type =
+            label_matcher::Type::from_i32(matcher.r#type).context(UnknownMatcherType {
+                name: &matcher.name,
+                matcher_type: matcher.r#type,
+            })?;
+
+        let values = match matcher_type {
+            label_matcher::Type::Eq | label_matcher::Type::Neq => Some(vec![matcher.value.clone()]),
+            label_matcher::Type::Re | label_matcher::Type::Nre => {
+                simple_alternation(&matcher.value)
+            }
+        }
+        // matching the empty string selects series *without* the label
+        .filter(|values| values.iter().all(|value| !value.is_empty()));
+
+        let values = values.context(UnsupportedMatcher {
+            name: &matcher.name,
+            matcher_type,
+            value: &matcher.value,
+        })?;
+
+        let negated = matches!(
+            matcher_type,
+            label_matcher::Type::Neq | label_matcher::Type::Nre
+        );
+
+        if matcher.name == METRIC_NAME_LABEL {
+            // tables can only be restricted once, and only positively
+            if negated || table_names.is_some() {
+                return unsupported_matcher(matcher, matcher_type);
+            }
+            table_names = Some(values);
+        } else {
+            builder = builder.add_expr(tag_expr(&matcher.name, values, negated));
+        }
+    }
+
+    if let Some(table_names) = table_names {
+        builder = builder.tables(table_names);
+    }
+
+    Ok(builder.build())
+}
+
+fn unsupported_matcher(
+    matcher: &LabelMatcher,
+    matcher_type: label_matcher::Type,
+) -> Result<Predicate> {
+    UnsupportedMatcher {
+        name: &matcher.name,
+        matcher_type,
+        value: &matcher.value,
+    }
+    .fail()
+}
+
+/// Returns the alternatives of `regex` if it is made up only of
+/// literal values separated by `|`, and `None` otherwise
+fn simple_alternation(regex: &str) -> Option<Vec<String>> {
+    const META_CHARACTERS: &str = r"\.+*?()[]{}^$";
+
+    if regex.chars().any(|c| META_CHARACTERS.contains(c)) {
+        return None;
+    }
+
+    Some(regex.split('|').map(|value| value.to_string()).collect())
+}
+
+/// Makes `(tag = v1 OR tag = v2 ...)`, or if `negated`,
+/// `(tag != v1 AND tag != v2 ...)`
+fn tag_expr(tag_name: &str, values: Vec<String>, negated: bool) -> Expr {
+    let make_expr = |value: String| Expr::BinaryExpr {
+        left: Box::new(Expr::Column(tag_name.to_string())),
+        op: if negated {
+            Operator::NotEq
+        } else {
+            Operator::Eq
+        },
+        right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(value)))),
+    };
+
+    let combine = |left: Expr, right: Expr| Expr::BinaryExpr {
+        left: Box::new(left),
+        op: if negated { Operator::And } else { Operator::Or },
+        right: Box::new(right),
+    };
+
+    let mut exprs = values.into_iter().map(make_expr);
+    let first = exprs.next().expect("at least one value");
+    exprs.fold(first, combine)
+}
+
+/// Converts the numeric fields of a `SeriesSet` into Prometheus time
+/// series, labeled with the metric (table) name and the series'
+/// tags. Timestamps are scaled back down to milliseconds and null
+/// values are skipped.
+pub fn series_set_to_time_series(series_set: &SeriesSet) -> Vec<TimeSeries> {
+    let batch = &series_set.batch;
+    let rows = series_set.start_row..series_set.start_row + series_set.num_rows;
+
+    let timestamps = batch
+        .column(series_set.timestamp_index)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .unwrap();
+
+    let mut labels = vec![Label {
+        name: METRIC_NAME_LABEL.to_string(),
+        value: series_set.table_name.to_string(),
+    }];
+    labels.extend(
+        series_set
+            .tags
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(name, value)| Label {
+                name: name.to_string(),
+                value: value.to_string(),
+            }),
+    );
+    labels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut time_series = Vec::new();
+    for &field_index in series_set.field_indices.iter() {
+        let array = batch.column(field_index);
+
+        let values: Vec<Option<f64>> = match array.data_type() {
+            DataType::Float64 => {
+                let array = array.as_any().downcast_ref::<Float64Array>().unwrap();
+                rows.clone()
+                    .map(|row| Some(array.value(row)).filter(|_| array.is_valid(row)))
+                    .collect()
+            }
+            DataType::Int64 => {
+                let array = array.as_any().downcast_ref::<Int64Array>().unwrap();
+                rows.clone()
+                    .map(|row| Some(array.value(row) as f64).filter(|_| array.is_valid(row)))
+                    .collect()
+            }
+            data_type => {
+                debug!(
+                    "Skipping field {} of type {:?} in remote read",
+                    batch.schema().field(field_index).name(),
+                    data_type
+                );
+                continue;
+            }
+        };
+
+        let samples = rows
+            .clone()
+            .zip(values)
+            .filter_map(|(row, value)| {
+                value.map(|value| Sample {
+                    value,
+                    timestamp: timestamps.value(row) / NANOS_PER_MILLI,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if !samples.is_empty() {
+            time_series.push(TimeSeries {
+                labels: labels.clone(),
+                samples,
+            });
+        }
+    }
+
+    time_series
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+    use storage::predicate::TimestampRange;
+
+    #[test]
+    fn test_write_request_to_lp() {
+        let write_request = WriteRequest {
+            timeseries: vec![
+                time_series(
+                    &[("__name__", "cpu_seconds"), ("mode", "idle"), ("host", "a")],
+                    &[(1.5, 1_600_000_000_000), (2.0, 1_600_000_001_000)],
+                ),
+                time_series(&[("__name__", "up"), ("job", "")], &[(1.0, 1000)]),
+            ],
+        };
+
+        let lp_data = write_request_to_lp(&write_request).unwrap();
+
+        assert_eq!(
+            lp_data,
+            "cpu_seconds,host=a,mode=idle value=1.5 1600000000000000000\n\
+             cpu_seconds,host=a,mode=idle value=2 1600000001000000000\n\
+             up value=1 1000000000\n"
+        );
+    }
+
+    #[test]
+    fn test_write_request_skips_non_finite() {
+        let write_request = WriteRequest {
+            timeseries: vec![time_series(
+                &[("__name__", "up")],
+                &[(f64::NAN, 1000), (f64::INFINITY, 2000), (1.0, 3000)],
+            )],
+        };
+
+        let lp_data = write_request_to_lp(&write_request).unwrap();
+
+        assert_eq!(lp_data, "up value=1 3000000000\n");
+    }
+
+    #[test]
+    fn test_write_request_missing_metric_name() {
+        let write_request = WriteRequest {
+            timeseries: vec![time_series(&[("host", "a")], &[(1.0, 1000)])],
+        };
+
+        let err = write_request_to_lp(&write_request).unwrap_err();
+
+        assert!(matches!(err, Error::MissingMetricName {}));
+    }
+
+    #[test]
+    fn test_write_request_timestamp_out_of_range() {
+        let write_request = WriteRequest {
+            timeseries: vec![time_series(&[("__name__", "up")], &[(1.0, i64::MAX)])],
+        };
+
+        let err = write_request_to_lp(&write_request).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Timestamp {}ms of sample of metric 'up' is out of range",
+                i64::MAX
+            )
+        );
+    }
+
+    #[test]
+    fn test_decode_write_request() {
+        let write_request = WriteRequest {
+            timeseries: vec![time_series(&[("__name__", "up")], &[(1.0, 1000)])],
+        };
+
+        let mut encoded = Vec::new();
+        write_request.encode(&mut encoded).unwrap();
+        let compressed = snap::raw::Encoder::new().compress_vec(&encoded).unwrap();
+
+        assert_eq!(decode_write_request(&compressed).unwrap(), write_request);
+
+        // not snappy compressed
+        let err = decode_write_request(b"\xff\xff\xff\xff\xff\xff").unwrap_err();
+        assert!(matches!(err, Error::DecompressingSnappy { .. }));
+
+        // snappy compressed, but not a protobuf
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(b"\xff\xff\xff not a protobuf")
+            .unwrap();
+        let err = decode_write_request(&compressed).unwrap_err();
+        assert!(matches!(err, Error::DecodingWriteRequest { .. }));
+    }
+
+    #[test]
+    fn test_query_to_predicate() {
+        let query = Query {
+            start_timestamp_ms: 1000,
+            end_timestamp_ms: 2000,
+            matchers: vec![
+                matcher(label_matcher::Type::Eq, "__name__", "cpu_seconds"),
+                matcher(label_matcher::Type::Neq, "host", "a"),
+                matcher(label_matcher::Type::Re, "mode", "idle|user"),
+                matcher(label_matcher::Type::Nre, "cpu", "0|1"),
+            ],
+        };
+
+        let predicate = query_to_predicate(&query).unwrap();
+
+        assert_eq!(predicate.table_names, Some(to_set(&["cpu_seconds"])));
+        assert_eq!(predicate.field_columns, Some(to_set(&["value"])));
+        assert_eq!(
+            predicate.range,
+            Some(TimestampRange::new(1_000_000_000, 2_001_000_000))
+        );
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            "[#host NotEq Utf8(\"a\"), \
+             #mode Eq Utf8(\"idle\") Or #mode Eq Utf8(\"user\"), \
+             #cpu NotEq Utf8(\"0\") And #cpu NotEq Utf8(\"1\")]"
+        );
+    }
+
+    #[test]
+    fn test_query_to_predicate_extreme_bounds() {
+        let query = Query {
+            start_timestamp_ms: i64::MIN,
+            end_timestamp_ms: i64::MAX,
+            matchers: vec![matcher(label_matcher::Type::Eq, "__name__", "up")],
+        };
+
+        let predicate = query_to_predicate(&query).unwrap();
+
+        assert_eq!(
+            predicate.range,
+            Some(TimestampRange::new(i64::MIN, i64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_query_to_predicate_metric_alternation() {
+        let query = Query {
+            start_timestamp_ms: 0,
+            end_timestamp_ms: 0,
+            matchers: vec![matcher(label_matcher::Type::Re, "__name__", "up|down")],
+        };
+
+        let predicate = query_to_predicate(&query).unwrap();
+
+        assert_eq!(predicate.table_names, Some(to_set(&["down", "up"])));
+        assert!(predicate.exprs.is_empty());
+    }
+
+    #[test]
+    fn test_query_to_predicate_unsupported() {
+        let unsupported = vec![
+            matcher(label_matcher::Type::Re, "mode", "id.*"),
+            matcher(label_matcher::Type::Nre, "mode", "(idle)"),
+            matcher(label_matcher::Type::Eq, "mode", ""),
+            matcher(label_matcher::Type::Re, "mode", "idle|"),
+            matcher(label_matcher::Type::Neq, "__name__", "up"),
+        ];
+
+        for matcher in unsupported {
+            let query = Query {
+                start_timestamp_ms: 0,
+                end_timestamp_ms: 0,
+                matchers: vec![matcher.clone()],
+            };
+
+            let err = query_to_predicate(&query).unwrap_err();
+            assert!(
+                matches!(err, Error::UnsupportedMatcher { .. }),
+                "unexpected result for {:?}: {}",
+                matcher,
+                err
+            );
+        }
+
+        let query = Query {
+            start_timestamp_ms: 0,
+            end_timestamp_ms: 0,
+            matchers: vec![LabelMatcher {
+                r#type: 42,
+                name: "mode".into(),
+                value: "idle".into(),
+            }],
+        };
+        let err = query_to_predicate(&query).unwrap_err();
+        assert!(matches!(err, Error::UnknownMatcherType { .. }));
+    }
+
+    fn matcher(matcher_type: label_matcher::Type, name: &str, value: &str) -> LabelMatcher {
+        LabelMatcher {
+            r#type: matcher_type as i32,
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    fn to_set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn time_series(labels: &[(&str, &str)], samples: &[(f64, i64)]) -> TimeSeries {
+        TimeSeries {
+            labels: labels
+                .iter()
+                .map(|(name, value)| Label {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+                .collect(),
+            samples: samples
+                .iter()
+                .map(|(value, timestamp)| Sample {
+                    value: *value,
+                    timestamp: *timestamp,
+                })
+                .collect(),
+        }
+    }
+}
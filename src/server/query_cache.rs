@@ -0,0 +1,257 @@
+//! This module contains an opt-in cache for the serialized responses
+//! of read requests, so that dashboards re-issuing identical queries
+//! don't run them again while their database is unchanged.
+//!
+//! The cache holds at most a fixed number of responses, evicting the
+//! least recently used one when full, and responses expire after a
+//! fixed time to live. Writes must `invalidate` the cached responses
+//! of their database before they are acknowledged.
+//!
+//! A query which started before an invalidation may finish after it,
+//! with results that don't reflect the write. Such results are not
+//! cached: `insert` requires the `generation` of the database from
+//! before the query ran, which each invalidation increments.
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+
+/// Identifies a cached response
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueryCacheKey {
+    pub database: String,
+    /// The query, with runs of whitespace collapsed into single spaces
+    pub sql: String,
+    /// The format of the response
+    pub format: &'static str,
+}
+
+impl QueryCacheKey {
+    pub fn new(database: impl Into<String>, sql: &str, format: &'static str) -> Self {
+        Self {
+            database: database.into(),
+            sql: sql.split_whitespace().collect::<Vec<_>>().join(" "),
+            format,
+        }
+    }
+}
+
+/// A response served from the cache
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub body: Bytes,
+    /// How long ago the response was computed
+    pub age: Duration,
+}
+
+#[derive(Debug)]
+pub struct QueryCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<QueryCacheKey, CacheEntry>,
+
+    /// The keys of `entries` by their last use, least recently used first
+    lru: BTreeMap<u64, QueryCacheKey>,
+
+    /// The id of the next use of an entry
+    next_use: u64,
+
+    /// The number of invalidations of each database
+    generations: HashMap<String, u64>,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    body: Bytes,
+    created: Instant,
+    last_use: u64,
+}
+
+impl CacheState {
+    fn next_use(&mut self) -> u64 {
+        let next_use = self.next_use;
+        self.next_use += 1;
+        next_use
+    }
+
+    fn remove(&mut self, key: &QueryCacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.last_use);
+        }
+    }
+}
+
+impl QueryCache {
+    /// Creates a cache holding up to `capacity` responses for at most
+    /// `ttl` each
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns the cached response for `key`, unless there is none or
+    /// it has expired
+    pub fn get(&self, key: &QueryCacheKey) -> Option<CachedResponse> {
+        let mut state = self.state.lock().expect("query cache mutex poisoned");
+        let age = state.entries.get(key)?.created.elapsed();
+
+        if age >= self.ttl {
+            state.remove(key);
+            return None;
+        }
+
+        let last_use = state.next_use();
+        let entry = state.entries.get_mut(key).expect("entry is present");
+        let previous_use = std::mem::replace(&mut entry.last_use, last_use);
+        let body = entry.body.clone();
+        state.lru.remove(&previous_use);
+        state.lru.insert(last_use, key.clone());
+
+        Some(CachedResponse { body, age })
+    }
+
+    /// Returns the generation of `database`, which must be read before
+    /// running a query whose response is then inserted
+    pub fn generation(&self, database: &str) -> u64 {
+        let state = self.state.lock().expect("query cache mutex poisoned");
+        state.generations.get(database).copied().unwrap_or_default()
+    }
+
+    /// Caches `body` as the response for `key`, unless the database
+    /// was invalidated since it was at `generation`
+    pub fn insert(&self, key: QueryCacheKey, generation: u64, body: Bytes) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("query cache mutex poisoned");
+        let current_generation = state
+            .generations
+            .get(&key.database)
+            .copied()
+            .unwrap_or_default();
+        if current_generation != generation {
+            return;
+        }
+
+        state.remove(&key);
+        while state.entries.len() >= self.capacity {
+            let least_recently_used = state
+                .lru
+                .values()
+                .next()
+                .cloned()
+                .expect("a full cache has entries");
+            state.remove(&least_recently_used);
+        }
+
+        let last_use = state.next_use();
+        state.lru.insert(last_use, key.clone());
+        state.entries.insert(
+            key,
+            CacheEntry {
+                body,
+                created: Instant::now(),
+                last_use,
+            },
+        );
+    }
+
+    /// Removes all cached responses for `database`
+    pub fn invalidate(&self, database: &str) {
+        let mut state = self.state.lock().expect("query cache mutex poisoned");
+        *state.generations.entry(database.to_string()).or_default() += 1;
+
+        let CacheState { entries, lru, .. } = &mut *state;
+        entries.retain(|key, entry| {
+            let keep = key.database != database;
+            if !keep {
+                lru.remove(&entry.last_use);
+            }
+            keep
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(database: &str, sql: &str) -> QueryCacheKey {
+        QueryCacheKey::new(database, sql, "pretty")
+    }
+
+    fn get_body(cache: &QueryCache, key: &QueryCacheKey) -> Option<Bytes> {
+        cache.get(key).map(|response| response.body)
+    }
+
+    #[test]
+    fn test_key_normalization() {
+        assert_eq!(
+            key("db", "select *\n  from cpu "),
+            key("db", "select * from cpu")
+        );
+        assert_ne!(
+            key("db", "select * from cpu"),
+            key("db", "select * from mem")
+        );
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cache = QueryCache::new(2, Duration::from_secs(60));
+        let (a, b, c) = (key("db", "a"), key("db", "b"), key("db", "c"));
+
+        cache.insert(a.clone(), 0, "A".into());
+        cache.insert(b.clone(), 0, "B".into());
+
+        // using a makes b the least recently used entry
+        assert_eq!(get_body(&cache, &a), Some("A".into()));
+        cache.insert(c.clone(), 0, "C".into());
+
+        assert_eq!(get_body(&cache, &a), Some("A".into()));
+        assert_eq!(get_body(&cache, &b), None);
+        assert_eq!(get_body(&cache, &c), Some("C".into()));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let cache = QueryCache::new(2, Duration::from_secs(0));
+        let a = key("db", "a");
+
+        cache.insert(a.clone(), 0, "A".into());
+        assert_eq!(get_body(&cache, &a), None);
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = QueryCache::new(10, Duration::from_secs(60));
+        let (a, b) = (key("db1", "a"), key("db2", "a"));
+
+        cache.insert(a.clone(), 0, "A".into());
+        cache.insert(b.clone(), 0, "B".into());
+
+        cache.invalidate("db1");
+        assert_eq!(get_body(&cache, &a), None);
+        assert_eq!(get_body(&cache, &b), Some("B".into()));
+
+        // results of queries that started before the invalidation are stale
+        assert_eq!(cache.generation("db1"), 1);
+        cache.insert(a.clone(), 0, "stale".into());
+        assert_eq!(get_body(&cache, &a), None);
+
+        cache.insert(a.clone(), 1, "A".into());
+        assert_eq!(get_body(&cache, &a), Some("A".into()));
+    }
+}
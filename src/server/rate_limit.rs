@@ -0,0 +1,140 @@
+//! This module contains an opt-in limit on the rate at which each org
+//! may write lines, so that one org writing too much can't slow down
+//! the writes of all others.
+//!
+//! Each org has a token bucket holding up to `burst` tokens, refilled
+//! at `lines_per_second` tokens per second. A write is only accepted
+//! while its org's bucket holds at least one token, and takes one
+//! token per line written. As the lines of a write are only known once
+//! its body has been read, a large write may leave the bucket in debt,
+//! delaying the org's next writes until it has been refilled.
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    lines_per_second: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<String, TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// May be negative, if the writes of the org took more tokens than
+    /// the bucket held
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    /// Limits each org to `lines_per_second` lines per second, with
+    /// bursts of up to `burst` lines
+    pub fn new(lines_per_second: u64, burst: u64) -> Self {
+        assert!(lines_per_second > 0, "lines_per_second must be positive");
+        Self {
+            lines_per_second: lines_per_second as f64,
+            burst: burst.max(1) as f64,
+            buckets: Default::default(),
+        }
+    }
+
+    /// Returns `Ok` if `org` may write now, or how long it has to wait
+    /// until it may
+    pub fn check(&self, org: &str) -> Result<(), Duration> {
+        self.check_at(org, Instant::now())
+    }
+
+    /// Takes one token for each of the `lines` lines written by `org`
+    pub fn consume(&self, org: &str, lines: usize) {
+        self.consume_at(org, lines, Instant::now())
+    }
+
+    fn check_at(&self, org: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().expect("rate limiter mutex poisoned");
+        let bucket = self.refilled_bucket(&mut buckets, org, now);
+
+        if bucket.tokens >= 1.0 {
+            Ok(())
+        } else {
+            let missing = 1.0 - bucket.tokens;
+            Err(Duration::from_secs_f64(missing / self.lines_per_second))
+        }
+    }
+
+    fn consume_at(&self, org: &str, lines: usize, now: Instant) {
+        let mut buckets = self.buckets.lock().expect("rate limiter mutex poisoned");
+        let bucket = self.refilled_bucket(&mut buckets, org, now);
+        bucket.tokens -= lines as f64;
+    }
+
+    /// Returns the bucket of `org`, with the tokens added since it was
+    /// last refilled
+    fn refilled_bucket<'a>(
+        &self,
+        buckets: &'a mut HashMap<String, TokenBucket>,
+        org: &str,
+        now: Instant,
+    ) -> &'a mut TokenBucket {
+        let burst = self.burst;
+        let bucket = buckets
+            .entry(org.to_string())
+            .or_insert_with(|| TokenBucket {
+                tokens: burst,
+                refilled: now,
+            });
+
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.lines_per_second).min(burst);
+        bucket.refilled = now;
+        bucket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let limiter = RateLimiter::new(4, 8);
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at("a", start), Ok(()));
+        limiter.consume_at("a", 10, start);
+
+        // 2 lines in debt, so one more token takes 0.75 seconds
+        assert_eq!(
+            limiter.check_at("a", start),
+            Err(Duration::from_millis(750))
+        );
+        assert_eq!(
+            limiter.check_at("a", start + Duration::from_millis(250)),
+            Err(Duration::from_millis(500))
+        );
+        assert_eq!(
+            limiter.check_at("a", start + Duration::from_millis(750)),
+            Ok(())
+        );
+
+        // other orgs have their own buckets
+        assert_eq!(limiter.check_at("b", start), Ok(()));
+    }
+
+    #[test]
+    fn test_refill_up_to_burst() {
+        let limiter = RateLimiter::new(4, 8);
+        let start = Instant::now();
+
+        limiter.consume_at("a", 8, start);
+        // a long pause only refills the bucket up to the burst size
+        let later = start + Duration::from_secs(60);
+        limiter.consume_at("a", 8, later);
+        assert_eq!(
+            limiter.check_at("a", later),
+            Err(Duration::from_millis(250))
+        );
+    }
+}
@@ -0,0 +1,407 @@
+//! This module contains the formats in which /api/v2/read returns
+//! query results. Clients choose a format with the `format` query
+//! parameter or, failing that, the `Accept` header. The default is a
+//! pretty printed table, which is meant for people rather than
+//! programs.
+//!
+//! The `json` format is an array with one object per row, mapping
+//! column names to values. Nulls become JSON nulls, as do non finite
+//! floats, and timestamps become RFC3339 strings.
+//!
+//! The `csv` format has a header row naming the columns, followed by
+//! the rows of all record batches.
+//!
+//! The `arrow` format is an Arrow IPC stream, for clients that process
+//! the results as Arrow themselves: a schema message followed by one
+//! message per record batch.
+use arrow_deps::arrow::{
+    self,
+    array::{
+        Array, BooleanArray, Float64Array, Int64Array, StringArray, TimestampNanosecondArray,
+        UInt64Array,
+    },
+    datatypes::{DataType, Schema, TimeUnit},
+    error::ArrowError,
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+    util::pretty,
+};
+use bytes::Bytes;
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+use std::sync::Arc;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unknown format '{}', expected one of pretty, json, csv or arrow",
+        format
+    ))]
+    UnknownFormat { format: String },
+
+    #[snafu(display("Error pretty printing results: {}", source))]
+    PrettyPrinting { source: ArrowError },
+
+    #[snafu(display("Error writing results as CSV: {}", source))]
+    WritingCsv { source: ArrowError },
+
+    #[snafu(display("Error writing results as an Arrow stream: {}", source))]
+    WritingArrowStream { source: ArrowError },
+
+    #[snafu(display(
+        "Unsupported data type {:?} of column '{}' for JSON results",
+        data_type,
+        column_name
+    ))]
+    UnsupportedDataType {
+        column_name: String,
+        data_type: DataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A format of query results
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFormat {
+    /// A table, as printed by `pretty_format_batches`
+    Pretty,
+    /// An array of row objects
+    Json,
+    /// Comma separated values with a header row
+    Csv,
+    /// An Arrow IPC stream
+    ArrowStream,
+}
+
+impl Default for ReadFormat {
+    fn default() -> Self {
+        Self::Pretty
+    }
+}
+
+impl ReadFormat {
+    /// Returns the format called `name`, the value of a `format` query
+    /// parameter
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "arrow" => Ok(Self::ArrowStream),
+            _ => UnknownFormat { format: name }.fail(),
+        }
+    }
+
+    /// Returns the format of the first media type listed by `accept`,
+    /// the value of an `Accept` header, that has a format. Quality
+    /// values are ignored.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .filter_map(|media_range| media_range.split(';').next())
+            .find_map(|media_type| match media_type.trim() {
+                "text/plain" => Some(Self::Pretty),
+                "application/json" => Some(Self::Json),
+                "text/csv" => Some(Self::Csv),
+                "application/vnd.apache.arrow.stream" => Some(Self::ArrowStream),
+                _ => None,
+            })
+    }
+
+    /// The name of the format, as accepted by `from_name`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Pretty => "pretty",
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::ArrowStream => "arrow",
+        }
+    }
+
+    /// The value of the `Content-Type` header of results in this format
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Pretty => "text/plain; charset=utf-8",
+            Self::Json => "application/json",
+            Self::Csv => "text/csv",
+            Self::ArrowStream => "application/vnd.apache.arrow.stream",
+        }
+    }
+
+    /// Serializes `batches` in this format
+    pub fn format(self, batches: &[RecordBatch]) -> Result<Bytes> {
+        match self {
+            Self::Pretty => {
+                let table = pretty::pretty_format_batches(batches).context(PrettyPrinting)?;
+                Ok(table.into())
+            }
+            Self::Json => {
+                let rows = batches_to_json(batches)?;
+                Ok(Value::Array(rows).to_string().into())
+            }
+            Self::Csv => {
+                let mut csv = Vec::new();
+                {
+                    // only writes the header before the first batch
+                    let mut writer = arrow::csv::Writer::new(&mut csv);
+                    for batch in batches {
+                        writer.write(batch).context(WritingCsv)?;
+                    }
+                }
+                Ok(csv.into())
+            }
+            Self::ArrowStream => {
+                let schema = match batches.first() {
+                    Some(batch) => batch.schema(),
+                    None => Arc::new(Schema::empty()),
+                };
+
+                let mut stream = Vec::new();
+                {
+                    let mut writer =
+                        StreamWriter::try_new(&mut stream, &schema).context(WritingArrowStream)?;
+                    for batch in batches {
+                        writer.write(batch).context(WritingArrowStream)?;
+                    }
+                    writer.finish().context(WritingArrowStream)?;
+                }
+                Ok(stream.into())
+            }
+        }
+    }
+}
+
+/// Converts the rows of `batches` into JSON objects
+fn batches_to_json(batches: &[RecordBatch]) -> Result<Vec<Value>> {
+    let mut rows = Vec::new();
+
+    for batch in batches {
+        let schema = batch.schema();
+        let mut batch_rows = vec![serde_json::Map::new(); batch.num_rows()];
+
+        for (field, column) in schema.fields().iter().zip(batch.columns()) {
+            let values = column_to_json(column.as_ref(), field.name())?;
+            for (row, value) in batch_rows.iter_mut().zip(values) {
+                row.insert(field.name().clone(), value);
+            }
+        }
+
+        rows.extend(batch_rows.into_iter().map(Value::Object));
+    }
+
+    Ok(rows)
+}
+
+/// Converts the values of `column` into JSON values
+fn column_to_json(column: &dyn Array, column_name: &str) -> Result<Vec<Value>> {
+    let values = match column.data_type() {
+        DataType::Float64 => json_values(column, |a: &Float64Array, row| a.value(row).into()),
+        DataType::Int64 => json_values(column, |a: &Int64Array, row| a.value(row).into()),
+        DataType::UInt64 => json_values(column, |a: &UInt64Array, row| a.value(row).into()),
+        DataType::Utf8 => json_values(column, |a: &StringArray, row| a.value(row).into()),
+        DataType::Boolean => json_values(column, |a: &BooleanArray, row| a.value(row).into()),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => {
+            json_values(column, |a: &TimestampNanosecondArray, row| {
+                Utc.timestamp_nanos(a.value(row))
+                    .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                    .into()
+            })
+        }
+        data_type => {
+            return UnsupportedDataType {
+                column_name,
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    };
+
+    Ok(values)
+}
+
+/// Converts the values of `column`, an `A`, with `value`, turning nulls
+/// into JSON nulls
+fn json_values<A: Array + 'static>(
+    column: &dyn Array,
+    value: impl Fn(&A, usize) -> Value,
+) -> Vec<Value> {
+    let column = column
+        .as_any()
+        .downcast_ref::<A>()
+        .expect("array matches its data type");
+
+    (0..column.len())
+        .map(|row| {
+            if column.is_null(row) {
+                Value::Null
+            } else {
+                value(column, row)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::{ArrayRef, Int32Array},
+        datatypes::Field,
+        ipc::reader::StreamReader,
+    };
+
+    fn batch(columns: Vec<(&str, ArrayRef)>) -> RecordBatch {
+        let fields = columns
+            .iter()
+            .map(|(name, array)| Field::new(name, array.data_type().clone(), true))
+            .collect();
+        RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            columns.into_iter().map(|(_, array)| array).collect(),
+        )
+        .expect("created record batch")
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(ReadFormat::from_name("json").unwrap(), ReadFormat::Json);
+        assert_eq!(
+            ReadFormat::from_name("xml").unwrap_err().to_string(),
+            "Unknown format 'xml', expected one of pretty, json, csv or arrow"
+        );
+    }
+
+    #[test]
+    fn test_from_accept() {
+        assert_eq!(
+            ReadFormat::from_accept("application/json"),
+            Some(ReadFormat::Json)
+        );
+        assert_eq!(
+            ReadFormat::from_accept("text/html, application/json;q=0.9, text/plain;q=0.5"),
+            Some(ReadFormat::Json)
+        );
+        assert_eq!(
+            ReadFormat::from_accept("text/plain; charset=utf-8"),
+            Some(ReadFormat::Pretty)
+        );
+        assert_eq!(ReadFormat::from_accept("text/csv"), Some(ReadFormat::Csv));
+        assert_eq!(
+            ReadFormat::from_accept("application/vnd.apache.arrow.stream"),
+            Some(ReadFormat::ArrowStream)
+        );
+        assert_eq!(ReadFormat::from_accept("*/*"), None);
+    }
+
+    #[test]
+    fn test_json() {
+        let batches = vec![
+            batch(vec![
+                (
+                    "host",
+                    Arc::new(StringArray::from(vec![Some("a"), None])) as ArrayRef,
+                ),
+                (
+                    "usage",
+                    Arc::new(Float64Array::from(vec![Some(1.5), Some(f64::NAN)])) as ArrayRef,
+                ),
+                (
+                    "count",
+                    Arc::new(Int64Array::from(vec![None, Some(2)])) as ArrayRef,
+                ),
+                (
+                    "time",
+                    Arc::new(TimestampNanosecondArray::from_vec(
+                        vec![100, 1_000_000_000],
+                        None,
+                    )) as ArrayRef,
+                ),
+            ]),
+            batch(vec![(
+                "up",
+                Arc::new(BooleanArray::from(vec![true])) as ArrayRef,
+            )]),
+        ];
+
+        let json = ReadFormat::Json.format(&batches).unwrap();
+        let json: Value = serde_json::from_slice(&json).unwrap();
+
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"host": "a", "usage": 1.5, "count": null, "time": "1970-01-01T00:00:00.000000100Z"},
+                {"host": null, "usage": null, "count": 2, "time": "1970-01-01T00:00:01Z"},
+                {"up": true},
+            ])
+        );
+    }
+
+    #[test]
+    fn test_csv() {
+        let columns = || {
+            vec![
+                (
+                    "host",
+                    Arc::new(StringArray::from(vec![Some("a"), None])) as ArrayRef,
+                ),
+                (
+                    "usage",
+                    Arc::new(Float64Array::from(vec![1.5, 2.5])) as ArrayRef,
+                ),
+            ]
+        };
+        let batches = vec![batch(columns()), batch(columns())];
+
+        let csv = ReadFormat::Csv.format(&batches).unwrap();
+
+        assert_eq!(
+            std::str::from_utf8(&csv).unwrap(),
+            "host,usage\na,1.5\n,2.5\na,1.5\n,2.5\n"
+        );
+    }
+
+    #[test]
+    fn test_arrow_stream() {
+        let batches = vec![
+            batch(vec![(
+                "usage",
+                Arc::new(Float64Array::from(vec![1.5, 2.5])) as ArrayRef,
+            )]),
+            batch(vec![(
+                "usage",
+                Arc::new(Float64Array::from(vec![3.5])) as ArrayRef,
+            )]),
+        ];
+
+        let stream = ReadFormat::ArrowStream.format(&batches).unwrap();
+        let reader = StreamReader::try_new(stream.as_ref()).unwrap();
+        assert_eq!(reader.schema(), batches[0].schema());
+
+        let read_batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read_batches.len(), batches.len());
+        for (read_batch, batch) in read_batches.iter().zip(&batches) {
+            assert_eq!(read_batch.columns(), batch.columns());
+        }
+
+        // without batches the stream has an empty schema
+        let stream = ReadFormat::ArrowStream.format(&[]).unwrap();
+        let reader = StreamReader::try_new(stream.as_ref()).unwrap();
+        assert_eq!(reader.schema().fields().len(), 0);
+        assert_eq!(reader.count(), 0);
+    }
+
+    #[test]
+    fn test_json_unsupported_data_type() {
+        let batches = vec![batch(vec![(
+            "count",
+            Arc::new(Int32Array::from(vec![1])) as ArrayRef,
+        )])];
+
+        assert_eq!(
+            ReadFormat::Json.format(&batches).unwrap_err().to_string(),
+            "Unsupported data type Int32 of column 'count' for JSON results"
+        );
+    }
+}
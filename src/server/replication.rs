@@ -0,0 +1,181 @@
+//! This module contains a replication sink, which forwards accepted
+//! writes to a secondary (standby) server via its /api/v2/write API.
+//!
+//! Forwarding happens in a background task, fed by a bounded queue, so
+//! replication never blocks or fails the client's write. When the
+//! queue is full (e.g. because the replica is down and the forwarder is
+//! retrying) further writes are dropped, counted and logged.
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, error, warn};
+
+/// Configures where and how writes are replicated
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// The base URL of the replica, e.g. `http://replica:8080`
+    pub target_url: String,
+
+    /// The token sent to the replica for authorization
+    pub auth_token: String,
+
+    /// The maximum number of writes queued for forwarding
+    pub buffer_size: usize,
+
+    /// How often a failed forward is retried before the write is dropped
+    pub max_retries: usize,
+
+    /// The delay before the first retry, doubled for each further retry
+    pub initial_backoff: Duration,
+
+    /// The maximum delay between retries
+    pub max_backoff: Duration,
+}
+
+impl ReplicationConfig {
+    pub fn new(target_url: impl Into<String>, auth_token: impl Into<String>) -> Self {
+        Self {
+            target_url: target_url.into(),
+            auth_token: auth_token.into(),
+            buffer_size: 1000,
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A write queued for forwarding
+#[derive(Debug)]
+struct ReplicatedWrite {
+    org: String,
+    bucket: String,
+    lp_data: String,
+}
+
+/// Counters describing the health of replication
+#[derive(Debug, Default)]
+pub struct ReplicationCounters {
+    /// Writes dropped because the queue was full
+    pub overflowed: AtomicUsize,
+
+    /// Writes dropped after exhausting all retries
+    pub failed: AtomicUsize,
+
+    /// Writes successfully forwarded to the replica
+    pub forwarded: AtomicUsize,
+}
+
+/// Queues writes for forwarding to a replica
+#[derive(Debug)]
+pub struct ReplicationSink {
+    tx: mpsc::Sender<ReplicatedWrite>,
+    counters: Arc<ReplicationCounters>,
+}
+
+impl ReplicationSink {
+    /// Creates a new sink and spawns the task forwarding its writes to
+    /// the replica described by `config`. Must be called from within
+    /// a tokio runtime.
+    pub fn new(config: ReplicationConfig) -> Self {
+        let (tx, rx) = mpsc::channel(config.buffer_size);
+        let counters = Arc::new(ReplicationCounters::default());
+
+        tokio::spawn(forward_writes(config, rx, Arc::clone(&counters)));
+
+        Self { tx, counters }
+    }
+
+    /// Queues `lp_data` for forwarding to the replica's `org` and
+    /// `bucket`. Never blocks: if the queue is full the write is
+    /// dropped and counted as overflowed.
+    pub fn replicate(&self, org: &str, bucket: &str, lp_data: impl Into<String>) {
+        let write = ReplicatedWrite {
+            org: org.to_string(),
+            bucket: bucket.to_string(),
+            lp_data: lp_data.into(),
+        };
+
+        match self.tx.clone().try_send(write) {
+            Ok(()) => {}
+            Err(TrySendError::Full(write)) => {
+                let overflowed = self.counters.overflowed.fetch_add(1, Ordering::Relaxed) + 1;
+                warn!(
+                    "Replication queue full, dropping write to org {} bucket {} ({} dropped so far)",
+                    write.org, write.bucket, overflowed
+                );
+            }
+            Err(TrySendError::Closed(write)) => {
+                self.counters.failed.fetch_add(1, Ordering::Relaxed);
+                error!(
+                    "Replication forwarder stopped, dropping write to org {} bucket {}",
+                    write.org, write.bucket
+                );
+            }
+        }
+    }
+
+    /// Returns the counters of this sink
+    pub fn counters(&self) -> &ReplicationCounters {
+        &self.counters
+    }
+}
+
+/// Forwards the writes received on `rx` one by one, retrying each
+/// with exponential backoff
+async fn forward_writes(
+    config: ReplicationConfig,
+    mut rx: mpsc::Receiver<ReplicatedWrite>,
+    counters: Arc<ReplicationCounters>,
+) {
+    let client = influxdb2_client::Client::new(&config.target_url, &config.auth_token);
+
+    while let Some(write) = rx.recv().await {
+        let mut backoff = config.initial_backoff;
+        let mut attempt = 0;
+
+        loop {
+            match client
+                .write_line_protocol(&write.org, &write.bucket, write.lp_data.clone())
+                .await
+            {
+                Ok(()) => {
+                    debug!(
+                        "Replicated write to org {} bucket {} to {}",
+                        write.org, write.bucket, config.target_url
+                    );
+                    counters.forwarded.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+                Err(e) if attempt < config.max_retries => {
+                    warn!(
+                        "Error replicating write to {} (attempt {}), retrying in {:?}: {}",
+                        config.target_url,
+                        attempt + 1,
+                        backoff,
+                        e
+                    );
+                    tokio::time::delay_for(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, config.max_backoff);
+                    attempt += 1;
+                }
+                Err(e) => {
+                    error!(
+                        "Error replicating write to {}, dropping it after {} attempts: {}",
+                        config.target_url,
+                        attempt + 1,
+                        e
+                    );
+                    counters.failed.fetch_add(1, Ordering::Relaxed);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -2,5 +2,6 @@
 
 pub mod data;
 pub mod expr;
+pub mod flight;
 pub mod input;
 pub mod storage;
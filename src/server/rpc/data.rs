@@ -1,19 +1,23 @@
 //! This module contains code to translate from InfluxDB IOx data
 //! formats into the formats needed by gRPC
 
-use std::{collections::BTreeSet, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
 
 use arrow_deps::arrow::{
-    array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+    array::{BooleanArray, Float64Array, Int64Array, StringArray},
     datatypes::DataType as ArrowDataType,
 };
 
 use storage::exec::{
     fieldlist::FieldList,
-    seriesset::{GroupDescription, GroupedSeriesSetItem, SeriesSet},
+    seriesset::{GroupedSeriesSetItem, SeriesSet},
 };
 
 use generated_types::{
+    aggregate::AggregateType,
     measurement_fields_response::{FieldType, MessageField},
     read_response::{
         frame::Data, BooleanPointsFrame, DataType, FloatPointsFrame, Frame, GroupFrame,
@@ -31,6 +35,12 @@ pub enum Error {
 
     #[snafu(display("Unsupported field data type in gRPC data translation: {}", type_name))]
     UnsupportedFieldType { type_name: String },
+
+    #[snafu(display("Unsupported aggregate {:?} for {} fields", aggregate, type_name))]
+    UnsupportedAggregate {
+        aggregate: AggregateType,
+        type_name: String,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -61,14 +71,20 @@ pub fn tag_keys_to_byte_vecs(tag_keys: Arc<BTreeSet<String>>) -> Vec<Vec<u8>> {
 ///
 /// The specific type of (*Points) depends on the type of field column.
 pub fn series_set_to_read_response(series_set: SeriesSet) -> Result<ReadResponse> {
-    let frames = series_set_to_frames(series_set)?;
+    let frames = series_set_to_frames(series_set, None)?;
     Ok(ReadResponse { frames })
 }
 
-fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
+/// Convert `series_set` into frames, reducing the points of each field
+/// to a single point if `aggregate` is specified (see
+/// `aggregate_points`)
+fn series_set_to_frames(
+    series_set: SeriesSet,
+    aggregate: Option<AggregateType>,
+) -> Result<Vec<Frame>> {
     let mut data_records = Vec::new();
     for field_index in series_set.field_indices.iter() {
-        field_to_data(&mut data_records, &series_set, *field_index)?
+        field_to_data(&mut data_records, &series_set, *field_index, aggregate)?
     }
 
     let frames = data_records
@@ -83,9 +99,59 @@ fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
     Ok(frames)
 }
 
-/// Convert `GroupedSeriesSetIem` into a form suitable for gRPC transport
+/// Collects the series sets produced for a read_group request into
+/// groups.
 ///
-/// Each `GroupedSeriesSetItem` gets converted into this pattern:
+/// The series sets of one group can arrive in several runs (e.g. one
+/// per table), so all of them are collected before any group is
+/// sent. Groups are ordered by the values of their group keys.
+#[derive(Debug, Default)]
+pub struct SeriesGroups {
+    /// group key values --> series sets of the group
+    groups: BTreeMap<Vec<Arc<String>>, Vec<SeriesSet>>,
+
+    /// The group key values of the most recently started group
+    current_group: Option<Vec<Arc<String>>>,
+}
+
+impl SeriesGroups {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the next item produced by the grouped series set converter
+    pub fn add(&mut self, item: GroupedSeriesSetItem) {
+        match item {
+            GroupedSeriesSetItem::GroupStart(group_description) => {
+                let partition_key_vals = group_description
+                    .tags
+                    .into_iter()
+                    .map(|(_, value)| value)
+                    .collect::<Vec<_>>();
+
+                self.groups
+                    .entry(partition_key_vals.clone())
+                    .or_insert_with(Vec::new);
+                self.current_group = Some(partition_key_vals);
+            }
+            GroupedSeriesSetItem::GroupData(series_set) => {
+                let current_group = self
+                    .current_group
+                    .as_ref()
+                    .expect("GroupStart precedes GroupData");
+
+                self.groups
+                    .get_mut(current_group)
+                    .expect("started group exists")
+                    .push(series_set);
+            }
+        }
+    }
+}
+
+/// Convert `SeriesGroups` into a form suitable for gRPC transport
+///
+/// Each group gets converted into this pattern:
 ///
 /// ```
 /// (GroupFrame)
@@ -102,52 +168,69 @@ fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
 /// (....)
 /// ```
 ///
+/// The `tag_keys` of the GroupFrame are the union of the tag keys of
+/// all series in the group, and its `partition_key_vals` are the
+/// values of the group keys. The series of a group are ordered by
+/// measurement and then tag values. If `aggregate` is specified the
+/// points of each field of each series are reduced to a single point
+/// (see `aggregate_points`).
+///
 /// The specific type of (*Points) depends on the type of field column.
-pub fn grouped_series_set_item_to_read_response(
-    grouped_series_set_item: GroupedSeriesSetItem,
-) -> Result<ReadResponse> {
-    let frames = match grouped_series_set_item {
-        GroupedSeriesSetItem::GroupStart(group_description) => {
-            group_description_to_frames(group_description)?
-        }
-        GroupedSeriesSetItem::GroupData(series_set) => series_set_to_frames(series_set)?,
-    };
-    Ok(ReadResponse { frames })
-}
+pub fn series_groups_to_read_responses(
+    series_groups: SeriesGroups,
+    aggregate: Option<AggregateType>,
+) -> Result<Vec<ReadResponse>> {
+    let mut responses = Vec::new();
 
-fn group_description_to_frames(group_description: GroupDescription) -> Result<Vec<Frame>> {
-    // split key=value pairs into two separate vectors
-    let (tag_keys, partition_key_vals): (Vec<Vec<u8>>, Vec<Vec<u8>>) = group_description
-        .tags
-        .into_iter()
-        .map(|(k, v)| (k.bytes().collect(), v.bytes().collect()))
-        .unzip();
+    for (partition_key_vals, mut series_sets) in series_groups.groups {
+        // the series sets of each table are already ordered by tag values
+        series_sets.sort_by(|a, b| a.table_name.cmp(&b.table_name));
 
-    let group_frame = GroupFrame {
-        tag_keys,
-        partition_key_vals,
-    };
+        let tag_keys = series_sets
+            .iter()
+            .flat_map(|series_set| series_set.tags.iter().map(|(key, _)| key.to_string()))
+            .collect::<BTreeSet<_>>();
+
+        let group_frame = GroupFrame {
+            tag_keys: tag_keys_to_byte_vecs(Arc::new(tag_keys)),
+            partition_key_vals: partition_key_vals
+                .iter()
+                .map(|value| value.bytes().collect())
+                .collect(),
+        };
+        responses.push(ReadResponse {
+            frames: vec![Frame {
+                data: Some(Data::Group(group_frame)),
+            }],
+        });
 
-    let data = Some(Data::Group(group_frame));
+        for series_set in series_sets {
+            let frames = series_set_to_frames(series_set, aggregate)?;
+            responses.push(ReadResponse { frames });
+        }
+    }
 
-    Ok(vec![Frame { data }])
+    Ok(responses)
 }
 
-fn data_type(array: &ArrayRef) -> Result<DataType> {
-    match array.data_type() {
-        ArrowDataType::Utf8 => Ok(DataType::String),
-        ArrowDataType::Float64 => Ok(DataType::Float),
-        ArrowDataType::Int64 => Ok(DataType::Integer),
-        ArrowDataType::Boolean => Ok(DataType::Boolean),
-        _ => UnsupportedDataType {
-            type_name: format!("{:?}", array.data_type()),
-        }
-        .fail(),
+/// The data type of the points in `points`
+fn points_data_type(points: &Data) -> DataType {
+    match points {
+        Data::StringPoints(_) => DataType::String,
+        Data::FloatPoints(_) => DataType::Float,
+        Data::IntegerPoints(_) => DataType::Integer,
+        Data::BooleanPoints(_) => DataType::Boolean,
+        _ => unreachable!("not a points frame: {:?}", points),
     }
 }
 
 // Convert and append a single field to a sequence of frames
-fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: usize) -> Result<()> {
+fn field_to_data(
+    frames: &mut Vec<Data>,
+    series_set: &SeriesSet,
+    field_index: usize,
+    aggregate: Option<AggregateType>,
+) -> Result<()> {
     let batch = &series_set.batch;
     let schema = batch.schema();
 
@@ -156,16 +239,6 @@ fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: us
     let start_row = series_set.start_row;
     let num_rows = series_set.num_rows;
 
-    let series_frame = SeriesFrame {
-        tags: convert_tags(
-            series_set.table_name.as_ref(),
-            schema.field(field_index).name(),
-            &series_set.tags,
-        ),
-        data_type: data_type(array)? as i32,
-    };
-    frames.push(Data::Series(series_frame));
-
     let timestamps = batch
         .column(series_set.timestamp_index)
         .as_any()
@@ -173,7 +246,7 @@ fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: us
         .unwrap()
         .extract_values(start_row, num_rows);
 
-    frames.push(match array.data_type() {
+    let points = match array.data_type() {
         ArrowDataType::Utf8 => {
             let values = array
                 .as_any()
@@ -212,10 +285,146 @@ fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: us
             }
             .fail();
         }
-    });
+    };
+
+    let points = match aggregate {
+        Some(aggregate) => aggregate_points(points, aggregate)?,
+        None => points,
+    };
+
+    let series_frame = SeriesFrame {
+        tags: convert_tags(
+            series_set.table_name.as_ref(),
+            schema.field(field_index).name(),
+            &series_set.tags,
+        ),
+        data_type: points_data_type(&points) as i32,
+    };
+    frames.push(Data::Series(series_frame));
+    frames.push(points);
+
     Ok(())
 }
 
+/// Reduces the points of one field of a series to (at most) a single
+/// point computed by `aggregate`.
+///
+/// `first`, `last`, `min` and `max` keep the time of the selected
+/// point, while `sum`, `count` and `mean` use the time of the last
+/// point. `count` always produces an integer and `mean` a float.
+/// String and boolean fields only support `count`, `first` and `last`.
+fn aggregate_points(points: Data, aggregate: AggregateType) -> Result<Data> {
+    let points = match (points, aggregate) {
+        (points, AggregateType::None) => points,
+        (points, AggregateType::Count) => {
+            let timestamps = match points {
+                Data::FloatPoints(FloatPointsFrame { timestamps, .. })
+                | Data::IntegerPoints(IntegerPointsFrame { timestamps, .. })
+                | Data::StringPoints(StringPointsFrame { timestamps, .. })
+                | Data::BooleanPoints(BooleanPointsFrame { timestamps, .. }) => timestamps,
+                _ => unreachable!("not a points frame: {:?}", points),
+            };
+            let count = timestamps.len() as i64;
+            let (timestamps, values) = reduce(&timestamps, || count);
+            Data::IntegerPoints(IntegerPointsFrame { timestamps, values })
+        }
+        (Data::FloatPoints(FloatPointsFrame { timestamps, values }), AggregateType::Sum) => {
+            let (timestamps, values) = reduce(&timestamps, || values.iter().sum());
+            Data::FloatPoints(FloatPointsFrame { timestamps, values })
+        }
+        (Data::IntegerPoints(IntegerPointsFrame { timestamps, values }), AggregateType::Sum) => {
+            let (timestamps, values) = reduce(&timestamps, || {
+                values
+                    .iter()
+                    .fold(0, |sum: i64, value| sum.wrapping_add(*value))
+            });
+            Data::IntegerPoints(IntegerPointsFrame { timestamps, values })
+        }
+        (Data::FloatPoints(FloatPointsFrame { timestamps, values }), AggregateType::Mean) => {
+            let (timestamps, values) = reduce(&timestamps, || {
+                values.iter().sum::<f64>() / values.len() as f64
+            });
+            Data::FloatPoints(FloatPointsFrame { timestamps, values })
+        }
+        (Data::IntegerPoints(IntegerPointsFrame { timestamps, values }), AggregateType::Mean) => {
+            let (timestamps, values) = reduce(&timestamps, || {
+                values.iter().map(|value| *value as f64).sum::<f64>() / values.len() as f64
+            });
+            Data::FloatPoints(FloatPointsFrame { timestamps, values })
+        }
+        (Data::FloatPoints(FloatPointsFrame { timestamps, values }), _) => {
+            let (timestamps, values) = select(timestamps, values, aggregate);
+            Data::FloatPoints(FloatPointsFrame { timestamps, values })
+        }
+        (Data::IntegerPoints(IntegerPointsFrame { timestamps, values }), _) => {
+            let (timestamps, values) = select(timestamps, values, aggregate);
+            Data::IntegerPoints(IntegerPointsFrame { timestamps, values })
+        }
+        (Data::StringPoints(StringPointsFrame { timestamps, values }), AggregateType::First)
+        | (Data::StringPoints(StringPointsFrame { timestamps, values }), AggregateType::Last) => {
+            let (timestamps, values) = select(timestamps, values, aggregate);
+            Data::StringPoints(StringPointsFrame { timestamps, values })
+        }
+        (Data::BooleanPoints(BooleanPointsFrame { timestamps, values }), AggregateType::First)
+        | (Data::BooleanPoints(BooleanPointsFrame { timestamps, values }), AggregateType::Last) => {
+            let (timestamps, values) = select(timestamps, values, aggregate);
+            Data::BooleanPoints(BooleanPointsFrame { timestamps, values })
+        }
+        (points, aggregate) => {
+            let type_name = match points {
+                Data::StringPoints(_) => "string",
+                Data::BooleanPoints(_) => "boolean",
+                _ => unreachable!("not a points frame: {:?}", points),
+            };
+            return UnsupportedAggregate {
+                aggregate,
+                type_name,
+            }
+            .fail();
+        }
+    };
+
+    Ok(points)
+}
+
+/// Returns a single point at the last of `timestamps` with the value
+/// computed by `value`, or no point if there are no timestamps
+fn reduce<T>(timestamps: &[i64], value: impl FnOnce() -> T) -> (Vec<i64>, Vec<T>) {
+    match timestamps.last() {
+        Some(&timestamp) => (vec![timestamp], vec![value()]),
+        None => (vec![], vec![]),
+    }
+}
+
+/// Returns the point selected by `aggregate` (which must be one of
+/// `first`, `last`, `min` or `max`), or no point if there are no
+/// points. Ties are resolved in favor of the earliest point.
+fn select<T: PartialOrd>(
+    timestamps: Vec<i64>,
+    mut values: Vec<T>,
+    aggregate: AggregateType,
+) -> (Vec<i64>, Vec<T>) {
+    let position = |is_better: fn(&T, &T) -> bool| {
+        (0..values.len()).fold(None, |best: Option<usize>, index| match best {
+            Some(best) if !is_better(&values[index], &values[best]) => Some(best),
+            _ => Some(index),
+        })
+    };
+
+    let index = match aggregate {
+        AggregateType::First => position(|_, _| false),
+        AggregateType::Last => values.len().checked_sub(1),
+        AggregateType::Min => position(|value, best| value < best),
+        AggregateType::Max => position(|value, best| value > best),
+        _ => unreachable!("{:?} does not select a point", aggregate),
+    };
+
+    match index {
+        Some(index) => (vec![timestamps[index]], vec![values.swap_remove(index)]),
+        None => (vec![], vec![]),
+    }
+}
+
 // Convert the tag=value pairs from the series set to the correct gRPC
 // format, and add the _f and _m tags for the field name and measurement
 fn convert_tags(
@@ -318,10 +527,11 @@ fn datatype_to_measurement_field_enum(data_type: &ArrowDataType) -> Result<Field
 #[cfg(test)]
 mod tests {
     use arrow_deps::arrow::{
+        array::ArrayRef,
         datatypes::{DataType as ArrowDataType, Field as ArrowField, Schema},
         record_batch::RecordBatch,
     };
-    use storage::exec::fieldlist::Field;
+    use storage::exec::{fieldlist::Field, seriesset::GroupDescription};
 
     use super::*;
 
@@ -368,28 +578,25 @@ mod tests {
     }
 
     #[test]
-    fn test_group_group_conversion() {
-        let group_description = GroupDescription {
-            tags: vec![
-                (Arc::new("tag1".into()), Arc::new("val1".into())),
-                (Arc::new("tag2".into()), Arc::new("val2".into())),
-            ],
-        };
+    fn test_series_groups_conversion() {
+        let responses = series_groups_to_read_responses(make_series_groups(), None)
+            .expect("Correctly converted series groups");
+        println!("Responses are: {:#?}", responses);
 
-        let grouped_series_set_item = GroupedSeriesSetItem::GroupStart(group_description);
+        let dumped_frames = dump_responses(&responses);
 
-        let response = grouped_series_set_item_to_read_response(grouped_series_set_item)
-            .expect("Correctly converted grouped_series_set_item");
-        println!("Response is: {:#?}", response);
-
-        let dumped_frames = response
-            .frames
-            .iter()
-            .map(|f| dump_frame(f))
-            .collect::<Vec<_>>();
-
-        let expected_frames =
-            vec!["GroupFrame, tag_keys: tag1,tag2, partition_key_vals: val1,val2"];
+        let expected_frames = vec![
+            "GroupFrame, tag_keys: _field,_measurement,tag_a,tag_b, partition_key_vals: a1",
+            "SeriesFrame, tags: _field=int_field,_measurement=m0,tag_a=a1,tag_b=b1, type: 1",
+            "IntegerPointsFrame, timestamps: [2000, 3000], values: \"2,3\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a1,tag_b=b1, type: 1",
+            "IntegerPointsFrame, timestamps: [1000, 2000], values: \"1,2\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a1,tag_b=b2, type: 1",
+            "IntegerPointsFrame, timestamps: [3000, 4000], values: \"3,4\"",
+            "GroupFrame, tag_keys: _field,_measurement,tag_a, partition_key_vals: a2",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a2, type: 1",
+            "IntegerPointsFrame, timestamps: [1000], values: \"1\"",
+        ];
 
         assert_eq!(
             dumped_frames, expected_frames,
@@ -399,51 +606,116 @@ mod tests {
     }
 
     #[test]
-    fn test_group_series_conversion() {
-        let schema = Arc::new(Schema::new(vec![
-            ArrowField::new("float_field", ArrowDataType::Float64, true),
-            ArrowField::new("time", ArrowDataType::Int64, true),
-        ]));
+    fn test_series_groups_conversion_aggregate() {
+        let responses =
+            series_groups_to_read_responses(make_series_groups(), Some(AggregateType::Sum))
+                .expect("Correctly converted series groups");
+        println!("Responses are: {:#?}", responses);
 
-        let float_array: ArrayRef = Arc::new(Float64Array::from(vec![10.1, 20.1, 30.1, 40.1]));
-        let timestamp_array: ArrayRef = Arc::new(Int64Array::from(vec![1000, 2000, 3000, 4000]));
+        let dumped_frames = dump_responses(&responses);
 
-        let batch = RecordBatch::try_new(schema, vec![float_array, timestamp_array])
-            .expect("created new record batch");
+        let expected_frames = vec![
+            "GroupFrame, tag_keys: _field,_measurement,tag_a,tag_b, partition_key_vals: a1",
+            "SeriesFrame, tags: _field=int_field,_measurement=m0,tag_a=a1,tag_b=b1, type: 1",
+            "IntegerPointsFrame, timestamps: [3000], values: \"5\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a1,tag_b=b1, type: 1",
+            "IntegerPointsFrame, timestamps: [2000], values: \"3\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a1,tag_b=b2, type: 1",
+            "IntegerPointsFrame, timestamps: [4000], values: \"7\"",
+            "GroupFrame, tag_keys: _field,_measurement,tag_a, partition_key_vals: a2",
+            "SeriesFrame, tags: _field=int_field,_measurement=m1,tag_a=a2, type: 1",
+            "IntegerPointsFrame, timestamps: [1000], values: \"1\"",
+        ];
 
-        let series_set = SeriesSet {
-            table_name: Arc::new("the_table".into()),
-            tags: vec![(Arc::new("tag1".into()), Arc::new("val1".into()))],
-            timestamp_index: 1,
-            field_indices: Arc::new(vec![0]),
-            start_row: 1,
-            num_rows: 2,
-            batch,
+        assert_eq!(
+            dumped_frames, expected_frames,
+            "Expected:\n{:#?}\nActual:\n{:#?}",
+            expected_frames, dumped_frames
+        );
+    }
+
+    #[test]
+    fn test_aggregate_points() {
+        let points = || {
+            Data::IntegerPoints(IntegerPointsFrame {
+                timestamps: vec![1000, 2000, 3000, 4000],
+                values: vec![3, 1, 5, 5],
+            })
         };
 
-        let grouped_series_set_item = GroupedSeriesSetItem::GroupData(series_set);
+        let cases = vec![
+            (
+                AggregateType::None,
+                "IntegerPointsFrame, timestamps: [1000, 2000, 3000, 4000], values: \"3,1,5,5\"",
+            ),
+            (
+                AggregateType::Sum,
+                "IntegerPointsFrame, timestamps: [4000], values: \"14\"",
+            ),
+            (
+                AggregateType::Count,
+                "IntegerPointsFrame, timestamps: [4000], values: \"4\"",
+            ),
+            (
+                AggregateType::Min,
+                "IntegerPointsFrame, timestamps: [2000], values: \"1\"",
+            ),
+            (
+                AggregateType::Max,
+                "IntegerPointsFrame, timestamps: [3000], values: \"5\"",
+            ),
+            (
+                AggregateType::First,
+                "IntegerPointsFrame, timestamps: [1000], values: \"3\"",
+            ),
+            (
+                AggregateType::Last,
+                "IntegerPointsFrame, timestamps: [4000], values: \"5\"",
+            ),
+            (
+                AggregateType::Mean,
+                "FloatPointsFrame, timestamps: [4000], values: \"3.5\"",
+            ),
+        ];
 
-        let response = grouped_series_set_item_to_read_response(grouped_series_set_item)
-            .expect("Correctly converted grouped_series_set_item");
+        for (aggregate, expected) in cases {
+            let data = aggregate_points(points(), aggregate).expect("aggregated points");
+            let actual = dump_frame(&Frame { data: Some(data) });
+            assert_eq!(actual, expected, "aggregate {:?}", aggregate);
+        }
 
-        println!("Response is: {:#?}", response);
+        // series without points stay empty
+        let empty = Data::FloatPoints(FloatPointsFrame {
+            timestamps: vec![],
+            values: vec![],
+        });
+        let data = aggregate_points(empty, AggregateType::Mean).expect("aggregated points");
+        assert_eq!(
+            dump_frame(&Frame { data: Some(data) }),
+            "FloatPointsFrame, timestamps: [], values: \"\""
+        );
 
-        let dumped_frames = response
-            .frames
-            .iter()
-            .map(|f| dump_frame(f))
-            .collect::<Vec<_>>();
+        let strings = || {
+            Data::StringPoints(StringPointsFrame {
+                timestamps: vec![1000, 2000],
+                values: vec!["foo".into(), "bar".into()],
+            })
+        };
 
-        let expected_frames = vec![
-            "SeriesFrame, tags: _field=float_field,_measurement=the_table,tag1=val1, type: 0",
-            "FloatPointsFrame, timestamps: [2000, 3000], values: \"20.1,30.1\"",
-        ];
+        let data = aggregate_points(strings(), AggregateType::Count).expect("aggregated points");
+        assert_eq!(
+            dump_frame(&Frame { data: Some(data) }),
+            "IntegerPointsFrame, timestamps: [2000], values: \"2\""
+        );
 
+        let data = aggregate_points(strings(), AggregateType::Last).expect("aggregated points");
         assert_eq!(
-            dumped_frames, expected_frames,
-            "Expected:\n{:#?}\nActual:\n{:#?}",
-            expected_frames, dumped_frames
+            dump_frame(&Frame { data: Some(data) }),
+            "StringPointsFrame, timestamps: [2000], values: bar"
         );
+
+        let e = aggregate_points(strings(), AggregateType::Sum).unwrap_err();
+        assert_eq!(e.to_string(), "Unsupported aggregate Sum for string fields");
     }
 
     #[test]
@@ -613,6 +885,66 @@ mod tests {
             .join(",")
     }
 
+    fn dump_responses(responses: &[ReadResponse]) -> Vec<String> {
+        responses
+            .iter()
+            .flat_map(|response| response.frames.iter())
+            .map(|f| dump_frame(f))
+            .collect()
+    }
+
+    /// Series of the measurements m0 and m1 with the tags tag_a and
+    /// tag_b, grouped by tag_a, as produced for one table after another
+    fn make_series_groups() -> SeriesGroups {
+        let schema = Arc::new(Schema::new(vec![
+            ArrowField::new("int_field", ArrowDataType::Int64, true),
+            ArrowField::new("time", ArrowDataType::Int64, true),
+        ]));
+
+        let int_array: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3, 4]));
+        let timestamp_array: ArrayRef = Arc::new(Int64Array::from(vec![1000, 2000, 3000, 4000]));
+
+        let batch = RecordBatch::try_new(schema, vec![int_array, timestamp_array])
+            .expect("created new record batch");
+
+        let group_start = |tag_a: &str| {
+            GroupedSeriesSetItem::GroupStart(GroupDescription {
+                tags: vec![(Arc::new("tag_a".into()), Arc::new(tag_a.into()))],
+            })
+        };
+
+        let group_data = |table_name: &str, tags: &[(&str, &str)], start_row, num_rows| {
+            GroupedSeriesSetItem::GroupData(SeriesSet {
+                table_name: Arc::new(table_name.into()),
+                tags: tags
+                    .iter()
+                    .map(|(key, value)| (Arc::new(key.to_string()), Arc::new(value.to_string())))
+                    .collect(),
+                timestamp_index: 1,
+                field_indices: Arc::new(vec![0]),
+                start_row,
+                num_rows,
+                batch: batch.clone(),
+            })
+        };
+
+        let items = vec![
+            group_start("a1"),
+            group_data("m1", &[("tag_a", "a1"), ("tag_b", "b1")], 0, 2),
+            group_data("m1", &[("tag_a", "a1"), ("tag_b", "b2")], 2, 2),
+            group_start("a2"),
+            group_data("m1", &[("tag_a", "a2")], 0, 1),
+            group_start("a1"),
+            group_data("m0", &[("tag_a", "a1"), ("tag_b", "b1")], 1, 2),
+        ];
+
+        let mut series_groups = SeriesGroups::new();
+        for item in items {
+            series_groups.add(item);
+        }
+        series_groups
+    }
+
     fn make_record_batch() -> RecordBatch {
         let schema = Arc::new(Schema::new(vec![
             ArrowField::new("string_field", ArrowDataType::Utf8, true),
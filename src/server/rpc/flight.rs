@@ -0,0 +1,556 @@
+//! This module contains an implementation of the Arrow Flight gRPC
+//! service, implemented in terms of `storage::Database` and
+//! `storage::DatabaseStore`, so query results can be fetched with any
+//! Flight client (e.g. `pyarrow.flight`)
+
+use std::{pin::Pin, sync::Arc};
+
+use arrow_deps::{
+    arrow::{self, datatypes::Schema, ipc::writer::IpcWriteOptions, record_batch::RecordBatch},
+    arrow_flight::{
+        self,
+        flight_service_server::{FlightService, FlightServiceServer},
+        Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint,
+        FlightInfo, HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+    },
+};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use storage::{Database, DatabaseError, DatabaseStore};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::info;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid ticket. Error: {:?} Ticket: {:?}", source, ticket))]
+    InvalidTicket {
+        source: std::string::FromUtf8Error,
+        ticket: Vec<u8>,
+    },
+
+    #[snafu(display("Invalid query, could not parse '{}': {}", query, source))]
+    InvalidQuery {
+        query: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Database not found: {}", db_name))]
+    DatabaseNotFound { db_name: String },
+
+    #[snafu(display("Invalid query '{}' in database '{}': {}", sql_query, db_name, source))]
+    InvalidSqlQuery {
+        db_name: String,
+        sql_query: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display(
+        "Error running query '{}' in database '{}': {}",
+        sql_query,
+        db_name,
+        source
+    ))]
+    Query {
+        db_name: String,
+        sql_query: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts a result from the business logic into the appropriate tonic status
+    fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::InvalidTicket { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidQuery { .. } => Status::invalid_argument(self.to_string()),
+            Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
+            Self::InvalidSqlQuery { .. } => Status::invalid_argument(self.to_string()),
+            Self::Query { .. } => Status::internal(self.to_string()),
+        }
+    }
+}
+
+/// The contents of a Flight `Ticket` (for `do_get`) or the `cmd` of a
+/// `FlightDescriptor` (for `get_flight_info`), encoded as JSON
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone)]
+pub struct ReadInfo {
+    pub database: String,
+    pub sql_query: String,
+}
+
+type TonicStream<T> = Pin<Box<dyn Stream<Item = Result<T, tonic::Status>> + Send + Sync + 'static>>;
+
+#[derive(Debug)]
+pub struct GrpcService<T: DatabaseStore> {
+    db_store: Arc<T>,
+}
+
+impl<T> GrpcService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    /// Create a new GrpcService connected to `db_store`
+    pub fn new(db_store: Arc<T>) -> Self {
+        Self { db_store }
+    }
+}
+
+/// Return a tonic service implementing Arrow Flight for `db_store`
+pub fn make_service<T>(db_store: Arc<T>) -> FlightServiceServer<GrpcService<T>>
+where
+    T: DatabaseStore + 'static,
+{
+    FlightServiceServer::new(GrpcService::new(db_store))
+}
+
+#[tonic::async_trait]
+impl<T> FlightService for GrpcService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    type HandshakeStream = TonicStream<HandshakeResponse>;
+    type ListFlightsStream = TonicStream<FlightInfo>;
+    type DoGetStream = TonicStream<FlightData>;
+    type DoPutStream = TonicStream<PutResult>;
+    type DoActionStream = TonicStream<arrow_flight::Result>;
+    type ListActionsStream = TonicStream<ActionType>;
+    type DoExchangeStream = TonicStream<FlightData>;
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(Status::unimplemented("get_schema"))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let read_info = parse_read_info(request.into_inner().ticket).map_err(|e| e.to_status())?;
+
+        info!(
+            "do_get for database {}, query: {}",
+            read_info.database, read_info.sql_query
+        );
+
+        let results = query_impl(self.db_store.clone(), &read_info)
+            .await
+            .map_err(|e| e.to_status())?;
+
+        let options = IpcWriteOptions::default();
+        let schema = results_schema(&results);
+
+        // The schema message must be sent before any record batches
+        let schema_flight_data =
+            arrow_flight::utils::flight_data_from_arrow_schema(&schema, &options);
+
+        let mut flights: Vec<Result<FlightData, Status>> = vec![Ok(schema_flight_data)];
+
+        let mut batches: Vec<Result<FlightData, Status>> = results
+            .iter()
+            .flat_map(|batch| {
+                let (flight_dictionaries, flight_batch) =
+                    arrow_flight::utils::flight_data_from_arrow_batch(batch, &options);
+                flight_dictionaries
+                    .into_iter()
+                    .chain(std::iter::once(flight_batch))
+                    .map(Ok)
+            })
+            .collect();
+
+        flights.append(&mut batches);
+
+        let output = futures::stream::iter(flights);
+
+        Ok(Response::new(Box::pin(output) as Self::DoGetStream))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("handshake"))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(Status::unimplemented("list_flights"))
+    }
+
+    /// Validates the query described by the descriptor's `cmd` and
+    /// returns its schema, along with a ticket that can be passed to
+    /// `do_get` to fetch the results.
+    ///
+    /// Note that the `Database` trait does not (yet) provide a way to
+    /// plan a query without executing it, so the query is run and its
+    /// results discarded.
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let descriptor = request.into_inner();
+        let read_info = parse_read_info(descriptor.cmd.clone()).map_err(|e| e.to_status())?;
+
+        info!(
+            "get_flight_info for database {}, query: {}",
+            read_info.database, read_info.sql_query
+        );
+
+        let results = query_impl(self.db_store.clone(), &read_info)
+            .await
+            .map_err(|e| e.to_status())?;
+
+        let options = IpcWriteOptions::default();
+        let schema = results_schema(&results);
+        let schema_result = arrow_flight::utils::flight_schema_from_arrow_schema(&schema, &options);
+
+        let total_records = results.iter().map(|batch| batch.num_rows() as i64).sum();
+
+        let endpoint = FlightEndpoint {
+            ticket: Some(Ticket {
+                ticket: descriptor.cmd.clone(),
+            }),
+            location: vec![],
+        };
+
+        Ok(Response::new(FlightInfo {
+            schema: schema_result.schema,
+            flight_descriptor: Some(descriptor),
+            endpoint: vec![endpoint],
+            total_records,
+            // The size of the IPC encoded data is not known up front
+            total_bytes: -1,
+        }))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("do_put"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("do_action"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(Status::unimplemented("list_actions"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("do_exchange"))
+    }
+}
+
+/// Decode the JSON encoded `ReadInfo` from a ticket or descriptor command
+fn parse_read_info(ticket: Vec<u8>) -> Result<ReadInfo> {
+    let json_str = String::from_utf8(ticket.clone()).context(InvalidTicket { ticket })?;
+
+    serde_json::from_str(&json_str).context(InvalidQuery { query: &json_str })
+}
+
+/// Run the query described by `read_info` against the appropriate database
+async fn query_impl<T>(db_store: Arc<T>, read_info: &ReadInfo) -> Result<Vec<RecordBatch>>
+where
+    T: DatabaseStore,
+{
+    let db_name = &read_info.database;
+    let sql_query = &read_info.sql_query;
+
+    let db = db_store
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { db_name })?;
+
+    db.query(sql_query).await.map_err(|e| {
+        let db_name = db_name.clone();
+        let sql_query = sql_query.clone();
+        // queries that can't be planned are the client's fault, failures
+        // running them are the server's
+        if e.is_invalid_request() {
+            Error::InvalidSqlQuery {
+                db_name,
+                sql_query,
+                source: Box::new(e),
+            }
+        } else {
+            Error::Query {
+                db_name,
+                sql_query,
+                source: Box::new(e),
+            }
+        }
+    })
+}
+
+/// Return the schema of the query results, or an empty schema if the
+/// query produced no batches at all
+fn results_schema(results: &[RecordBatch]) -> arrow::datatypes::SchemaRef {
+    results
+        .first()
+        .map(|batch| batch.schema())
+        .unwrap_or_else(|| Arc::new(Schema::empty()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::{
+            array::{Float64Array, StringArray},
+            datatypes::{DataType, Field},
+        },
+        arrow_flight::flight_service_client::FlightServiceClient,
+    };
+    use std::{
+        convert::TryFrom,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        time::Duration,
+    };
+    use storage::{exec::Executor as StorageExecutor, test::TestDatabaseStore};
+    use tonic::Code;
+
+    use futures::prelude::*;
+
+    type FlightClient = FlightServiceClient<tonic::transport::Channel>;
+
+    #[tokio::test]
+    async fn test_do_get() {
+        let mut fixture = Fixture::new(11950).await;
+
+        let expected_batch = test_batch();
+        fixture
+            .test_storage
+            .db_or_create("MyOrg_MyBucket")
+            .await
+            .unwrap()
+            .set_query_values(vec![expected_batch.clone()])
+            .await;
+
+        let ticket = Ticket {
+            ticket: read_info_json("MyOrg_MyBucket", "select * from cpu"),
+        };
+
+        let mut response = fixture
+            .client
+            .do_get(ticket)
+            .await
+            .expect("do_get succeeds")
+            .into_inner();
+
+        // schema comes first
+        let schema_data = response
+            .next()
+            .await
+            .expect("schema message")
+            .expect("schema message ok");
+        let schema = Arc::new(Schema::try_from(&schema_data).expect("decoding schema"));
+        assert_eq!(schema, expected_batch.schema());
+
+        let mut batches = vec![];
+        while let Some(data) = response.next().await {
+            let data = data.expect("flight data ok");
+            let batch = arrow_flight::utils::flight_data_to_arrow_batch(&data, schema.clone(), &[])
+                .expect("a record batch")
+                .expect("decoding record batch");
+            batches.push(batch);
+        }
+
+        assert_eq!(batches.len(), 1);
+        assert_batches_equal(&batches[0], &expected_batch);
+
+        let actual_request = fixture
+            .test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .unwrap()
+            .get_query_request()
+            .await
+            .expect("query was made");
+        assert_eq!(actual_request.query, "select * from cpu");
+    }
+
+    #[tokio::test]
+    async fn test_get_flight_info() {
+        let mut fixture = Fixture::new(11951).await;
+
+        let expected_batch = test_batch();
+        fixture
+            .test_storage
+            .db_or_create("MyOrg_MyBucket")
+            .await
+            .unwrap()
+            .set_query_values(vec![expected_batch.clone()])
+            .await;
+
+        let cmd = read_info_json("MyOrg_MyBucket", "select * from cpu");
+        let descriptor = FlightDescriptor {
+            r#type: arrow_flight::flight_descriptor::DescriptorType::Cmd as i32,
+            cmd: cmd.clone(),
+            path: vec![],
+        };
+
+        let info = fixture
+            .client
+            .get_flight_info(descriptor)
+            .await
+            .expect("get_flight_info succeeds")
+            .into_inner();
+
+        assert_eq!(info.total_records, 2);
+        assert_eq!(info.endpoint.len(), 1);
+        assert_eq!(info.endpoint[0].ticket, Some(Ticket { ticket: cmd }));
+
+        let expected_schema = arrow_flight::utils::flight_schema_from_arrow_schema(
+            &expected_batch.schema(),
+            &IpcWriteOptions::default(),
+        );
+        assert_eq!(info.schema, expected_schema.schema);
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let mut fixture = Fixture::new(11952).await;
+
+        // unknown database
+        let ticket = Ticket {
+            ticket: read_info_json("NotMyOrg_NotMyBucket", "select * from cpu"),
+        };
+        let status = fixture.client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // ticket that is not a valid ReadInfo
+        let ticket = Ticket {
+            ticket: b"not json".to_vec(),
+        };
+        let status = fixture.client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // failure running the query (the test database has no results
+        // saved)
+        fixture
+            .test_storage
+            .db_or_create("MyOrg_MyBucket")
+            .await
+            .unwrap();
+        let ticket = Ticket {
+            ticket: read_info_json("MyOrg_MyBucket", "select * from cpu"),
+        };
+        let status = fixture.client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::Internal);
+    }
+
+    #[test]
+    fn test_query_error_status() {
+        let invalid = Error::InvalidSqlQuery {
+            db_name: "MyOrg_MyBucket".into(),
+            sql_query: "selec * from cpu".into(),
+            source: "syntax error".into(),
+        };
+        assert_eq!(invalid.to_status().code(), Code::InvalidArgument);
+
+        let failed = Error::Query {
+            db_name: "MyOrg_MyBucket".into(),
+            sql_query: "select * from cpu".into(),
+            source: "disk on fire".into(),
+        };
+        assert_eq!(failed.to_status().code(), Code::Internal);
+    }
+
+    fn read_info_json(database: &str, sql_query: &str) -> Vec<u8> {
+        let read_info = ReadInfo {
+            database: database.into(),
+            sql_query: sql_query.into(),
+        };
+        serde_json::to_string(&read_info).unwrap().into_bytes()
+    }
+
+    fn test_batch() -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("usage", DataType::Float64, true),
+        ]));
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b"])),
+                Arc::new(Float64Array::from(vec![Some(1.5), None])),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn assert_batches_equal(actual: &RecordBatch, expected: &RecordBatch) {
+        assert_eq!(actual.schema(), expected.schema());
+
+        let actual = arrow::util::pretty::pretty_format_batches(&[actual.clone()]).unwrap();
+        let expected = arrow::util::pretty::pretty_format_batches(&[expected.clone()]).unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    // Wrapper around a flight client and test database
+    struct Fixture {
+        client: FlightClient,
+        test_storage: Arc<TestDatabaseStore>,
+    }
+
+    impl Fixture {
+        /// Start up a test rpc server listening on `port`, returning
+        /// a fixture with the test server and a flight client
+        async fn new(port: u16) -> Self {
+            let test_storage = Arc::new(TestDatabaseStore::new());
+            let test_executor = Arc::new(StorageExecutor::default());
+
+            let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), port);
+
+            let server = crate::server::rpc::storage::make_server(
+                bind_addr,
+                test_storage.clone(),
+                test_executor,
+            );
+            tokio::task::spawn(server);
+
+            let client = connect_to_server(bind_addr).await;
+
+            Self {
+                client,
+                test_storage,
+            }
+        }
+    }
+
+    /// loop and try to make a client connection for 5 seconds
+    async fn connect_to_server(bind_addr: SocketAddr) -> FlightClient {
+        const MAX_RETRIES: u32 = 10;
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+
+        for retry_count in 0..MAX_RETRIES {
+            match FlightClient::connect(format!("http://{}", bind_addr)).await {
+                Ok(client) => return client,
+                Err(e) => println!(
+                    "Server not yet up. Retrying ({}/{}): {}",
+                    retry_count, MAX_RETRIES, e
+                ),
+            }
+            interval.tick().await;
+        }
+        panic!("Server did not start in time");
+    }
+}
@@ -5,7 +5,9 @@
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
 use generated_types::{
+    aggregate::AggregateType,
     i_ox_server::{IOx, IOxServer},
+    read_group_request::Group,
     storage_server::{Storage, StorageServer},
     CapabilitiesResponse, CreateBucketRequest, CreateBucketResponse, DeleteBucketRequest,
     DeleteBucketResponse, GetBucketsResponse, Int64ValuesResponse, MeasurementFieldsRequest,
@@ -23,6 +25,7 @@ use data_types::error::ErrorLogger;
 use generated_types::{node, Node};
 
 use crate::server::rpc::expr::{AddRPCNode, SpecialTagKeys};
+use crate::server::rpc::flight;
 use crate::server::rpc::input::GrpcInputs;
 
 use storage::{
@@ -35,15 +38,15 @@ use storage::{
     Database, DatabaseStore,
 };
 
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
 use tokio::sync::mpsc;
 use tonic::Status;
 use tracing::{info, warn};
 
 use super::data::{
-    fieldlist_to_measurement_fields_response, grouped_series_set_item_to_read_response,
-    series_set_to_read_response, tag_keys_to_byte_vecs,
+    fieldlist_to_measurement_fields_response, series_groups_to_read_responses,
+    series_set_to_read_response, tag_keys_to_byte_vecs, SeriesGroups,
 };
 
 #[derive(Debug, Snafu)]
@@ -135,8 +138,17 @@ pub enum Error {
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
+    #[snafu(display("Invalid group type in read_group request: {}", group))]
+    InvalidGroup { group: i32 },
+
+    #[snafu(display("Invalid aggregate type in read_group request: {}", aggregate))]
+    InvalidAggregate { aggregate: i32 },
+
     #[snafu(display("Operation not yet implemented:  {}", operation))]
     NotYetImplemented { operation: String },
+
+    #[snafu(display("Predicates on _field are not supported when listing measurement names"))]
+    UnsupportedFieldPredicate,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -167,7 +179,10 @@ impl Error {
             Self::ConvertingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingFieldList { .. } => Status::invalid_argument(self.to_string()),
             Self::SendingResults { .. } => Status::internal(self.to_string()),
+            Self::InvalidGroup { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidAggregate { .. } => Status::invalid_argument(self.to_string()),
             Self::NotYetImplemented { .. } => Status::internal(self.to_string()),
+            Self::UnsupportedFieldPredicate => Status::invalid_argument(self.to_string()),
         }
     }
 }
@@ -282,19 +297,38 @@ where
             range,
             predicate,
             group_keys,
-            // TODO: handle Group::None
-            group: _group,
-            // TODO: handle aggregate values, especially whether None is the same as
-            // Some(AggregateType::None) or not
-            aggregate: _aggregate,
+            group,
+            aggregate,
             hints: _,
         } = read_group_request;
 
         info!(
-            "read_group for database {}, range: {:?}, group_keys: {:?}",
-            db_name, range, group_keys
+            "read_group for database {}, range: {:?}, group_keys: {:?}, group: {:?}, aggregate: {:?}",
+            db_name, range, group_keys, group, aggregate
         );
 
+        let group_keys = match Group::from_i32(group) {
+            // all series form a single group
+            Some(Group::None) => vec![],
+            Some(Group::By) => group_keys,
+            None => return Err(Error::InvalidGroup { group }.to_status()),
+        };
+
+        // No aggregate and AggregateType::None both return all points
+        let aggregate = match aggregate {
+            Some(aggregate) => match AggregateType::from_i32(aggregate.r#type) {
+                Some(AggregateType::None) => None,
+                Some(aggregate_type) => Some(aggregate_type),
+                None => {
+                    return Err(Error::InvalidAggregate {
+                        aggregate: aggregate.r#type,
+                    }
+                    .to_status())
+                }
+            },
+            None => None,
+        };
+
         read_group_impl(
             tx.clone(),
             self.db_store.clone(),
@@ -303,6 +337,7 @@ where
             range,
             predicate,
             group_keys,
+            aggregate,
         )
         .await
         .map_err(|e| e.to_status())?;
@@ -387,12 +422,14 @@ where
                 db_name, range
             );
 
-            if predicate.is_some() {
-                unimplemented!("tag_value for a measurement, with general predicate");
-            }
-
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
+            measurement_name_impl(
+                self.db_store.clone(),
+                self.executor.clone(),
+                db_name,
+                range,
+                predicate,
+            )
+            .await
         } else {
             info!(
                 "tag_values for database {}, range: {:?}, tag_key: {}",
@@ -462,26 +499,20 @@ where
             predicate,
         } = measurement_names_request;
 
-        if let Some(predicate) = predicate {
-            return NotYetImplemented {
-                operation: format!(
-                    "measurement_names request with a predicate: {:?}",
-                    predicate
-                ),
-            }
-            .fail()
-            .map_err(|e| e.to_status());
-        }
-
         info!(
-            "measurement_names for database {}, range: {:?}",
-            db_name, range
+            "measurement_names for database {}, range: {:?}, predicate: {:?}",
+            db_name, range, predicate
         );
 
-        let response =
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
-                .map_err(|e| e.to_status());
+        let response = measurement_name_impl(
+            self.db_store.clone(),
+            self.executor.clone(),
+            db_name,
+            range,
+            predicate,
+        )
+        .await
+        .map_err(|e| e.to_status());
 
         tx.send(response)
             .await
@@ -652,17 +683,29 @@ fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
 // to the appropriate tonic Status
 
 /// Gathers all measurement names that have data in the specified
-/// (optional) range
+/// (optional) range and pass the (optional) predicate
 async fn measurement_name_impl<T>(
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
     db_name: String,
     range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
 ) -> Result<StringValuesResponse>
 where
     T: DatabaseStore,
 {
-    let predicate = PredicateBuilder::default().set_range(range).build();
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+
+    let predicate = PredicateBuilder::default()
+        .set_range(range)
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    // a predicate on _field selects field columns, not measurements
+    ensure!(predicate.field_columns.is_none(), UnsupportedFieldPredicate);
 
     let plan = db_store
         .db(&db_name)
@@ -883,6 +926,11 @@ async fn convert_series_set(
 }
 
 /// Launch async tasks that send the result of executing read_group to `tx`
+///
+/// The series are grouped by the values of `group_keys` (a single
+/// group if there are none), and reduced to one point per field by
+/// `aggregate`, if specified.
+#[allow(clippy::too_many_arguments)]
 async fn read_group_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
@@ -891,6 +939,7 @@ async fn read_group_impl<T>(
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
     group_keys: Vec<String>,
+    aggregate: Option<AggregateType>,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -922,7 +971,7 @@ where
     // client before we start sending result)
     let (tx_series, rx_series) = mpsc::channel(4);
     tokio::spawn(async move {
-        convert_grouped_series_set(rx_series, tx)
+        convert_grouped_series_set(rx_series, tx, aggregate)
             .await
             .log_if_error("Converting grouped series set")
     });
@@ -942,21 +991,39 @@ where
     Ok(())
 }
 
-/// Receives SeriesSets from rx, converts them to ReadResponse and
-/// and sends them to tx
+/// Receives grouped SeriesSets from rx, converts them to ReadResponse
+/// and sends them to tx.
+///
+/// As the series of a group may come from several tables, all of them
+/// are received before the first group is sent.
 async fn convert_grouped_series_set(
     mut rx: mpsc::Receiver<Result<GroupedSeriesSetItem, SeriesSetError>>,
     mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    aggregate: Option<AggregateType>,
 ) -> Result<()> {
+    let mut series_groups = SeriesGroups::new();
+
+    let mut responses = Vec::new();
     while let Some(grouped_series_set_item) = rx.recv().await {
-        let response = grouped_series_set_item
-            .context(ComputingGroupedSeriesSet)
-            .and_then(|grouped_series_set_item| {
-                grouped_series_set_item_to_read_response(grouped_series_set_item)
-                    .context(ConvertingSeriesSet)
-            })
-            .map_err(|e| Status::internal(e.to_string()));
+        match grouped_series_set_item.context(ComputingGroupedSeriesSet) {
+            Ok(grouped_series_set_item) => series_groups.add(grouped_series_set_item),
+            Err(e) => {
+                responses.push(Err(Status::internal(e.to_string())));
+                break;
+            }
+        }
+    }
+
+    if responses.is_empty() {
+        responses = match series_groups_to_read_responses(series_groups, aggregate)
+            .context(ConvertingSeriesSet)
+        {
+            Ok(responses) => responses.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(Status::internal(e.to_string()))],
+        };
+    }
 
+    for response in responses {
         tx.send(response)
             .await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
@@ -1015,7 +1082,7 @@ where
 }
 
 /// Instantiate a server listening on the specified address
-/// implementing the IOx and Storage gRPC interfaces, the
+/// implementing the IOx, Storage, and Arrow Flight gRPC interfaces, the
 /// underlying hyper server instance. Resolves when the server has
 /// shutdown.
 pub async fn make_server<T>(
@@ -1035,6 +1102,7 @@ where
             storage.clone(),
             executor.clone(),
         )))
+        .add_service(flight::make_service(storage.clone()))
         .serve(bind_addr)
         .await
         .context(ServerError {})
@@ -1060,6 +1128,7 @@ mod tests {
         test::ColumnNamesRequest,
         test::FieldColumnsRequest,
         test::QueryGroupsRequest,
+        test::TableNamesRequest,
         test::TestDatabaseStore,
         test::{ColumnValuesRequest, QuerySeriesRequest},
     };
@@ -1068,7 +1137,9 @@ mod tests {
 
     use futures::prelude::*;
 
-    use generated_types::{i_ox_client, read_response::frame, storage_client, ReadSource};
+    use generated_types::{
+        i_ox_client, read_response::frame, storage_client, Aggregate, ReadSource,
+    };
     use prost::Message;
 
     type IOxClient = i_ox_client::IOxClient<tonic::transport::Channel>;
@@ -1155,7 +1226,7 @@ mod tests {
             end: 200,
         };
         let request = MeasurementNamesRequest {
-            source,
+            source: source.clone(),
             range: Some(range),
             predicate: None,
         };
@@ -1164,6 +1235,41 @@ mod tests {
         let expected_measurements = to_string_vec(&["o2"]);
         assert_eq!(actual_measurements, expected_measurements);
 
+        // --- Timestamp range and predicate
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let request = MeasurementNamesRequest {
+            source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+        };
+
+        let actual_measurements = fixture.storage_client.measurement_names(request).await?;
+        assert_eq!(actual_measurements, expected_measurements);
+
+        let expected_request = Some(TableNamesRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        });
+        assert_eq!(test_db.get_table_names_request().await, expected_request);
+
+        // --- predicates on _field are rejected
+        let request = MeasurementNamesRequest {
+            source,
+            range: None,
+            predicate: make_field_predicate(),
+        };
+
+        let status = fixture
+            .storage_client
+            .measurement_names(request)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
         Ok(())
     }
 
@@ -1419,6 +1525,20 @@ mod tests {
             "unexpected tag values while getting tag values for measurement names"
         );
 
+        let request = TagValuesRequest {
+            tags_source: source.clone(),
+            range: None,
+            predicate: make_field_predicate(),
+            tag_key: "\x00".into(),
+        };
+
+        let status = fixture
+            .storage_client
+            .tag_values(request)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
         // ---
         // test error
         // ---
@@ -1709,7 +1829,7 @@ mod tests {
             partition_id,
         ));
 
-        let group = generated_types::read_group_request::Group::None as i32;
+        let group = generated_types::read_group_request::Group::By as i32;
 
         let request = ReadGroupRequest {
             read_source: source.clone(),
@@ -1717,7 +1837,9 @@ mod tests {
             predicate: make_state_ma_predicate(),
             group_keys: vec![String::from("tag1")],
             group,
-            aggregate: None,
+            aggregate: Some(Aggregate {
+                r#type: AggregateType::Sum as i32,
+            }),
             hints: 0,
         };
 
@@ -1743,6 +1865,54 @@ mod tests {
             "unexpected request to query_groups"
         );
 
+        // ---
+        // test group none ignores the group keys
+        // ---
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+            group_keys: vec![String::from("tag1")],
+            group: generated_types::read_group_request::Group::None as i32,
+            aggregate: None,
+            hints: 0,
+        };
+
+        let dummy_groups_set_plan = GroupedSeriesSetPlans::from(vec![]);
+        test_db.set_query_groups_values(dummy_groups_set_plan).await;
+
+        let actual_frames = fixture.storage_client.read_group(request).await?;
+        assert_eq!(actual_frames, expected_frames);
+
+        let expected_request = Some(QueryGroupsRequest {
+            predicate: "Predicate {}".into(),
+            group_columns: vec![],
+        });
+        assert_eq!(test_db.get_query_groups_request().await, expected_request);
+
+        // ---
+        // test invalid group
+        // ---
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+            group_keys: vec![],
+            group: 42,
+            aggregate: None,
+            hints: 0,
+        };
+
+        let response = fixture.storage_client.read_group(request).await;
+        let response_string = format!("{:?}", response);
+        let expected_error = "Invalid group type in read_group request: 42";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
         // ---
         // test error
         // ---
@@ -1892,6 +2062,30 @@ mod tests {
         Some(Predicate { root: Some(root) })
     }
 
+    /// return a predicate like
+    ///
+    /// _field="temp"
+    fn make_field_predicate() -> Option<Predicate> {
+        use node::{Comparison, Type, Value};
+        let root = Node {
+            node_type: Type::ComparisonExpression as i32,
+            value: Some(Value::Comparison(Comparison::Equal as i32)),
+            children: vec![
+                Node {
+                    node_type: Type::TagRef as i32,
+                    value: Some(Value::TagRefValue(vec![255])),
+                    children: vec![],
+                },
+                Node {
+                    node_type: Type::Literal as i32,
+                    value: Some(Value::StringValue("temp".to_string())),
+                    children: vec![],
+                },
+            ],
+        };
+        Some(Predicate { root: Some(root) })
+    }
+
     /// Convert to a Vec<String> to facilitate comparison with results of client
     fn to_string_vec(v: &[&str]) -> Vec<String> {
         v.iter().map(|s| s.to_string()).collect()
@@ -0,0 +1,323 @@
+//! This module contains optional per-database rules for the schema of
+//! written data, to keep typo'd measurements, unexpected tags and
+//! wrongly typed fields out of a database:
+//!
+//! ```text
+//! {
+//!   "policy": "drop",
+//!   "measurements": {
+//!     "cpu": {"tags": ["host", "region"], "fields": {"usage_user": "float"}}
+//!   }
+//! }
+//! ```
+//!
+//! Only the listed measurements may be written. If a measurement lists
+//! `tags`, lines may only have those tag keys, and each listed field
+//! must have the given type (`float`, `integer`, `string` or
+//! `boolean`) when present. Other fields are allowed.
+//!
+//! The `policy` decides what happens to a write with lines that violate
+//! the rules: `reject` (the default) writes none of its lines, `drop`
+//! writes the conforming ones.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use influxdb2_client::{DataPoint, WriteDataPoint};
+use influxdb_line_protocol::{FieldValue, ParsedLine};
+use serde::{Deserialize, Serialize};
+use snafu::Snafu;
+
+/// A reason a line violates the schema rules
+#[derive(Debug, Snafu, PartialEq)]
+pub enum Violation {
+    #[snafu(display("Measurement '{}' is not allowed", measurement))]
+    MeasurementNotAllowed { measurement: String },
+
+    #[snafu(display(
+        "Tag key '{}' is not allowed in measurement '{}'",
+        tag_key,
+        measurement
+    ))]
+    TagKeyNotAllowed {
+        measurement: String,
+        tag_key: String,
+    },
+
+    #[snafu(display(
+        "Field '{}' of measurement '{}' must be {}, not {}",
+        field,
+        measurement,
+        expected,
+        actual
+    ))]
+    FieldTypeMismatch {
+        measurement: String,
+        field: String,
+        expected: FieldType,
+        actual: FieldType,
+    },
+}
+
+/// What to do with a write containing lines that violate the rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ViolationPolicy {
+    /// Write none of the lines
+    Reject,
+    /// Write the lines that conform to the rules
+    Drop,
+}
+
+impl Default for ViolationPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// The type of a field value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Float,
+    Integer,
+    String,
+    Boolean,
+}
+
+impl FieldType {
+    fn of(value: &FieldValue<'_>) -> Self {
+        match value {
+            FieldValue::F64(_) => Self::Float,
+            FieldValue::I64(_) => Self::Integer,
+            FieldValue::String(_) => Self::String,
+            FieldValue::Boolean(_) => Self::Boolean,
+        }
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Float => "float",
+            Self::Integer => "integer",
+            Self::String => "string",
+            Self::Boolean => "boolean",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The rules for the schema of one database
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaRules {
+    #[serde(default)]
+    pub policy: ViolationPolicy,
+
+    /// The allowed measurements and their rules
+    pub measurements: BTreeMap<String, MeasurementRules>,
+}
+
+/// The rules for the schema of one measurement
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeasurementRules {
+    /// If set, the only allowed tag keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<BTreeSet<String>>,
+
+    /// The required types of fields
+    #[serde(default)]
+    pub fields: BTreeMap<String, FieldType>,
+}
+
+/// Lines split by whether they conform to the rules
+#[derive(Debug, Default)]
+pub struct CheckedLines<'a> {
+    pub conforming: Vec<ParsedLine<'a>>,
+
+    /// Each violating line, as line protocol, with its violation
+    pub violations: Vec<(String, Violation)>,
+}
+
+impl CheckedLines<'_> {
+    /// The conforming lines as line protocol
+    pub fn conforming_lp(&self) -> String {
+        self.conforming
+            .iter()
+            .map(line_to_lp)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl SchemaRules {
+    /// Returns the first violation of the rules by `line`, if any
+    pub fn check(&self, line: &ParsedLine<'_>) -> Result<(), Violation> {
+        let measurement = line.series.measurement.as_str();
+        let rules =
+            self.measurements
+                .get(measurement)
+                .ok_or_else(|| Violation::MeasurementNotAllowed {
+                    measurement: measurement.to_string(),
+                })?;
+
+        if let (Some(allowed_tags), Some(tag_set)) = (&rules.tags, &line.series.tag_set) {
+            for (tag_key, _) in tag_set {
+                if !allowed_tags.contains(tag_key.as_str()) {
+                    return Err(Violation::TagKeyNotAllowed {
+                        measurement: measurement.to_string(),
+                        tag_key: tag_key.to_string(),
+                    });
+                }
+            }
+        }
+
+        for (field, value) in &line.field_set {
+            if let Some(&expected) = rules.fields.get(field.as_str()) {
+                let actual = FieldType::of(value);
+                if actual != expected {
+                    return Err(Violation::FieldTypeMismatch {
+                        measurement: measurement.to_string(),
+                        field: field.to_string(),
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Splits `lines` into the conforming and the violating ones
+    pub fn check_lines<'a>(&self, lines: Vec<ParsedLine<'a>>) -> CheckedLines<'a> {
+        let mut checked = CheckedLines::default();
+
+        for line in lines {
+            match self.check(&line) {
+                Ok(()) => checked.conforming.push(line),
+                Err(violation) => checked.violations.push((line_to_lp(&line), violation)),
+            }
+        }
+
+        checked
+    }
+}
+
+/// Converts `line` back into line protocol. Unlike its `Display`
+/// implementation this quotes string field values.
+pub fn line_to_lp(line: &ParsedLine<'_>) -> String {
+    let mut builder = DataPoint::builder(line.series.measurement.as_str());
+    for (key, value) in line.series.tag_set.iter().flatten() {
+        builder = builder.tag(key.as_str(), value.as_str());
+    }
+    for (key, value) in &line.field_set {
+        builder = match value {
+            FieldValue::I64(value) => builder.field(key.as_str(), *value),
+            FieldValue::F64(value) => builder.field(key.as_str(), *value),
+            FieldValue::String(value) => builder.field(key.as_str(), value.as_str()),
+            FieldValue::Boolean(value) => builder.field(key.as_str(), *value),
+        };
+    }
+    if let Some(timestamp) = line.timestamp {
+        builder = builder.timestamp(timestamp);
+    }
+
+    let point = builder.build().expect("parsed lines have fields");
+
+    let mut lp_data = Vec::new();
+    point
+        .write_data_point_to(&mut lp_data)
+        .expect("writing to a Vec never fails");
+    lp_data.pop(); // trailing newline
+
+    String::from_utf8(lp_data).expect("line protocol is valid utf8")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    fn rules() -> SchemaRules {
+        serde_json::from_str(
+            r#"{
+                "measurements": {
+                    "cpu": {"tags": ["host"], "fields": {"usage": "float"}},
+                    "mem": {}
+                }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn check(lp: &str) -> Result<(), Violation> {
+        let line = parse_lines(lp).next().unwrap().unwrap();
+        rules().check(&line)
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let rules = rules();
+        assert_eq!(rules.policy, ViolationPolicy::Reject);
+        assert_eq!(rules.measurements["mem"], MeasurementRules::default());
+
+        let error = serde_json::from_str::<SchemaRules>(
+            r#"{"measurements": {"cpu": {"fields": {"usage": "double"}}}}"#,
+        )
+        .unwrap_err();
+        assert!(
+            error.to_string().starts_with("unknown variant `double`"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("cpu,host=a usage=1.5,other=2i 100"), Ok(()));
+        assert_eq!(check("cpu other=2i 100"), Ok(()));
+        assert_eq!(check("mem,any=tag free=1i 100"), Ok(()));
+
+        assert_eq!(
+            check("cpuu,host=a usage=1.5 100").unwrap_err().to_string(),
+            "Measurement 'cpuu' is not allowed"
+        );
+        assert_eq!(
+            check("cpu,host=a,hots=b usage=1.5 100")
+                .unwrap_err()
+                .to_string(),
+            "Tag key 'hots' is not allowed in measurement 'cpu'"
+        );
+        assert_eq!(
+            check("cpu,host=a usage=1i 100").unwrap_err().to_string(),
+            "Field 'usage' of measurement 'cpu' must be float, not integer"
+        );
+    }
+
+    #[test]
+    fn test_check_lines() {
+        let lines = parse_lines("cpu,host=a usage=1.5 100\nfoo bar=\"b az\" 100\nmem free=1i 100")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let checked = rules().check_lines(lines);
+
+        assert_eq!(
+            checked.conforming_lp(),
+            "cpu,host=a usage=1.5 100\nmem free=1i 100"
+        );
+        assert_eq!(
+            checked.violations,
+            vec![(
+                r#"foo bar="b az" 100"#.to_string(),
+                Violation::MeasurementNotAllowed {
+                    measurement: "foo".to_string()
+                }
+            )]
+        );
+    }
+}
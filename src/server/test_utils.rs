@@ -0,0 +1,164 @@
+//! This module contains a harness for end-to-end tests of the HTTP
+//! API: it runs the HTTP service on an ephemeral port and provides
+//! helpers to write line protocol, run queries and compare the
+//! results against expected tables.
+//!
+//! It is compiled for this crate's tests and, for other users, behind
+//! the `test_utils` feature. The API is intended for tests only and
+//! may change along with the HTTP API without notice, so it is not
+//! covered by any stability guarantees.
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use arrow_deps::arrow::{self, record_batch::RecordBatch};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Client, Method, Request, Server, StatusCode,
+};
+use storage::{org_and_bucket_to_database, test::TestDatabaseStore, Database, DatabaseStore};
+
+use super::http_routes::{self, AppServer};
+
+/// An HTTP server, listening on an ephemeral port of localhost, which
+/// serves the IOx HTTP API from `T`
+#[derive(Debug)]
+pub struct TestServer<T = TestDatabaseStore> {
+    server: Arc<AppServer<T>>,
+    url: String,
+}
+
+impl TestServer<TestDatabaseStore> {
+    /// Starts a server backed by a new `TestDatabaseStore`. Must be
+    /// called from within a tokio runtime.
+    pub fn new() -> Self {
+        Self::with_store(Arc::new(TestDatabaseStore::new()))
+    }
+}
+
+impl Default for TestServer<TestDatabaseStore> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: DatabaseStore + 'static> TestServer<T> {
+    /// Starts a server backed by `store`. Must be called from within
+    /// a tokio runtime.
+    pub fn with_store(store: Arc<T>) -> Self {
+        Self::with_app_server(Arc::new(AppServer::new(store)))
+    }
+
+    /// Starts a server for `server`, e.g. to test replication. Must
+    /// be called from within a tokio runtime.
+    pub fn with_app_server(server: Arc<AppServer<T>>) -> Self {
+        let service_server = Arc::clone(&server);
+        let make_svc = make_service_fn(move |_conn| {
+            let server = Arc::clone(&service_server);
+            async move {
+                Ok::<_, http::Error>(service_fn(move |req| {
+                    let server = Arc::clone(&server);
+                    http_routes::service(req, server)
+                }))
+            }
+        });
+
+        // NB: specify port 0 to let the OS pick the port.
+        let bind_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+        let http_server = Server::bind(&bind_addr).serve(make_svc);
+        let url = format!("http://{}", http_server.local_addr());
+        tokio::task::spawn(http_server);
+        println!("Started server at {}", url);
+
+        Self { server, url }
+    }
+
+    /// The base url of the server, e.g. `http://127.0.0.1:4321`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The application state served by the server
+    pub fn app_server(&self) -> &Arc<AppServer<T>> {
+        &self.server
+    }
+
+    /// The database store backing the server
+    pub fn store(&self) -> &Arc<T> {
+        &self.server.write_buffer
+    }
+
+    /// Writes `lp_data` to `org` and `bucket` via the `/api/v2/write`
+    /// endpoint. Panics if the write is not accepted.
+    pub async fn write_lp(&self, org: &str, bucket: &str, lp_data: &str) {
+        let uri = format!("{}/api/v2/write?org={}&bucket={}", self.url, org, bucket);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .body(Body::from(lp_data.to_string()))
+            .expect("building write request");
+
+        let response = Client::new()
+            .request(request)
+            .await
+            .expect("sending write request");
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("reading write response");
+
+        assert_eq!(
+            status,
+            StatusCode::NO_CONTENT,
+            "write rejected: {}",
+            String::from_utf8_lossy(&body)
+        );
+    }
+
+    /// Runs `sql_query` against the database of `org` and `bucket`.
+    /// Panics if the database does not exist or the query fails.
+    pub async fn query(&self, org: &str, bucket: &str, sql_query: &str) -> Vec<RecordBatch> {
+        let db_name = org_and_bucket_to_database(org, bucket);
+
+        self.store()
+            .db(&db_name)
+            .await
+            .unwrap_or_else(|| panic!("database {} not found", db_name))
+            .query(sql_query)
+            .await
+            .unwrap_or_else(|e| panic!("running query '{}': {}", sql_query, e))
+    }
+
+    /// Runs `sql_query` like `query` and asserts the results, pretty
+    /// printed, equal the `expected` lines
+    pub async fn assert_query(&self, org: &str, bucket: &str, sql_query: &str, expected: &[&str]) {
+        let batches = self.query(org, bucket, sql_query).await;
+        assert_batches_eq(expected, &batches);
+    }
+}
+
+/// Asserts that `batches`, pretty printed, equal the `expected` lines:
+///
+/// ```text
+/// assert_batches_eq(&[
+///     "+------+------+",
+///     "| host | time |",
+///     "+------+------+",
+///     "| a    | 100  |",
+///     "+------+------+",
+/// ], &batches);
+/// ```
+pub fn assert_batches_eq(expected: &[&str], batches: &[RecordBatch]) {
+    let formatted = arrow::util::pretty::pretty_format_batches(batches)
+        .expect("pretty printing record batches");
+    let actual: Vec<_> = formatted.trim().lines().collect();
+
+    assert_eq!(
+        expected,
+        &actual[..],
+        "\n\nexpected:\n\n{}\n\nactual:\n\n{}\n",
+        expected.join("\n"),
+        formatted
+    );
+}
@@ -0,0 +1,194 @@
+//! This module contains code to extract distributed trace context
+//! from incoming HTTP requests.
+//!
+//! The W3C `traceparent` / `tracestate` headers are preferred; B3
+//! headers (either the single `b3` header or the `X-B3-*` headers) are
+//! used if no valid `traceparent` is present. Invalid headers are
+//! ignored, as required by the W3C spec, and the request is treated as
+//! the start of a new trace.
+use http::HeaderMap;
+
+/// The trace context propagated by the caller of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    /// The id of the whole trace, as 32 lowercase hex characters
+    pub trace_id: String,
+
+    /// The id of the caller's span, as 16 lowercase hex characters
+    pub parent_span_id: String,
+
+    /// Whether the caller sampled (recorded) this trace
+    pub sampled: bool,
+
+    /// Vendor specific trace state (the `tracestate` header), if any
+    pub trace_state: Option<String>,
+}
+
+impl TraceContext {
+    /// Extracts the trace context from `headers`, if they contain one
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+
+        if let Some(context) = header("traceparent").and_then(parse_traceparent) {
+            return Some(Self {
+                trace_state: header("tracestate").map(|state| state.to_string()),
+                ..context
+            });
+        }
+
+        if let Some(context) = header("b3").and_then(parse_b3_single) {
+            return Some(context);
+        }
+
+        let trace_id = header("x-b3-traceid")?;
+        let span_id = header("x-b3-spanid")?;
+        let sampled = header("x-b3-sampled").map_or(true, |sampled| sampled == "1");
+        make_b3_context(trace_id, span_id, sampled)
+    }
+}
+
+/// Parses a W3C `traceparent` header: `version-trace_id-parent_id-flags`
+fn parse_traceparent(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_span_id = parts.next()?;
+    let flags = parts.next()?;
+
+    // version 00 has exactly four parts, later versions may add more
+    if !is_hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some()) {
+        return None;
+    }
+
+    if !is_valid_id(trace_id, 32) || !is_valid_id(parent_span_id, 16) || !is_hex(flags, 2) {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+
+    Some(TraceContext {
+        trace_id: trace_id.to_string(),
+        parent_span_id: parent_span_id.to_string(),
+        sampled: flags & 0x01 == 0x01,
+        trace_state: None,
+    })
+}
+
+/// Parses a single `b3` header: `trace_id-span_id[-sampled[-parent_id]]`
+fn parse_b3_single(value: &str) -> Option<TraceContext> {
+    let mut parts = value.trim().split('-');
+    let trace_id = parts.next()?;
+    let span_id = parts.next()?;
+    let sampled = parts
+        .next()
+        .map_or(true, |sampled| sampled == "1" || sampled == "d");
+
+    make_b3_context(trace_id, span_id, sampled)
+}
+
+fn make_b3_context(trace_id: &str, span_id: &str, sampled: bool) -> Option<TraceContext> {
+    // B3 allows 64 bit trace ids, which are left padded to 128 bits
+    let trace_id = match trace_id.len() {
+        16 => format!("{:0>32}", trace_id),
+        _ => trace_id.to_string(),
+    };
+
+    if !is_valid_id(&trace_id, 32) || !is_valid_id(span_id, 16) {
+        return None;
+    }
+
+    Some(TraceContext {
+        trace_id,
+        parent_span_id: span_id.to_string(),
+        sampled,
+        trace_state: None,
+    })
+}
+
+/// Returns true if `s` is `len` lowercase hex characters
+fn is_hex(s: &str, len: usize) -> bool {
+    s.len() == len && s.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
+
+/// Returns true if `id` is `len` lowercase hex characters, not all zero
+fn is_valid_id(id: &str, len: usize) -> bool {
+    is_hex(id, len) && id.chars().any(|c| c != '0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let context = TraceContext::from_headers(&headers(&[
+            (
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            ),
+            ("tracestate", "congo=t61rcWkgMzE"),
+        ]));
+
+        assert_eq!(
+            context,
+            Some(TraceContext {
+                trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".into(),
+                parent_span_id: "00f067aa0ba902b7".into(),
+                sampled: true,
+                trace_state: Some("congo=t61rcWkgMzE".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_traceparent() {
+        for traceparent in &[
+            // all zero trace id
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            // all zero span id
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            // upper case
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            // invalid version
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            // too many parts for version 00
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+            // too short
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(parse_traceparent(traceparent), None, "{}", traceparent);
+        }
+    }
+
+    #[test]
+    fn test_b3() {
+        let expected = Some(TraceContext {
+            trace_id: "0000000000000000a3ce929d0e0e4736".into(),
+            parent_span_id: "00f067aa0ba902b7".into(),
+            sampled: false,
+            trace_state: None,
+        });
+
+        let context =
+            TraceContext::from_headers(&headers(&[("b3", "a3ce929d0e0e4736-00f067aa0ba902b7-0")]));
+        assert_eq!(context, expected);
+
+        let context = TraceContext::from_headers(&headers(&[
+            ("x-b3-traceid", "a3ce929d0e0e4736"),
+            ("x-b3-spanid", "00f067aa0ba902b7"),
+            ("x-b3-sampled", "0"),
+        ]));
+        assert_eq!(context, expected);
+    }
+
+    #[test]
+    fn test_no_trace_context() {
+        assert_eq!(TraceContext::from_headers(&headers(&[])), None);
+    }
+}
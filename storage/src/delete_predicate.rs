@@ -0,0 +1,509 @@
+//! This module contains the predicates of InfluxDB 2.0 style delete
+//! requests, such as `_measurement="cpu" AND host="a"`.
+//!
+//! A delete predicate is a (possibly empty) conjunction of
+//! `column="value"` and `column!="value"` comparisons. The special
+//! `_measurement` column selects the table to delete from. Column
+//! names may be double quoted, values may use double or single quotes
+//! and `\` escapes the next character within quotes.
+use std::fmt;
+
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use snafu::Snafu;
+
+use crate::predicate::{Predicate, PredicateBuilder, TimestampRange};
+
+/// The name of the pseudo column that refers to the measurement
+/// (table) name in delete predicates
+pub const MEASUREMENT_COLUMN_NAME: &str = "_measurement";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Syntax error at position {}: {}", position, message))]
+    InvalidPredicate { position: usize, message: String },
+
+    #[snafu(display("Unsupported delete predicate: {}", description))]
+    UnsupportedPredicate { description: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The comparison of a `DeleteExpr`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeleteOp {
+    Eq,
+    NotEq,
+}
+
+impl fmt::Display for DeleteOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Eq => write!(f, "="),
+            Self::NotEq => write!(f, "!="),
+        }
+    }
+}
+
+/// One `column="value"` or `column!="value"` comparison. Rows
+/// without a value for `column` compare as the empty string.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeleteExpr {
+    pub column: String,
+    pub op: DeleteOp,
+    pub value: String,
+}
+
+impl DeleteExpr {
+    /// Returns true if a row with `value` in `self.column` matches
+    pub fn matches(&self, value: Option<&str>) -> bool {
+        let equal = value.unwrap_or_default() == self.value;
+        match self.op {
+            DeleteOp::Eq => equal,
+            DeleteOp::NotEq => !equal,
+        }
+    }
+
+    fn to_expr(&self) -> Expr {
+        let op = match self.op {
+            DeleteOp::Eq => Operator::Eq,
+            DeleteOp::NotEq => Operator::NotEq,
+        };
+
+        Expr::BinaryExpr {
+            left: Box::new(Expr::Column(self.column.clone())),
+            op,
+            right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(self.value.clone())))),
+        }
+    }
+
+    fn from_expr(expr: &Expr) -> Option<Self> {
+        match expr {
+            Expr::BinaryExpr { left, op, right } => {
+                let op = match op {
+                    Operator::Eq => DeleteOp::Eq,
+                    Operator::NotEq => DeleteOp::NotEq,
+                    _ => return None,
+                };
+
+                match (left.as_ref(), right.as_ref()) {
+                    (Expr::Column(column), Expr::Literal(ScalarValue::Utf8(Some(value)))) => {
+                        Some(Self {
+                            column: column.clone(),
+                            op,
+                            value: value.clone(),
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DeleteExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.column.is_empty() && self.column.chars().all(is_identifier_char) {
+            write!(f, "{}", self.column)?;
+        } else {
+            write_quoted(f, &self.column)?;
+        }
+        write!(f, "{}", self.op)?;
+        write_quoted(f, &self.value)
+    }
+}
+
+/// A parsed delete predicate. Only rows of the table
+/// `table_name`, if specified, which match all `exprs` are deleted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DeletePredicate {
+    pub table_name: Option<String>,
+    pub exprs: Vec<DeleteExpr>,
+}
+
+impl DeletePredicate {
+    /// Parses `predicate`, e.g. `_measurement="cpu" AND host="a"`.
+    /// Errors report the byte position in `predicate` at which
+    /// parsing failed.
+    pub fn parse(predicate: &str) -> Result<Self> {
+        let mut parser = Parser {
+            input: predicate,
+            position: 0,
+        };
+        let mut parsed = Self::default();
+
+        parser.skip_whitespace();
+        if parser.is_at_end() {
+            return Ok(parsed);
+        }
+
+        loop {
+            parser.skip_whitespace();
+            let position = parser.position;
+            let column = parser.identifier()?;
+            parser.skip_whitespace();
+            let op = parser.operator()?;
+            parser.skip_whitespace();
+            let value = parser.value()?;
+
+            if column == MEASUREMENT_COLUMN_NAME {
+                if op != DeleteOp::Eq {
+                    return InvalidPredicate {
+                        position,
+                        message: format!("only {}=\"...\" is supported", MEASUREMENT_COLUMN_NAME),
+                    }
+                    .fail();
+                }
+                if parsed.table_name.is_some() {
+                    return InvalidPredicate {
+                        position,
+                        message: format!("{} may only be specified once", MEASUREMENT_COLUMN_NAME),
+                    }
+                    .fail();
+                }
+                parsed.table_name = Some(value);
+            } else {
+                parsed.exprs.push(DeleteExpr { column, op, value });
+            }
+
+            parser.skip_whitespace();
+            if parser.is_at_end() {
+                break;
+            }
+            parser.and()?;
+        }
+
+        Ok(parsed)
+    }
+
+    /// Converts `predicate` back into a delete predicate. Fails if it
+    /// uses anything but a single table name and the comparisons
+    /// `parse` produces; the range of `predicate` is ignored.
+    pub fn from_predicate(predicate: &Predicate) -> Result<Self> {
+        if predicate.field_columns.is_some() {
+            return UnsupportedPredicate {
+                description: "field column restrictions",
+            }
+            .fail();
+        }
+
+        let table_name = match &predicate.table_names {
+            None => None,
+            Some(table_names) if table_names.len() == 1 => table_names.iter().next().cloned(),
+            Some(table_names) => {
+                return UnsupportedPredicate {
+                    description: format!("{} table names", table_names.len()),
+                }
+                .fail()
+            }
+        };
+
+        let exprs = predicate
+            .exprs
+            .iter()
+            .map(|expr| {
+                DeleteExpr::from_expr(expr).ok_or_else(|| Error::UnsupportedPredicate {
+                    description: format!("expression {:?}", expr),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { table_name, exprs })
+    }
+
+    /// Creates the storage predicate selecting the rows this predicate
+    /// deletes within `range`
+    pub fn to_predicate(&self, range: TimestampRange) -> Predicate {
+        self.exprs
+            .iter()
+            .fold(
+                PredicateBuilder::default()
+                    .table_option(self.table_name.clone())
+                    .timestamp_range(range.start, range.end),
+                |builder, expr| builder.add_expr(expr.to_expr()),
+            )
+            .build()
+    }
+}
+
+/// Formats the predicate so that `parse` returns it again
+impl fmt::Display for DeletePredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(table_name) = &self.table_name {
+            write!(f, "{}=", MEASUREMENT_COLUMN_NAME)?;
+            write_quoted(f, table_name)?;
+            separator = " AND ";
+        }
+        for expr in &self.exprs {
+            write!(f, "{}{}", separator, expr)?;
+            separator = " AND ";
+        }
+        Ok(())
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        if matches!(c, '"' | '\\') {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    write!(f, "\"")
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.position..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.rest().is_empty()
+    }
+
+    fn fail<T>(&self, message: &str) -> Result<T> {
+        InvalidPredicate {
+            position: self.position,
+            message,
+        }
+        .fail()
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn bare_word(&mut self) -> &'a str {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !is_identifier_char(c))
+            .unwrap_or_else(|| rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    fn identifier(&mut self) -> Result<String> {
+        match self.peek() {
+            Some('"') => self.quoted(),
+            Some(c) if is_identifier_char(c) => Ok(self.bare_word().to_string()),
+            _ => self.fail("expected a column name"),
+        }
+    }
+
+    fn operator(&mut self) -> Result<DeleteOp> {
+        let rest = self.rest();
+        if rest.starts_with("!=") {
+            self.position += 2;
+            Ok(DeleteOp::NotEq)
+        } else if rest.starts_with('=') {
+            self.position += 1;
+            Ok(DeleteOp::Eq)
+        } else {
+            self.fail("expected '=' or '!='")
+        }
+    }
+
+    fn value(&mut self) -> Result<String> {
+        match self.peek() {
+            Some('"') | Some('\'') => self.quoted(),
+            _ => self.fail("expected a quoted value"),
+        }
+    }
+
+    /// Parses a string quoted by its first character
+    fn quoted(&mut self) -> Result<String> {
+        let mut chars = self.rest().char_indices();
+        let quote = match chars.next() {
+            Some((_, quote)) => quote,
+            None => return self.fail("expected a quoted value"),
+        };
+
+        let mut value = String::new();
+        let mut escaped = false;
+        for (offset, c) in chars {
+            if escaped {
+                value.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == quote {
+                self.position += offset + c.len_utf8();
+                return Ok(value);
+            } else {
+                value.push(c);
+            }
+        }
+
+        self.fail("unterminated quoted string")
+    }
+
+    fn and(&mut self) -> Result<()> {
+        let position = self.position;
+        if self.bare_word().eq_ignore_ascii_case("and") {
+            Ok(())
+        } else {
+            self.position = position;
+            self.fail("expected AND")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(column: &str, op: DeleteOp, value: &str) -> DeleteExpr {
+        DeleteExpr {
+            column: column.into(),
+            op,
+            value: value.into(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let predicate = DeletePredicate::parse(r#"_measurement="cpu" AND host="a""#).unwrap();
+        assert_eq!(
+            predicate,
+            DeletePredicate {
+                table_name: Some("cpu".into()),
+                exprs: vec![expr("host", DeleteOp::Eq, "a")],
+            }
+        );
+
+        let predicate =
+            DeletePredicate::parse(r#"  region != 'west'and "my tag"="a \"b\"" "#).unwrap();
+        assert_eq!(
+            predicate,
+            DeletePredicate {
+                table_name: None,
+                exprs: vec![
+                    expr("region", DeleteOp::NotEq, "west"),
+                    expr("my tag", DeleteOp::Eq, r#"a "b""#),
+                ],
+            }
+        );
+
+        assert_eq!(
+            DeletePredicate::parse("  ").unwrap(),
+            DeletePredicate::default()
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = vec![
+            (
+                r#"host"#,
+                "Syntax error at position 4: expected '=' or '!='",
+            ),
+            (
+                r#"host=a"#,
+                "Syntax error at position 5: expected a quoted value",
+            ),
+            (
+                r#"host="a" OR host="b""#,
+                "Syntax error at position 9: expected AND",
+            ),
+            (
+                r#"host="a" AND"#,
+                "Syntax error at position 12: expected a column name",
+            ),
+            (
+                r#"host="a"#,
+                "Syntax error at position 5: unterminated quoted string",
+            ),
+            (
+                r#"host="a" AND _measurement!="cpu""#,
+                "Syntax error at position 13: only _measurement=\"...\" is supported",
+            ),
+            (
+                r#"_measurement="cpu" AND _measurement="mem""#,
+                "Syntax error at position 23: _measurement may only be specified once",
+            ),
+        ];
+
+        for (predicate, expected) in cases {
+            let error = DeletePredicate::parse(predicate).unwrap_err().to_string();
+            assert_eq!(error, expected, "parsing {}", predicate);
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let predicate = DeletePredicate {
+            table_name: Some("c\"pu".into()),
+            exprs: vec![
+                expr("host", DeleteOp::NotEq, r#"a\b"#),
+                expr("my tag", DeleteOp::Eq, ""),
+            ],
+        };
+
+        let formatted = predicate.to_string();
+        assert_eq!(
+            formatted,
+            r#"_measurement="c\"pu" AND host!="a\\b" AND "my tag"="""#
+        );
+        assert_eq!(DeletePredicate::parse(&formatted).unwrap(), predicate);
+    }
+
+    #[test]
+    fn test_predicate_round_trip() {
+        let predicate =
+            DeletePredicate::parse(r#"_measurement="cpu" AND host!="a" AND region="west""#)
+                .unwrap();
+
+        let storage_predicate = predicate.to_predicate(TimestampRange::new(100, 200));
+        assert_eq!(storage_predicate.range, Some(TimestampRange::new(100, 200)));
+        assert_eq!(storage_predicate.exprs.len(), 2);
+
+        assert_eq!(
+            DeletePredicate::from_predicate(&storage_predicate).unwrap(),
+            predicate
+        );
+
+        let unsupported = PredicateBuilder::default()
+            .tables(vec!["cpu".into(), "mem".into()])
+            .build();
+        assert_eq!(
+            DeletePredicate::from_predicate(&unsupported)
+                .unwrap_err()
+                .to_string(),
+            "Unsupported delete predicate: 2 table names"
+        );
+    }
+
+    #[test]
+    fn test_delete_expr_matches() {
+        let eq = expr("host", DeleteOp::Eq, "a");
+        assert!(eq.matches(Some("a")));
+        assert!(!eq.matches(Some("b")));
+        assert!(!eq.matches(None));
+
+        let not_eq = expr("host", DeleteOp::NotEq, "a");
+        assert!(!not_eq.matches(Some("a")));
+        assert!(not_eq.matches(Some("b")));
+        assert!(not_eq.matches(None));
+
+        assert!(expr("host", DeleteOp::Eq, "").matches(None));
+    }
+}
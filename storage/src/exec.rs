@@ -6,6 +6,7 @@ pub mod fieldlist;
 mod planning;
 mod schema_pivot;
 pub mod seriesset;
+pub mod seriesset_lp;
 pub mod stringset;
 
 use std::sync::Arc;
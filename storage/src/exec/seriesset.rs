@@ -27,8 +27,8 @@ use arrow_deps::{
     datafusion::physical_plan::SendableRecordBatchStream,
 };
 use data_types::TIME_COLUMN_NAME;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
 use snafu::{ResultExt, Snafu};
-use tokio::stream::StreamExt;
 use tokio::sync::mpsc::{self, error::SendError};
 
 use croaring::bitmap::Bitmap;
@@ -61,9 +61,6 @@ pub enum Error {
     SendingDuringGroupedConversion {
         source: Box<SendError<Result<GroupedSeriesSetItem>>>,
     },
-
-    #[snafu(display("Joining conversion execution task: {}", source))]
-    JoinError { source: tokio::task::JoinError },
 }
 
 #[allow(dead_code)]
@@ -133,6 +130,34 @@ impl SeriesSetConverter {
     /// Convert the results from running a DataFusion plan into the
     /// appropriate SeriesSets, sending them self.tx
     ///
+    /// This is a wrapper around `convert_stream` for callers that
+    /// consume the results from a channel
+    pub async fn convert(
+        &mut self,
+        table_name: Arc<String>,
+        tag_columns: Arc<Vec<Arc<String>>>,
+        field_columns: Arc<Vec<Arc<String>>>,
+        it: SendableRecordBatchStream,
+    ) -> Result<()> {
+        // Any error that results from processing is sent along as
+        // the last item of the stream
+        let mut series_sets = Self::convert_stream(table_name, tag_columns, field_columns, it);
+        while let Some(series_set) = series_sets.next().await {
+            self.tx
+                .send(series_set)
+                .await
+                .map_err(|e| Error::SendingDuringConversion {
+                    source: Box::new(e),
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Convert the results from running a DataFusion plan into a
+    /// stream of the appropriate SeriesSets. The record batches are
+    /// only read from `it` as the stream is polled. If processing fails,
+    /// the error is the last item of the stream.
+    ///
     /// The results must be in the logical format described in this
     /// module's documentation (i.e. ordered by tag keys)
     ///
@@ -143,122 +168,109 @@ impl SeriesSetConverter {
     /// field_columns: The names of the columns which are "fields"
     ///
     /// it: record batch iterator that produces data in the desired order
-    pub async fn convert(
-        &mut self,
+    pub fn convert_stream(
         table_name: Arc<String>,
         tag_columns: Arc<Vec<Arc<String>>>,
         field_columns: Arc<Vec<Arc<String>>>,
         it: SendableRecordBatchStream,
-    ) -> Result<()> {
-        // Make sure that any error that results from processing is sent along
-        if let Err(e) = self
-            .convert_impl(table_name, tag_columns, field_columns, it)
-            .await
-        {
-            self.tx
-                .send(Err(e))
-                .await
-                .map_err(|e| Error::SendingDuringConversion {
-                    source: Box::new(e),
-                })?
-        }
-        Ok(())
+    ) -> impl Stream<Item = Result<SeriesSet>> + Send + Unpin {
+        stream::once(Box::pin(Self::convert_batches(
+            table_name,
+            tag_columns,
+            field_columns,
+            it,
+        )))
+        .map_ok(|series_sets| stream::iter(series_sets.into_iter().map(Ok)))
+        .try_flatten()
     }
 
-    /// Does the actual conversion logic, but returns any error in processing
-    pub async fn convert_impl(
-        &mut self,
+    /// Does the actual conversion logic, returning the SeriesSets of
+    /// the record batches in `it`
+    async fn convert_batches(
         table_name: Arc<String>,
         tag_columns: Arc<Vec<Arc<String>>>,
         field_columns: Arc<Vec<Arc<String>>>,
         mut it: SendableRecordBatchStream,
-    ) -> Result<()> {
+    ) -> Result<Vec<SeriesSet>> {
         // for now, only handle a single record batch
-        if let Some(batch) = it.next().await {
-            let batch = batch.context(ReadingRecordBatch)?;
-
-            if it.next().await.is_some() {
-                // but not yet
-                unimplemented!("Computing series across multiple record batches not yet supported");
-            }
+        let batch = match it.next().await {
+            Some(batch) => batch.context(ReadingRecordBatch)?,
+            None => return Ok(vec![]),
+        };
+
+        if it.next().await.is_some() {
+            // but not yet
+            unimplemented!("Computing series across multiple record batches not yet supported");
+        }
 
-            let schema = batch.schema();
-            // TODO: check that the tag columns are sorted by tag name...
+        let schema = batch.schema();
+        // TODO: check that the tag columns are sorted by tag name...
+
+        let timestamp_index =
+            schema
+                .index_of(TIME_COLUMN_NAME)
+                .context(ColumnNotFoundForSeriesSet {
+                    column_name: TIME_COLUMN_NAME,
+                })?;
+        let tag_indicies = Self::names_to_indices(&schema, &tag_columns)?;
+        let field_indicies = Arc::new(Self::names_to_indices(&schema, &field_columns)?);
+
+        // Algorithm: compute, via bitsets, the rows at which each
+        // tag column changes and thereby where the tagset
+        // changes. Emit a new SeriesSet at each such transition
+        let mut tag_transitions = tag_indicies
+            .iter()
+            .map(|&col| Self::compute_transitions(&batch, col))
+            .collect::<Result<Vec<_>>>()?;
+
+        // no tag columns, emit a single tagset
+        let intersections = if tag_transitions.is_empty() {
+            let mut b = Bitmap::create_with_capacity(1);
+            let end_row = batch.num_rows();
+            b.add(end_row as u32);
+            b
+        } else {
+            // OR bitsets together to to find all rows where the
+            // keyset (values of the tag keys) changes
+            let remaining = tag_transitions.split_off(1);
+
+            remaining
+                .into_iter()
+                .for_each(|b| tag_transitions[0].or_inplace(&b));
+            // take the first item
+            tag_transitions.into_iter().next().unwrap()
+        };
+
+        let mut start_row: u32 = 0;
+
+        // create each series (since bitmap are not Send, they must
+        // not be held across an await, so collect the series first).
+        // Each series shares the record batch, so this takes little
+        // memory beyond the batch itself
+        let series_sets = intersections
+            .iter()
+            .map(|end_row| {
+                let series_set = SeriesSet {
+                    table_name: table_name.clone(),
+                    tags: Self::get_tag_keys(
+                        &batch,
+                        start_row as usize,
+                        &tag_columns,
+                        &tag_indicies,
+                    ),
+                    timestamp_index,
+                    field_indices: field_indicies.clone(),
+                    start_row: start_row as usize,
+                    num_rows: (end_row - start_row) as usize,
+                    batch: batch.clone(),
+                };
 
-            let timestamp_index =
-                schema
-                    .index_of(TIME_COLUMN_NAME)
-                    .context(ColumnNotFoundForSeriesSet {
-                        column_name: TIME_COLUMN_NAME,
-                    })?;
-            let tag_indicies = Self::names_to_indices(&schema, &tag_columns)?;
-            let field_indicies = Arc::new(Self::names_to_indices(&schema, &field_columns)?);
-
-            // Algorithm: compute, via bitsets, the rows at which each
-            // tag column changes and thereby where the tagset
-            // changes. Emit a new SeriesSet at each such transition
-            let mut tag_transitions = tag_indicies
-                .iter()
-                .map(|&col| Self::compute_transitions(&batch, col))
-                .collect::<Result<Vec<_>>>()?;
-
-            // no tag columns, emit a single tagset
-            let intersections = if tag_transitions.is_empty() {
-                let mut b = Bitmap::create_with_capacity(1);
-                let end_row = batch.num_rows();
-                b.add(end_row as u32);
-                b
-            } else {
-                // OR bitsets together to to find all rows where the
-                // keyset (values of the tag keys) changes
-                let remaining = tag_transitions.split_off(1);
-
-                remaining
-                    .into_iter()
-                    .for_each(|b| tag_transitions[0].or_inplace(&b));
-                // take the first item
-                tag_transitions.into_iter().next().unwrap()
-            };
+                start_row = end_row;
+                series_set
+            })
+            .collect::<Vec<_>>();
 
-            let mut start_row: u32 = 0;
-
-            // create each series (since bitmap are not Send, we can't
-            // call await during the loop)
-
-            // emit each series
-            let series_sets = intersections
-                .iter()
-                .map(|end_row| {
-                    let series_set = SeriesSet {
-                        table_name: table_name.clone(),
-                        tags: Self::get_tag_keys(
-                            &batch,
-                            start_row as usize,
-                            &tag_columns,
-                            &tag_indicies,
-                        ),
-                        timestamp_index,
-                        field_indices: field_indicies.clone(),
-                        start_row: start_row as usize,
-                        num_rows: (end_row - start_row) as usize,
-                        batch: batch.clone(),
-                    };
-
-                    start_row = end_row;
-                    series_set
-                })
-                .collect::<Vec<_>>();
-
-            for series_set in series_sets {
-                self.tx
-                    .send(Ok(series_set))
-                    .await
-                    .map_err(|e| Error::SendingDuringConversion {
-                        source: Box::new(e),
-                    })?;
-            }
-        }
-        Ok(())
+        Ok(series_sets)
     }
 
     // look up which column index correponds to each column name
@@ -412,65 +424,47 @@ impl GroupedSeriesSetConverter {
         // Convert the batches into series sets using
         // SeriesSetConverter, and insert the appropriate GroupStart
         // frames
+        let mut series_sets =
+            SeriesSetConverter::convert_stream(table_name, tag_columns, field_columns, it);
 
-        let (tx, mut rx) = mpsc::channel(2);
-
-        // task that processes the sets from the series
-        let mut output_tx = self.tx.clone();
-        let task = tokio::task::spawn(async move {
-            // vec of num_prefix_tag_group_columns
-            let mut last_group_tags: Option<Vec<(Arc<String>, Arc<String>)>> = None;
-
-            while let Some(series_set) = rx.recv().await {
-                let series_set: SeriesSet = series_set?;
-
-                // figure out if we are in a new group
-                let need_group_start = match &last_group_tags {
-                    None => true,
-                    Some(last_group_tags) => {
-                        last_group_tags.as_slice()
-                            != &series_set.tags[0..num_prefix_tag_group_columns]
-                    }
-                };
+        // vec of num_prefix_tag_group_columns
+        let mut last_group_tags: Option<Vec<(Arc<String>, Arc<String>)>> = None;
 
-                if need_group_start {
-                    let group_tags = series_set.tags[0..num_prefix_tag_group_columns].to_vec();
+        while let Some(series_set) = series_sets.next().await {
+            let series_set: SeriesSet = series_set?;
 
-                    let group_desc = GroupDescription {
-                        tags: group_tags.clone(),
-                    };
+            // figure out if we are in a new group
+            let need_group_start = match &last_group_tags {
+                None => true,
+                Some(last_group_tags) => {
+                    last_group_tags.as_slice() != &series_set.tags[0..num_prefix_tag_group_columns]
+                }
+            };
 
-                    output_tx
-                        .send(Ok(GroupedSeriesSetItem::GroupStart(group_desc)))
-                        .await
-                        .map_err(|e| Error::SendingDuringGroupedConversion {
-                            source: Box::new(e),
-                        })?;
+            if need_group_start {
+                let group_tags = series_set.tags[0..num_prefix_tag_group_columns].to_vec();
 
-                    last_group_tags = Some(group_tags);
-                }
+                let group_desc = GroupDescription {
+                    tags: group_tags.clone(),
+                };
 
-                output_tx
-                    .send(Ok(GroupedSeriesSetItem::GroupData(series_set)))
+                self.tx
+                    .send(Ok(GroupedSeriesSetItem::GroupStart(group_desc)))
                     .await
                     .map_err(|e| Error::SendingDuringGroupedConversion {
                         source: Box::new(e),
                     })?;
-            }
-            Ok(()) as Result<()>
-        });
 
-        // Setup the task pipeline and start it running!
-        let mut series_converter = SeriesSetConverter::new(tx);
-        series_converter
-            .convert(table_name, tag_columns, field_columns, it)
-            .await?;
+                last_group_tags = Some(group_tags);
+            }
 
-        // tear down series converter manually, to drop the sending
-        // channel so the `task` knows no more results are coming
-        // before waiting for it to resolve.
-        std::mem::drop(series_converter);
-        task.await.context(JoinError)??;
+            self.tx
+                .send(Ok(GroupedSeriesSetItem::GroupData(series_set)))
+                .await
+                .map_err(|e| Error::SendingDuringGroupedConversion {
+                    source: Box::new(e),
+                })?;
+        }
 
         Ok(())
     }
@@ -698,6 +692,71 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_convert_missing_time_column() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tag_a", DataType::Utf8, true),
+            Field::new("float_field", DataType::Float64, true),
+        ]));
+        let input = parse_to_iterator(schema, "one,10.0\n");
+
+        let table_name = "foo";
+        let tag_columns = ["tag_a"];
+        let field_columns = ["float_field"];
+        let results = convert(table_name, &tag_columns, &field_columns, input).await;
+
+        // the error is the only item of the stream
+        assert_eq!(results.len(), 1);
+        assert!(
+            matches!(results[0], Err(Error::ColumnNotFoundForSeriesSet { .. })),
+            "results were\n{:#?}",
+            results
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_convert_to_channel() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tag_a", DataType::Utf8, true),
+            Field::new("float_field", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let input = parse_to_iterator(
+            schema,
+            "one,10.0,1000\n\
+             two,10.1,2000\n",
+        );
+
+        let (tx, mut rx) = mpsc::channel(1);
+        let mut converter = SeriesSetConverter::new(tx);
+        let table_name = Arc::new("foo".into());
+        let tag_columns = str_vec_to_arc_vec(&["tag_a"]);
+        let field_columns = str_vec_to_arc_vec(&["float_field"]);
+
+        tokio::task::spawn(async move {
+            converter
+                .convert(table_name, tag_columns, field_columns, input)
+                .await
+                .expect("Conversion happened without error")
+        });
+
+        let mut tags = Vec::new();
+        while let Some(r) = rx.recv().await {
+            tags.push(r.expect("Correctly converted").tags)
+        }
+        assert_eq!(
+            tags,
+            vec![
+                str_pair_vec_to_vec(&[("tag_a", "one")]),
+                str_pair_vec_to_vec(&[("tag_a", "two")]),
+            ]
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_convert_groups() -> Result<()> {
         let schema = Arc::new(Schema::new(vec![
@@ -847,25 +906,13 @@ mod tests {
         field_columns: &'a [&'a str],
         it: SendableRecordBatchStream,
     ) -> Vec<Result<SeriesSet>> {
-        let (tx, mut rx) = mpsc::channel(1);
-        let mut converter = SeriesSetConverter::new(tx);
-
         let table_name = Arc::new(table_name.into());
         let tag_columns = str_vec_to_arc_vec(tag_columns);
         let field_columns = str_vec_to_arc_vec(field_columns);
 
-        tokio::task::spawn(async move {
-            converter
-                .convert(table_name, tag_columns, field_columns, it)
-                .await
-                .expect("Conversion happened without error")
-        });
-
-        let mut results = Vec::new();
-        while let Some(r) = rx.recv().await {
-            results.push(r)
-        }
-        results
+        SeriesSetConverter::convert_stream(table_name, tag_columns, field_columns, it)
+            .collect()
+            .await
     }
 
     /// Test helper: run conversion to groups and return a Vec
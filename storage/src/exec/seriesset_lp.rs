@@ -0,0 +1,428 @@
+//! This module contains code to convert the rows of a `SeriesSet`
+//! back into InfluxDB line protocol, one line per row.
+//!
+//! The measurement is the table name of the series and the tags are
+//! the tags of the series, except for empty (null) tag values, which
+//! line protocol can not represent. Each field column becomes a field whose
+//! value is formatted according to its type (`i` suffix for integers,
+//! plain floats and booleans, and quoted strings). Null field values
+//! and non finite floats (`NaN` and infinities), which line protocol
+//! can not represent, are left out, and rows without any field values
+//! are skipped. Line protocol can not represent newlines in names or
+//! values either, so series sets containing them are rejected.
+use std::fmt::Write;
+
+use arrow_deps::arrow::{
+    array::{Array, BooleanArray, Float64Array, Int64Array, StringArray},
+    datatypes::DataType,
+};
+use snafu::{ensure, Snafu};
+
+use super::seriesset::SeriesSet;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Unsupported type {:?} of field column '{}' for line protocol",
+        data_type,
+        column_name
+    ))]
+    UnsupportedFieldType {
+        column_name: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display(
+        "Unsupported type {:?} of timestamp column '{}' for line protocol",
+        data_type,
+        column_name
+    ))]
+    UnsupportedTimestampType {
+        column_name: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display("Can not write {:?} as line protocol: it contains a newline", value))]
+    ContainsNewline { value: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Characters to escape in measurements
+const MEASUREMENT_DELIMITERS: &[char] = &['\\', ',', ' '];
+
+/// Characters to escape in tag keys, tag values and field keys
+const KEY_DELIMITERS: &[char] = &['\\', ',', '=', ' '];
+
+/// Characters to escape in string field values
+const STRING_FIELD_DELIMITERS: &[char] = &['\\', '"'];
+
+/// Converts each row of `series_set` into a line of line protocol,
+/// each ending with a newline
+pub fn series_set_to_line_protocol(series_set: &SeriesSet) -> Result<String> {
+    let batch = &series_set.batch;
+    let schema = batch.schema();
+
+    let timestamp_column = batch.column(series_set.timestamp_index);
+    let timestamps = timestamp_column.as_any().downcast_ref::<Int64Array>();
+    ensure!(
+        timestamps.is_some(),
+        UnsupportedTimestampType {
+            column_name: schema.field(series_set.timestamp_index).name(),
+            data_type: timestamp_column.data_type().clone(),
+        }
+    );
+    let timestamps = timestamps.expect("checked timestamp type");
+
+    for &field_index in series_set.field_indices.iter() {
+        let data_type = batch.column(field_index).data_type();
+        ensure!(
+            matches!(
+                data_type,
+                DataType::Int64 | DataType::Float64 | DataType::Utf8 | DataType::Boolean
+            ),
+            UnsupportedFieldType {
+                column_name: schema.field(field_index).name(),
+                data_type: data_type.clone(),
+            }
+        );
+    }
+
+    // the measurement and tags are the same for all rows
+    let mut series_key = String::new();
+    write_escaped(
+        &mut series_key,
+        &series_set.table_name,
+        MEASUREMENT_DELIMITERS,
+    )?;
+    for (key, value) in series_set
+        .tags
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+    {
+        series_key.push(',');
+        write_escaped(&mut series_key, key, KEY_DELIMITERS)?;
+        series_key.push('=');
+        write_escaped(&mut series_key, value, KEY_DELIMITERS)?;
+    }
+
+    let mut lines = String::new();
+    let mut fields = String::new();
+    let end_row = series_set.start_row + series_set.num_rows;
+    for row in series_set.start_row..end_row {
+        fields.clear();
+        for &field_index in series_set.field_indices.iter() {
+            let column = batch.column(field_index);
+            if column.is_null(row) || is_non_finite(column.as_ref(), row) {
+                continue;
+            }
+
+            if !fields.is_empty() {
+                fields.push(',');
+            }
+            write_escaped(
+                &mut fields,
+                schema.field(field_index).name(),
+                KEY_DELIMITERS,
+            )?;
+            fields.push('=');
+            write_field_value(&mut fields, column.as_ref(), row)?;
+        }
+
+        if fields.is_empty() {
+            continue;
+        }
+
+        lines.push_str(&series_key);
+        lines.push(' ');
+        lines.push_str(&fields);
+        if !timestamps.is_null(row) {
+            write!(lines, " {}", timestamps.value(row)).expect("writing to a String never fails");
+        }
+        lines.push('\n');
+    }
+
+    Ok(lines)
+}
+
+/// Returns true if the value at `row` of `column` is a `NaN` or
+/// infinite float
+fn is_non_finite(column: &dyn Array, row: usize) -> bool {
+    column
+        .as_any()
+        .downcast_ref::<Float64Array>()
+        .map_or(false, |column| !column.value(row).is_finite())
+}
+
+/// Writes the value at `row` of `column`, which must have one of the
+/// supported field types
+fn write_field_value(out: &mut String, column: &dyn Array, row: usize) -> Result<()> {
+    let column = column.as_any();
+    if let Some(column) = column.downcast_ref::<Int64Array>() {
+        write!(out, "{}i", column.value(row))
+    } else if let Some(column) = column.downcast_ref::<Float64Array>() {
+        write!(out, "{}", column.value(row))
+    } else if let Some(column) = column.downcast_ref::<BooleanArray>() {
+        write!(out, "{}", column.value(row))
+    } else if let Some(column) = column.downcast_ref::<StringArray>() {
+        out.push('"');
+        write_escaped(out, column.value(row), STRING_FIELD_DELIMITERS)?;
+        out.push('"');
+        Ok(())
+    } else {
+        unreachable!("field types are checked before writing")
+    }
+    .expect("writing to a String never fails");
+    Ok(())
+}
+
+/// Writes `value` to `out`, escaping all of the `delimiters` in it
+/// with a backslash. Fails if `value` contains a newline.
+fn write_escaped(out: &mut String, value: &str, delimiters: &[char]) -> Result<()> {
+    ensure!(
+        !value.contains('\n'),
+        ContainsNewline {
+            value: value.to_string()
+        }
+    );
+
+    for c in value.chars() {
+        if delimiters.contains(&c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow_deps::arrow::{
+        array::ArrayRef,
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use influxdb_line_protocol::{parse_lines, FieldValue};
+    use test_helpers::str_pair_vec_to_vec;
+
+    use super::*;
+
+    fn make_series_set(
+        table_name: &str,
+        tags: &[(&str, &str)],
+        field_columns: Vec<(&str, ArrayRef)>,
+        timestamps: Vec<Option<i64>>,
+    ) -> SeriesSet {
+        let num_rows = timestamps.len();
+        let mut fields: Vec<Field> = field_columns
+            .iter()
+            .map(|(name, array)| Field::new(name, array.data_type().clone(), true))
+            .collect();
+        fields.push(Field::new("time", DataType::Int64, true));
+
+        let mut arrays: Vec<ArrayRef> = field_columns.into_iter().map(|(_, a)| a).collect();
+        arrays.push(Arc::new(Int64Array::from(timestamps)));
+
+        let timestamp_index = arrays.len() - 1;
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays)
+            .expect("created new record batch");
+
+        SeriesSet {
+            table_name: Arc::new(table_name.into()),
+            tags: str_pair_vec_to_vec(tags),
+            timestamp_index,
+            field_indices: Arc::new((0..timestamp_index).collect()),
+            start_row: 0,
+            num_rows,
+            batch,
+        }
+    }
+
+    #[test]
+    fn test_series_set_to_line_protocol() {
+        let series_set = make_series_set(
+            "h2o",
+            &[("city", "Boston"), ("state", "MA")],
+            vec![
+                ("temp", Arc::new(Float64Array::from(vec![Some(70.4), None]))),
+                ("count", Arc::new(Int64Array::from(vec![Some(5), Some(6)]))),
+                (
+                    "ok",
+                    Arc::new(BooleanArray::from(vec![Some(true), Some(false)])),
+                ),
+                (
+                    "note",
+                    Arc::new(StringArray::from(vec![Some("fine"), None])),
+                ),
+            ],
+            vec![Some(100), Some(200)],
+        );
+
+        assert_eq!(
+            series_set_to_line_protocol(&series_set).unwrap(),
+            "h2o,city=Boston,state=MA temp=70.4,count=5i,ok=true,note=\"fine\" 100\n\
+             h2o,city=Boston,state=MA count=6i,ok=false 200\n"
+        );
+    }
+
+    #[test]
+    fn test_start_row_and_skipped_rows() {
+        let mut series_set = make_series_set(
+            "cpu",
+            &[],
+            vec![(
+                "usage",
+                Arc::new(Float64Array::from(vec![
+                    Some(1.0),
+                    Some(2.0),
+                    None,
+                    Some(4.0),
+                ])),
+            )],
+            vec![Some(1), Some(2), Some(3), None],
+        );
+        series_set.start_row = 1;
+        series_set.num_rows = 3;
+
+        // the row without field values is skipped, and the row without
+        // a timestamp is written without one
+        assert_eq!(
+            series_set_to_line_protocol(&series_set).unwrap(),
+            "cpu usage=2 2\n\
+             cpu usage=4\n"
+        );
+    }
+
+    #[test]
+    fn test_escaping_round_trip() {
+        let series_set = make_series_set(
+            "my measurement,1",
+            &[("tag key", "a=b,c d\\")],
+            vec![
+                (
+                    "field,key",
+                    Arc::new(StringArray::from(vec![r#"say "hi" \ bye"#])),
+                ),
+                ("f=2", Arc::new(Int64Array::from(vec![-3]))),
+            ],
+            vec![Some(1_000_000_000)],
+        );
+
+        let lp = series_set_to_line_protocol(&series_set).unwrap();
+        let lines = parse_lines(&lp)
+            .collect::<Result<Vec<_>, _>>()
+            .expect("generated line protocol parses");
+        assert_eq!(lines.len(), 1, "lines were\n{:#?}", lines);
+        let line = &lines[0];
+
+        assert_eq!(line.series.measurement.as_str(), "my measurement,1");
+        assert_eq!(
+            line.tag_value("tag key").map(|v| v.as_str()),
+            Some("a=b,c d\\")
+        );
+        match line.field_value("field,key") {
+            Some(FieldValue::String(value)) => assert_eq!(value.as_str(), r#"say "hi" \ bye"#),
+            other => panic!("unexpected field value {:?}", other),
+        }
+        assert_eq!(line.field_value("f=2"), Some(&FieldValue::I64(-3)));
+        assert_eq!(line.timestamp, Some(1_000_000_000));
+    }
+
+    #[test]
+    fn test_empty_tag_values() {
+        let series_set = make_series_set(
+            "cpu",
+            &[("host", "a"), ("region", "")],
+            vec![("usage", Arc::new(Float64Array::from(vec![1.0])))],
+            vec![Some(1)],
+        );
+
+        assert_eq!(
+            series_set_to_line_protocol(&series_set).unwrap(),
+            "cpu,host=a usage=1 1\n"
+        );
+    }
+
+    #[test]
+    fn test_non_finite_floats() {
+        let series_set = make_series_set(
+            "cpu",
+            &[],
+            vec![
+                (
+                    "usage",
+                    Arc::new(Float64Array::from(vec![
+                        f64::NAN,
+                        f64::INFINITY,
+                        f64::NEG_INFINITY,
+                        1.5,
+                    ])),
+                ),
+                (
+                    "count",
+                    Arc::new(Int64Array::from(vec![Some(1), None, None, Some(4)])),
+                ),
+            ],
+            vec![Some(1), Some(2), Some(3), Some(4)],
+        );
+
+        // non finite values are left out, like nulls, and rows with no
+        // other field values are skipped
+        assert_eq!(
+            series_set_to_line_protocol(&series_set).unwrap(),
+            "cpu count=1i 1\n\
+             cpu usage=1.5,count=4i 4\n"
+        );
+    }
+
+    #[test]
+    fn test_newlines() {
+        let tag_value = make_series_set(
+            "cpu",
+            &[("host", "a\nb")],
+            vec![("usage", Arc::new(Float64Array::from(vec![1.0])))],
+            vec![Some(1)],
+        );
+        let string_value = make_series_set(
+            "cpu",
+            &[("host", "a")],
+            vec![("note", Arc::new(StringArray::from(vec!["one\ntwo"])))],
+            vec![Some(1)],
+        );
+
+        assert_eq!(
+            series_set_to_line_protocol(&tag_value)
+                .unwrap_err()
+                .to_string(),
+            r#"Can not write "a\nb" as line protocol: it contains a newline"#
+        );
+        assert_eq!(
+            series_set_to_line_protocol(&string_value)
+                .unwrap_err()
+                .to_string(),
+            r#"Can not write "one\ntwo" as line protocol: it contains a newline"#
+        );
+    }
+
+    #[test]
+    fn test_unsupported_field_type() {
+        let series_set = make_series_set(
+            "cpu",
+            &[],
+            vec![(
+                "usage",
+                Arc::new(arrow_deps::arrow::array::UInt32Array::from(vec![1])),
+            )],
+            vec![Some(1)],
+        );
+
+        assert_eq!(
+            series_set_to_line_protocol(&series_set)
+                .unwrap_err()
+                .to_string(),
+            "Unsupported type UInt32 of field column 'usage' for line protocol"
+        );
+    }
+}
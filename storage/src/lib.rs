@@ -12,15 +12,20 @@ use data_types::data::ReplicatedWrite;
 use exec::{FieldListPlan, GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan};
 use influxdb_line_protocol::ParsedLine;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::BTreeMap, fmt::Debug, sync::Arc};
 
+pub mod delete_predicate;
 pub mod exec;
 pub mod id;
 pub mod predicate;
+pub mod schema;
+pub mod summary;
 pub mod util;
 pub mod window;
 
 use self::predicate::{Predicate, TimestampRange};
+use self::schema::ColumnSchema;
+use self::summary::PartitionSummary;
 
 #[async_trait]
 
@@ -45,7 +50,7 @@ use self::predicate::{Predicate, TimestampRange};
 /// categories with the same data type, columns of different
 /// categories are treated differently in the different query types.
 pub trait Database: Debug + Send + Sync {
-    type Error: std::error::Error + Send + Sync + 'static;
+    type Error: DatabaseError + Send + Sync + 'static;
 
     /// writes parsed lines into this database
     async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error>;
@@ -53,6 +58,33 @@ pub trait Database: Debug + Send + Sync {
     /// Stores the replicated write in the write buffer and, if enabled, the write ahead log.
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error>;
 
+    /// Deletes all rows which pass the conditions specified by
+    /// `predicate`, returning the number of deleted rows
+    async fn delete(&self, predicate: Predicate) -> Result<usize, Self::Error>;
+
+    /// Returns the keys of the partitions of this database, sorted
+    async fn partition_keys(&self) -> Result<Vec<String>, Self::Error>;
+
+    /// Returns the size, table count and row count of each partition
+    /// of this database, sorted by partition key
+    async fn partition_summaries(&self) -> Result<Vec<PartitionSummary>, Self::Error>;
+
+    /// Returns the names of the tables in the partition with
+    /// `partition_key`, sorted. There are none if there is no such
+    /// partition.
+    async fn table_names_for_partition(
+        &self,
+        partition_key: &str,
+    ) -> Result<Vec<String>, Self::Error>;
+
+    /// Returns the columns of each table in the partition with
+    /// `partition_key`, by table name, with the columns of each table
+    /// sorted by name. There are none if there is no such partition.
+    async fn partition_table_schemas(
+        &self,
+        partition_key: &str,
+    ) -> Result<BTreeMap<String, Vec<ColumnSchema>>, Self::Error>;
+
     /// Execute the specified query and return arrow record batches with the result
     async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error>;
 
@@ -114,6 +146,15 @@ pub trait Database: Debug + Send + Sync {
     ) -> Result<Vec<RecordBatch>, Self::Error>;
 }
 
+/// The errors of a `Database`, which tell callers whether the request
+/// or the database itself was at fault
+pub trait DatabaseError: std::error::Error {
+    /// Returns true if the error was caused by the request, such as a
+    /// query that can't be planned or a delete predicate that can't be
+    /// applied, rather than by a failure of the database
+    fn is_invalid_request(&self) -> bool;
+}
+
 #[async_trait]
 /// Storage for `Databases` which can be retrieved by name
 pub trait DatabaseStore: Debug + Send + Sync {
@@ -130,6 +171,14 @@ pub trait DatabaseStore: Debug + Send + Sync {
     /// Retrieve the database specified by `name`, creating it if it
     /// doesn't exist.
     async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error>;
+
+    /// The names of all databases, sorted
+    async fn db_names(&self) -> Vec<String>;
+
+    /// Checks that the store is usable, returning an error describing
+    /// why if it is not. This should be cheap enough to run on every
+    /// readiness probe.
+    async fn health(&self) -> Result<(), Self::Error>;
 }
 
 /// Compatibility: return the database name to use for the specified
@@ -0,0 +1,40 @@
+//! This module contains descriptions of the columns of tables, as
+//! reported by `Database::partition_table_schemas`
+use arrow_deps::arrow::datatypes::DataType;
+
+/// The category of a column in the InfluxDB data model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnCategory {
+    Tag,
+    Field,
+    Time,
+}
+
+impl ColumnCategory {
+    /// The name of the category
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tag => "tag",
+            Self::Field => "field",
+            Self::Time => "time",
+        }
+    }
+}
+
+/// The name, Arrow type and category of a column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnSchema {
+    pub name: String,
+    pub data_type: DataType,
+    pub category: ColumnCategory,
+}
+
+impl ColumnSchema {
+    pub fn new(name: impl Into<String>, data_type: DataType, category: ColumnCategory) -> Self {
+        Self {
+            name: name.into(),
+            data_type,
+            category,
+        }
+    }
+}
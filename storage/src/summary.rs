@@ -0,0 +1,17 @@
+//! This module contains summaries of the partitions of a database, as
+//! reported by `Database::partition_summaries`
+
+/// The approximate size and contents of a partition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionSummary {
+    pub key: String,
+
+    /// The approximate number of bytes of memory used by the
+    /// partition's data
+    pub size_bytes: usize,
+
+    pub table_count: usize,
+
+    /// The total number of rows of all tables in the partition
+    pub row_count: usize,
+}
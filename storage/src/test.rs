@@ -9,7 +9,9 @@ use crate::{
         stringset::{StringSet, StringSetRef},
         GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan,
     },
-    Database, DatabaseStore, Predicate, TimestampRange,
+    schema::ColumnSchema,
+    summary::PartitionSummary,
+    Database, DatabaseError, DatabaseStore, Predicate, TimestampRange,
 };
 
 use data_types::data::ReplicatedWrite;
@@ -17,7 +19,7 @@ use influxdb_line_protocol::{parse_lines, ParsedLine};
 
 use async_trait::async_trait;
 use snafu::{OptionExt, Snafu};
-use std::{collections::BTreeMap, collections::BTreeSet, sync::Arc};
+use std::{collections::BTreeMap, collections::BTreeSet, sync::Arc, time::Duration};
 
 use std::fmt::Write;
 
@@ -31,6 +33,9 @@ pub struct TestDatabase {
     /// Replicated writes which have been written to this database, in order
     replicated_writes: Mutex<Vec<ReplicatedWrite>>,
 
+    /// The last request for `table_names`
+    table_names_request: Arc<Mutex<Option<TableNamesRequest>>>,
+
     /// `column_names` to return upon next request
     column_names: Arc<Mutex<Option<StringSetRef>>>,
 
@@ -60,6 +65,40 @@ pub struct TestDatabase {
 
     /// The last request for `query_series`
     field_columns_request: Arc<Mutex<Option<FieldColumnsRequest>>>,
+
+    /// Responses to return on the next request to `query`
+    query_values: Arc<Mutex<Option<Vec<RecordBatch>>>>,
+
+    /// The last request for `query`
+    query_request: Arc<Mutex<Option<QueryRequest>>>,
+
+    /// How long `query` takes to return, if set
+    query_delay: Arc<Mutex<Option<Duration>>>,
+
+    /// Number of deleted rows to return on the next request to `delete`
+    delete_count: Arc<Mutex<Option<usize>>>,
+
+    /// The last request for `delete`
+    delete_request: Arc<Mutex<Option<DeleteRequest>>>,
+
+    /// `partition_keys` to return on every request
+    partition_keys: Arc<Mutex<Vec<String>>>,
+
+    /// The table names to return for each partition key
+    partition_table_names: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
+
+    /// The table schemas to return for each partition key
+    partition_table_schemas: Arc<Mutex<BTreeMap<String, BTreeMap<String, Vec<ColumnSchema>>>>>,
+
+    /// `partition_summaries` to return on every request
+    partition_summaries: Arc<Mutex<Vec<PartitionSummary>>>,
+}
+
+/// Records the parameters passed to a table names request
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableNamesRequest {
+    /// Stringified '{:?}' version of the predicate
+    pub predicate: String,
 }
 
 /// Records the parameters passed to a column name request
@@ -101,6 +140,20 @@ pub struct FieldColumnsRequest {
     pub predicate: String,
 }
 
+/// Records the parameters passed to a `query` request
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryRequest {
+    /// The SQL text of the query
+    pub query: String,
+}
+
+/// Records the parameters passed to a `delete` request
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeleteRequest {
+    /// Stringified '{:?}' version of the predicate
+    pub predicate: String,
+}
+
 #[derive(Snafu, Debug)]
 pub enum TestError {
     #[snafu(display("Test database error:  {}", message))]
@@ -110,6 +163,13 @@ pub enum TestError {
     Execution { source: crate::exec::Error },
 }
 
+impl DatabaseError for TestError {
+    fn is_invalid_request(&self) -> bool {
+        // the errors of the test database are missing mocked results
+        false
+    }
+}
+
 impl TestDatabase {
     pub fn new() -> Self {
         Self::default()
@@ -137,6 +197,11 @@ impl TestDatabase {
             .expect("writing lines");
     }
 
+    /// Get the parameters from the last table names request
+    pub async fn get_table_names_request(&self) -> Option<TableNamesRequest> {
+        self.table_names_request.clone().lock().await.take()
+    }
+
     /// Set the list of column names that will be returned on a call to column_names
     pub async fn set_column_names(&self, column_names: Vec<String>) {
         let column_names = column_names.into_iter().collect::<StringSet>();
@@ -192,6 +257,68 @@ impl TestDatabase {
     pub async fn get_field_columns_request(&self) -> Option<FieldColumnsRequest> {
         self.field_columns_request.clone().lock().await.take()
     }
+
+    /// Set the record batches that will be returned on the next call to query
+    pub async fn set_query_values(&self, batches: Vec<RecordBatch>) {
+        *(self.query_values.clone().lock().await) = Some(batches);
+    }
+
+    /// Set how long calls to query take to return
+    pub async fn set_query_delay(&self, delay: Duration) {
+        *(self.query_delay.clone().lock().await) = Some(delay);
+    }
+
+    /// Get the parameters from the last query request
+    pub async fn get_query_request(&self) -> Option<QueryRequest> {
+        self.query_request.clone().lock().await.take()
+    }
+
+    /// Set the number of deleted rows that will be returned on the next call to delete
+    pub async fn set_delete_count(&self, count: usize) {
+        *(self.delete_count.clone().lock().await) = Some(count);
+    }
+
+    /// Get the parameters from the last delete request
+    pub async fn get_delete_request(&self) -> Option<DeleteRequest> {
+        self.delete_request.clone().lock().await.take()
+    }
+
+    /// Set the partition keys that will be returned on calls to partition_keys
+    pub async fn set_partition_keys(&self, partition_keys: Vec<String>) {
+        *(self.partition_keys.clone().lock().await) = partition_keys;
+    }
+
+    /// Set the partition summaries that will be returned on calls to
+    /// partition_summaries
+    pub async fn set_partition_summaries(&self, partition_summaries: Vec<PartitionSummary>) {
+        *(self.partition_summaries.clone().lock().await) = partition_summaries;
+    }
+
+    /// Set the table names that will be returned on calls to
+    /// table_names_for_partition with `partition_key`
+    pub async fn set_partition_table_names(
+        &self,
+        partition_key: impl Into<String>,
+        table_names: Vec<String>,
+    ) {
+        self.partition_table_names
+            .lock()
+            .await
+            .insert(partition_key.into(), table_names);
+    }
+
+    /// Set the table schemas that will be returned on calls to
+    /// partition_table_schemas with `partition_key`
+    pub async fn set_partition_table_schemas(
+        &self,
+        partition_key: impl Into<String>,
+        table_schemas: BTreeMap<String, Vec<ColumnSchema>>,
+    ) {
+        self.partition_table_schemas
+            .lock()
+            .await
+            .insert(partition_key.into(), table_schemas);
+    }
 }
 
 /// returns true if this line is within the range of the timestamp
@@ -261,13 +388,93 @@ impl Database for TestDatabase {
         Ok(())
     }
 
-    /// Execute the specified query and return arrow record batches with the result
-    async fn query(&self, _query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
-        unimplemented!("query Not yet implemented");
+    /// Return the mocked out number of deleted rows, recording the request
+    async fn delete(&self, predicate: Predicate) -> Result<usize, Self::Error> {
+        let predicate = predicate_to_test_string(&predicate);
+
+        let new_delete_request = Some(DeleteRequest { predicate });
+
+        *self.delete_request.clone().lock().await = new_delete_request;
+
+        self.delete_count
+            .clone()
+            .lock()
+            .await
+            .take()
+            // Turn None into an error
+            .context(General {
+                message: "No saved delete_count in TestDatabase",
+            })
+    }
+
+    /// Return the mocked out partition keys
+    async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.partition_keys.lock().await.clone())
+    }
+
+    /// Return the mocked out partition summaries
+    async fn partition_summaries(&self) -> Result<Vec<PartitionSummary>, Self::Error> {
+        Ok(self.partition_summaries.lock().await.clone())
+    }
+
+    /// Return the mocked out table names of the partition
+    async fn table_names_for_partition(
+        &self,
+        partition_key: &str,
+    ) -> Result<Vec<String>, Self::Error> {
+        let partition_table_names = self.partition_table_names.lock().await;
+        Ok(partition_table_names
+            .get(partition_key)
+            .cloned()
+            .unwrap_or_default())
     }
 
-    /// Return all table names that are saved in this database
+    /// Return the mocked out table schemas of the partition
+    async fn partition_table_schemas(
+        &self,
+        partition_key: &str,
+    ) -> Result<BTreeMap<String, Vec<ColumnSchema>>, Self::Error> {
+        let partition_table_schemas = self.partition_table_schemas.lock().await;
+        Ok(partition_table_schemas
+            .get(partition_key)
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Return the mocked out query results, recording the request
+    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
+        let new_query_request = Some(QueryRequest {
+            query: query.into(),
+        });
+
+        *self.query_request.clone().lock().await = new_query_request;
+
+        let query_delay = *self.query_delay.lock().await;
+        if let Some(query_delay) = query_delay {
+            tokio::time::delay_for(query_delay).await;
+        }
+
+        self.query_values
+            .clone()
+            .lock()
+            .await
+            .take()
+            // Turn None into an error
+            .context(General {
+                message: "No saved query_values in TestDatabase",
+            })
+    }
+
+    /// Return all table names that are saved in this database,
+    /// recording the request. Only the range of the predicate is
+    /// applied.
     async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+        let new_table_names_request = Some(TableNamesRequest {
+            predicate: predicate_to_test_string(&predicate),
+        });
+
+        *self.table_names_request.clone().lock().await = new_table_names_request;
+
         let saved_lines = self.saved_lines.lock().await;
 
         let names = parse_lines(&saved_lines.join("\n"))
@@ -415,6 +622,9 @@ impl Database for TestDatabase {
 #[derive(Debug)]
 pub struct TestDatabaseStore {
     databases: Mutex<BTreeMap<String, Arc<TestDatabase>>>,
+
+    /// The error to return from `health`, if any
+    health_error: Mutex<Option<String>>,
 }
 
 impl TestDatabaseStore {
@@ -430,12 +640,18 @@ impl TestDatabaseStore {
             .add_lp_string(lp_data)
             .await
     }
+
+    /// Makes `health` fail with `message`
+    pub async fn set_health_error(&self, message: impl Into<String>) {
+        *self.health_error.lock().await = Some(message.into());
+    }
 }
 
 impl Default for TestDatabaseStore {
     fn default() -> Self {
         Self {
             databases: Mutex::new(BTreeMap::new()),
+            health_error: Mutex::new(None),
         }
     }
 }
@@ -464,4 +680,17 @@ impl DatabaseStore for TestDatabaseStore {
             Ok(new_db)
         }
     }
+
+    async fn db_names(&self) -> Vec<String> {
+        let databases = self.databases.lock().await;
+
+        databases.keys().cloned().collect()
+    }
+
+    async fn health(&self) -> Result<(), Self::Error> {
+        match self.health_error.lock().await.as_ref() {
+            Some(message) => General { message }.fail(),
+            None => Ok(()),
+        }
+    }
 }
@@ -11,7 +11,8 @@ use tracing::{
     Event,
 };
 
-/// This struct captures tracing `Event`s as strings, and can be used
+/// This struct captures tracing `Event`s (and the fields of new
+/// spans) as strings, and can be used
 /// to verify that messages are making it to logs correctly
 ///
 /// Upon creation it registers itself as the global default span
@@ -53,7 +54,13 @@ struct TracingCaptureSubscriber {
 }
 
 impl Subscriber for TracingCaptureSubscriber {
-    fn new_span(&self, _span: &Attributes<'_>) -> Id {
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut v = StringVisitor {
+            string: format!("span {}: ", span.metadata().name()),
+        };
+        span.record(&mut v);
+        let mut logs = self.logs.lock().expect("got span mutex lock");
+        logs.push(v.string);
         Id::from_u64(1)
     }
 
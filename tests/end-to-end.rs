@@ -467,8 +467,11 @@ async fn test_http_error_messages(client: &influxdb2_client::Client) -> Result<(
         .await
         .expect_err("Should have errored");
 
-    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"error\":\"Error parsing line protocol: A generic parsing error occurred: TakeWhile1\"}`";
-    assert_eq!(result.to_string(), expected_error);
+    // followed by the generated id of the request
+    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"code\":\"invalid\",\"line\":\"arbitrary\",\"line_number\":1,\"message\":\"Error parsing line protocol at line 1: A generic parsing error occurred: TakeWhile1\",\"request_id\":\"";
+    let error = result.to_string();
+    assert!(error.starts_with(expected_error), "{}", error);
+    assert!(error.ends_with("\"}`"), "{}", error);
 
     Ok(())
 }
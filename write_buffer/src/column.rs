@@ -1,7 +1,12 @@
 use generated_types::wal as wb;
 use snafu::Snafu;
+use std::{
+    fmt::{Debug, Display},
+    mem,
+};
 
 use crate::dictionary::Dictionary;
+use arrow_deps::arrow::datatypes::DataType as ArrowDataType;
 use data_types::{data::type_description, partition_metadata::Statistics};
 
 #[derive(Debug, Snafu)]
@@ -114,6 +119,21 @@ impl Column {
         self.len() == 0
     }
 
+    /// The approximate number of bytes used by the values of the
+    /// column, not counting the tag values in the dictionary
+    pub fn size(&self) -> usize {
+        match self {
+            Self::F64(v, _) => v.len() * mem::size_of::<Option<f64>>(),
+            Self::I64(v, _) => v.len() * mem::size_of::<Option<i64>>(),
+            Self::String(v, _) => {
+                v.len() * mem::size_of::<Option<String>>()
+                    + v.iter().flatten().map(String::len).sum::<usize>()
+            }
+            Self::Bool(v, _) => v.len() * mem::size_of::<Option<bool>>(),
+            Self::Tag(v, _) => v.len() * mem::size_of::<Option<u32>>(),
+        }
+    }
+
     pub fn type_description(&self) -> &'static str {
         match self {
             Self::F64(_, _) => "f64",
@@ -124,6 +144,16 @@ impl Column {
         }
     }
 
+    /// The type of the column when converted to Arrow
+    pub fn arrow_data_type(&self) -> ArrowDataType {
+        match self {
+            Self::F64(_, _) => ArrowDataType::Float64,
+            Self::I64(_, _) => ArrowDataType::Int64,
+            Self::String(_, _) | Self::Tag(_, _) => ArrowDataType::Utf8,
+            Self::Bool(_, _) => ArrowDataType::Boolean,
+        }
+    }
+
     pub fn push(&mut self, dictionary: &mut Dictionary, value: &wb::Value<'_>) -> Result<()> {
         let inserted = match self {
             Self::Tag(vals, stats) => match value.value_as_tag_value() {
@@ -217,6 +247,43 @@ impl Column {
         }
     }
 
+    /// Removes the rows for which `delete` is true and recomputes the
+    /// statistics from the remaining values. Returns false if no
+    /// non-null values remain, in which case the statistics are
+    /// stale and the column should be removed.
+    pub fn delete_rows(&mut self, dictionary: &Dictionary, delete: &[bool]) -> bool {
+        match self {
+            Self::F64(vals, stats) => {
+                retain_rows(vals, delete);
+                replace_statistics(stats, statistics(vals.iter().flatten().copied()))
+            }
+            Self::I64(vals, stats) => {
+                retain_rows(vals, delete);
+                replace_statistics(stats, statistics(vals.iter().flatten().copied()))
+            }
+            Self::String(vals, stats) => {
+                retain_rows(vals, delete);
+                replace_statistics(
+                    stats,
+                    string_statistics(vals.iter().flatten().map(String::as_str)),
+                )
+            }
+            Self::Bool(vals, stats) => {
+                retain_rows(vals, delete);
+                replace_statistics(stats, statistics(vals.iter().flatten().copied()))
+            }
+            Self::Tag(vals, stats) => {
+                retain_rows(vals, delete);
+                let values = vals.iter().flatten().map(|&id| {
+                    dictionary
+                        .lookup_id(id)
+                        .expect("tag value id is in the partition dictionary")
+                });
+                replace_statistics(stats, string_statistics(values))
+            }
+        }
+    }
+
     /// Returns true if any rows are within the range [min_value,
     /// max_value). Inclusive of `start`, exclusive of `end`
     pub fn has_i64_range(&self, start: i64, end: i64) -> Result<bool> {
@@ -257,6 +324,40 @@ impl Column {
     }
 }
 
+/// Removes the values for which `delete` is true
+fn retain_rows<T>(vals: &mut Vec<Option<T>>, delete: &[bool]) {
+    let mut delete = delete.iter();
+    vals.retain(|_| !delete.next().expect("one delete flag per row"));
+}
+
+fn statistics<T>(mut values: impl Iterator<Item = T>) -> Option<Statistics<T>>
+where
+    T: PartialEq + PartialOrd + Debug + Display + Clone,
+{
+    let mut stats = Statistics::new(values.next()?);
+    values.for_each(|value| stats.update(value));
+    Some(stats)
+}
+
+fn string_statistics<'a>(mut values: impl Iterator<Item = &'a str>) -> Option<Statistics<String>> {
+    let mut stats = Statistics::new(values.next()?.to_string());
+    values.for_each(|value| Statistics::update_string(&mut stats, value));
+    Some(stats)
+}
+
+fn replace_statistics<T>(stats: &mut Statistics<T>, new_stats: Option<Statistics<T>>) -> bool
+where
+    T: PartialEq + PartialOrd + Debug + Display + Clone,
+{
+    match new_stats {
+        Some(new_stats) => {
+            *stats = new_stats;
+            true
+        }
+        None => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -302,6 +403,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_delete_rows() {
+        let mut dictionary = Dictionary::new();
+        let a = dictionary.lookup_value_or_insert("a");
+        let b = dictionary.lookup_value_or_insert("b");
+        let c = dictionary.lookup_value_or_insert("c");
+
+        let mut stats = Statistics::new("a".to_string());
+        stats.update("b".to_string());
+        stats.update("c".to_string());
+        let mut col = Column::Tag(vec![Some(a), None, Some(b), Some(c)], stats);
+
+        assert!(col.delete_rows(&dictionary, &[true, false, false, true]));
+        match &col {
+            Column::Tag(vals, stats) => {
+                assert_eq!(vals, &vec![None, Some(b)]);
+                assert_eq!((stats.min.as_str(), stats.max.as_str()), ("b", "b"));
+                assert_eq!(stats.count, 1);
+            }
+            _ => panic!("expected a tag column"),
+        }
+
+        // no values remain
+        assert!(!col.delete_rows(&dictionary, &[false, true]));
+        assert_eq!(col.len(), 1);
+
+        let mut stats = Statistics::new(1);
+        stats.update(2);
+        stats.update(3);
+        let mut col = Column::I64(vec![Some(1), Some(2), Some(3)], stats);
+        assert!(col.delete_rows(&dictionary, &[false, false, true]));
+        match &col {
+            Column::I64(vals, stats) => {
+                assert_eq!(vals, &vec![Some(1), Some(2)]);
+                assert_eq!((stats.min, stats.max, stats.count), (1, 2, 2));
+            }
+            _ => panic!("expected an i64 column"),
+        }
+    }
+
     #[test]
     fn test_has_non_null_i64_range_() -> Result {
         let none_col: Vec<Option<u32>> = vec![None, None, None];
@@ -1,12 +1,15 @@
 use generated_types::wal as wb;
 use influxdb_line_protocol::ParsedLine;
 use storage::{
+    delete_predicate::{DeletePredicate, Error as DeletePredicateError},
     exec::{
         stringset::StringSet, FieldListPlan, GroupedSeriesSetPlan, GroupedSeriesSetPlans,
         SeriesSetPlan, SeriesSetPlans, StringSetPlan,
     },
-    predicate::Predicate,
-    Database,
+    predicate::{Predicate, TimestampRange},
+    schema::{ColumnCategory, ColumnSchema},
+    summary::PartitionSummary,
+    Database, DatabaseError,
 };
 use wal::{
     writer::{start_wal_sync_task, Error as WalWriterError, WalDetails},
@@ -21,7 +24,7 @@ use std::io::ErrorKind;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::{
-    collections::{BTreeSet, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     path::Path,
 };
 
@@ -34,14 +37,17 @@ use arrow_deps::{
         datasource::MemTable, error::DataFusionError, execution::context::ExecutionContext,
     },
 };
-use data_types::data::{split_lines_into_write_entry_partitions, ReplicatedWrite};
+use data_types::data::{
+    delete_to_write_entry_partitions, split_lines_into_write_entry_partitions, ReplicatedWrite,
+};
+use data_types::TIME_COLUMN_NAME;
 
 use crate::dictionary::Error as DictionaryError;
 use crate::partition::restore_partitions_from_wal;
 
 use async_trait::async_trait;
 use chrono::{offset::TimeZone, Utc};
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use sqlparser::{
     ast::{SetExpr, Statement, TableFactor},
     dialect::GenericDialect,
@@ -185,6 +191,12 @@ pub enum Error {
         source: sqlparser::parser::ParserError,
     },
 
+    #[snafu(display("error planning query {}: {}", query, source))]
+    PlanningQuery {
+        query: String,
+        source: DataFusionError,
+    },
+
     #[snafu(display("error executing query {}: {}", query, source))]
     QueryError {
         query: String,
@@ -202,6 +214,30 @@ pub enum Error {
 
     #[snafu(display("replicated write from writer {} missing payload", writer))]
     MissingPayload { writer: u32 },
+
+    #[snafu(display("Invalid delete: {}", source))]
+    InvalidDelete { source: DeletePredicateError },
+
+    #[snafu(display("Invalid delete: {}", source))]
+    UnsupportedDelete { source: crate::partition::Error },
+
+    #[snafu(display("Field restrictions are not supported when listing table names"))]
+    UnsupportedFieldRestriction,
+}
+
+impl DatabaseError for Error {
+    fn is_invalid_request(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidSqlQuery { .. }
+                | Self::UnsupportedStatement { .. }
+                | Self::PlanningQuery { .. }
+                | Self::UnsupportedColumnTypeForListingValues { .. }
+                | Self::InvalidDelete { .. }
+                | Self::UnsupportedDelete { .. }
+                | Self::UnsupportedFieldRestriction
+        )
+    }
 }
 
 impl From<crate::table::Error> for Error {
@@ -317,10 +353,15 @@ impl Db {
         })
     }
 
-    async fn write_entries_to_partitions(&self, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
+    /// Writes the entries of `batch` to `partitions`. Callers hold the
+    /// write lock on the partitions until the batch has also been
+    /// appended to the WAL, so that the WAL has writes and deletes in
+    /// the order they were applied in memory.
+    fn write_entries_to_partitions(
+        partitions: &mut Vec<Partition>,
+        batch: &wb::WriteBufferBatch<'_>,
+    ) -> Result<()> {
         if let Some(entries) = batch.entries() {
-            let mut partitions = self.partitions.write().await;
-
             for entry in entries {
                 let key = entry
                     .partition_key()
@@ -351,7 +392,8 @@ impl Database for Db {
         let data = split_lines_into_write_entry_partitions(partition_key, lines);
         let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
 
-        self.write_entries_to_partitions(&batch).await?;
+        let mut partitions = self.partitions.write().await;
+        Self::write_entries_to_partitions(&mut partitions, &batch)?;
 
         if let Some(wal) = &self.wal_details {
             wal.write_and_sync(data).await.context(WritingWal {
@@ -363,8 +405,9 @@ impl Database for Db {
     }
 
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
+        let mut partitions = self.partitions.write().await;
         match write.write_buffer_batch() {
-            Some(b) => self.write_entries_to_partitions(&b).await?,
+            Some(b) => Self::write_entries_to_partitions(&mut partitions, &b)?,
             None => {
                 return MissingPayload {
                     writer: write.to_fb().writer(),
@@ -386,26 +429,184 @@ impl Database for Db {
         Ok(())
     }
 
+    async fn delete(&self, predicate: Predicate) -> Result<usize, Self::Error> {
+        let delete_predicate =
+            DeletePredicate::from_predicate(&predicate).context(InvalidDelete)?;
+        let range = predicate
+            .range
+            .unwrap_or_else(|| TimestampRange::new(i64::MIN, i64::MAX));
+
+        // the lock is held until the delete has been appended to the WAL,
+        // so that no write can come between them
+        let mut partitions = self.partitions.write().await;
+
+        // check all partitions first, so that a predicate which can't be
+        // evaluated against some table leaves all of them unchanged
+        for partition in partitions.iter() {
+            partition
+                .check_delete(&delete_predicate)
+                .context(UnsupportedDelete)?;
+        }
+
+        let mut deleted = 0;
+        let mut partition_keys = Vec::new();
+        for partition in partitions.iter_mut() {
+            let partition_deleted = partition.delete(&delete_predicate, range)?;
+            if partition_deleted > 0 {
+                deleted += partition_deleted;
+                partition_keys.push(partition.key.clone());
+            }
+        }
+
+        if let Some(wal) = &self.wal_details {
+            if !partition_keys.is_empty() {
+                // the table name is stored separately in the WAL
+                let predicate = DeletePredicate {
+                    table_name: None,
+                    exprs: delete_predicate.exprs,
+                };
+                let partition_keys = partition_keys
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                let data = delete_to_write_entry_partitions(
+                    &partition_keys,
+                    delete_predicate.table_name.as_deref(),
+                    &predicate.to_string(),
+                    range.start,
+                    range.end,
+                );
+
+                wal.write_and_sync(data).await.context(WritingWal {
+                    database: &self.name,
+                })?;
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    async fn partition_keys(&self) -> Result<Vec<String>, Self::Error> {
+        let partitions = self.partitions.read().await;
+
+        let mut partition_keys: Vec<_> = partitions
+            .iter()
+            .map(|partition| partition.key.clone())
+            .collect();
+        partition_keys.sort();
+
+        Ok(partition_keys)
+    }
+
+    async fn partition_summaries(&self) -> Result<Vec<PartitionSummary>, Self::Error> {
+        let partitions = self.partitions.read().await;
+
+        let mut summaries: Vec<_> = partitions
+            .iter()
+            .map(|partition| PartitionSummary {
+                key: partition.key.clone(),
+                size_bytes: partition.size(),
+                table_count: partition.tables.len(),
+                row_count: partition.row_count(),
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(summaries)
+    }
+
+    async fn table_names_for_partition(
+        &self,
+        partition_key: &str,
+    ) -> Result<Vec<String>, Self::Error> {
+        let partitions = self.partitions.read().await;
+
+        let mut table_names: Vec<_> = partitions
+            .iter()
+            .filter(|partition| partition.key == partition_key)
+            .flat_map(|partition| {
+                partition.tables.keys().map(move |table_name_symbol| {
+                    partition
+                        .dictionary
+                        .lookup_id(*table_name_symbol)
+                        .expect("table name is in the dictionary")
+                        .to_string()
+                })
+            })
+            .collect();
+        table_names.sort();
+
+        Ok(table_names)
+    }
+
+    async fn partition_table_schemas(
+        &self,
+        partition_key: &str,
+    ) -> Result<BTreeMap<String, Vec<ColumnSchema>>, Self::Error> {
+        let partitions = self.partitions.read().await;
+
+        let mut table_schemas = BTreeMap::new();
+        for partition in partitions.iter().filter(|p| p.key == partition_key) {
+            for table in partition.tables.values() {
+                let table_name = partition.dictionary.lookup_id(table.id).context(
+                    TableIdNotFoundInDictionary {
+                        table: table.id,
+                        partition: &partition.key,
+                    },
+                )?;
+
+                let mut columns = table
+                    .column_id_to_index
+                    .iter()
+                    .map(|(&column_id, &column_index)| {
+                        let column_name = partition.dictionary.lookup_id(column_id).context(
+                            ColumnIdNotFoundInDictionary {
+                                column_id,
+                                partition: &partition.key,
+                            },
+                        )?;
+                        let column = &table.columns[column_index];
+                        let category = match column {
+                            Column::Tag(_, _) => ColumnCategory::Tag,
+                            _ if column_name == TIME_COLUMN_NAME => ColumnCategory::Time,
+                            _ => ColumnCategory::Field,
+                        };
+                        Ok(ColumnSchema::new(
+                            column_name,
+                            column.arrow_data_type(),
+                            category,
+                        ))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                columns.sort_by(|a, b| a.name.cmp(&b.name));
+
+                table_schemas.insert(table_name.to_string(), columns);
+            }
+        }
+
+        Ok(table_schemas)
+    }
+
     async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+        // a field restriction selects columns, not tables
+        ensure!(
+            predicate.field_columns.is_none(),
+            UnsupportedFieldRestriction
+        );
+
+        if predicate.has_exprs() {
+            let mut filter = PartitionTableFilter::new(predicate);
+            let mut visitor = TableNamePredVisitor::new();
+            self.visit_tables(&mut filter, &mut visitor).await?;
+            return Ok(visitor.plans.into());
+        }
+
         // TODO: Cache this information to avoid creating this each time
         let partitions = self.partitions.read().await;
 
         let mut table_names: BTreeSet<String> = BTreeSet::new();
         for partition in partitions.iter() {
             let partition_predicate = partition.compile_predicate(&predicate)?;
-            // this doesn't seem to make any sense
-            assert!(
-                partition_predicate.field_restriction.is_none(),
-                "Column selection for table names not supported"
-            );
-
-            // It might make sense to ask for all table names that
-            // have rows that pass a general purpose predicate. I am
-            // not sure if it is needed now, so panic
-            assert!(
-                partition_predicate.partition_exprs.is_empty(),
-                "General partition exprs on table name list are not supported"
-            );
 
             for (table_name_symbol, table) in &partition.tables {
                 if table.could_match_predicate(&partition_predicate)? {
@@ -544,8 +745,8 @@ impl Database for Db {
 
         let plan = ctx
             .create_logical_plan(&query)
-            .context(QueryError { query })?;
-        let plan = ctx.optimize(&plan).context(QueryError { query })?;
+            .context(PlanningQuery { query })?;
+        let plan = ctx.optimize(&plan).context(PlanningQuery { query })?;
         let plan = ctx
             .create_physical_plan(&plan)
             .context(QueryError { query })?;
@@ -829,6 +1030,31 @@ impl Visitor for NamePredVisitor {
     }
 }
 
+/// Return the names of all tables in this database that have
+/// rows passing a general purpose predicate
+struct TableNamePredVisitor {
+    plans: Vec<LogicalPlan>,
+}
+
+impl TableNamePredVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for TableNamePredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.table_name_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
 /// return a plan that selects all values from field columns after
 /// applying timestamp and other predicates
 #[derive(Debug)]
@@ -1203,6 +1429,60 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn list_table_names_predicate() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        let lp_data = "h2o,state=CA,city=LA temp=70.4 100\n\
+                       h2o,state=MA,city=Boston temp=72.4 250\n\
+                       o2,state=CA temp=79.0 300\n\
+                       cpu,region=west user=23.2 150\n";
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // Predicate: state=MA
+        let expr = logical_plan::col("state").eq("MA".lit());
+        let predicate = PredicateBuilder::default().add_expr(expr).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["h2o"]));
+
+        // Predicate: state=CA
+        let expr = logical_plan::col("state").eq("CA".lit());
+        let predicate = PredicateBuilder::default().add_expr(expr).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["h2o", "o2"]));
+
+        // Predicate: state=CA and a timestamp range excluding h2o's row
+        let expr = logical_plan::col("state").eq("CA".lit());
+        let predicate = PredicateBuilder::default()
+            .add_expr(expr)
+            .timestamp_range(200, 400)
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["o2"]));
+
+        // Predicate: state=NY
+        let expr = logical_plan::col("state").eq("NY".lit());
+        let predicate = PredicateBuilder::default().add_expr(expr).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+
+        // Predicate: a field restriction, alone or with an expression
+        let field_restriction = || PredicateBuilder::default().field_columns(vec!["temp".into()]);
+        let expr = logical_plan::col("state").eq("CA".lit());
+        for predicate in vec![
+            field_restriction().build(),
+            field_restriction().add_expr(expr).build(),
+        ] {
+            let err = db.table_names(predicate).await.unwrap_err();
+            assert!(
+                matches!(err, Error::UnsupportedFieldRestriction),
+                "unexpected error {:?}",
+                err
+            );
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn missing_tags_are_null() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
@@ -1340,6 +1620,133 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn delete_and_recover() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+--------+------+------+------+
+| region | host | user | time |
++--------+------+------+------+
+| west   | A    | 23.2 | 10   |
+| east   | C    | 21.5 | 20   |
++--------+------+------+------+
+"#;
+        let cpu_columns = &["region", "host", "user", "time"];
+
+        let delete_predicate = |predicate: &str, start: i64, end: i64| {
+            DeletePredicate::parse(predicate)
+                .unwrap()
+                .to_predicate(TimestampRange::new(start, end))
+        };
+
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            let lp_data = "cpu,region=west,host=A user=23.2 10\n\
+                           cpu,region=west,host=B user=23.1 15\n\
+                           cpu,region=east,host=C user=21.5 20\n\
+                           cpu,region=west,host=B user=24.0 30\n\
+                           mem,region=west,host=B val=1i 15\n";
+            let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+
+            let predicate = delete_predicate(
+                r#"_measurement="cpu" AND region="west" AND host!="A""#,
+                10,
+                31,
+            );
+            assert_eq!(db.delete(predicate).await?, 2);
+
+            let predicate = delete_predicate(r#"_measurement="mem""#, i64::MIN, i64::MAX);
+            assert_eq!(db.delete(predicate).await?, 1);
+
+            // only tag and string columns can be compared
+            let predicate = delete_predicate(r#"user="23.2""#, i64::MIN, i64::MAX);
+            let error = db.delete(predicate).await.unwrap_err().to_string();
+            assert!(
+                error.contains("Can not delete by column 'user' of type f64"),
+                "unexpected error: {}",
+                error
+            );
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+            assert_eq!(
+                table_names(&db, Predicate::default()).await?,
+                to_set(&["cpu"])
+            );
+        }
+
+        // check that the deletes are recovered from the wal
+        {
+            let db = Db::restore_from_wal(&dir).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+            assert_eq!(
+                table_names(&db, Predicate::default()).await?,
+                to_set(&["cpu"])
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_is_checked_against_all_tables() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+------+------+------+
+| host | user | time |
++------+------+------+
+| A    | 23.2 | 10   |
++------+------+------+
+"#;
+        let expected_mem_table = r#"+------+------+
+| host | time |
++------+------+
+| 1    | 10   |
++------+------+
+"#;
+
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            // host is a tag of cpu, but an integer field of mem
+            let lp_data = "cpu,host=A user=23.2 10
+                           mem host=1i 10
+";
+            let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+
+            let predicate = DeletePredicate::parse(r#"host="A""#)
+                .unwrap()
+                .to_predicate(TimestampRange::new(i64::MIN, i64::MAX));
+            let error = db.delete(predicate).await.unwrap_err().to_string();
+            assert!(
+                error.contains("Can not delete by column 'host' of type i64"),
+                "unexpected error: {}",
+                error
+            );
+
+            // neither table was changed
+            let partitions = db.table_to_arrow("cpu", &["host", "user", "time"]).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+            let partitions = db.table_to_arrow("mem", &["host", "time"]).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+        }
+
+        // and nothing was deleted when replaying the wal
+        {
+            let db = Db::restore_from_wal(&dir).await?;
+
+            let partitions = db.table_to_arrow("cpu", &["host", "user", "time"]).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+            let partitions = db.table_to_arrow("mem", &["host", "time"]).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+        }
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn write_and_query() -> Result {
         let db = Db::new("foo");
@@ -1496,6 +1903,121 @@ disk bytes=23432323i 1600136510000000000",
         Ok(())
     }
 
+    #[tokio::test]
+    async fn list_partition_keys() -> Result {
+        let db = Db::new("partition_keys_db");
+        assert!(db.partition_keys().await?.is_empty());
+
+        let lines: Vec<_> = parse_lines(
+            "\
+disk bytes=23432323i 1600136510000000000
+cpu user=23.2 1600107710000000000
+cpu user=24.2 1600107720000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        assert_eq!(
+            db.partition_keys().await?,
+            vec!["2020-09-14T18", "2020-09-15T02"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_table_names_for_partition() -> Result {
+        let db = Db::new("partition_tables_db");
+
+        let lines: Vec<_> = parse_lines(
+            "\
+disk bytes=23432323i 1600136510000000000
+mem free=10i 1600107710000000000
+cpu user=23.2 1600107710000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        assert_eq!(
+            db.table_names_for_partition("2020-09-14T18").await?,
+            vec!["cpu", "mem"]
+        );
+        assert_eq!(
+            db.table_names_for_partition("2020-09-15T02").await?,
+            vec!["disk"]
+        );
+        assert!(db
+            .table_names_for_partition("2020-09-16T00")
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_partition_summaries() -> Result {
+        let db = Db::new("partition_summaries_db");
+
+        let lines: Vec<_> = parse_lines(
+            "\
+cpu,host=a user=23.2 1600107710000000000
+cpu,host=b user=10.1 1600107720000000000
+disk,host=a bytes=23432323i 1600107730000000000
+disk bytes=23432323i 1600136510000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let summaries = db.partition_summaries().await?;
+        let keys: Vec<_> = summaries.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(keys, vec!["2020-09-14T18", "2020-09-15T02"]);
+        assert_eq!(summaries[0].table_count, 2);
+        assert_eq!(summaries[0].row_count, 3);
+        assert_eq!(summaries[1].table_count, 1);
+        assert_eq!(summaries[1].row_count, 1);
+        assert!(summaries[0].size_bytes > summaries[1].size_bytes);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_partition_table_schemas() -> Result {
+        let db = Db::new("partition_schemas_db");
+
+        let lines: Vec<_> = parse_lines(
+            "\
+cpu,host=a user=23.2,count=2i,up=true,msg=\"ok\" 1600107710000000000
+disk bytes=23432323i 1600136510000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let schemas = db.partition_table_schemas("2020-09-14T18").await?;
+        assert_eq!(schemas.keys().collect::<Vec<_>>(), vec!["cpu"]);
+        assert_eq!(
+            schemas["cpu"],
+            vec![
+                ColumnSchema::new("count", DataType::Int64, ColumnCategory::Field),
+                ColumnSchema::new("host", DataType::Utf8, ColumnCategory::Tag),
+                ColumnSchema::new("msg", DataType::Utf8, ColumnCategory::Field),
+                ColumnSchema::new("time", DataType::Int64, ColumnCategory::Time),
+                ColumnSchema::new("up", DataType::Boolean, ColumnCategory::Field),
+                ColumnSchema::new("user", DataType::Float64, ColumnCategory::Field),
+            ]
+        );
+
+        assert!(db
+            .partition_table_schemas("2020-09-16T00")
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn list_column_names() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
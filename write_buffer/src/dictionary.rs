@@ -50,6 +50,15 @@ impl Dictionary {
         self.0.get(value).map(symbol_to_u32)
     }
 
+    /// The number of bytes of the strings in the dictionary
+    pub fn size(&self) -> usize {
+        (0..self.0.len())
+            .filter_map(DefaultSymbol::try_from_usize)
+            .filter_map(|symbol| self.0.resolve(symbol))
+            .map(str::len)
+            .sum()
+    }
+
     /// Returns the str in self.dictionary that corresponds to `id`,
     /// if any. Returns an error if no such id is found
     pub fn lookup_id(&self, id: u32) -> Result<&str> {
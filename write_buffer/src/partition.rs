@@ -9,6 +9,7 @@ use wal::{Entry as WalEntry, Result as WalResult};
 
 use data_types::TIME_COLUMN_NAME;
 use storage::{
+    delete_predicate::DeletePredicate,
     predicate::{Predicate, TimestampRange},
     util::{visit_expression, AndExprBuilder, ExpressionVisitor},
 };
@@ -68,6 +69,17 @@ pub enum Error {
 
     #[snafu(display("Error restoring WAL entry, missing partition key"))]
     MissingPartitionKey,
+
+    #[snafu(display("Error deleting from table '{}': {}", table_name, source))]
+    TableDelete {
+        table_name: String,
+        source: crate::table::Error,
+    },
+
+    #[snafu(display("Error restoring delete from WAL: {}", source))]
+    InvalidWalDelete {
+        source: storage::delete_predicate::Error,
+    },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -211,6 +223,17 @@ impl Partition {
         }
     }
 
+    /// The approximate number of bytes used by the tables and the
+    /// dictionary of the partition
+    pub fn size(&self) -> usize {
+        self.dictionary.size() + self.tables.values().map(Table::size).sum::<usize>()
+    }
+
+    /// The total number of rows of the tables of the partition
+    pub fn row_count(&self) -> usize {
+        self.tables.values().map(Table::row_count).sum()
+    }
+
     pub fn write_entry(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
         if let Some(table_batches) = entry.table_batches() {
             for batch in table_batches {
@@ -218,9 +241,80 @@ impl Partition {
             }
         }
 
+        if let Some(delete) = entry.delete() {
+            let mut predicate = DeletePredicate::parse(delete.predicate().unwrap_or_default())
+                .context(InvalidWalDelete)?;
+            predicate.table_name = delete.table_name().map(|name| name.to_string());
+            let range = TimestampRange::new(delete.start_time(), delete.stop_time());
+
+            self.delete(&predicate, range)?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks that `predicate` can be evaluated against all tables it
+    /// deletes from, so that a delete spanning several tables and
+    /// partitions can be checked before any of them are changed
+    pub fn check_delete(&self, predicate: &DeletePredicate) -> Result<()> {
+        for table_id in self.delete_table_ids(predicate) {
+            if let Some(table) = self.tables.get(&table_id) {
+                table
+                    .check_delete(&self.dictionary, predicate)
+                    .with_context(|| TableDelete {
+                        table_name: self
+                            .dictionary
+                            .lookup_id(table_id)
+                            .expect("table id is in the partition dictionary"),
+                    })?;
+            }
+        }
+
         Ok(())
     }
 
+    /// Deletes the rows within `range` that match `predicate`,
+    /// removing tables without any remaining rows. Returns the number
+    /// of deleted rows.
+    pub fn delete(&mut self, predicate: &DeletePredicate, range: TimestampRange) -> Result<usize> {
+        let time_column_id = match self.dictionary.id(TIME_COLUMN_NAME) {
+            Some(time_column_id) => time_column_id,
+            // nothing was written to this partition yet
+            None => return Ok(0),
+        };
+
+        let mut deleted = 0;
+        for table_id in self.delete_table_ids(predicate) {
+            let dictionary = &self.dictionary;
+            let table = match self.tables.get_mut(&table_id) {
+                Some(table) => table,
+                None => continue,
+            };
+
+            deleted += table
+                .delete_rows(dictionary, predicate, time_column_id, range)
+                .with_context(|| TableDelete {
+                    table_name: dictionary
+                        .lookup_id(table_id)
+                        .expect("table id is in the partition dictionary"),
+                })?;
+
+            if table.row_count() == 0 {
+                self.tables.remove(&table_id);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// The ids of the tables `predicate` deletes from
+    fn delete_table_ids(&self, predicate: &DeletePredicate) -> Vec<u32> {
+        match &predicate.table_name {
+            Some(table_name) => self.dictionary.id(table_name).into_iter().collect(),
+            None => self.tables.keys().copied().collect(),
+        }
+    }
+
     fn write_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
         let table_name = batch.name().context(TableWriteWithoutName)?;
         let table_id = self.dictionary.lookup_value_or_insert(table_name);
@@ -112,4 +112,18 @@ impl DatabaseStore for WriteBufferDatabases {
 
         Ok(db)
     }
+
+    async fn db_names(&self) -> Vec<String> {
+        let databases = self.databases.read().await;
+
+        databases.keys().cloned().collect()
+    }
+
+    /// Checks that the directory holding the WALs can be read
+    async fn health(&self) -> Result<(), Self::Error> {
+        fs::read_dir(&self.base_dir).context(ReadError {
+            dir: &self.base_dir,
+        })?;
+        Ok(())
+    }
 }
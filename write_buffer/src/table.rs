@@ -1,5 +1,9 @@
 use generated_types::wal as wb;
-use storage::exec::{make_schema_pivot, GroupedSeriesSetPlan, SeriesSetPlan};
+use storage::{
+    delete_predicate::{DeleteExpr, DeletePredicate},
+    exec::{make_schema_pivot, GroupedSeriesSetPlan, SeriesSetPlan},
+    predicate::TimestampRange,
+};
 use tracing::debug;
 
 use std::{collections::BTreeSet, collections::HashMap, sync::Arc};
@@ -12,7 +16,7 @@ use crate::{
     partition::{Partition, PartitionPredicate},
 };
 use data_types::TIME_COLUMN_NAME;
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
 use arrow_deps::{
     arrow,
@@ -25,6 +29,7 @@ use arrow_deps::{
     datafusion::logical_plan::Expr,
     datafusion::logical_plan::LogicalPlan,
     datafusion::logical_plan::LogicalPlanBuilder,
+    datafusion::scalar::ScalarValue,
 };
 
 #[derive(Debug, Snafu)]
@@ -147,6 +152,9 @@ pub enum Error {
         source: crate::column::Error,
     },
 
+    #[snafu(display("Field restrictions are not supported in {} plans", plan))]
+    UnsupportedFieldRestriction { plan: &'static str },
+
     #[snafu(display("Row insert to table {} missing column name", table))]
     ColumnNameNotInRow { table: u32 },
 
@@ -162,6 +170,16 @@ pub enum Error {
 
     #[snafu(display("Duplicate group column '{}'", column_name))]
     DuplicateGroupColumn { column_name: String },
+
+    #[snafu(display(
+        "Can not delete by column '{}' of type {}, only tag and string columns are supported",
+        column_name,
+        column_type
+    ))]
+    UnsupportedDeleteColumn {
+        column_name: String,
+        column_type: String,
+    },
 }
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -234,6 +252,11 @@ impl Table {
         self.columns.first().map_or(0, |v| v.len())
     }
 
+    /// The approximate number of bytes used by the columns of the table
+    pub fn size(&self) -> usize {
+        self.columns.iter().map(Column::size).sum()
+    }
+
     /// Returns a reference to the specified column
     fn column(&self, column_id: u32) -> Result<&Column> {
         Ok(self
@@ -272,6 +295,102 @@ impl Table {
         Ok(())
     }
 
+    /// Checks that all expressions of `predicate` can be evaluated
+    /// against the columns of this table, returning the error
+    /// `delete_rows` would return otherwise
+    pub fn check_delete(&self, dictionary: &Dictionary, predicate: &DeletePredicate) -> Result<()> {
+        for expr in &predicate.exprs {
+            self.delete_column(dictionary, expr)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the rows within `range` which match all expressions of
+    /// `predicate` (its table name is not checked). Columns left
+    /// without any values are removed. Returns the number of deleted
+    /// rows.
+    pub fn delete_rows(
+        &mut self,
+        dictionary: &Dictionary,
+        predicate: &DeletePredicate,
+        time_column_id: u32,
+        range: TimestampRange,
+    ) -> Result<usize> {
+        let mut delete = self
+            .column_i64(time_column_id)?
+            .iter()
+            .map(|&time| range.contains_opt(time))
+            .collect::<Vec<_>>();
+
+        for expr in &predicate.exprs {
+            let matches = self.delete_expr_matches(dictionary, expr)?;
+            for (row, matches) in delete.iter_mut().zip(matches) {
+                *row = *row && matches;
+            }
+        }
+
+        let deleted = delete.iter().filter(|&&delete| delete).count();
+        if deleted == 0 {
+            return Ok(0);
+        }
+
+        // columns are stored in order of their index
+        let mut column_ids = self.column_id_to_index.drain().collect::<Vec<_>>();
+        column_ids.sort_by_key(|&(_, index)| index);
+        let columns = std::mem::take(&mut self.columns);
+
+        for ((column_id, _), mut column) in column_ids.into_iter().zip(columns) {
+            if column.delete_rows(dictionary, &delete) {
+                self.column_id_to_index
+                    .insert(column_id, self.columns.len());
+                self.columns.push(column);
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    /// Returns the column `expr` compares against, or `None` if this
+    /// table doesn't have it. Only tag and string columns can be
+    /// compared.
+    fn delete_column(&self, dictionary: &Dictionary, expr: &DeleteExpr) -> Result<Option<&Column>> {
+        let column = dictionary
+            .id(&expr.column)
+            .and_then(|column_id| self.column_id_to_index.get(&column_id))
+            .map(|&column_index| &self.columns[column_index]);
+
+        match column {
+            None | Some(Column::Tag(..)) | Some(Column::String(..)) => Ok(column),
+            Some(column) => UnsupportedDeleteColumn {
+                column_name: &expr.column,
+                column_type: column.type_description(),
+            }
+            .fail(),
+        }
+    }
+
+    /// Returns, for each row, whether it matches `expr`
+    fn delete_expr_matches(&self, dictionary: &Dictionary, expr: &DeleteExpr) -> Result<Vec<bool>> {
+        Ok(match self.delete_column(dictionary, expr)? {
+            None => vec![expr.matches(None); self.row_count()],
+            Some(Column::Tag(vals, _)) => vals
+                .iter()
+                .map(|value_id| {
+                    expr.matches(value_id.map(|value_id| {
+                        dictionary
+                            .lookup_id(value_id)
+                            .expect("tag value id is in the partition dictionary")
+                    }))
+                })
+                .collect(),
+            Some(Column::String(vals, _)) => vals
+                .iter()
+                .map(|value| expr.matches(value.as_deref()))
+                .collect(),
+            Some(_) => unreachable!("delete_column only returns tag and string columns"),
+        })
+    }
+
     /// Creates and adds a datafuson filtering expression, if any out of the
     /// combination of predicate and timestamp. Returns the builder
     fn add_datafusion_predicate(
@@ -339,8 +458,14 @@ impl Table {
             projected_schema,
         });
 
-        // Shouldn't have field selections here (as we are getting the tags...)
-        assert!(!partition_predicate.has_field_restriction());
+        // field restrictions select field columns, which these plans
+        // don't return
+        ensure!(
+            !partition_predicate.has_field_restriction(),
+            UnsupportedFieldRestriction {
+                plan: "tag column names"
+            }
+        );
 
         let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
 
@@ -377,6 +502,65 @@ impl Table {
         Ok(plan)
     }
 
+    /// Creates a DataFusion LogicalPlan that returns the name of this
+    /// table as a single column of Strings if any of its rows pass the
+    /// predicate, and no rows otherwise
+    ///
+    /// The created plan looks like:
+    ///
+    ///    Projection (the table name as a literal)
+    ///      Limit(1)
+    ///        Filter(predicate)
+    ///          InMemoryScan
+    pub fn table_name_plan(
+        &self,
+        partition_predicate: &PartitionPredicate,
+        partition: &Partition,
+    ) -> Result<LogicalPlan> {
+        let table_name = partition
+            .dictionary
+            .lookup_id(self.id)
+            .expect("looking up table name in dictionary")
+            .to_string();
+
+        // TODO avoid materializing all the columns here (ideally
+        // DataFusion can prune them out)
+        let data = self.all_to_arrow(partition)?;
+
+        let schema = data.schema();
+
+        let projection = None;
+        let projected_schema = schema.clone();
+        let select_exprs =
+            vec![Expr::Literal(ScalarValue::Utf8(Some(table_name))).alias("table_name")];
+
+        let plan_builder = LogicalPlanBuilder::from(&LogicalPlan::InMemoryScan {
+            data: vec![vec![data]],
+            schema,
+            projection,
+            projected_schema,
+        });
+
+        // field restrictions select field columns, which these plans
+        // don't return
+        ensure!(
+            !partition_predicate.has_field_restriction(),
+            UnsupportedFieldRestriction {
+                plan: "table names"
+            }
+        );
+
+        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
+
+        plan_builder
+            .limit(1)
+            .context(BuildingPlan)?
+            .project(select_exprs)
+            .context(BuildingPlan)?
+            .build()
+            .context(BuildingPlan)
+    }
+
     /// Creates a DataFusion LogicalPlan that returns column *values* as a
     /// single column of Strings
     ///
@@ -409,8 +593,12 @@ impl Table {
             projected_schema,
         });
 
-        // shouldn't have columns selection (as this is getting tag values...)
-        assert!(!partition_predicate.has_field_restriction());
+        // field restrictions select field columns, which these plans
+        // don't return
+        ensure!(
+            !partition_predicate.has_field_restriction(),
+            UnsupportedFieldRestriction { plan: "tag values" }
+        );
 
         let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
 